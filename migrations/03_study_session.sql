@@ -0,0 +1,33 @@
+-- Reader heartbeats, sent periodically while a student has a book open
+CREATE TABLE study_heartbeat (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    student_id INTEGER NOT NULL,
+    book_id INTEGER NOT NULL,
+    chapter_number CHAR(20),
+    time DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    FOREIGN KEY (student_id) REFERENCES student(id) ON DELETE CASCADE,
+    FOREIGN KEY (book_id) REFERENCES book(id) ON DELETE CASCADE
+);
+
+CREATE INDEX study_heartbeat_student_book_time ON study_heartbeat (student_id, book_id, time);
+
+-- Study sessions derived from history messages and heartbeats
+CREATE TABLE study_session (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    student_id INTEGER NOT NULL,
+    book_id INTEGER NOT NULL,
+    start_time DATETIME NOT NULL,
+    end_time DATETIME NOT NULL,
+    chapters TEXT NOT NULL,
+    message_count INTEGER NOT NULL,
+    FOREIGN KEY (student_id) REFERENCES student(id) ON DELETE CASCADE,
+    FOREIGN KEY (book_id) REFERENCES book(id) ON DELETE CASCADE
+);
+
+CREATE INDEX study_session_student_start ON study_session (student_id, start_time);
+
+CREATE TABLE study_goal (
+    student_id INTEGER PRIMARY KEY NOT NULL,
+    daily_minutes INTEGER NOT NULL,
+    FOREIGN KEY (student_id) REFERENCES student(id) ON DELETE CASCADE
+);
@@ -0,0 +1,19 @@
+-- History of the teaching plans edited, regenerated or reverted by managers,
+-- the latest version of a plan is the one in `teaching_plan.toml`
+CREATE TABLE plan_version (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    book_id INTEGER NOT NULL,
+    -- chapter of a chapter plan, NULL for the teaching plan of the book
+    chapter_number TEXT,
+    plan TEXT NOT NULL,
+    -- summary of the chapter, NULL for the teaching plan of the book
+    summary TEXT,
+    -- generated, edited, regenerated or reverted
+    change TEXT NOT NULL,
+    manager_id INTEGER,
+    create_time DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    FOREIGN KEY (book_id) REFERENCES book(id) ON DELETE CASCADE,
+    FOREIGN KEY (manager_id) REFERENCES manager(id) ON DELETE SET NULL
+);
+
+CREATE INDEX plan_version_book ON plan_version (book_id, chapter_number, id);
@@ -0,0 +1,5 @@
+-- Book ids were derived from the content, they are now assigned at the first import and
+-- kept by new versions of the book, the content hash tells versions apart
+ALTER TABLE book ADD COLUMN content_hash TEXT;
+
+CREATE INDEX book_content_hash ON book (content_hash);
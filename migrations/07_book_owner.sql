@@ -0,0 +1,9 @@
+-- The student who uploaded a private book, students can only add public books and their own.
+-- Private books of a single student before owners were recorded are given to that student
+ALTER TABLE book ADD COLUMN owner_student_id INTEGER REFERENCES student(id) ON DELETE SET NULL;
+
+UPDATE book SET owner_student_id = (
+    SELECT min(student_id) FROM teacher_agent WHERE teacher_agent.book_id = book.id
+)
+WHERE NOT is_public
+    AND (SELECT count(*) FROM teacher_agent WHERE teacher_agent.book_id = book.id) = 1;
@@ -0,0 +1,8 @@
+-- Instances behind a load balancer keep their own copies of the books, the revision
+-- changes with the content and the plans of a book so every instance loads it again
+ALTER TABLE book ADD COLUMN revision INTEGER NOT NULL DEFAULT 0;
+
+-- The instance running a job renews its lease, the jobs of instances that stopped are
+-- queued again once their lease expired. Running jobs are cancelled through the table
+ALTER TABLE import_job ADD COLUMN lease_until DATETIME;
+ALTER TABLE import_job ADD COLUMN cancel_requested BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,79 @@
+-- Postgres version of ../01_book_server_init.sql, integers are BIGINT to decode as i64
+CREATE TABLE book (
+    id BIGSERIAL PRIMARY KEY NOT NULL,
+    title TEXT NOT NULL,
+    authors TEXT NOT NULL,
+    description TEXT,
+    is_public BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE TABLE chapter (
+    book_id BIGINT NOT NULL,
+    chapter_number TEXT NOT NULL,
+    name TEXT NOT NULL,
+    PRIMARY KEY (book_id, chapter_number),
+    FOREIGN KEY (book_id) REFERENCES book(id) ON DELETE CASCADE
+);
+
+CREATE TABLE student (
+    id BIGSERIAL PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    email TEXT NOT NULL UNIQUE,
+    password TEXT NOT NULL
+);
+
+CREATE TABLE manager (
+    id BIGSERIAL PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    email TEXT NOT NULL UNIQUE,
+    password TEXT NOT NULL
+);
+
+CREATE TABLE teacher_agent (
+    book_id BIGINT NOT NULL,
+    student_id BIGINT NOT NULL,
+    current_chapter_number TEXT NOT NULL,
+    memories TEXT NOT NULL,
+    update_time TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    PRIMARY KEY (book_id, student_id),
+    FOREIGN KEY (book_id) REFERENCES book(id) ON DELETE CASCADE,
+    FOREIGN KEY (student_id) REFERENCES student(id) ON DELETE CASCADE
+);
+
+CREATE TABLE history_message (
+    id BIGSERIAL PRIMARY KEY NOT NULL,
+    student_id BIGINT NOT NULL,
+    book_id BIGINT NOT NULL,
+    content TEXT NOT NULL,
+    update_time TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    FOREIGN KEY (student_id) REFERENCES student(id) ON DELETE CASCADE,
+    FOREIGN KEY (book_id) REFERENCES book(id) ON DELETE CASCADE
+);
+
+-- the chapters of a book are replaced while progress still refers to them
+CREATE TABLE chapter_progress (
+    student_id BIGINT NOT NULL,
+    book_id BIGINT NOT NULL,
+    chapter_number TEXT NOT NULL,
+    status BIGINT CHECK(
+        status BETWEEN 0
+        AND 2
+    ) NOT NULL,
+    objectives TEXT NOT NULL,
+    update_time TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    PRIMARY KEY (student_id, book_id, chapter_number),
+    FOREIGN KEY (student_id) REFERENCES student(id) ON DELETE CASCADE,
+    FOREIGN KEY (book_id) REFERENCES book(id) ON DELETE CASCADE,
+    FOREIGN KEY (book_id, chapter_number) REFERENCES chapter(book_id, chapter_number) ON DELETE CASCADE DEFERRABLE
+);
+
+CREATE TABLE agent_setting (
+    id BIGSERIAL PRIMARY KEY NOT NULL,
+    ai_model TEXT NOT NULL,
+    token_budget BIGINT NOT NULL
+);
+
+INSERT INTO
+    agent_setting (ai_model, token_budget)
+VALUES
+    ('grok-2-latest', 100000);
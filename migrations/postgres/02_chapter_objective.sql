@@ -0,0 +1,9 @@
+-- Canonical learning objectives of each chapter, extracted from the chapter plan at import time
+CREATE TABLE chapter_objective (
+    book_id BIGINT NOT NULL,
+    chapter_number TEXT NOT NULL,
+    objective_id BIGINT NOT NULL,
+    description TEXT NOT NULL,
+    PRIMARY KEY (book_id, chapter_number, objective_id),
+    FOREIGN KEY (book_id, chapter_number) REFERENCES chapter(book_id, chapter_number) ON DELETE CASCADE DEFERRABLE
+);
@@ -0,0 +1,22 @@
+-- Book imports processed in the background, the uploaded file and the converted
+-- book are kept in `bookbase/imports/job_{id}` until the import succeeds
+CREATE TABLE import_job (
+    id BIGSERIAL PRIMARY KEY NOT NULL,
+    file_name TEXT NOT NULL,
+    -- student whose library receives the book, NULL for uploads of managers
+    student_id BIGINT,
+    -- queued, running, done, failed or cancelled
+    status TEXT NOT NULL DEFAULT 'queued',
+    -- queued, converting, parsing, planning, storing or done
+    stage TEXT NOT NULL DEFAULT 'queued',
+    progress_done BIGINT NOT NULL DEFAULT 0,
+    progress_total BIGINT NOT NULL DEFAULT 0,
+    book_id BIGINT,
+    error TEXT,
+    create_time TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    update_time TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    FOREIGN KEY (student_id) REFERENCES student(id) ON DELETE CASCADE
+);
+
+CREATE INDEX import_job_status ON import_job (status, id);
+CREATE INDEX import_job_student ON import_job (student_id, id);
@@ -0,0 +1,10 @@
+-- Two imports of the same content could both miss the check for an existing book, the
+-- unique index turns the second into an error. Duplicates stored before keep their
+-- oldest book
+UPDATE book SET content_hash = NULL
+WHERE content_hash IS NOT NULL
+    AND id > (SELECT min(id) FROM book AS first WHERE first.content_hash = book.content_hash);
+
+DROP INDEX book_content_hash;
+
+CREATE UNIQUE INDEX book_content_hash ON book (content_hash);
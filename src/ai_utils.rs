@@ -1,30 +1,87 @@
-use std::sync::LazyLock;
+use std::{sync::LazyLock, time::Duration};
 
 use async_openai::{
     Client,
     config::OpenAIConfig,
+    error::OpenAIError,
     types::{
-        ChatCompletionNamedToolChoice, ChatCompletionRequestMessage, ChatCompletionTool,
-        ChatCompletionToolChoiceOption, ChatCompletionToolType, CreateChatCompletionRequestArgs,
-        FunctionName, FunctionObject,
+        ChatCompletionNamedToolChoice, ChatCompletionRequestMessage,
+        ChatCompletionRequestMessageContentPartImage, ChatCompletionRequestMessageContentPartText,
+        ChatCompletionRequestUserMessage, ChatCompletionRequestUserMessageContent,
+        ChatCompletionTool, ChatCompletionToolChoiceOption, ChatCompletionToolType,
+        CreateChatCompletionRequestArgs, FunctionName, FunctionObject, ImageDetail, ImageUrl,
     },
 };
+use base64::{Engine, engine::general_purpose::STANDARD};
 
 use schemars::{JsonSchema, schema_for};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use serde_json::json;
+use tracing::warn;
 
-pub static AI_MODEL: LazyLock<String> = LazyLock::new(|| dotenvy::var("AI_MODEL").unwrap());
+use crate::{books::asset, config::config, server::metrics::LlmRequest};
+
+pub static AI_MODEL: LazyLock<String> = LazyLock::new(|| config().llm.model.clone());
+
+/// Whether `AI_MODEL` accepts images, figures are only sent to the teacher if it does
+pub static AI_VISION: LazyLock<bool> = LazyLock::new(|| config().llm.vision);
+
+/// Vision model used to describe figures at import time, figures are not described if unset
+pub static AI_FIGURE_MODEL: LazyLock<Option<String>> =
+    LazyLock::new(|| config().llm.figure_model.clone());
+
+/// Number of chapter plans generated at the same time
+pub static AI_PLAN_CONCURRENCY: LazyLock<usize> =
+    LazyLock::new(|| config().llm.plan_concurrency.max(1));
+
+/// Attempts of a request that keeps failing with transient errors
+const RETRY_ATTEMPTS: u32 = 5;
 
 pub static AI_CLIENT: LazyLock<Client<OpenAIConfig>> = LazyLock::new(|| {
-    let api_key = dotenvy::var("OPENAI_API_KEY").unwrap();
-    let base_url = dotenvy::var("OPENAI_BASE_URL").unwrap();
+    let llm = &config().llm;
     let config = OpenAIConfig::default()
-        .with_api_base(base_url)
-        .with_api_key(api_key);
+        .with_api_base(&llm.base_url)
+        .with_api_key(&llm.api_key);
     Client::with_config(config)
 });
 
+/// Whether repeating the request may succeed: network errors, rate limits,
+/// server errors and answers that could not be parsed
+pub fn is_transient(error: &anyhow::Error) -> bool {
+    if error.is::<serde_json::Error>() {
+        return true;
+    }
+    match error.downcast_ref::<OpenAIError>() {
+        Some(OpenAIError::Reqwest(_) | OpenAIError::JSONDeserialize(_)) => true,
+        Some(OpenAIError::ApiError(e)) => [&e.r#type, &e.code].into_iter().flatten().any(|kind| {
+            ["rate_limit", "server_error", "overloaded", "timeout"]
+                .iter()
+                .any(|transient| kind.contains(transient))
+        }),
+        _ => false,
+    }
+}
+
+/// Run `request` until it succeeds, waiting 1s, 2s, 4s... after transient errors
+pub async fn with_retry<T, F, Fut>(mut request: F) -> anyhow::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<T>>,
+{
+    let mut attempt = 1;
+    loop {
+        match request().await {
+            Err(e) if attempt < RETRY_ATTEMPTS && is_transient(&e) => {
+                let delay = Duration::from_secs(1 << (attempt - 1));
+                warn!("request failed ({}), retry in {:?}", e, delay);
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
 pub trait Tokens {
     fn tokens(&self) -> u64;
 }
@@ -38,6 +95,32 @@ impl Tokens for str {
         (self.len() + 2) as u64 / 4
     }
 }
+/// 85 tokens per image plus 170 per 512px tile once the image is scaled
+/// to fit 2048x2048 and its shortest side to 768px
+impl Tokens for ImageUrl {
+    fn tokens(&self) -> u64 {
+        if matches!(self.detail, Some(ImageDetail::Low)) {
+            return 85;
+        }
+        // the header is enough to read the dimensions, jpeg may need a few segments
+        let dimensions = self
+            .url
+            .strip_prefix("data:")
+            .and_then(|url| url.split_once(";base64,"))
+            .and_then(|(_, data)| STANDARD.decode(&data[..data.len().min(64 * 1024)]).ok())
+            .and_then(|data| asset::image_dimensions(&data));
+        let Some((width, height)) = dimensions else {
+            return 85 + 170 * 4;
+        };
+        let (mut width, mut height) = (width as f64, height as f64);
+        let scale = (2048.0 / width.max(height)).min(1.0);
+        (width, height) = (width * scale, height * scale);
+        let scale = (768.0 / width.min(height)).min(1.0);
+        (width, height) = (width * scale, height * scale);
+        let tiles = (width / 512.0).ceil() * (height / 512.0).ceil();
+        85 + 170 * tiles as u64
+    }
+}
 impl Tokens for ChatCompletionRequestMessage {
     fn tokens(&self) -> u64 {
         match self {
@@ -54,7 +137,7 @@ impl Tokens for ChatCompletionRequestMessage {
                     async_openai::types::ChatCompletionRequestUserMessageContent::Text(text) => text.tokens(),
                     async_openai::types::ChatCompletionRequestUserMessageContent::Array(parts) => parts.iter().map(|p| match p{
                         async_openai::types::ChatCompletionRequestUserMessageContentPart::Text(text) => text.text.tokens(),
-                        async_openai::types::ChatCompletionRequestUserMessageContentPart::ImageUrl(_image) => 170*4,
+                        async_openai::types::ChatCompletionRequestUserMessageContentPart::ImageUrl(image) => image.image_url.tokens(),
                         async_openai::types::ChatCompletionRequestUserMessageContentPart::InputAudio(audio) => audio.input_audio.data.tokens(),
                     }).sum(),
                 }
@@ -110,7 +193,8 @@ pub async fn summarize(
         .messages(vec![ChatCompletionRequestMessage::User(prompt.into())])
         .build()
         .unwrap();
-    let response = AI_CLIENT.chat().create(request).await?;
+    let llm = LlmRequest::start(AI_MODEL.as_str(), "summarize");
+    let response = llm.response(AI_CLIENT.chat().create(request).await)?;
     let summary = response
         .choices
         .first()
@@ -122,20 +206,75 @@ pub async fn summarize(
     Ok(summary)
 }
 
+/// Describe a figure for models and readers that cannot see it
+pub async fn describe_image(model: &str, image: ImageUrl, caption: &str) -> anyhow::Result<String> {
+    let prompt = format!(
+        "Describe this figure from a book so that someone who cannot see it can follow the text that refers to it. \
+        Include the labels, values and relationships it shows. \
+        The caption of the figure is: {}\n\
+        Return only the description without any additional text or explanation.",
+        caption
+    );
+    let message = ChatCompletionRequestUserMessage {
+        content: ChatCompletionRequestUserMessageContent::Array(vec![
+            ChatCompletionRequestMessageContentPartText { text: prompt }.into(),
+            ChatCompletionRequestMessageContentPartImage { image_url: image }.into(),
+        ]),
+        name: None,
+    };
+    let request = CreateChatCompletionRequestArgs::default()
+        .model(model)
+        .messages(vec![ChatCompletionRequestMessage::User(message)])
+        .build()
+        .unwrap();
+    let llm = LlmRequest::start(model, "describe_image");
+    let response = llm.response(AI_CLIENT.chat().create(request).await)?;
+    let description = response
+        .choices
+        .first()
+        .ok_or(anyhow::anyhow!("No response from OpenAI"))?
+        .message
+        .content
+        .clone()
+        .ok_or(anyhow::anyhow!("No response from OpenAI"))?;
+    Ok(description)
+}
+
 pub async fn extract_key_points(content: &str) -> anyhow::Result<Vec<String>> {
     #[derive(Debug, JsonSchema, Serialize, Deserialize)]
     struct KeyPoints(Vec<String>);
-    let tool = extract_tool::<KeyPoints>(None);
+    let prompt = format!(
+        "Extract the key points from the following text:\n{}",
+        content
+    );
+    let key_points: KeyPoints = extract(prompt).await?;
+    Ok(key_points.0)
+}
+
+/// Extract the learning objectives a chapter plan sets for the student,
+/// one short sentence each, in teaching order.
+pub async fn extract_learning_objectives(plan: &str) -> anyhow::Result<Vec<String>> {
+    #[derive(Debug, JsonSchema, Serialize, Deserialize)]
+    struct LearningObjectives(Vec<String>);
+    let prompt = format!(
+        "Extract the learning objectives from the following chapter teaching plan. \
+        Each objective must be a single short sentence describing something the student should be able to do. \
+        Keep them in teaching order and do not repeat objectives:\n{}",
+        plan
+    );
+    let objectives: LearningObjectives = extract(prompt).await?;
+    Ok(objectives.0)
+}
+
+/// Force the model to answer `prompt` by calling a tool whose arguments are `T`
+pub async fn extract<T: JsonSchema + DeserializeOwned>(prompt: String) -> anyhow::Result<T> {
+    let tool = extract_tool::<T>(None);
     let tool_choice = ChatCompletionToolChoiceOption::Named(ChatCompletionNamedToolChoice {
         r#type: ChatCompletionToolType::Function,
         function: FunctionName {
             name: tool.function.name.clone(),
         },
     });
-    let prompt = format!(
-        "Extract the key points from the following text:\n{}",
-        content
-    );
     let request = CreateChatCompletionRequestArgs::default()
         .model(AI_MODEL.as_str())
         .messages(vec![ChatCompletionRequestMessage::User(prompt.into())])
@@ -143,10 +282,9 @@ pub async fn extract_key_points(content: &str) -> anyhow::Result<Vec<String>> {
         .tool_choice(tool_choice)
         .build()
         .unwrap();
-    let response = AI_CLIENT
-        .chat()
-        .create(request)
-        .await?
+    let llm = LlmRequest::start(AI_MODEL.as_str(), "extract");
+    let response = llm
+        .response(AI_CLIENT.chat().create(request).await)?
         .choices
         .first()
         .ok_or(anyhow::anyhow!("No response from OpenAI"))?
@@ -158,8 +296,7 @@ pub async fn extract_key_points(content: &str) -> anyhow::Result<Vec<String>> {
         .function
         .arguments
         .clone();
-    let key_points: KeyPoints = serde_json::from_str(&response)?;
-    Ok(key_points.0)
+    Ok(serde_json::from_str(&response)?)
 }
 
 pub fn extract_tool<T: JsonSchema>(strict: Option<bool>) -> ChatCompletionTool {
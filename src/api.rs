@@ -1,33 +1,78 @@
+pub mod jobs;
 pub mod manager;
 pub mod public;
 pub mod user;
 
-use std::sync::Arc;
+use std::{path::Path, sync::Arc};
 
-use axum::extract::Multipart;
+use axum::{
+    extract::Multipart,
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
 use tokio::{fs::File, io::AsyncWriteExt};
 
-use crate::books::library::Library;
+use crate::books::{
+    export::{self, ExportFormat},
+    library::Library,
+};
 
+/// save the uploaded files and queue their imports, as new versions of `book_id` if given,
+/// returns the ids of the import jobs
 pub async fn upload_books(
     mut multipart: Multipart,
     library: Arc<Library>,
+    student_id: Option<i64>,
+    book_id: Option<i64>,
 ) -> anyhow::Result<Vec<i64>> {
-    let mut book_ids = Vec::new();
+    let mut job_ids = Vec::new();
     while let Some(mut field) = multipart.next_field().await? {
         let filename = field
             .file_name()
+            .and_then(|name| Path::new(name).file_name())
             .ok_or_else(|| anyhow::anyhow!("No filename found"))?
+            .to_string_lossy()
             .to_string();
+        // copied into the book storage by the job queue
         let temp_dir = tempfile::tempdir()?;
-        let path = temp_dir.path().join(filename);
+        let path = temp_dir.path().join(&filename);
         let mut file = File::create(&path).await?;
         while let Some(chunk) = field.chunk().await? {
             file.write_all(&chunk).await?;
         }
-        let book_id = library.upload_book(path).await?;
-        book_ids.push(book_id);
+        file.flush().await?;
+        let job_id = library
+            .jobs
+            .submit(&library, temp_dir, &filename, student_id, book_id)
+            .await?;
+        job_ids.push(job_id);
     }
-    Ok(book_ids)
+    Ok(job_ids)
 }
 
+/// package a book for download as `book_{id}.zip` or `book_{id}.epub`
+pub async fn export_book(
+    library: &Library,
+    book_id: i64,
+    format: ExportFormat,
+    include_plan: bool,
+) -> Response {
+    match export::export_book(library, book_id, format, include_plan).await {
+        Ok(data) => {
+            let disposition = format!(
+                "attachment; filename=\"book_{}.{}\"",
+                book_id,
+                format.extension()
+            );
+            (
+                [
+                    (header::CONTENT_TYPE, format.content_type().to_string()),
+                    (header::CONTENT_DISPOSITION, disposition),
+                ],
+                data,
+            )
+                .into_response()
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
@@ -0,0 +1,209 @@
+use std::{convert::Infallible, sync::Arc, time::Duration};
+
+use axum::{
+    Router,
+    extract::{Json, Path, State},
+    http::StatusCode,
+    response::{
+        IntoResponse, Response, Sse,
+        sse::{self, Event},
+    },
+    routing::{get, post},
+};
+use tokio::sync::{broadcast::error::RecvError, mpsc::channel};
+use tokio_stream::wrappers::ReceiverStream;
+use tower_sessions::Session;
+
+use crate::{
+    books::{
+        job::{self, ImportJob},
+        library::Library,
+    },
+    server::metrics,
+};
+
+/// the student whose jobs the session may see, none for managers who see every job
+async fn session_scope(session: &Session) -> Result<Option<i64>, Response> {
+    if let Ok(Some(_)) = session.get::<i64>("manager_id").await {
+        return Ok(None);
+    }
+    match session.get::<i64>("student_id").await {
+        Ok(Some(student_id)) => Ok(Some(student_id)),
+        _ => Err((StatusCode::UNAUTHORIZED, ()).into_response()),
+    }
+}
+
+async fn visible_job(library: &Library, session: &Session, id: i64) -> Result<ImportJob, Response> {
+    let scope = session_scope(session).await?;
+    match job::get_job(&library.database, id).await {
+        Ok(job) if scope.is_none() || job.student_id == scope => Ok(job),
+        _ => Err((StatusCode::NOT_FOUND, "Job not found").into_response()),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/jobs",
+    path = "/list",
+    method(get),
+    responses(
+        (status = 200, description = "Import jobs of the student, or all jobs for managers", body = Vec<ImportJob>),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn list_jobs(State(library): State<Arc<Library>>, session: Session) -> impl IntoResponse {
+    let scope = match session_scope(&session).await {
+        Ok(scope) => scope,
+        Err(response) => return response,
+    };
+    match job::list_jobs(&library.database, scope).await {
+        Ok(jobs) => Json(jobs).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/jobs",
+    path = "/{id}",
+    method(get),
+    params(
+        ("id" = i64, Path, description = "ID of the import job")
+    ),
+    responses(
+        (status = 200, description = "Status, stage and progress of the job", body = ImportJob),
+        (status = 401, description = "Unauthorized"),
+        (status = 404, description = "Job not found")
+    )
+)]
+pub async fn get_job(
+    State(library): State<Arc<Library>>,
+    session: Session,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    match visible_job(&library, &session, id).await {
+        Ok(job) => Json(job).into_response(),
+        Err(response) => response,
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/jobs",
+    path = "/{id}/events",
+    method(get),
+    params(
+        ("id" = i64, Path, description = "ID of the import job")
+    ),
+    responses(
+        (status = 200, description = "Stream of `job` events with the job, until it is finished", content_type = "text/event-stream"),
+        (status = 401, description = "Unauthorized"),
+        (status = 404, description = "Job not found")
+    )
+)]
+pub async fn job_events(
+    State(library): State<Arc<Library>>,
+    session: Session,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    // subscribe first so no change between reading the job and streaming is lost
+    let mut updates = library.jobs.subscribe();
+    let job = match visible_job(&library, &session, id).await {
+        Ok(job) => job,
+        Err(response) => return response,
+    };
+    let (tx, rx) = channel::<Result<Event, Infallible>>(16);
+    tokio::spawn(async move {
+        let mut job = job;
+        loop {
+            let Ok(event) = Event::default().event("job").json_data(&job) else {
+                break;
+            };
+            if tx.send(Ok(event)).await.is_err() || job.status.is_finished() {
+                break;
+            }
+            job = loop {
+                match updates.recv().await {
+                    Ok(update) if update.id == id => break update,
+                    Ok(_) => continue,
+                    // missed updates, the database has the latest state
+                    Err(RecvError::Lagged(_)) => match job::get_job(&library.database, id).await {
+                        Ok(job) => break job,
+                        Err(_) => return,
+                    },
+                    Err(RecvError::Closed) => return,
+                }
+            };
+        }
+    });
+
+    let stream = metrics::track_stream("job", ReceiverStream::new(rx));
+    let sse = Sse::new(stream).keep_alive(sse::KeepAlive::new().interval(Duration::from_secs(10)));
+    sse.into_response()
+}
+
+#[utoipa::path(
+    context_path = "/api/jobs",
+    path = "/{id}/cancel",
+    method(post),
+    params(
+        ("id" = i64, Path, description = "ID of the import job")
+    ),
+    responses(
+        (status = 200, description = "Job cancelled"),
+        (status = 400, description = "Job is already finished"),
+        (status = 401, description = "Unauthorized"),
+        (status = 404, description = "Job not found")
+    )
+)]
+pub async fn cancel_job(
+    State(library): State<Arc<Library>>,
+    session: Session,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    if let Err(response) = visible_job(&library, &session, id).await {
+        return response;
+    }
+    match library.jobs.cancel(&library.database, id).await {
+        Ok(_) => "Job cancelled".into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/jobs",
+    path = "/{id}/retry",
+    method(post),
+    params(
+        ("id" = i64, Path, description = "ID of the import job")
+    ),
+    responses(
+        (status = 200, description = "Job queued again"),
+        (status = 400, description = "Job is not failed or cancelled"),
+        (status = 401, description = "Unauthorized"),
+        (status = 404, description = "Job not found")
+    )
+)]
+pub async fn retry_job(
+    State(library): State<Arc<Library>>,
+    session: Session,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    if let Err(response) = visible_job(&library, &session, id).await {
+        return response;
+    }
+    match library.jobs.retry(&library.database, id).await {
+        Ok(_) => "Job queued".into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+pub fn get_jobs_scope() -> Router<Arc<Library>> {
+    Router::new().nest(
+        "/jobs",
+        Router::new()
+            .route("/list", get(list_jobs))
+            .route("/{id}", get(get_job))
+            .route("/{id}/events", get(job_events))
+            .route("/{id}/cancel", post(cancel_job))
+            .route("/{id}/retry", post(retry_job)),
+    )
+}
@@ -1,19 +1,23 @@
-use crate::books::book::BookMeta;
+use crate::books::book::{BookMeta, BookTeachingPlan};
+use crate::books::chapter::ChapterNumber;
+use crate::books::export::ExportFormat;
 use crate::books::library::Library;
+use crate::books::plan::{self, PlanDiff, PlanEdit, PlanVersion};
+use crate::db::Database;
 use crate::student;
 use crate::student::StudentInfo;
+use crate::study::{self, StudyStats};
 use argon2::{Argon2, PasswordHash, PasswordVerifier};
 use axum::{
     Router,
-    extract::{Json, Multipart, Query, State},
+    extract::{Json, Multipart, Path, Query, State},
     response::IntoResponse,
     routing::{get, post},
 };
 use serde::Deserialize;
-use sqlx::SqlitePool;
 use std::sync::Arc;
 use tower_sessions::Session;
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
 
 use super::upload_books;
 
@@ -24,19 +28,17 @@ pub struct LoginRequest {
 }
 
 async fn manager_login(
-    database: &SqlitePool,
+    database: &Database,
     email: String,
     password: String,
 ) -> anyhow::Result<i64> {
-    let manager = sqlx::query!("SELECT id, password FROM manager WHERE email = ?", email)
-        .fetch_one(database)
-        .await?;
-    let parsed_hash = PasswordHash::new(&manager.password)
+    let (id, password_hash) = database.manager_password(&email).await?;
+    let parsed_hash = PasswordHash::new(&password_hash)
         .map_err(|e| anyhow::anyhow!("Failed to parse password hash: {}", e))?;
     Argon2::default()
         .verify_password(password.as_bytes(), &parsed_hash)
         .map_err(|e| anyhow::anyhow!("Failed to verify password: {}", e))?;
-    Ok(manager.id)
+    Ok(id)
 }
 
 #[utoipa::path(
@@ -105,7 +107,7 @@ pub async fn list_books(
     path = "/upload_public_book",
     method(post),
     responses(
-        (status = 200, description = "Book uploaded successfully", body = Vec<i64>),
+        (status = 200, description = "Imports queued, ids of the import jobs", body = Vec<i64>),
         (status = 401, description = "Unauthorized"),
         (status = 500, description = "Internal server error")
     )
@@ -118,12 +120,83 @@ pub async fn upload_public_book(
     let Ok(Some(_)) = session.get::<i64>("manager_id").await else {
         return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
     };
-    match upload_books(multipart, library).await {
-        Ok(book_ids) => Json(book_ids).into_response(),
+    match upload_books(multipart, library, None, None).await {
+        Ok(job_ids) => Json(job_ids).into_response(),
         Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
     }
 }
 
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/book/{id}/upload_version",
+    method(post),
+    params(
+        ("id" = i64, Path, description = "ID of the book")
+    ),
+    responses(
+        (status = 200, description = "Import of the new version queued, ids of the import jobs", body = Vec<i64>),
+        (status = 401, description = "Unauthorized"),
+        (status = 404, description = "Book not found"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn upload_book_version(
+    State(library): State<Arc<Library>>,
+    session: Session,
+    Path(book_id): Path<i64>,
+    multipart: Multipart,
+) -> impl IntoResponse {
+    let Ok(Some(_)) = session.get::<i64>("manager_id").await else {
+        return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
+    };
+    match library.book_exists(book_id).await {
+        Ok(true) => {}
+        Ok(false) => return (axum::http::StatusCode::NOT_FOUND, "Book not found").into_response(),
+        Err(e) => {
+            return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+        }
+    }
+    match upload_books(multipart, library, None, Some(book_id)).await {
+        Ok(job_ids) => Json(job_ids).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct ManagerExportQuery {
+    /// `mdbook` (a zip that can be uploaded to another server) or `epub`
+    pub format: ExportFormat,
+    /// include `teaching_plan.toml` in the mdbook zip, so the plans are not generated again
+    #[serde(default)]
+    pub include_plan: bool,
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/book/{id}/export",
+    method(get),
+    params(
+        ("id" = i64, Path, description = "Book ID"),
+        ManagerExportQuery
+    ),
+    responses(
+        (status = 200, description = "The book as an mdbook zip or an epub", content_type = "application/octet-stream"),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn export_book(
+    State(library): State<Arc<Library>>,
+    session: Session,
+    Path(book_id): Path<i64>,
+    Query(query): Query<ManagerExportQuery>,
+) -> impl IntoResponse {
+    let Ok(Some(_)) = session.get::<i64>("manager_id").await else {
+        return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
+    };
+    super::export_book(&library, book_id, query.format, query.include_plan).await
+}
+
 #[utoipa::path(
     context_path = "/api/manager",
     path = "/remove_book",
@@ -203,6 +276,305 @@ pub async fn list_students(
     }
 }
 
+#[derive(Deserialize, IntoParams)]
+pub struct StudentQuery {
+    /// ID of the student
+    pub student_id: i64,
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/student_study_stats",
+    method(get),
+    params(StudentQuery),
+    responses(
+        (status = 200, description = "Study time, streaks and goal of the student", body = StudyStats),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn student_study_stats(
+    State(library): State<Arc<Library>>,
+    session: Session,
+    Query(query): Query<StudentQuery>,
+) -> impl IntoResponse {
+    let db = &library.database;
+    let Ok(Some(_)) = session.get::<i64>("manager_id").await else {
+        return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
+    };
+    match study::get_study_stats(db, query.student_id).await {
+        Ok(stats) => Json(stats).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/book/{id}/plan",
+    method(get),
+    params(
+        ("id" = i64, Path, description = "Book ID")
+    ),
+    responses(
+        (status = 200, description = "Teaching plan of the book and plans of its chapters", body = BookTeachingPlan),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn get_book_plan(
+    State(library): State<Arc<Library>>,
+    session: Session,
+    Path(book_id): Path<i64>,
+) -> impl IntoResponse {
+    let Ok(Some(_)) = session.get::<i64>("manager_id").await else {
+        return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
+    };
+    if let Err(e) = library.get_book(book_id).await {
+        return (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response();
+    }
+    let book_path = match library.book_dir(book_id).await {
+        Ok(book_path) => book_path,
+        Err(e) => return (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    Json(BookTeachingPlan::load(&book_path).await).into_response()
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/book/{id}/plan",
+    method(post),
+    params(
+        ("id" = i64, Path, description = "Book ID")
+    ),
+    request_body = PlanEdit,
+    responses(
+        (status = 200, description = "New version of the teaching plan", body = PlanVersion),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn edit_book_plan(
+    State(library): State<Arc<Library>>,
+    session: Session,
+    Path(book_id): Path<i64>,
+    Json(edit): Json<PlanEdit>,
+) -> impl IntoResponse {
+    let Ok(Some(manager_id)) = session.get::<i64>("manager_id").await else {
+        return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
+    };
+    match plan::edit_plan(&library, book_id, None, edit, Some(manager_id)).await {
+        Ok(version) => Json(version).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/book/{id}/plan/regenerate",
+    method(post),
+    params(
+        ("id" = i64, Path, description = "Book ID")
+    ),
+    responses(
+        (status = 200, description = "New version of the teaching plan", body = PlanVersion),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn regenerate_book_plan(
+    State(library): State<Arc<Library>>,
+    session: Session,
+    Path(book_id): Path<i64>,
+) -> impl IntoResponse {
+    let Ok(Some(manager_id)) = session.get::<i64>("manager_id").await else {
+        return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
+    };
+    match plan::regenerate_plan(&library, book_id, None, Some(manager_id)).await {
+        Ok(version) => Json(version).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/book/{id}/chapter/{number}/plan",
+    method(post),
+    params(
+        ("id" = i64, Path, description = "Book ID"),
+        ("number" = String, Path, description = "Chapter number, e.g. `1.2.`")
+    ),
+    request_body = PlanEdit,
+    responses(
+        (status = 200, description = "New version of the chapter plan", body = PlanVersion),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn edit_chapter_plan(
+    State(library): State<Arc<Library>>,
+    session: Session,
+    Path((book_id, number)): Path<(i64, String)>,
+    Json(edit): Json<PlanEdit>,
+) -> impl IntoResponse {
+    let Ok(Some(manager_id)) = session.get::<i64>("manager_id").await else {
+        return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
+    };
+    let result = async {
+        let number: ChapterNumber = number.parse()?;
+        plan::edit_plan(&library, book_id, Some(&number), edit, Some(manager_id)).await
+    }
+    .await;
+    match result {
+        Ok(version) => Json(version).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/book/{id}/chapter/{number}/plan/regenerate",
+    method(post),
+    params(
+        ("id" = i64, Path, description = "Book ID"),
+        ("number" = String, Path, description = "Chapter number, e.g. `1.2.`")
+    ),
+    responses(
+        (status = 200, description = "New version of the chapter plan", body = PlanVersion),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn regenerate_chapter_plan(
+    State(library): State<Arc<Library>>,
+    session: Session,
+    Path((book_id, number)): Path<(i64, String)>,
+) -> impl IntoResponse {
+    let Ok(Some(manager_id)) = session.get::<i64>("manager_id").await else {
+        return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
+    };
+    let result = async {
+        let number: ChapterNumber = number.parse()?;
+        plan::regenerate_plan(&library, book_id, Some(&number), Some(manager_id)).await
+    }
+    .await;
+    match result {
+        Ok(version) => Json(version).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct PlanHistoryQuery {
+    /// Chapter number, e.g. `1.2.`, all plans of the book if not given
+    pub chapter: Option<String>,
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/book/{id}/plan/history",
+    method(get),
+    params(
+        ("id" = i64, Path, description = "Book ID"),
+        PlanHistoryQuery
+    ),
+    responses(
+        (status = 200, description = "Versions of the plans, newest first", body = Vec<PlanVersion>),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn plan_history(
+    State(library): State<Arc<Library>>,
+    session: Session,
+    Path(book_id): Path<i64>,
+    Query(query): Query<PlanHistoryQuery>,
+) -> impl IntoResponse {
+    let Ok(Some(_)) = session.get::<i64>("manager_id").await else {
+        return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
+    };
+    let result = async {
+        let chapter: Option<ChapterNumber> = query.chapter.map(|n| n.parse()).transpose()?;
+        plan::list_versions(&library.database, book_id, chapter.as_ref()).await
+    }
+    .await;
+    match result {
+        Ok(versions) => Json(versions).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct PlanDiffQuery {
+    /// ID of the older version
+    pub from: i64,
+    /// ID of the newer version, the latest version if not given
+    pub to: Option<i64>,
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/book/{id}/plan/diff",
+    method(get),
+    params(
+        ("id" = i64, Path, description = "Book ID"),
+        PlanDiffQuery
+    ),
+    responses(
+        (status = 200, description = "Line diffs of the plan and the summary", body = PlanDiff),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn plan_diff(
+    State(library): State<Arc<Library>>,
+    session: Session,
+    Path(book_id): Path<i64>,
+    Query(query): Query<PlanDiffQuery>,
+) -> impl IntoResponse {
+    let Ok(Some(_)) = session.get::<i64>("manager_id").await else {
+        return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
+    };
+    match plan::diff_versions(&library.database, book_id, query.from, query.to).await {
+        Ok(diff) => Json(diff).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct PlanRevertQuery {
+    /// ID of the version to make current again
+    pub version: i64,
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/book/{id}/plan/revert",
+    method(post),
+    params(
+        ("id" = i64, Path, description = "Book ID"),
+        PlanRevertQuery
+    ),
+    responses(
+        (status = 200, description = "New version with the content of the reverted one", body = PlanVersion),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn revert_plan(
+    State(library): State<Arc<Library>>,
+    session: Session,
+    Path(book_id): Path<i64>,
+    Query(query): Query<PlanRevertQuery>,
+) -> impl IntoResponse {
+    let Ok(Some(manager_id)) = session.get::<i64>("manager_id").await else {
+        return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
+    };
+    match plan::revert_plan(&library, book_id, query.version, Some(manager_id)).await {
+        Ok(version) => Json(version).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
 pub fn get_manager_scope() -> Router<Arc<Library>> {
     Router::new().nest(
         "/manager",
@@ -211,8 +583,21 @@ pub fn get_manager_scope() -> Router<Arc<Library>> {
             .route("/logout", post(logout))
             .route("/list_books", get(list_books))
             .route("/upload_public_book", post(upload_public_book))
+            .route("/book/{id}/upload_version", post(upload_book_version))
+            .route("/book/{id}/export", get(export_book))
             .route("/remove_book", post(remove_book))
             .route("/set_book_public", post(set_book_public))
-            .route("/list_students", get(list_students)),
+            .route("/list_students", get(list_students))
+            .route("/student_study_stats", get(student_study_stats))
+            .route("/book/{id}/plan", get(get_book_plan).post(edit_book_plan))
+            .route("/book/{id}/plan/regenerate", post(regenerate_book_plan))
+            .route("/book/{id}/plan/history", get(plan_history))
+            .route("/book/{id}/plan/diff", get(plan_diff))
+            .route("/book/{id}/plan/revert", post(revert_plan))
+            .route("/book/{id}/chapter/{number}/plan", post(edit_chapter_plan))
+            .route(
+                "/book/{id}/chapter/{number}/plan/regenerate",
+                post(regenerate_chapter_plan),
+            ),
     )
 }
@@ -1,4 +1,9 @@
-use std::{convert::Infallible, sync::Arc, time::Duration};
+use std::{
+    convert::Infallible,
+    io::SeekFrom,
+    sync::Arc,
+    time::{Duration, UNIX_EPOCH},
+};
 
 use async_openai::types::{
     ChatCompletionRequestAssistantMessageContent, ChatCompletionRequestAssistantMessageContentPart,
@@ -8,24 +13,47 @@ use async_openai::types::{
 };
 use axum::{
     Extension, Router,
-    extract::{Json, Multipart, Query, State},
+    body::Body,
+    extract::{Json, Multipart, Path, Query, State},
+    http::{HeaderMap, HeaderValue, header},
     response::{
-        IntoResponse, Sse,
+        IntoResponse, Response, Sse,
         sse::{self, Event},
     },
     routing::{get, post},
 };
+use futures::StreamExt;
 use moka::future::Cache;
 use serde::{Deserialize, Serialize};
-use tokio::sync::{Mutex, mpsc::channel};
+use sha2::{Digest, Sha256};
+use tokio::{
+    io::{AsyncReadExt, AsyncSeekExt},
+    sync::{Mutex, mpsc::channel},
+};
 use tokio_stream::wrappers::ReceiverStream;
 use tower_sessions::Session;
-use utoipa::ToSchema;
+use tracing::error;
+use utoipa::{IntoParams, ToSchema};
 
 use crate::{
-    books::{book::BookMeta, library::Library},
+    books::{
+        asset::{self, ByteRange},
+        book::BookMeta,
+        chapter::ChapterNumber,
+        export::ExportFormat,
+        library::Library,
+        render::{self, RenderedChapter, TocItem},
+    },
+    cluster::turn_lock::TurnLock,
+    server::{metrics, shutdown::Shutdown},
     student::{self, StudentInfo},
-    teacher::TeacherAgent,
+    study::{self, DailyGoal, StudyStats},
+    teacher::{
+        TeacherAgent,
+        messages::MessagesDatabase,
+        recommend::{self, Recommendation},
+        study_guide::{self, GuideFormat},
+    },
 };
 
 use super::upload_books;
@@ -154,7 +182,7 @@ pub async fn list_books(
     path = "/upload_and_add_books",
     method(post),
     responses(
-        (status = 200, description = "Upload successful"),
+        (status = 200, description = "Imports queued, ids of the import jobs", body = Vec<i64>),
         (status = 401, description = "Unauthorized"),
         (status = 500, description = "Internal server error")
     )
@@ -164,17 +192,11 @@ pub async fn upload_and_add_books(
     session: Session,
     multipart: Multipart,
 ) -> impl IntoResponse {
-    let db = library.database.clone();
     let Ok(Some(student_id)) = session.get::<i64>("student_id").await else {
         return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
     };
-    match upload_books(multipart, library).await {
-        Ok(book_ids) => match student::add_student_books(&db, student_id, book_ids).await {
-            Ok(_) => "Upload successful".into_response(),
-            Err(e) => {
-                (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
-            }
-        },
+    match upload_books(multipart, library, Some(student_id), None).await {
+        Ok(job_ids) => Json(job_ids).into_response(),
         Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
     }
 }
@@ -189,6 +211,7 @@ pub async fn upload_and_add_books(
     responses(
         (status = 200, description = "Book added successfully"),
         (status = 401, description = "Unauthorized"),
+        (status = 403, description = "The book is private and was not uploaded by the student"),
         (status = 400, description = "Bad request")
     )
 )]
@@ -201,6 +224,11 @@ pub async fn add_book(
     let Ok(Some(student_id)) = session.get::<i64>("student_id").await else {
         return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
     };
+    match student::can_add_book(&db, student_id, book_id).await {
+        Ok(true) => {}
+        Ok(false) => return (axum::http::StatusCode::FORBIDDEN, ()).into_response(),
+        Err(e) => return (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
     match TeacherAgent::init(student_id, book_id, db).await {
         Ok(_) => ().into_response(),
         Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
@@ -235,7 +263,87 @@ pub async fn delete_book(
     }
 }
 
-type TeacherAgentCache = Cache<(i64, i64), Arc<Mutex<TeacherAgent>>>;
+/// a teacher and the number of turns its conversation has seen, other instances may
+/// have taken turns since
+pub struct CachedTeacher {
+    agent: TeacherAgent,
+    turn: u64,
+}
+
+pub type TeacherAgentCache = Cache<(i64, i64), Arc<Mutex<CachedTeacher>>>;
+
+/// teachers summarized at the same time at shutdown
+const SUMMARY_CONCURRENCY: usize = 8;
+
+/// the cached teacher of the student in the book, loaded if it is not cached
+async fn cached_teacher(
+    library: Arc<Library>,
+    cache: &TeacherAgentCache,
+    turn_lock: &TurnLock,
+    student_id: i64,
+    book_id: i64,
+) -> Result<Arc<Mutex<CachedTeacher>>, Arc<String>> {
+    let entry = cache
+        .entry((student_id, book_id))
+        .or_try_insert_with(async move {
+            let load = async {
+                let turn = turn_lock.turn(student_id, book_id).await?;
+                let agent = TeacherAgent::new(library, student_id, book_id).await?;
+                anyhow::Ok(Arc::new(Mutex::new(CachedTeacher { agent, turn })))
+            };
+            load.await.map_err(|e| e.to_string())
+        })
+        .await?;
+    metrics::cache_lookup("teacher", !entry.is_fresh());
+    Ok(entry.into_value())
+}
+
+/// run the end of session summary of every cached teacher, at shutdown. Teachers of a
+/// conversation another instance took turns in since are skipped, their copy is stale
+pub async fn summarize_teachers(cache: &TeacherAgentCache, turn_lock: &TurnLock) {
+    futures::stream::iter(cache.iter())
+        .for_each_concurrent(SUMMARY_CONCURRENCY, async |(key, teacher)| {
+            let (student_id, book_id) = *key;
+            let mut teacher = teacher.lock().await;
+            if !teacher.agent.needs_summary() {
+                return;
+            }
+            let guard = match turn_lock.acquire(student_id, book_id).await {
+                Ok(guard) => guard,
+                Err(e) => {
+                    error!("teacher {}-{} summary failed: {}", student_id, book_id, e);
+                    return;
+                }
+            };
+            if guard.turn() != teacher.turn {
+                return;
+            }
+            if let Err(e) = teacher.agent.summarize().await {
+                error!("teacher {}-{} summary failed: {}", student_id, book_id, e);
+            }
+            if let Err(e) = guard.finish().await {
+                error!("teacher {}-{} turn failed: {}", student_id, book_id, e);
+            }
+        })
+        .await;
+}
+
+impl CachedTeacher {
+    /// load the teacher again if another instance took a turn since `turn`
+    async fn sync(
+        &mut self,
+        library: Arc<Library>,
+        student_id: i64,
+        book_id: i64,
+        turn: u64,
+    ) -> anyhow::Result<()> {
+        if self.turn != turn {
+            self.agent = TeacherAgent::new(library, student_id, book_id).await?;
+            self.turn = turn;
+        }
+        Ok(())
+    }
+}
 
 #[derive(Serialize, ToSchema)]
 pub enum ConversationMessage {
@@ -329,31 +437,30 @@ impl TryFrom<ChatCompletionRequestMessage> for ConversationMessage {
 pub async fn get_conversation(
     State(library): State<Arc<Library>>,
     Extension(cache): Extension<Arc<TeacherAgentCache>>,
+    Extension(turn_lock): Extension<TurnLock>,
     session: Session,
     Query(book_id): Query<i64>,
 ) -> impl IntoResponse {
     let Ok(Some(student_id)) = session.get::<i64>("student_id").await else {
         return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
     };
-    let teacher = match cache
-        .try_get_with((student_id, book_id), async move {
-            match TeacherAgent::new(library, student_id, book_id).await {
-                Ok(teacher) => {
-                    let teacher = Arc::new(Mutex::new(teacher));
-                    Ok(teacher)
-                }
-                Err(e) => Err(e.to_string()),
+    let teacher =
+        match cached_teacher(library.clone(), &cache, &turn_lock, student_id, book_id).await {
+            Ok(teacher) => teacher,
+            Err(e) => {
+                return (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response();
             }
-        })
-        .await
-    {
-        Ok(teacher) => teacher,
-        Err(e) => {
-            return (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response();
-        }
+        };
+    let mut teacher = teacher.lock().await;
+    let synced = async {
+        let turn = turn_lock.turn(student_id, book_id).await?;
+        teacher.sync(library, student_id, book_id, turn).await
     };
-    let teacher = teacher.lock().await;
+    if let Err(e) = synced.await {
+        return (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response();
+    }
     let history: Vec<ConversationMessage> = teacher
+        .agent
         .get_conversation()
         .await
         .into_iter()
@@ -376,49 +483,527 @@ pub struct ChatRequest {
     responses(
         (status = 200, description = "Chat response stream", content_type = "text/event-stream"),
         (status = 401, description = "Unauthorized"),
-        (status = 400, description = "Bad request")
+        (status = 400, description = "Bad request"),
+        (status = 503, description = "The server is shutting down")
     )
 )]
 pub async fn chat(
     State(library): State<Arc<Library>>,
     Extension(cache): Extension<Arc<TeacherAgentCache>>,
+    Extension(turn_lock): Extension<TurnLock>,
+    Extension(shutdown): Extension<Shutdown>,
     session: Session,
     Json(req): Json<ChatRequest>,
 ) -> impl IntoResponse {
     let Ok(Some(student_id)) = session.get::<i64>("student_id").await else {
         return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
     };
+    // the shutdown waits for the turns that got a permit
+    let Some(permit) = shutdown.begin_turn() else {
+        return (
+            axum::http::StatusCode::SERVICE_UNAVAILABLE,
+            "The server is shutting down",
+        )
+            .into_response();
+    };
     let ChatRequest { book_id, message } = req;
-    let teacher = match cache
-        .try_get_with((student_id, book_id), async move {
-            match TeacherAgent::new(library, student_id, book_id).await {
-                Ok(teacher) => {
-                    let teacher = Arc::new(Mutex::new(teacher));
-                    Ok(teacher)
-                }
-                Err(e) => Err(e.to_string()),
+    let teacher =
+        match cached_teacher(library.clone(), &cache, &turn_lock, student_id, book_id).await {
+            Ok(teacher) => teacher,
+            Err(e) => {
+                return (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response();
             }
-        })
-        .await
-    {
-        Ok(teacher) => teacher,
-        Err(e) => {
-            return (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response();
-        }
-    };
+        };
     let (tx, rx) = channel::<Result<Event, Infallible>>(100);
     tokio::spawn(async move {
         let mut teacher = teacher.lock().await;
-        let _ = teacher.input(message.into(), tx).await;
+        // one turn at a time across instances, on the latest conversation
+        let guard = match turn_lock.acquire(student_id, book_id).await {
+            Ok(guard) => guard,
+            Err(e) => {
+                error!("teacher {}-{} turn failed: {}", student_id, book_id, e);
+                return;
+            }
+        };
+        if let Err(e) = teacher
+            .sync(library, student_id, book_id, guard.turn())
+            .await
+        {
+            error!("teacher {}-{} reload failed: {}", student_id, book_id, e);
+            return;
+        }
+        let _ = teacher.agent.input(message.into(), tx).await;
+        match guard.finish().await {
+            Ok(turn) => teacher.turn = turn,
+            Err(e) => error!("teacher {}-{} turn failed: {}", student_id, book_id, e),
+        }
+        drop(permit);
     });
 
-    let stream = ReceiverStream::new(rx);
+    let stream = metrics::track_stream("chat", ReceiverStream::new(rx));
     let sse = Sse::new(stream).keep_alive(sse::KeepAlive::new().interval(Duration::from_secs(10)));
 
     sse.into_response()
 }
 
-pub fn get_user_scope(cache: Arc<TeacherAgentCache>) -> Router<Arc<Library>> {
+#[derive(Deserialize, IntoParams)]
+pub struct BookQuery {
+    /// ID of the book
+    pub book_id: i64,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/recommend_next",
+    method(get),
+    params(BookQuery),
+    responses(
+        (status = 200, description = "Recommended chapter or review topic", body = Recommendation),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "The book is not added and not public"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn recommend_next(
+    State(library): State<Arc<Library>>,
+    session: Session,
+    Query(query): Query<BookQuery>,
+) -> impl IntoResponse {
+    let Ok(Some(student_id)) = session.get::<i64>("student_id").await else {
+        return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
+    };
+    let book_id = query.book_id;
+    match student::can_read_book(&library.database, student_id, book_id).await {
+        Ok(true) => {}
+        Ok(false) => return (axum::http::StatusCode::FORBIDDEN, ()).into_response(),
+        Err(e) => return (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+    let result = async {
+        let messages_db =
+            MessagesDatabase::new(book_id, student_id, library.database.clone()).await?;
+        let progress = messages_db.get_book_progress().await?;
+        let book = library.get_book(book_id).await?;
+        anyhow::Ok(recommend::recommend_next(&book, &progress))
+    }
+    .await;
+    match result {
+        Ok(recommendation) => Json(recommendation).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct StudyGuideQuery {
+    /// ID of the book
+    pub book_id: i64,
+    /// `markdown`, `html` or `mdbook` (a zip), markdown if not given
+    pub format: Option<GuideFormat>,
+    /// add generated quiz questions to every chapter, true if not given
+    pub quiz: Option<bool>,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/study_guide",
+    method(get),
+    params(StudyGuideQuery),
+    responses(
+        (status = 200, description = "Study guide of the book for the student", content_type = "application/octet-stream"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "The book is not added and not public"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn study_guide(
+    State(library): State<Arc<Library>>,
+    session: Session,
+    Query(query): Query<StudyGuideQuery>,
+) -> impl IntoResponse {
+    let Ok(Some(student_id)) = session.get::<i64>("student_id").await else {
+        return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
+    };
+    let book_id = query.book_id;
+    match student::can_read_book(&library.database, student_id, book_id).await {
+        Ok(true) => {}
+        Ok(false) => return (axum::http::StatusCode::FORBIDDEN, ()).into_response(),
+        Err(e) => return (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+    let format = query.format.unwrap_or(GuideFormat::Markdown);
+    let quiz = query.quiz.unwrap_or(true);
+    match study_guide::study_guide(&library, student_id, book_id, format, quiz).await {
+        Ok(data) => {
+            let disposition = format!(
+                "attachment; filename=\"study_guide_{}.{}\"",
+                book_id,
+                format.extension()
+            );
+            (
+                [
+                    (header::CONTENT_TYPE, format.content_type().to_string()),
+                    (header::CONTENT_DISPOSITION, disposition),
+                ],
+                data,
+            )
+                .into_response()
+        }
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct HeartbeatRequest {
+    pub book_id: i64,
+    /// The chapter the student is reading, if any
+    pub chapter_number: Option<ChapterNumber>,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/heartbeat",
+    method(post),
+    request_body = HeartbeatRequest,
+    responses(
+        (status = 200, description = "Heartbeat recorded"),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn heartbeat(
+    State(library): State<Arc<Library>>,
+    session: Session,
+    Json(req): Json<HeartbeatRequest>,
+) -> impl IntoResponse {
+    let db = library.database.clone();
+    let Ok(Some(student_id)) = session.get::<i64>("student_id").await else {
+        return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
+    };
+    match study::record_heartbeat(&db, student_id, req.book_id, req.chapter_number).await {
+        Ok(_) => ().into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/study_stats",
+    method(get),
+    responses(
+        (status = 200, description = "Study time, streaks and goal", body = StudyStats),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn study_stats(
+    State(library): State<Arc<Library>>,
+    session: Session,
+) -> impl IntoResponse {
+    let db = library.database.clone();
+    let Ok(Some(student_id)) = session.get::<i64>("student_id").await else {
+        return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
+    };
+    match study::get_study_stats(&db, student_id).await {
+        Ok(stats) => Json(stats).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/daily_goal",
+    method(post),
+    request_body = DailyGoal,
+    responses(
+        (status = 200, description = "Daily goal updated"),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn set_daily_goal(
+    State(library): State<Arc<Library>>,
+    session: Session,
+    Json(req): Json<DailyGoal>,
+) -> impl IntoResponse {
+    let db = library.database.clone();
+    let Ok(Some(student_id)) = session.get::<i64>("student_id").await else {
+        return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
+    };
+    match study::set_daily_goal(&db, student_id, req.minutes).await {
+        Ok(_) => ().into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+/// respond with `body` as json tagged with an ETag, or 304 if the client already has it
+fn json_with_etag<T: Serialize>(headers: &HeaderMap, body: &T) -> Response {
+    let json = match serde_json::to_vec(body) {
+        Ok(json) => json,
+        Err(e) => {
+            return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+        }
+    };
+    // the same on every instance and across releases, unlike `std::hash`
+    let etag = format!("\"{:x}\"", Sha256::digest(&json));
+    let cache_headers = [
+        (header::ETAG, etag.clone()),
+        (header::CACHE_CONTROL, "private, no-cache".to_string()),
+    ];
+    let not_modified = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag));
+    if not_modified {
+        return (axum::http::StatusCode::NOT_MODIFIED, cache_headers).into_response();
+    }
+    (
+        cache_headers,
+        [(header::CONTENT_TYPE, "application/json")],
+        json,
+    )
+        .into_response()
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/book/{id}/toc",
+    method(get),
+    params(("id" = i64, Path, description = "Book ID")),
+    responses(
+        (status = 200, description = "Chapter tree of the book", body = Vec<TocItem>),
+        (status = 304, description = "Not modified"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "The book is not added and not public"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn book_toc(
+    State(library): State<Arc<Library>>,
+    session: Session,
+    headers: HeaderMap,
+    Path(book_id): Path<i64>,
+) -> impl IntoResponse {
+    let Ok(Some(student_id)) = session.get::<i64>("student_id").await else {
+        return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
+    };
+    match student::can_read_book(&library.database, student_id, book_id).await {
+        Ok(true) => {}
+        Ok(false) => return (axum::http::StatusCode::FORBIDDEN, ()).into_response(),
+        Err(e) => return (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+    match library.get_book(book_id).await {
+        Ok(book) => json_with_etag(&headers, &render::table_of_contents(&book)),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/book/{id}/chapter/{number}",
+    method(get),
+    params(
+        ("id" = i64, Path, description = "Book ID"),
+        ("number" = String, Path, description = "Chapter number, e.g. `1.2.`")
+    ),
+    responses(
+        (status = 200, description = "Chapter rendered to html", body = RenderedChapter),
+        (status = 304, description = "Not modified"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "The book is not added and not public"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn book_chapter(
+    State(library): State<Arc<Library>>,
+    session: Session,
+    headers: HeaderMap,
+    Path((book_id, number)): Path<(i64, String)>,
+) -> impl IntoResponse {
+    let Ok(Some(student_id)) = session.get::<i64>("student_id").await else {
+        return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
+    };
+    match student::can_read_book(&library.database, student_id, book_id).await {
+        Ok(true) => {}
+        Ok(false) => return (axum::http::StatusCode::FORBIDDEN, ()).into_response(),
+        Err(e) => return (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+    let result = async {
+        let number: ChapterNumber = number.parse()?;
+        let book = library.get_book(book_id).await?;
+        render::render_chapter(&book, &number, &format!("/api/user/book/{book_id}"))
+    }
+    .await;
+    match result {
+        Ok(chapter) => json_with_etag(&headers, &chapter),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct ExportQuery {
+    /// `epub` to read offline, or `mdbook` for a zip of the book sources
+    pub format: ExportFormat,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/book/{id}/export",
+    method(get),
+    params(
+        ("id" = i64, Path, description = "Book ID"),
+        ExportQuery
+    ),
+    responses(
+        (status = 200, description = "The book as an epub or an mdbook zip", content_type = "application/octet-stream"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "The book is not added and not public"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn export_book(
+    State(library): State<Arc<Library>>,
+    session: Session,
+    Path(book_id): Path<i64>,
+    Query(query): Query<ExportQuery>,
+) -> impl IntoResponse {
+    let Ok(Some(student_id)) = session.get::<i64>("student_id").await else {
+        return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
+    };
+    match student::can_read_book(&library.database, student_id, book_id).await {
+        Ok(true) => {}
+        Ok(false) => return (axum::http::StatusCode::FORBIDDEN, ()).into_response(),
+        Err(e) => return (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+    // the teaching plans are for teachers and managers
+    super::export_book(&library, book_id, query.format, false).await
+}
+
+/// stream a book file, honoring `If-None-Match` and a single `Range`
+async fn asset_response(headers: &HeaderMap, path: &std::path::Path) -> anyhow::Result<Response> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let metadata = file.metadata().await?;
+    let len = metadata.len();
+    let modified = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_secs();
+    let etag = format!("\"{len:x}-{modified:x}\"");
+
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(header::ETAG, etag.parse()?);
+    response_headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static("private, max-age=3600"),
+    );
+    response_headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    // book files are uploaded content, scripts in svg or html must not run on our origin
+    response_headers.insert(
+        header::CONTENT_SECURITY_POLICY,
+        HeaderValue::from_static("sandbox"),
+    );
+    response_headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+    let not_modified = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag));
+    if not_modified {
+        return Ok((axum::http::StatusCode::NOT_MODIFIED, response_headers).into_response());
+    }
+
+    // a range for another version of the file is answered with the whole file
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .filter(|_| {
+            headers
+                .get(header::IF_RANGE)
+                .is_none_or(|value| value.as_bytes() == etag.as_bytes())
+        });
+    let (status, start, count) = match asset::parse_range(range, len) {
+        ByteRange::Full => (axum::http::StatusCode::OK, 0, len),
+        ByteRange::Partial(range) => {
+            let content_range = format!("bytes {}-{}/{}", range.start(), range.end(), len);
+            response_headers.insert(header::CONTENT_RANGE, content_range.parse()?);
+            (
+                axum::http::StatusCode::PARTIAL_CONTENT,
+                *range.start(),
+                range.end() - range.start() + 1,
+            )
+        }
+        ByteRange::Unsatisfiable => {
+            response_headers.insert(header::CONTENT_RANGE, format!("bytes */{len}").parse()?);
+            return Ok((
+                axum::http::StatusCode::RANGE_NOT_SATISFIABLE,
+                response_headers,
+            )
+                .into_response());
+        }
+    };
+    response_headers.insert(
+        header::CONTENT_TYPE,
+        asset::content_type(path).as_ref().parse()?,
+    );
+    response_headers.insert(header::CONTENT_LENGTH, count.into());
+
+    file.seek(SeekFrom::Start(start)).await?;
+    let chunks = futures::stream::unfold(file.take(count), |mut reader| async move {
+        let mut buf = vec![0; 64 * 1024];
+        match reader.read(&mut buf).await {
+            Ok(0) => None,
+            Ok(n) => {
+                buf.truncate(n);
+                Some((Ok(buf), reader))
+            }
+            Err(e) => Some((Err(e), reader)),
+        }
+    });
+    Ok((status, response_headers, Body::from_stream(chunks)).into_response())
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/book/{id}/asset/{path}",
+    method(get),
+    params(
+        ("id" = i64, Path, description = "Book ID"),
+        ("path" = String, Path, description = "File path relative to the book root, e.g. `src/images/cover.png`")
+    ),
+    responses(
+        (status = 200, description = "The file"),
+        (status = 206, description = "The requested range of the file"),
+        (status = 304, description = "Not modified"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "The book is not added and not public"),
+        (status = 404, description = "File not found"),
+        (status = 416, description = "Range not satisfiable")
+    )
+)]
+pub async fn book_asset(
+    State(library): State<Arc<Library>>,
+    session: Session,
+    headers: HeaderMap,
+    Path((book_id, path)): Path<(i64, String)>,
+) -> impl IntoResponse {
+    let Ok(Some(student_id)) = session.get::<i64>("student_id").await else {
+        return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
+    };
+    match student::can_read_book(&library.database, student_id, book_id).await {
+        Ok(true) => {}
+        Ok(false) => return (axum::http::StatusCode::FORBIDDEN, ()).into_response(),
+        Err(e) => return (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+    let Ok(file_path) = library.asset_path(book_id, &path).await else {
+        return (axum::http::StatusCode::NOT_FOUND, ()).into_response();
+    };
+    match asset_response(&headers, &file_path).await {
+        Ok(response) => response,
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+pub fn get_user_scope(
+    cache: Arc<TeacherAgentCache>,
+    turn_lock: TurnLock,
+    shutdown: Shutdown,
+) -> Router<Arc<Library>> {
     Router::new().nest(
         "/user",
         Router::new()
@@ -430,10 +1015,23 @@ pub fn get_user_scope(cache: Arc<TeacherAgentCache>) -> Router<Arc<Library>> {
             .route("/delete_book", post(delete_book))
             .route("/add_book", post(add_book))
             .route("/upload_and_add_books", post(upload_and_add_books))
+            .route("/recommend_next", get(recommend_next))
+            .route("/study_guide", get(study_guide))
+            .route("/heartbeat", post(heartbeat))
+            .route("/study_stats", get(study_stats))
+            .route("/daily_goal", post(set_daily_goal))
+            .route("/book/{id}/toc", get(book_toc))
+            .route("/book/{id}/chapter/{number}", get(book_chapter))
+            .route("/book/{id}/asset/{*path}", get(book_asset))
+            .route("/book/{id}/export", get(export_book))
             .route(
                 "/get_conversation",
-                get(get_conversation).layer(Extension(cache.clone())),
+                get(get_conversation)
+                    .layer((Extension(cache.clone()), Extension(turn_lock.clone()))),
             )
-            .route("/chat", post(chat).layer(Extension(cache))),
+            .route(
+                "/chat",
+                post(chat).layer((Extension(cache), Extension(turn_lock), Extension(shutdown))),
+            ),
     )
 }
@@ -1,8 +1,17 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use async_openai::types::ChatCompletionRequestUserMessage;
 use ai_reader::{
-    books::library::Library,
+    books::{
+        chapter::ChapterNumber,
+        export::{self, ExportFormat},
+        library::Library,
+    },
+    config::Config,
+    db::Database,
     student::{
         create_student, delete_student, delete_student_book, get_student_books, get_student_list,
     },
@@ -10,7 +19,6 @@ use ai_reader::{
     utils::init_log,
 };
 use clap::Parser;
-use sqlx::SqlitePool;
 use tokio::{
     io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
     sync::mpsc,
@@ -20,10 +28,18 @@ use tokio::{
 struct Args {
     #[command(subcommand)]
     command: Commands,
-    #[arg(short, long, default_value = "database/book.db")]
-    database: PathBuf,
-    #[arg(short, long, default_value = "bookbase")]
-    bookbase: PathBuf,
+    /// TOML configuration file of the server, `config.toml` is read if it exists
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// `server.database` of the configuration, `database/book.db` by default
+    #[arg(short, long)]
+    database: Option<String>,
+    /// `server.bookbase` of the configuration, `bookbase` by default
+    #[arg(short, long)]
+    bookbase: Option<PathBuf>,
+    /// do not apply the pending migrations of the database
+    #[arg(long)]
+    no_migrate: bool,
 }
 
 #[derive(Debug, clap::Subcommand)]
@@ -41,14 +57,66 @@ enum Commands {
         #[command(subcommand)]
         command: LoginCommand,
     },
+    Db {
+        #[command(subcommand)]
+        command: DbCommand,
+    },
+}
+
+impl Commands {
+    /// whether the command calls the model and needs its credentials
+    fn uses_llm(&self) -> bool {
+        matches!(
+            self,
+            Commands::Book {
+                command: BookCommand::Upload { .. }
+                    | BookCommand::UploadDir { .. }
+                    | BookCommand::RegeneratePlan { .. }
+            } | Commands::Login {
+                command: LoginCommand::Learn { .. },
+                ..
+            }
+        )
+    }
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum DbCommand {
+    /// apply the pending migrations
+    Migrate,
+    /// list the migrations and whether they are applied
+    Status,
 }
 
 #[derive(Debug, clap::Subcommand)]
 enum BookCommand {
     List,
-    Upload { file: PathBuf },
-    UploadDir { dir: PathBuf },
-    Delete { id: i64 },
+    Upload {
+        file: PathBuf,
+    },
+    UploadDir {
+        dir: PathBuf,
+    },
+    Delete {
+        id: i64,
+    },
+    /// generate the plans of the chapters again, e.g. `--chapter 3. --chapter 4.1.`
+    RegeneratePlan {
+        id: i64,
+        #[arg(short, long, required = true)]
+        chapter: Vec<ChapterNumber>,
+    },
+    /// write the book as an mdbook zip or an epub
+    Export {
+        id: i64,
+        #[arg(short, long, value_enum, default_value = "epub")]
+        format: ExportFormat,
+        /// include the teaching plan in the mdbook zip
+        #[arg(long)]
+        include_plan: bool,
+        #[arg(short, long)]
+        output: PathBuf,
+    },
 }
 
 #[derive(Debug, clap::Subcommand)]
@@ -82,9 +150,40 @@ async fn main() {
         eprintln!("{:?}", e);
     }
 }
+
+/// the configuration of the server with the database and bookbase of the command line
+fn load_config(args: &Args) -> anyhow::Result<Config> {
+    let default_path = Path::new("config.toml");
+    let path = args
+        .config
+        .as_deref()
+        .or(default_path.is_file().then_some(default_path));
+    let mut config = Config::load(path)?;
+    if let Some(database) = &args.database {
+        config.server.database = database.clone();
+    }
+    if let Some(bookbase) = &args.bookbase {
+        config.server.bookbase = bookbase.clone();
+    }
+    Ok(config)
+}
+
 async fn run(args: Args) -> anyhow::Result<()> {
-    let database = SqlitePool::connect(&args.database.to_string_lossy()).await?;
-    let library = Library::new(database.clone(), args.bookbase).await?;
+    let config = load_config(&args)?;
+    if args.command.uses_llm() {
+        config.validate_offline()?;
+    } else {
+        config.validate_without_llm()?;
+    }
+    config.clone().install()?;
+    let database = Database::connect(&config.server.database).await?;
+    if let Commands::Db { command } = &args.command {
+        return run_db(&database, command).await;
+    }
+    if config.server.migrate && !args.no_migrate {
+        database.migrate().await?;
+    }
+    let library = Library::new(database.clone(), &config.server.bookbase, &config.storage).await?;
 
     match args.command {
         Commands::Book { command } => match command {
@@ -105,6 +204,20 @@ async fn run(args: Args) -> anyhow::Result<()> {
                 println!("Deleting book with id: {}", id);
                 library.delete_book(id).await?;
             }
+            BookCommand::RegeneratePlan { id, chapter } => {
+                println!("Regenerating plans of book {}", id);
+                library.regenerate_plans(id, &chapter).await?;
+            }
+            BookCommand::Export {
+                id,
+                format,
+                include_plan,
+                output,
+            } => {
+                let data = export::export_book(&library, id, format, include_plan).await?;
+                tokio::fs::write(&output, data).await?;
+                println!("Book {} exported to {}", id, output.display());
+            }
         },
         Commands::User { command } => match command {
             UserCommand::List => {
@@ -140,6 +253,28 @@ async fn run(args: Args) -> anyhow::Result<()> {
                 println!("Book deleted with id: {}", book_id);
             }
         },
+        Commands::Db { .. } => unreachable!(),
+    }
+    Ok(())
+}
+
+async fn run_db(database: &Database, command: &DbCommand) -> anyhow::Result<()> {
+    match command {
+        DbCommand::Migrate => {
+            let pending = database.pending_migrations().await?;
+            database.migrate().await?;
+            println!("Applied {} migrations", pending.len());
+        }
+        DbCommand::Status => {
+            for status in database.migration_status().await? {
+                println!(
+                    "{:<6} {:<10} {}",
+                    status.version,
+                    status.state.as_str(),
+                    status.description
+                );
+            }
+        }
     }
     Ok(())
 }
@@ -1,3 +1,5 @@
+use std::path::Path;
+
 use async_openai::{
     tools::{Tool, ToolCallStreamManager, ToolManager},
     types::{
@@ -7,6 +9,7 @@ use async_openai::{
 };
 use ai_reader::{
     ai_utils::{AI_CLIENT, AI_MODEL},
+    config::Config,
     utils::init_log,
 };
 use futures::StreamExt;
@@ -21,6 +24,10 @@ use tokio::{
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let _guard = init_log(None);
+    let default_path = Path::new("config.toml");
+    let config = Config::load(default_path.is_file().then_some(default_path))?;
+    config.validate_offline()?;
+    config.install()?;
     let mut manager = ChatManager::default();
     manager.tools.add_tool(WeatherTool);
     println!("AI_MODEL: {}", AI_MODEL.as_str());
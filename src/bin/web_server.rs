@@ -1,38 +1,73 @@
 use std::sync::Arc;
-use std::{net::SocketAddr, path::PathBuf};
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
 
 use ai_reader::{
-    api::{manager::get_manager_scope, public::get_public_scope, user::get_user_scope},
-    books::library::Library,
-    utils::init_log,
+    api::{
+        jobs::get_jobs_scope,
+        manager::get_manager_scope,
+        public::get_public_scope,
+        user::{get_user_scope, summarize_teachers},
+    },
+    books::{job::JobQueue, library::Library},
+    cluster::{connect_redis, session::RedisStore, turn_lock::TurnLock},
+    config::{Config, CorsConfig},
+    db::Database,
+    server::{
+        health::{get_diagnostics_route, get_health_routes},
+        metrics::{self, get_metrics_route, track_requests},
+        proxy::{ProxySettings, forwarded, redirect_to_https, request_span},
+        shutdown::{self, Shutdown},
+        telemetry,
+        tls::watch_certificate,
+    },
+    utils::init_tracing,
 };
-use axum::Router;
-use axum_server::tls_rustls::RustlsConfig;
+use axum::{Router, extract::DefaultBodyLimit, http::HeaderValue, middleware};
+use axum_server::{Handle, tls_rustls::RustlsConfig};
 use clap::Parser;
 use moka::future::Cache;
-use sqlx::SqlitePool;
 use time::Duration;
-use tower_http::{cors::CorsLayer, trace::TraceLayer};
+use tower_http::{
+    cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer},
+    trace::{DefaultOnResponse, TraceLayer},
+};
 use tower_sessions::{CachingSessionStore, Expiry, SessionManagerLayer};
 use tower_sessions_moka_store::MokaStore;
-use tower_sessions_sqlx_store::SqliteStore;
-use tracing::info;
+use tower_sessions_sqlx_store::{SqliteStore, sqlx::SqlitePool};
+use tracing::{Level, info, warn};
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
 #[derive(Debug, Parser)]
 #[command(disable_help_flag = true)]
 struct Args {
-    #[arg(short, long, default_value = "database/book.db")]
-    database: PathBuf,
-    #[arg(short, long, default_value = "bookbase")]
-    bookbase: PathBuf,
-    #[arg(short = 'H', long, default_value = "127.0.0.1")]
-    host: String,
-    #[arg(short, long, default_value = "8080")]
-    port: u16,
-    #[arg(short, long, default_value = "database/session.db")]
-    session_database: PathBuf,
+    /// TOML configuration file, `config.toml` is read if it exists
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// print the configuration with every layer applied, then check it and exit
+    #[arg(long)]
+    print_config: bool,
+    #[arg(short, long)]
+    database: Option<String>,
+    #[arg(short, long)]
+    bookbase: Option<PathBuf>,
+    #[arg(short = 'H', long)]
+    host: Option<String>,
+    #[arg(short, long)]
+    port: Option<u16>,
+    #[arg(short, long)]
+    session_database: Option<PathBuf>,
+    #[arg(long)]
+    log_dir: Option<PathBuf>,
+    /// serve plain http, behind a reverse proxy that terminates TLS or in development
+    #[arg(long)]
+    http: bool,
+    /// do not apply the pending migrations of the database at startup
+    #[arg(long)]
+    no_migrate: bool,
 }
 
 #[derive(OpenApi)]
@@ -47,6 +82,20 @@ struct Args {
     ai_reader::api::user::delete_book,
     ai_reader::api::user::get_conversation,
     ai_reader::api::user::chat,
+    ai_reader::api::user::recommend_next,
+    ai_reader::api::user::study_guide,
+    ai_reader::api::user::heartbeat,
+    ai_reader::api::user::study_stats,
+    ai_reader::api::user::set_daily_goal,
+    ai_reader::api::user::book_toc,
+    ai_reader::api::user::book_chapter,
+    ai_reader::api::user::book_asset,
+    ai_reader::api::user::export_book,
+    ai_reader::api::jobs::list_jobs,
+    ai_reader::api::jobs::get_job,
+    ai_reader::api::jobs::job_events,
+    ai_reader::api::jobs::cancel_job,
+    ai_reader::api::jobs::retry_job,
     ai_reader::api::public::get_public_books,
 ))]
 struct UserApiDoc;
@@ -57,34 +106,74 @@ struct UserApiDoc;
     ai_reader::api::manager::logout,
     ai_reader::api::manager::list_books,
     ai_reader::api::manager::upload_public_book,
+    ai_reader::api::manager::upload_book_version,
+    ai_reader::api::manager::export_book,
     ai_reader::api::manager::remove_book,
     ai_reader::api::manager::set_book_public,
     ai_reader::api::manager::list_students,
+    ai_reader::api::manager::student_study_stats,
+    ai_reader::api::manager::get_book_plan,
+    ai_reader::api::manager::edit_book_plan,
+    ai_reader::api::manager::regenerate_book_plan,
+    ai_reader::api::manager::edit_chapter_plan,
+    ai_reader::api::manager::regenerate_chapter_plan,
+    ai_reader::api::manager::plan_history,
+    ai_reader::api::manager::plan_diff,
+    ai_reader::api::manager::revert_plan,
+    ai_reader::api::jobs::list_jobs,
+    ai_reader::api::jobs::get_job,
+    ai_reader::api::jobs::job_events,
+    ai_reader::api::jobs::cancel_job,
+    ai_reader::api::jobs::retry_job,
     ai_reader::api::public::get_public_books,
+    ai_reader::server::health::diagnostics,
 ))]
 struct ManagerApiDoc;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let _guard = init_log(None);
     let args = Args::parse();
+    let config = load_config(&args)?;
+    if args.print_config {
+        print!("{}", config.to_redacted_toml()?);
+        return config.validate();
+    }
+    config.validate()?;
+    config.clone().install()?;
+    let _guard = init_tracing(
+        config.log.dir.clone(),
+        telemetry::tracer_provider(&config.telemetry)?,
+    );
+    let metrics_handle = metrics::install()?;
 
     // Initialize crypto provider for Rustls
     rustls::crypto::ring::default_provider()
         .install_default()
         .expect("Failed to install default crypto provider");
 
-    let database = SqlitePool::connect(&args.database.to_string_lossy()).await?;
-    let library = Arc::new(Library::new(database.clone(), args.bookbase).await?);
+    let database = Database::connect(&config.server.database).await?;
+    if config.server.migrate {
+        database.migrate().await?;
+    } else {
+        let pending = database.pending_migrations().await?;
+        if !pending.is_empty() {
+            warn!("the database misses the migrations {:?}", pending);
+        }
+    }
+    let library =
+        Arc::new(Library::new(database.clone(), &config.server.bookbase, &config.storage).await?);
+    tokio::spawn(JobQueue::run(library.clone()));
 
-    let sqlite_store = init_session_database(args.session_database).await?;
-    let moka_store = MokaStore::new(Some(2000));
-    let caching_store = CachingSessionStore::new(moka_store, sqlite_store);
-    let session_layer = SessionManagerLayer::new(caching_store)
-        .with_expiry(Expiry::OnInactivity(Duration::days(5)));
+    // with Redis the sessions and the turns of the teachers are shared between instances
+    let redis = connect_redis(&config.redis).await?;
+    let turn_lock = match &redis {
+        Some(connection) => TurnLock::Redis(connection.clone()),
+        None => TurnLock::Local,
+    };
 
     // Initialize teacher cache
-    let cache = Arc::new(Cache::new(1000));
+    let cache = Arc::new(Cache::new(config.cache.teachers));
+    let shutdown = Shutdown::default();
 
     // Build the router
     let app = Router::new()
@@ -96,32 +185,191 @@ async fn main() -> anyhow::Result<()> {
         .nest(
             "/api",
             Router::new()
-                .merge(get_user_scope(cache.clone()))
+                .merge(get_user_scope(
+                    cache.clone(),
+                    turn_lock.clone(),
+                    shutdown.clone(),
+                ))
                 .merge(get_manager_scope())
-                .merge(get_public_scope()),
+                .merge(get_jobs_scope())
+                .merge(get_public_scope())
+                .merge(get_diagnostics_route(cache.clone())),
+        )
+        .merge(get_health_routes(shutdown.clone()))
+        .merge(get_metrics_route(metrics_handle, cache.clone()))
+        .with_state(library.clone())
+        .layer(middleware::from_fn(track_requests));
+    // the cookies are marked secure by the proxy layer for the clients that use https
+    let expiry = Expiry::OnInactivity(Duration::days(config.session.expiry_days));
+    // Redis saves the sessions as they change, the SQLite pool is closed at shutdown
+    let (app, session_pool) = match redis {
+        Some(connection) => {
+            info!("Sessions are stored in Redis");
+            let layer = SessionManagerLayer::new(RedisStore::new(connection))
+                .with_secure(false)
+                .with_expiry(expiry);
+            (app.layer(layer), None)
+        }
+        None => {
+            let pool = init_session_database(&config.server.session_database).await?;
+            let sqlite_store = SqliteStore::new(pool.clone());
+            let moka_store = MokaStore::new(Some(config.cache.sessions));
+            let caching_store = CachingSessionStore::new(moka_store, sqlite_store);
+            let layer = SessionManagerLayer::new(caching_store)
+                .with_secure(false)
+                .with_expiry(expiry);
+            (app.layer(layer), Some(pool))
+        }
+    };
+    let app = app
+        .layer(DefaultBodyLimit::max(
+            config.quotas.max_upload_mb * 1024 * 1024,
+        ))
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(request_span)
+                .on_response(DefaultOnResponse::new().level(Level::INFO)),
         )
-        .with_state(library)
-        .layer(session_layer)
-        .layer(TraceLayer::new_for_http())
-        .layer(CorsLayer::permissive());
+        .layer(middleware::from_fn_with_state(
+            Arc::new(ProxySettings::new(&config)?),
+            forwarded,
+        ))
+        .layer(cors_layer(&config.cors));
 
     // Start the server
-    let listener = SocketAddr::new(args.host.parse()?, args.port);
-    let tls_config = RustlsConfig::from_pem_file("./cert.pem", "./key.pem").await?;
-
-    info!("Starting server at {}", listener);
+    let server = &config.server;
+    let listener = SocketAddr::new(server.host.parse()?, server.port);
+    let scheme = if config.tls.enabled { "https" } else { "http" };
+    info!("Starting server at {}://{}", scheme, listener);
     info!(
-        "Swagger UI available at https://{}:{}/swagger-ui/",
-        args.host, args.port
+        "Swagger UI available at {}://{}:{}/swagger-ui/",
+        scheme, server.host, server.port
     );
 
-    axum_server::bind_rustls(listener, tls_config)
-        .serve(app.into_make_service())
-        .await?;
+    // on a signal new chats are refused, the running turns finish, then the connections close
+    let (handle, redirect_handle) = (Handle::new(), Handle::new());
+    let shutdown_timeout = std::time::Duration::from_secs(server.shutdown_timeout_secs);
+    tokio::spawn({
+        let (handle, redirect_handle, shutdown) =
+            (handle.clone(), redirect_handle.clone(), shutdown.clone());
+        async move {
+            shutdown::signal().await;
+            info!("Shutting down, waiting for the running teacher turns");
+            shutdown.trigger();
+            if !shutdown.drain(shutdown_timeout).await {
+                warn!("Teacher turns still running after {:?}", shutdown_timeout);
+            }
+            handle.graceful_shutdown(Some(std::time::Duration::from_secs(5)));
+            redirect_handle.graceful_shutdown(Some(std::time::Duration::from_secs(5)));
+        }
+    });
+
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
+    let tls = &config.tls;
+    if tls.enabled {
+        let tls_config = RustlsConfig::from_pem_file(&tls.cert, &tls.key).await?;
+        if tls.reload_interval_secs > 0 {
+            tokio::spawn(watch_certificate(
+                tls_config.clone(),
+                tls.cert.clone(),
+                tls.key.clone(),
+                std::time::Duration::from_secs(tls.reload_interval_secs),
+            ));
+        }
+        if let Some(port) = tls.redirect_port {
+            let redirect = SocketAddr::new(listener.ip(), port);
+            info!("Redirecting http://{} to https", redirect);
+            let server = axum_server::bind(redirect)
+                .handle(redirect_handle)
+                .serve(redirect_to_https(server.port).into_make_service());
+            tokio::spawn(async move {
+                if let Err(e) = server.await {
+                    warn!("https redirect server failed: {}", e);
+                }
+            });
+        }
+        axum_server::bind_rustls(listener, tls_config)
+            .handle(handle)
+            .serve(app)
+            .await?;
+    } else {
+        axum_server::bind(listener)
+            .handle(handle)
+            .serve(app)
+            .await?;
+    }
+
+    info!(
+        "Saving the progress of {} cached teachers",
+        cache.entry_count()
+    );
+    if tokio::time::timeout(shutdown_timeout, summarize_teachers(&cache, &turn_lock))
+        .await
+        .is_err()
+    {
+        warn!("Teacher summaries unfinished after {:?}", shutdown_timeout);
+    }
+    if let Some(pool) = session_pool {
+        pool.close().await;
+    }
+    library.database.close().await;
+    info!("Server stopped");
     Ok(())
 }
 
-async fn init_session_database(path: PathBuf) -> anyhow::Result<SqliteStore> {
+/// the file, the environment, then the flags of the command line
+fn load_config(args: &Args) -> anyhow::Result<Config> {
+    let default_path = Path::new("config.toml");
+    let path = args
+        .config
+        .as_deref()
+        .or(default_path.is_file().then_some(default_path));
+    let mut config = Config::load(path)?;
+    let server = &mut config.server;
+    if let Some(database) = &args.database {
+        server.database = database.clone();
+    }
+    if let Some(bookbase) = &args.bookbase {
+        server.bookbase = bookbase.clone();
+    }
+    if let Some(host) = &args.host {
+        server.host = host.clone();
+    }
+    if let Some(port) = args.port {
+        server.port = port;
+    }
+    if let Some(session_database) = &args.session_database {
+        server.session_database = session_database.clone();
+    }
+    if let Some(log_dir) = &args.log_dir {
+        config.log.dir = Some(log_dir.clone());
+    }
+    if args.http {
+        config.tls.enabled = false;
+    }
+    if args.no_migrate {
+        config.server.migrate = false;
+    }
+    Ok(config)
+}
+
+/// any origin without credentials for `*`, otherwise the listed origins with cookies
+fn cors_layer(cors: &CorsConfig) -> CorsLayer {
+    if cors.origins.iter().any(|origin| origin == "*") {
+        return CorsLayer::permissive();
+    }
+    let origins = cors
+        .origins
+        .iter()
+        .filter_map(|origin| HeaderValue::from_str(origin).ok());
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods(AllowMethods::mirror_request())
+        .allow_headers(AllowHeaders::mirror_request())
+        .allow_credentials(true)
+}
+
+async fn init_session_database(path: &Path) -> anyhow::Result<SqlitePool> {
     if !path.exists() {
         // Create parent directories if they don't exist
         if let Some(parent) = path.parent()
@@ -130,11 +378,9 @@ async fn init_session_database(path: PathBuf) -> anyhow::Result<SqliteStore> {
             tokio::fs::create_dir_all(parent).await?;
         }
         // Create an empty file
-        let _ = tokio::fs::File::create(&path).await?;
+        let _ = tokio::fs::File::create(path).await?;
     }
-    let pool =
-        tower_sessions_sqlx_store::sqlx::SqlitePool::connect(&path.to_string_lossy()).await?;
-    let store = SqliteStore::new(pool);
-    store.migrate().await?;
-    Ok(store)
+    let pool = SqlitePool::connect(&path.to_string_lossy()).await?;
+    SqliteStore::new(pool.clone()).migrate().await?;
+    Ok(pool)
 }
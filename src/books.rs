@@ -1,4 +1,13 @@
+pub mod asset;
 pub mod book;
 pub mod chapter;
+pub mod export;
+pub mod figure;
+pub mod import;
+pub mod job;
 pub mod library;
+pub mod plan;
+pub mod render;
+pub mod revision;
+pub mod storage;
 pub mod tools;
@@ -0,0 +1,175 @@
+use std::{ops::RangeInclusive, path::Path};
+
+use mime::Mime;
+
+/// MIME type of a book file from its extension
+pub fn content_type(path: &Path) -> Mime {
+    let ext = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    let mime = match ext.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "bmp" => "image/bmp",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "css" => "text/css; charset=utf-8",
+        "js" => "application/javascript; charset=utf-8",
+        "json" => "application/json",
+        "pdf" => "application/pdf",
+        "txt" | "md" => "text/plain; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "html" | "htm" | "xhtml" => "text/html; charset=utf-8",
+        "xml" => "text/xml",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "wav" => "audio/wav",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "zip" => "application/zip",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        _ => return mime::APPLICATION_OCTET_STREAM,
+    };
+    mime.parse().unwrap_or(mime::APPLICATION_OCTET_STREAM)
+}
+
+/// width and height from the header of a png, gif, jpeg or webp image
+pub fn image_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let be16 = |i: usize| Some(u16::from_be_bytes(data.get(i..i + 2)?.try_into().ok()?) as u32);
+    let le16 = |i: usize| Some(u16::from_le_bytes(data.get(i..i + 2)?.try_into().ok()?) as u32);
+    let le24 = |i: usize| Some(le16(i)? | ((*data.get(i + 2)? as u32) << 16));
+    let be32 = |i: usize| Some(u32::from_be_bytes(data.get(i..i + 4)?.try_into().ok()?));
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Some((be32(16)?, be32(20)?));
+    }
+    if data.starts_with(b"GIF8") {
+        return Some((le16(6)?, le16(8)?));
+    }
+    if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+        return match data.get(12..16)? {
+            b"VP8X" => Some((le24(24)? + 1, le24(27)? + 1)),
+            b"VP8 " => Some((le16(26)? & 0x3fff, le16(28)? & 0x3fff)),
+            b"VP8L" => {
+                let bits = u32::from_le_bytes(data.get(21..25)?.try_into().ok()?);
+                Some(((bits & 0x3fff) + 1, ((bits >> 14) & 0x3fff) + 1))
+            }
+            _ => None,
+        };
+    }
+    if data.starts_with(&[0xff, 0xd8]) {
+        // walk the segments until a start of frame
+        let mut i = 2;
+        while *data.get(i)? == 0xff {
+            let marker = *data.get(i + 1)?;
+            if (0xc0..=0xcf).contains(&marker) && !matches!(marker, 0xc4 | 0xc8 | 0xcc) {
+                return Some((be16(i + 7)?, be16(i + 5)?));
+            }
+            i += 2 + be16(i + 2)? as usize;
+        }
+    }
+    None
+}
+
+/// Result of matching a `Range` header against a file
+#[derive(Debug, PartialEq)]
+pub enum ByteRange {
+    /// no (supported) range requested, send the whole file
+    Full,
+    Partial(RangeInclusive<u64>),
+    Unsatisfiable,
+}
+
+/// parse a single `bytes=` range, multiple ranges are answered with the whole file
+pub fn parse_range(header: Option<&str>, len: u64) -> ByteRange {
+    let Some(spec) = header.and_then(|h| h.trim().strip_prefix("bytes=")) else {
+        return ByteRange::Full;
+    };
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return ByteRange::Unsatisfiable;
+    };
+    let range = match (start.parse::<u64>(), end.parse::<u64>()) {
+        (Ok(start), Ok(end)) if start <= end => start..=end.min(len.saturating_sub(1)),
+        (Ok(start), Err(_)) if end.is_empty() => start..=len.saturating_sub(1),
+        // suffix range, the last `n` bytes
+        (Err(_), Ok(n)) if start.is_empty() && n > 0 => {
+            len.saturating_sub(n)..=len.saturating_sub(1)
+        }
+        _ => return ByteRange::Unsatisfiable,
+    };
+    if len == 0 || *range.start() >= len {
+        return ByteRange::Unsatisfiable;
+    }
+    ByteRange::Partial(range)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range(None, 100), ByteRange::Full);
+        assert_eq!(
+            parse_range(Some("bytes=0-9"), 100),
+            ByteRange::Partial(0..=9)
+        );
+        assert_eq!(
+            parse_range(Some("bytes=90-"), 100),
+            ByteRange::Partial(90..=99)
+        );
+        assert_eq!(
+            parse_range(Some("bytes=-10"), 100),
+            ByteRange::Partial(90..=99)
+        );
+        assert_eq!(
+            parse_range(Some("bytes=50-500"), 100),
+            ByteRange::Partial(50..=99)
+        );
+        assert_eq!(parse_range(Some("bytes=0-1,5-6"), 100), ByteRange::Full);
+        assert_eq!(
+            parse_range(Some("bytes=100-"), 100),
+            ByteRange::Unsatisfiable
+        );
+        assert_eq!(
+            parse_range(Some("bytes=9-0"), 100),
+            ByteRange::Unsatisfiable
+        );
+    }
+
+    #[test]
+    fn mime_types() {
+        assert_eq!(content_type(Path::new("a/b.PNG")), mime::IMAGE_PNG);
+        assert_eq!(content_type(Path::new("fig.svg")), mime::IMAGE_SVG);
+        assert_eq!(
+            content_type(Path::new("x.webp")).essence_str(),
+            "image/webp"
+        );
+        assert_eq!(
+            content_type(Path::new("data")),
+            mime::APPLICATION_OCTET_STREAM
+        );
+    }
+
+    #[test]
+    fn dimensions() {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend_from_slice(&[0, 0, 3, 0, 0, 0, 2, 0]);
+        assert_eq!(image_dimensions(&png), Some((768, 512)));
+        assert_eq!(image_dimensions(b"GIF89a\x10\0\x20\0"), Some((16, 32)));
+        let jpeg = [
+            0xff, 0xd8, 0xff, 0xe0, 0, 4, 0, 0, 0xff, 0xc0, 0, 17, 8, 1, 0, 2, 0,
+        ];
+        assert_eq!(image_dimensions(&jpeg), Some((512, 256)));
+        assert_eq!(image_dimensions(b"not an image"), None);
+    }
+}
@@ -1,15 +1,20 @@
 use std::{
-    collections::{BTreeMap, BTreeSet, btree_map::Entry},
-    hash::{DefaultHasher, Hash, Hasher},
+    collections::{BTreeMap, BTreeSet},
     path::{Path, PathBuf},
 };
 
-use crate::ai_utils;
+use crate::ai_utils::{self, AI_FIGURE_MODEL, AI_PLAN_CONCURRENCY};
 
-use super::chapter::{Chapter, ChapterNumber, ChapterPlan, ChapterRaw};
+use super::{
+    chapter::{Chapter, ChapterNumber, ChapterPlan, ChapterRaw},
+    figure,
+};
 use anyhow::bail;
+use futures::StreamExt;
 use mdbook::book;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use tracing::{error, info};
 use tree_iter::{
     iter::TreeIter,
@@ -17,19 +22,69 @@ use tree_iter::{
 };
 use utoipa::ToSchema;
 
+/// Generated plans of a book, next to its `book.toml`
+pub const TEACHING_PLAN_FILE: &str = "teaching_plan.toml";
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default, ToSchema)]
 pub struct BookTeachingPlan {
     pub teaching_plan: Option<String>,
     pub chapter_plans: BTreeMap<ChapterNumber, ChapterPlan>,
+    /// chapter -> chapters that should be learned before it, `None` if not inferred yet
+    #[serde(default)]
+    pub prerequisites: Option<BTreeMap<ChapterNumber, BTreeSet<ChapterNumber>>>,
+    /// figure path (relative to the book root) -> text description
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub figure_descriptions: BTreeMap<PathBuf, String>,
+}
+
+impl BookTeachingPlan {
+    /// the plan saved in `book_path`, empty if there is none yet
+    pub async fn load(book_path: &Path) -> BookTeachingPlan {
+        match tokio::fs::read_to_string(book_path.join(TEACHING_PLAN_FILE))
+            .await
+            .map(|s| toml::from_str::<BookTeachingPlan>(&s))
+        {
+            Ok(Ok(plan)) => plan,
+            _ => BookTeachingPlan::default(),
+        }
+    }
+
+    /// replace the plan in `book_path` at once, an interrupted write leaves the old plan
+    pub async fn save(&self, book_path: &Path) -> anyhow::Result<()> {
+        let temp_path = book_path.join(format!("{}.tmp", TEACHING_PLAN_FILE));
+        tokio::fs::write(&temp_path, toml::to_string(self)?).await?;
+        tokio::fs::rename(&temp_path, book_path.join(TEACHING_PLAN_FILE)).await?;
+        Ok(())
+    }
+}
+
+/// sha256 of `parts` in hex, stable across builds unlike `std::hash`
+pub fn content_hash<'a>(parts: impl IntoIterator<Item = &'a str>) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part.as_bytes());
+        // moving text from one part to the next changes the hash
+        hasher.update([0]);
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
 }
 
 #[derive(Debug, Clone)]
 pub struct BookRaw {
+    /// assigned by the library when the book is first imported, 0 before
     pub id: i64,
     pub title: String,
     pub chapters: BTreeMap<ChapterNumber, ChapterRaw>,
     pub authors: Vec<String>,
     pub description: Option<String>,
+    /// directory of the markdown sources and assets, relative to the book root
+    pub src_dir: PathBuf,
+    /// hash of the metadata and chapters, changes with every new version of the book
+    pub content_hash: String,
 }
 
 #[derive(Debug, Clone, Serialize, ToSchema)]
@@ -44,8 +99,21 @@ pub struct Book {
     pub authors: Vec<String>,
     pub description: Option<String>,
     pub teaching_plan: String,
+    /// chapter -> chapters that should be learned before it
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub prerequisites: BTreeMap<ChapterNumber, BTreeSet<ChapterNumber>>,
     #[serde(skip_serializing)]
     pub chapters: BTreeMap<ChapterNumber, Chapter>,
+    /// directory of the markdown sources and assets, relative to the book root
+    #[serde(skip_serializing)]
+    #[schema(ignore)]
+    pub src_dir: PathBuf,
+    /// figure path (relative to the book root) -> text description
+    #[serde(skip_serializing)]
+    #[schema(ignore)]
+    pub figure_descriptions: BTreeMap<PathBuf, String>,
+    #[serde(skip_serializing)]
+    pub content_hash: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
@@ -58,7 +126,7 @@ pub struct BookMeta {
 }
 
 impl BookRaw {
-    async fn load(root_dir: impl AsRef<Path>) -> anyhow::Result<BookRaw> {
+    pub async fn load(root_dir: impl AsRef<Path>) -> anyhow::Result<BookRaw> {
         let root_dir = root_dir.as_ref();
         info!("Loading book from {}", root_dir.display());
         let file_name = root_dir
@@ -69,7 +137,7 @@ impl BookRaw {
             .to_string();
         let book_toml_content = tokio::fs::read_to_string(root_dir.join("book.toml")).await?;
         let book_cfg = toml::from_str::<mdbook::config::Config>(&book_toml_content)?.book;
-        let src_dir = root_dir.join(book_cfg.src);
+        let src_dir = root_dir.join(&book_cfg.src);
         let build_config = mdbook::config::BuildConfig {
             build_dir: PathBuf::from(""),
             create_missing: true,
@@ -85,6 +153,8 @@ impl BookRaw {
             chapters: BTreeMap::new(),
             authors: book_cfg.authors,
             description: book_cfg.description,
+            src_dir: book_cfg.src,
+            content_hash: String::new(),
         };
         let ori_book = mdbook::book::load_book(src_dir.clone(), &build_config)?;
         let mut chapters: Vec<ChapterRaw> = vec![];
@@ -122,12 +192,7 @@ impl BookRaw {
             error!("chapter number is not unique, path: {}", root_dir.display());
             bail!("chapter number is not unique");
         }
-        let mut hasher = DefaultHasher::new();
-        book.title.hash(&mut hasher);
-        book.authors.hash(&mut hasher);
-        book.description.hash(&mut hasher);
-        book.chapters.hash(&mut hasher);
-        book.id = (hasher.finish() as i64).abs();
+        book.content_hash = book.hash_content();
         Ok(book)
     }
 
@@ -187,34 +252,113 @@ The book is divided into three stages, each designed to progressively build the
 - **Comprehensive Exams**: Midterm and final tests covering multiple topics.
 - **Practical Tasks**: Assignments that apply grammar rules to real-life writing or speaking scenarios.
 ```"#;
-        let teaching_plan =
-            ai_utils::summarize(&chapter_summaries, 1000, Some(prompt.to_string())).await?;
+        let teaching_plan = ai_utils::with_retry(|| {
+            ai_utils::summarize(&chapter_summaries, 1000, Some(prompt.to_string()))
+        })
+        .await?;
         Ok(teaching_plan)
     }
 
-    async fn to_book(&self, book_path: impl AsRef<Path>) -> anyhow::Result<Book> {
-        let teaching_plan_path = book_path.as_ref().join("teaching_plan.toml");
+    /// infer which chapters depend on which, a chapter can only depend on earlier chapters
+    async fn generate_prerequisites(
+        &self,
+        chapters: &BTreeMap<ChapterNumber, Chapter>,
+    ) -> anyhow::Result<BTreeMap<ChapterNumber, BTreeSet<ChapterNumber>>> {
+        /// The chapters a student must understand before learning a chapter
+        #[derive(Debug, JsonSchema, Deserialize)]
+        struct ChapterDependency {
+            chapter_number: ChapterNumber,
+            prerequisites: Vec<ChapterNumber>,
+        }
+        #[derive(Debug, JsonSchema, Deserialize)]
+        struct ChapterDependencies(Vec<ChapterDependency>);
+
+        let mut chapter_summaries = format!("# Book Title: {}\n\n", self.title);
+        for ch in chapters.values() {
+            chapter_summaries.push_str(&format!(
+                "### {} {}:\n{}\n\n",
+                ch.number, ch.name, ch.chapter_plan.summary,
+            ));
+        }
+        info!("generating chapter prerequisites for book: {}", self.title);
+        let prompt = format!(
+            "For each chapter of the following book, list the earlier chapters whose content a student must understand first. \
+            Only list direct prerequisites, leave the list empty if the chapter can be learned on its own:\n{}",
+            chapter_summaries
+        );
+        let dependencies: ChapterDependencies =
+            ai_utils::with_retry(|| ai_utils::extract(prompt.clone())).await?;
+        let mut prerequisites = BTreeMap::new();
+        for dependency in dependencies.0 {
+            if !chapters.contains_key(&dependency.chapter_number) {
+                continue;
+            }
+            // only keep edges to earlier chapters so the graph stays acyclic
+            let requires: BTreeSet<ChapterNumber> = dependency
+                .prerequisites
+                .into_iter()
+                .filter(|p| chapters.contains_key(p) && *p < dependency.chapter_number)
+                .collect();
+            if !requires.is_empty() {
+                prerequisites.insert(dependency.chapter_number, requires);
+            }
+        }
+        Ok(prerequisites)
+    }
+
+    /// generate the missing plans, `AI_PLAN_CONCURRENCY` chapters at a time, `progress` is
+    /// called with the number of planned chapters and the number of chapters
+    pub async fn to_book(
+        &self,
+        book_path: impl AsRef<Path>,
+        progress: &(dyn Fn(usize, usize) + Send + Sync),
+    ) -> anyhow::Result<Book> {
+        let book_path = book_path.as_ref();
         let mut changed = false;
-        let mut book_plan = match tokio::fs::read_to_string(&teaching_plan_path)
-            .await
-            .map(|s| toml::from_str::<BookTeachingPlan>(&s))
-        {
-            Ok(Ok(plan)) => plan,
-            _ => BookTeachingPlan::default(),
-        };
+        let mut book_plan = BookTeachingPlan::load(book_path).await;
 
-        let mut chapters = BTreeMap::new();
-        for ch in self.iter() {
-            let chapter_plan = match book_plan.chapter_plans.entry(ch.number.clone()) {
-                Entry::Vacant(o) => {
-                    changed = true;
-                    o.insert(ch.generate_chapter_plan().await?).clone()
-                }
-                Entry::Occupied(o) => o.get().clone(),
-            };
-            let chapter = ch.to_chapter(chapter_plan);
-            chapters.insert(ch.number.clone(), chapter);
+        let total = self.iter().count();
+        // plans generated before objectives were introduced only miss their objectives
+        let pending: Vec<_> = self
+            .iter()
+            .filter(|ch| {
+                book_plan
+                    .chapter_plans
+                    .get(&ch.number)
+                    .is_none_or(|plan| plan.objectives.is_empty())
+            })
+            .map(|ch| (ch, book_plan.chapter_plans.get(&ch.number).cloned()))
+            .collect();
+        let mut planned = total - pending.len();
+        progress(planned, total);
+        let mut plans = futures::stream::iter(pending)
+            .map(|(ch, plan)| async move {
+                let plan = match plan {
+                    Some(mut plan) => {
+                        plan.generate_objectives().await?;
+                        plan
+                    }
+                    None => ch.generate_chapter_plan().await?,
+                };
+                anyhow::Ok((ch.number.clone(), plan))
+            })
+            .buffer_unordered(*AI_PLAN_CONCURRENCY);
+        while let Some(result) = plans.next().await {
+            let (number, plan) = result?;
+            book_plan.chapter_plans.insert(number, plan);
+            // saved after every chapter, an interrupted generation resumes from here
+            book_plan.save(book_path).await?;
+            planned += 1;
+            progress(planned, total);
         }
+
+        let chapters: BTreeMap<_, _> = self
+            .iter()
+            .map(|ch| {
+                let plan = book_plan.chapter_plans[&ch.number].clone();
+                (ch.number.clone(), ch.to_chapter(plan))
+            })
+            .collect();
         let teaching_plan = match &book_plan.teaching_plan {
             Some(teaching_plan) => teaching_plan.clone(),
             None => {
@@ -224,8 +368,22 @@ The book is divided into three stages, each designed to progressively build the
                 teaching_plan
             }
         };
+        let prerequisites = match &book_plan.prerequisites {
+            Some(prerequisites) => prerequisites.clone(),
+            None => {
+                let prerequisites = self.generate_prerequisites(&chapters).await?;
+                book_plan.prerequisites = Some(prerequisites.clone());
+                changed = true;
+                prerequisites
+            }
+        };
+        if let Some(model) = AI_FIGURE_MODEL.as_deref() {
+            changed |= self
+                .describe_figures(model, book_path, &mut book_plan.figure_descriptions)
+                .await?;
+        }
         if changed {
-            tokio::fs::write(&teaching_plan_path, toml::to_string(&book_plan)?).await?;
+            book_plan.save(book_path).await?;
         }
         let book = Book {
             id: self.id,
@@ -234,12 +392,58 @@ The book is divided into three stages, each designed to progressively build the
             authors: self.authors.clone(),
             description: self.description.clone(),
             teaching_plan,
+            prerequisites,
             chapters,
             chapter_numbers: self.chapters.keys().cloned().collect(),
+            src_dir: self.src_dir.clone(),
+            figure_descriptions: book_plan.figure_descriptions,
+            content_hash: self.content_hash.clone(),
         };
         Ok(book)
     }
 
+    /// describe the figures that have no description yet, returns whether any was added
+    async fn describe_figures(
+        &self,
+        model: &str,
+        book_path: &Path,
+        descriptions: &mut BTreeMap<PathBuf, String>,
+    ) -> anyhow::Result<bool> {
+        let mut changed = false;
+        for ch in self.iter() {
+            for figure in figure::find_figures(&ch.content, ch.path.as_deref(), &self.src_dir) {
+                if descriptions.contains_key(&figure.path) {
+                    continue;
+                }
+                let image = match figure::read_image(&book_path.join(&figure.path)).await {
+                    Ok(image) => image,
+                    Err(e) => {
+                        info!("skip describing figure {}: {}", figure.path.display(), e);
+                        continue;
+                    }
+                };
+                info!("describing figure: {}", figure.path.display());
+                let description = ai_utils::describe_image(model, image, &figure.alt).await?;
+                descriptions.insert(figure.path, description);
+                changed = true;
+            }
+        }
+        Ok(changed)
+    }
+
+    fn hash_content(&self) -> String {
+        let numbers: Vec<String> = self.iter().map(|ch| ch.number.to_string()).collect();
+        let mut parts = vec![
+            self.title.as_str(),
+            self.description.as_deref().unwrap_or(""),
+        ];
+        parts.extend(self.authors.iter().map(String::as_str));
+        for (ch, number) in self.iter().zip(&numbers) {
+            parts.extend([number.as_str(), ch.name.as_str(), ch.content.as_str()]);
+        }
+        content_hash(parts)
+    }
+
     pub fn iter(&self) -> TreeIter<'_, ChapterRaw, DepthFirst> {
         TreeIter::<ChapterRaw, DepthFirst>::new(self.chapters.values())
     }
@@ -257,8 +461,10 @@ The book is divided into three stages, each designed to progressively build the
 }
 
 impl Book {
-    pub async fn load(book_path: impl AsRef<Path>) -> anyhow::Result<Book> {
-        let book_raw = BookRaw::load(&book_path).await?;
-        book_raw.to_book(&book_path).await
+    /// load the book with the given id, generating the missing plans
+    pub async fn load(book_path: impl AsRef<Path>, id: i64) -> anyhow::Result<Book> {
+        let mut book_raw = BookRaw::load(&book_path).await?;
+        book_raw.id = id;
+        book_raw.to_book(&book_path, &|_, _| {}).await
     }
 }
@@ -33,6 +33,31 @@ pub struct ChapterRaw {
 pub struct ChapterPlan {
     pub plan: String,
     pub summary: String,
+    /// Canonical learning objectives of the chapter, extracted from `plan`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub objectives: Vec<PlannedObjective>,
+}
+
+/// A learning objective seeded from the chapter plan at import time.
+/// The id is stable for the lifetime of the plan and unique within the chapter.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, JsonSchema)]
+pub struct PlannedObjective {
+    pub id: u32,
+    pub description: String,
+}
+
+impl ChapterPlan {
+    /// extract the objectives from the plan, ids start from 1
+    pub async fn generate_objectives(&mut self) -> anyhow::Result<()> {
+        let objectives =
+            ai_utils::with_retry(|| ai_utils::extract_learning_objectives(&self.plan)).await?;
+        self.objectives = objectives
+            .into_iter()
+            .zip(1..)
+            .map(|(description, id)| PlannedObjective { id, description })
+            .collect();
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Serialize, ToSchema)]
@@ -48,6 +73,11 @@ pub struct Chapter {
 }
 
 impl ChapterRaw {
+    /// hash of the name and content, equal for a chapter that only moved
+    pub fn content_hash(&self) -> String {
+        super::book::content_hash([self.name.as_str(), self.content.as_str()])
+    }
+
     pub async fn generate_chapter_plan(&self) -> anyhow::Result<ChapterPlan> {
         info!(
             "generating chapter plan for chapter: {} {}",
@@ -93,13 +123,20 @@ Example:
 - Assign homework to reinforce tense usage.
 - Prepare for the next chapter ("Subject-Verb Agreement") by linking it to tense knowledge.
 ```"#;
-        let chapter_plan =
-            ai_utils::summarize(&self.content, 1000, Some(prompt.to_string())).await?;
-        let summary = ai_utils::summarize(&self.content, 100, None).await?;
-        Ok(ChapterPlan {
+        // provider errors are retried per request so finished requests are not repeated
+        let chapter_plan = ai_utils::with_retry(|| {
+            ai_utils::summarize(&self.content, 1000, Some(prompt.to_string()))
+        })
+        .await?;
+        let summary =
+            ai_utils::with_retry(|| ai_utils::summarize(&self.content, 100, None)).await?;
+        let mut chapter_plan = ChapterPlan {
             plan: chapter_plan,
             summary,
-        })
+            objectives: vec![],
+        };
+        chapter_plan.generate_objectives().await?;
+        Ok(chapter_plan)
     }
 
     pub fn to_chapter(&self, chapter_plan: ChapterPlan) -> Chapter {
@@ -0,0 +1,331 @@
+use std::{
+    fs,
+    io::{Cursor, Seek, Write},
+    path::Path,
+};
+
+use serde::Deserialize;
+use time::{OffsetDateTime, macros::format_description};
+use tokio::task::spawn_blocking;
+use utoipa::ToSchema;
+use zip::{CompressionMethod, ZipWriter, write::SimpleFileOptions};
+
+use super::{
+    asset,
+    book::{Book, TEACHING_PLAN_FILE},
+    library::Library,
+    render::{self, LinkStyle, TocItem},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    /// zip of the mdbook, the format books are imported from
+    Mdbook,
+    Epub,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Mdbook => "zip",
+            ExportFormat::Epub => "epub",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Mdbook => "application/zip",
+            ExportFormat::Epub => "application/epub+zip",
+        }
+    }
+}
+
+/// files of the book directory relative to it, in a stable order
+fn book_files(dir: &Path) -> Vec<std::path::PathBuf> {
+    let mut files: Vec<_> = walkdir::WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| entry.path().strip_prefix(dir).ok().map(Path::to_path_buf))
+        .collect();
+    files.sort();
+    files
+}
+
+/// name of a file in a zip, always with `/` separators
+fn zip_name(path: &Path) -> String {
+    let parts: Vec<_> = path.iter().map(|c| c.to_string_lossy()).collect();
+    parts.join("/")
+}
+
+/// zip the mdbook in `book_dir` with `book.toml` at the root, the teaching plan is only
+/// included if `include_plan`, so another server does not need to generate it again
+pub fn write_mdbook_zip<W: Write + Seek>(
+    book_dir: &Path,
+    writer: W,
+    include_plan: bool,
+) -> anyhow::Result<W> {
+    let mut zip = ZipWriter::new(writer);
+    let options = SimpleFileOptions::default();
+    for path in book_files(book_dir) {
+        let name = zip_name(&path);
+        if name.starts_with(TEACHING_PLAN_FILE) && !(include_plan && name == TEACHING_PLAN_FILE) {
+            continue;
+        }
+        zip.start_file(name, options)?;
+        zip.write_all(&fs::read(book_dir.join(&path))?)?;
+    }
+    Ok(zip.finish()?)
+}
+
+pub(crate) fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn xhtml(language: &str, title: &str, body: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops" lang="{language}" xml:lang="{language}">
+<head>
+<meta charset="UTF-8"/>
+<title>{}</title>
+<link rel="stylesheet" type="text/css" href="style.css"/>
+</head>
+<body>
+{body}
+</body>
+</html>
+"#,
+        escape_xml(title)
+    )
+}
+
+/// nested list of the chapters for the navigation document
+fn nav_items(items: &[TocItem], nav: &mut String) {
+    nav.push_str("<ol>\n");
+    for item in items {
+        nav.push_str(&format!(
+            "<li><a href=\"{}\">{}</a>",
+            render::epub_chapter_file(&item.number),
+            escape_xml(&item.name)
+        ));
+        if !item.sub_items.is_empty() {
+            nav_items(&item.sub_items, nav);
+        }
+        nav.push_str("</li>\n");
+    }
+    nav.push_str("</ol>\n");
+}
+
+const STYLE: &str = "pre { white-space: pre-wrap; }\nimg { max-width: 100%; }\n";
+
+/// render the book in `book_dir` as an EPUB 3, with a chapter per file and the
+/// table of contents as navigation
+pub fn write_epub<W: Write + Seek>(book: &Book, book_dir: &Path, writer: W) -> anyhow::Result<W> {
+    let config = fs::read_to_string(book_dir.join("book.toml"))?;
+    let config = toml::from_str::<mdbook::config::Config>(&config)?.book;
+    let language = escape_xml(config.language.as_deref().unwrap_or("en"));
+    let identifier = format!("urn:ai-reader:book:{}:{}", book.id, book.content_hash);
+    let modified = OffsetDateTime::now_utc().format(format_description!(
+        "[year]-[month]-[day]T[hour]:[minute]:[second]Z"
+    ))?;
+
+    let mut zip = ZipWriter::new(writer);
+    // the mimetype comes first and uncompressed, so readers can identify the file
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    let options = SimpleFileOptions::default();
+    zip.start_file("mimetype", stored)?;
+    zip.write_all(b"application/epub+zip")?;
+    zip.start_file("META-INF/container.xml", options)?;
+    zip.write_all(
+        br#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+<rootfiles>
+<rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+</rootfiles>
+</container>
+"#,
+    )?;
+
+    let mut manifest = String::from(
+        "<item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n\
+        <item id=\"style\" href=\"style.css\" media-type=\"text/css\"/>\n",
+    );
+    let mut spine = String::new();
+    for (i, chapter) in book.chapters.values().enumerate() {
+        let rendered = render::render_chapter_with(book, &chapter.number, LinkStyle::Epub)?;
+        let file = render::epub_chapter_file(&chapter.number);
+        let body = format!(
+            "<section epub:type=\"chapter\">\n{}</section>",
+            rendered.html
+        );
+        zip.start_file(format!("OEBPS/{}", file), options)?;
+        zip.write_all(xhtml(&language, &chapter.name, &body).as_bytes())?;
+        manifest.push_str(&format!(
+            "<item id=\"chapter-{i}\" href=\"{}\" media-type=\"application/xhtml+xml\"/>\n",
+            escape_xml(&file)
+        ));
+        spine.push_str(&format!("<itemref idref=\"chapter-{i}\"/>\n"));
+    }
+
+    // images and attachments keep their path relative to the book root
+    let src_dir = book_dir.join(&book.src_dir);
+    for (i, path) in book_files(&src_dir).into_iter().enumerate() {
+        if path.extension().is_some_and(|ext| ext == "md") {
+            continue;
+        }
+        let name = zip_name(&book.src_dir.join(&path));
+        zip.start_file(format!("OEBPS/{}", name), options)?;
+        zip.write_all(&fs::read(src_dir.join(&path))?)?;
+        manifest.push_str(&format!(
+            "<item id=\"asset-{i}\" href=\"{}\" media-type=\"{}\"/>\n",
+            escape_xml(&name),
+            asset::content_type(&path).essence_str()
+        ));
+    }
+
+    let mut nav = format!(
+        "<nav epub:type=\"toc\" id=\"toc\">\n<h1>{}</h1>\n",
+        escape_xml(&book.title)
+    );
+    nav_items(&render::table_of_contents(book), &mut nav);
+    nav.push_str("</nav>");
+    zip.start_file("OEBPS/nav.xhtml", options)?;
+    zip.write_all(xhtml(&language, &book.title, &nav).as_bytes())?;
+    zip.start_file("OEBPS/style.css", options)?;
+    zip.write_all(STYLE.as_bytes())?;
+
+    let mut metadata = format!(
+        "<dc:identifier id=\"book-id\">{}</dc:identifier>\n\
+        <dc:title>{}</dc:title>\n\
+        <dc:language>{}</dc:language>\n\
+        <meta property=\"dcterms:modified\">{}</meta>\n",
+        escape_xml(&identifier),
+        escape_xml(&book.title),
+        language,
+        modified
+    );
+    for author in &book.authors {
+        metadata.push_str(&format!(
+            "<dc:creator>{}</dc:creator>\n",
+            escape_xml(author)
+        ));
+    }
+    if let Some(description) = &book.description {
+        metadata.push_str(&format!(
+            "<dc:description>{}</dc:description>\n",
+            escape_xml(description)
+        ));
+    }
+    zip.start_file("OEBPS/content.opf", options)?;
+    zip.write_all(
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="book-id" xml:lang="{language}">
+<metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+{metadata}</metadata>
+<manifest>
+{manifest}</manifest>
+<spine>
+{spine}</spine>
+</package>
+"#
+        )
+        .as_bytes(),
+    )?;
+    Ok(zip.finish()?)
+}
+
+/// package book `book_id` in `format`, the teaching plan is only included in mdbook zips
+pub async fn export_book(
+    library: &Library,
+    book_id: i64,
+    format: ExportFormat,
+    include_plan: bool,
+) -> anyhow::Result<Vec<u8>> {
+    let book = library.get_book(book_id).await?;
+    let book_dir = library.book_dir(book_id).await?;
+    spawn_blocking(move || {
+        let writer = Cursor::new(Vec::new());
+        let writer = match format {
+            ExportFormat::Mdbook => write_mdbook_zip(&book_dir, writer, include_plan)?,
+            ExportFormat::Epub => write_epub(&book, &book_dir, writer)?,
+        };
+        Ok(writer.into_inner())
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use zip::ZipArchive;
+
+    use super::*;
+
+    #[test]
+    fn mdbook_zip_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("src/img")).unwrap();
+        for (path, content) in [
+            ("book.toml", "[book]\ntitle = \"Test\"\n"),
+            ("src/SUMMARY.md", "- [Intro](intro.md)\n"),
+            ("src/intro.md", "# Intro\n"),
+            ("src/img/logo.png", "png"),
+            (TEACHING_PLAN_FILE, "teaching_plan = \"plan\"\n"),
+        ] {
+            fs::write(dir.path().join(path), content).unwrap();
+        }
+
+        let names = |include_plan| {
+            let data = write_mdbook_zip(dir.path(), Cursor::new(vec![]), include_plan)
+                .unwrap()
+                .into_inner();
+            let archive = ZipArchive::new(Cursor::new(data)).unwrap();
+            let mut names: Vec<String> = archive.file_names().map(str::to_string).collect();
+            names.sort();
+            names
+        };
+        assert_eq!(
+            names(false),
+            [
+                "book.toml",
+                "src/SUMMARY.md",
+                "src/img/logo.png",
+                "src/intro.md"
+            ]
+        );
+        assert!(names(true).contains(&TEACHING_PLAN_FILE.to_string()));
+
+        let data = write_mdbook_zip(dir.path(), Cursor::new(vec![]), false)
+            .unwrap()
+            .into_inner();
+        let mut archive = ZipArchive::new(Cursor::new(data)).unwrap();
+        let mut intro = String::new();
+        archive
+            .by_name("src/intro.md")
+            .unwrap()
+            .read_to_string(&mut intro)
+            .unwrap();
+        assert_eq!(intro, "# Intro\n");
+    }
+
+    #[test]
+    fn xml_escape() {
+        assert_eq!(escape_xml("a < b & \"c\""), "a &lt; b &amp; &quot;c&quot;");
+    }
+}
@@ -0,0 +1,205 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use async_openai::{
+    tools::Tool,
+    types::{ImageDetail, ImageUrl},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use parking_lot::Mutex;
+use pulldown_cmark::{Event, Parser, Tag, TagEnd};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    asset,
+    book::Book,
+    chapter::{Chapter, ChapterNumber},
+    library::Library,
+    render::{is_relative_url, normalize_path},
+};
+use crate::ai_utils::AI_VISION;
+
+/// Images larger than this are not sent to the model
+const MAX_IMAGE_SIZE: u64 = 4 * 1024 * 1024;
+
+/// An image referenced by a chapter
+#[derive(Debug, Clone, Serialize)]
+pub struct Figure {
+    /// position of the figure in the chapter, starting from 1
+    pub index: usize,
+    pub alt: String,
+    /// path of the image relative to the book root
+    pub path: PathBuf,
+    /// text description of the image, generated at import time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// images of a chapter, `chapter_path` is relative to `src_dir`
+pub fn find_figures(content: &str, chapter_path: Option<&Path>, src_dir: &Path) -> Vec<Figure> {
+    let chapter_dir = chapter_path.and_then(Path::parent).unwrap_or(Path::new(""));
+    let mut figures = vec![];
+    let mut current: Option<(PathBuf, String)> = None;
+    for event in Parser::new(content) {
+        match event {
+            Event::Start(Tag::Image { dest_url, .. }) if is_relative_url(&dest_url) => {
+                current = normalize_path(&chapter_dir.join(dest_url.as_ref()))
+                    .map(|path| (src_dir.join(path), String::new()));
+            }
+            Event::Text(text) | Event::Code(text) => {
+                if let Some((_, alt)) = &mut current {
+                    alt.push_str(&text);
+                }
+            }
+            Event::End(TagEnd::Image) => {
+                if let Some((path, alt)) = current.take() {
+                    figures.push(Figure {
+                        index: figures.len() + 1,
+                        alt,
+                        path,
+                        description: None,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    figures
+}
+
+/// images of a chapter with the descriptions generated at import time
+pub fn chapter_figures(book: &Book, chapter: &Chapter) -> Vec<Figure> {
+    let mut figures = find_figures(&chapter.content, chapter.path.as_deref(), &book.src_dir);
+    for figure in &mut figures {
+        figure.description = book.figure_descriptions.get(&figure.path).cloned();
+    }
+    figures
+}
+
+/// read an image as a data url, only formats accepted by vision models are supported
+pub async fn read_image(path: &Path) -> anyhow::Result<ImageUrl> {
+    let mime = asset::content_type(path);
+    if !matches!(
+        mime.essence_str(),
+        "image/png" | "image/jpeg" | "image/gif" | "image/webp"
+    ) {
+        anyhow::bail!("Unsupported image format: {}", mime);
+    }
+    let size = tokio::fs::metadata(path).await?.len();
+    if size > MAX_IMAGE_SIZE {
+        anyhow::bail!("Image is too large: {} bytes", size);
+    }
+    let data = tokio::fs::read(path).await?;
+    Ok(ImageUrl {
+        url: format!(
+            "data:{};base64,{}",
+            mime.essence_str(),
+            STANDARD.encode(data)
+        ),
+        detail: Some(ImageDetail::Auto),
+    })
+}
+
+/// Images requested by tool calls, the teacher attaches them to the conversation
+/// as a user message because tool messages can only contain text
+#[derive(Debug, Clone, Default)]
+pub struct FigureAttachments(Arc<Mutex<Vec<ImageUrl>>>);
+
+impl FigureAttachments {
+    pub fn push(&self, image: ImageUrl) {
+        self.0.lock().push(image);
+    }
+    pub fn take(&self) -> Vec<ImageUrl> {
+        std::mem::take(&mut *self.0.lock())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct FigureLocation {
+    pub chapter_number: ChapterNumber,
+    /// The index of the figure in the chapter, as listed by GetChapterContent
+    pub index: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FigureResult {
+    #[serde(flatten)]
+    pub figure: Figure,
+    /// whether the image is attached to the next message
+    pub attached: bool,
+}
+
+pub struct GetFigureTool {
+    book_id: i64,
+    library: Arc<Library>,
+    attachments: FigureAttachments,
+}
+
+impl GetFigureTool {
+    pub fn new(book_id: i64, library: Arc<Library>, attachments: FigureAttachments) -> Self {
+        Self {
+            book_id,
+            library,
+            attachments,
+        }
+    }
+}
+
+impl Tool for GetFigureTool {
+    type Args = FigureLocation;
+    type Output = FigureResult;
+    type Error = anyhow::Error;
+    fn name() -> String {
+        "GetFigure".to_string()
+    }
+    fn description() -> Option<String> {
+        Some(
+            "Look at a figure of a chapter. The image is attached to the next message \
+            if it can be shown, otherwise use its description"
+                .to_string(),
+        )
+    }
+    async fn call(&self, args: Self::Args) -> anyhow::Result<Self::Output> {
+        let book = self.library.get_book(self.book_id).await?;
+        let chapter = book
+            .chapters
+            .get(&args.chapter_number)
+            .ok_or(anyhow::anyhow!(
+                "Chapter not found: {}",
+                args.chapter_number
+            ))?;
+        let figure = chapter_figures(&book, chapter)
+            .into_iter()
+            .find(|figure| figure.index == args.index)
+            .ok_or(anyhow::anyhow!("Figure not found: {}", args.index))?;
+        let mut attached = false;
+        if *AI_VISION {
+            let path = self
+                .library
+                .asset_path(self.book_id, &figure.path.to_string_lossy())
+                .await?;
+            self.attachments.push(read_image(&path).await?);
+            attached = true;
+        }
+        Ok(FigureResult { figure, attached })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_chapter_figures() {
+        let content = "![A *bold* cat](../img/cat.png)\n\n![remote](https://example.com/a.png)\n\n![](figs/b.svg)";
+        let figures = find_figures(content, Some(Path::new("part1/ch1.md")), Path::new("src"));
+        assert_eq!(figures.len(), 2);
+        assert_eq!(figures[0].alt, "A bold cat");
+        assert_eq!(figures[0].path, PathBuf::from("src/img/cat.png"));
+        assert_eq!(figures[1].index, 2);
+        assert_eq!(figures[1].path, PathBuf::from("src/part1/figs/b.svg"));
+    }
+}
@@ -0,0 +1,323 @@
+pub mod html;
+pub mod markdown;
+pub mod notebook;
+pub mod office;
+pub mod text;
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use pulldown_cmark::{Event, HeadingLevel, Parser, Tag, TagEnd};
+use serde::Serialize;
+
+use super::{
+    book::BookRaw,
+    chapter::{ChapterNumber, ChapterRaw},
+    render::{is_relative_url, normalize_path},
+};
+
+/// A file referenced by the chapters of an imported book
+#[derive(Debug, Clone)]
+pub enum Asset {
+    /// copied from a file on disk
+    File(PathBuf),
+    Data(Vec<u8>),
+}
+
+/// A book read by an importer, stored in the bookbase as an mdbook
+#[derive(Debug, Clone)]
+pub struct ImportedBook {
+    pub book: BookRaw,
+    /// path relative to the source directory -> content
+    pub assets: BTreeMap<PathBuf, Asset>,
+}
+
+/// Converts a file or directory that is not an mdbook into a book
+pub trait BookImporter: Send + Sync {
+    /// whether the importer can read `path`
+    fn accepts(&self, path: &Path) -> bool;
+    fn import(&self, path: &Path) -> anyhow::Result<ImportedBook>;
+}
+
+/// all importers, the first one accepting a path is used
+pub fn importers() -> Vec<Box<dyn BookImporter>> {
+    vec![
+        Box::new(markdown::MarkdownFolderImporter),
+        Box::new(markdown::MarkdownFileImporter),
+        Box::new(notebook::NotebookImporter),
+        Box::new(text::TextImporter),
+        Box::new(office::docx::DocxImporter),
+        Box::new(office::odt::OdtImporter),
+        Box::new(html::HtmlFileImporter),
+        Box::new(html::HtmlSiteImporter),
+    ]
+}
+
+pub fn find_importer(path: &Path) -> Option<Box<dyn BookImporter>> {
+    importers()
+        .into_iter()
+        .find(|importer| importer.accepts(path))
+}
+
+/// convert an uploaded file or directory into an mdbook in `output`: an mdbook directory
+/// is copied, other books are read by an importer, converted from epub or extracted from a zip
+pub fn convert_to_mdbook(source: &Path, output: &Path) -> anyhow::Result<()> {
+    fs::create_dir_all(output)?;
+    if source.is_dir() && source.join("book.toml").is_file() {
+        let options = fs_extra::dir::CopyOptions {
+            overwrite: true,
+            content_only: true,
+            ..Default::default()
+        };
+        fs_extra::dir::copy(source, output, &options)?;
+    } else if let Some(importer) = find_importer(source) {
+        importer.import(source)?.write_mdbook(output)?;
+    } else if source.is_file() && has_extension(source, &["epub"]) {
+        epub2mdbook::convert_epub_to_mdbook(source, output, false)?;
+    } else if source.is_file() && has_extension(source, &["zip"]) {
+        zip::ZipArchive::new(fs::File::open(source)?)?.extract(output)?;
+    } else {
+        anyhow::bail!("Invalid book path: {}", source.display());
+    }
+    Ok(())
+}
+
+pub fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .is_some_and(|ext| extensions.contains(&ext.as_str()))
+}
+
+/// a chapter without sub chapters, the number is assigned by `ImportedBook::new`
+pub fn chapter(name: String, path: PathBuf, content: String) -> ChapterRaw {
+    ChapterRaw {
+        name,
+        path: Some(path),
+        content,
+        ..Default::default()
+    }
+}
+
+/// number the chapters 1., 2., 2.1., ... in tree order
+fn number_chapters(chapters: &mut [ChapterRaw], parent: &ChapterNumber, parent_names: &[String]) {
+    for (chapter, i) in chapters.iter_mut().zip(1..) {
+        chapter.number = parent.iter().copied().chain([i]).collect();
+        chapter.parent_names = parent_names.to_vec();
+        let mut names = parent_names.to_vec();
+        names.push(chapter.name.clone());
+        let number = chapter.number.clone();
+        number_chapters(&mut chapter.sub_chapters, &number, &names);
+    }
+}
+
+/// `[` and `]` would end the link text in SUMMARY.md
+fn escape_link_text(text: &str) -> String {
+    text.replace('[', "\\[").replace(']', "\\]")
+}
+
+fn summary_items(chapters: &[ChapterRaw], depth: usize, summary: &mut String) {
+    for chapter in chapters {
+        let path = chapter
+            .path
+            .as_ref()
+            .map(|path| format!("<{}>", path.to_string_lossy()))
+            .unwrap_or_default();
+        summary.push_str(&format!(
+            "{}- [{}]({})\n",
+            "  ".repeat(depth),
+            escape_link_text(&chapter.name),
+            path
+        ));
+        summary_items(&chapter.sub_chapters, depth + 1, summary);
+    }
+}
+
+fn write_chapters(chapters: &[ChapterRaw], src_dir: &Path) -> anyhow::Result<()> {
+    for chapter in chapters {
+        if let Some(path) = &chapter.path {
+            let path = normalize_path(path)
+                .ok_or_else(|| anyhow::anyhow!("Invalid chapter path: {}", path.display()))?;
+            let path = src_dir.join(path);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, &chapter.content)?;
+        }
+        write_chapters(&chapter.sub_chapters, src_dir)?;
+    }
+    Ok(())
+}
+
+impl ImportedBook {
+    /// a book of the given chapter tree, chapter paths are relative to `src`
+    pub fn new(title: String, mut chapters: Vec<ChapterRaw>) -> Self {
+        number_chapters(&mut chapters, &ChapterNumber::default(), &[]);
+        let book = BookRaw {
+            id: 0,
+            title,
+            chapters: chapters
+                .into_iter()
+                .map(|ch| (ch.number.clone(), ch))
+                .collect(),
+            authors: vec![],
+            description: None,
+            src_dir: PathBuf::from("src"),
+            content_hash: String::new(),
+        };
+        Self {
+            book,
+            assets: BTreeMap::new(),
+        }
+    }
+
+    /// write `book.toml`, `SUMMARY.md`, the chapters and the assets to `dir`
+    pub fn write_mdbook(&self, dir: &Path) -> anyhow::Result<()> {
+        #[derive(Serialize)]
+        struct BookToml {
+            book: mdbook::config::BookConfig,
+        }
+        let book = mdbook::config::BookConfig {
+            title: Some(self.book.title.clone()),
+            authors: self.book.authors.clone(),
+            description: self.book.description.clone(),
+            src: self.book.src_dir.clone(),
+            ..Default::default()
+        };
+        fs::create_dir_all(dir)?;
+        fs::write(dir.join("book.toml"), toml::to_string(&BookToml { book })?)?;
+
+        let src_dir = dir.join(&self.book.src_dir);
+        fs::create_dir_all(&src_dir)?;
+        let chapters: Vec<ChapterRaw> = self.book.chapters.values().cloned().collect();
+        let mut summary = "# Summary\n\n".to_string();
+        summary_items(&chapters, 0, &mut summary);
+        fs::write(src_dir.join("SUMMARY.md"), summary)?;
+        write_chapters(&chapters, &src_dir)?;
+
+        for (path, asset) in &self.assets {
+            let path = normalize_path(path)
+                .ok_or_else(|| anyhow::anyhow!("Invalid asset path: {}", path.display()))?;
+            let path = src_dir.join(path);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            match asset {
+                Asset::File(source) => {
+                    fs::copy(source, path)?;
+                }
+                Asset::Data(data) => fs::write(path, data)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A part of a markdown document starting at a heading
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub title: String,
+    /// the markdown of the section, including its heading
+    pub content: String,
+}
+
+pub fn heading_level(level: HeadingLevel) -> usize {
+    level as usize
+}
+
+/// number of headings of each level (1 to 6)
+pub fn count_headings(content: &str) -> [usize; 7] {
+    let mut counts = [0; 7];
+    for event in Parser::new(content) {
+        if let Event::Start(Tag::Heading { level, .. }) = event {
+            counts[heading_level(level)] += 1;
+        }
+    }
+    counts
+}
+
+/// split a markdown document at the headings of `level`,
+/// the text before the first of these headings is returned separately
+pub fn split_at_headings(content: &str, level: usize) -> (String, Vec<Section>) {
+    // (offset of the heading, title)
+    let mut headings: Vec<(usize, String)> = vec![];
+    let mut in_heading = false;
+    for (event, range) in Parser::new(content).into_offset_iter() {
+        match event {
+            Event::Start(Tag::Heading { level: l, .. }) if heading_level(l) == level => {
+                headings.push((range.start, String::new()));
+                in_heading = true;
+            }
+            Event::Text(text) | Event::Code(text) if in_heading => {
+                if let Some((_, title)) = headings.last_mut() {
+                    title.push_str(&text);
+                }
+            }
+            Event::End(TagEnd::Heading(_)) => in_heading = false,
+            _ => {}
+        }
+    }
+    let first = headings.first().map_or(content.len(), |(start, _)| *start);
+    let preamble = content[..first].to_string();
+    let ends: Vec<usize> = headings
+        .iter()
+        .skip(1)
+        .map(|(start, _)| *start)
+        .chain([content.len()])
+        .collect();
+    let sections = headings
+        .into_iter()
+        .zip(ends)
+        .map(|((start, title), end)| Section {
+            title: title.trim().to_string(),
+            content: content[start..end].to_string(),
+        })
+        .collect();
+    (preamble, sections)
+}
+
+/// file name of the `index`-th chapter, e.g. `03-getting-started.md`
+pub fn chapter_file_name(index: usize, title: &str) -> PathBuf {
+    let mut slug = String::new();
+    for c in title.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug: String = slug.trim_end_matches('-').chars().take(48).collect();
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        PathBuf::from(format!("{index:02}.md"))
+    } else {
+        PathBuf::from(format!("{index:02}-{slug}.md"))
+    }
+}
+
+/// files linked from `content` that exist under `root`, `dir` is the directory
+/// of the document relative to `root`; the paths of the assets are relative to `root`
+pub fn collect_assets(content: &str, root: &Path, dir: &Path) -> BTreeMap<PathBuf, Asset> {
+    let mut assets = BTreeMap::new();
+    for event in Parser::new(content) {
+        let url = match event {
+            Event::Start(Tag::Image { dest_url, .. })
+            | Event::Start(Tag::Link { dest_url, .. }) => dest_url,
+            _ => continue,
+        };
+        if !is_relative_url(&url) {
+            continue;
+        }
+        let url = url.split(['#', '?']).next().unwrap_or_default();
+        let Some(path) = normalize_path(&dir.join(url)) else {
+            continue;
+        };
+        let source = root.join(&path);
+        if source.is_file() && !has_extension(&path, &["md", "markdown"]) {
+            assets.insert(path, Asset::File(source));
+        }
+    }
+    assets
+}
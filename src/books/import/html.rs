@@ -0,0 +1,268 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use regex::Regex;
+
+use super::{
+    Asset, BookImporter, ImportedBook, chapter, collect_assets, has_extension,
+    markdown::{document_chapters, first_heading, humanize_file_name},
+};
+use crate::books::render::normalize_path;
+
+static TITLE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)<title[^>]*>(.*?)</title>").unwrap());
+static MAIN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)<main\b[^>]*>(.*)</main>").unwrap());
+static TABLE_TAG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)<(/?)table\b[^>]*>").unwrap());
+static LINK_HREF: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?is)<a\s[^>]*?href\s*=\s*["']([^"'#?]+)"#).unwrap());
+static IMG_SRC: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?is)<img\s[^>]*?src\s*=\s*["']([^"']+)["']"#).unwrap());
+
+/// Elements of a page that are not part of its content
+const SKIPPED_TAGS: &[&str] = &[
+    "head", "script", "style", "noscript", "nav", "header", "footer",
+];
+
+fn is_html(path: &Path) -> bool {
+    has_extension(path, &["html", "htm", "xhtml"])
+}
+
+fn html_title(html: &str) -> Option<String> {
+    TITLE
+        .captures(html)
+        .map(|caps| caps[1].split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|title| !title.is_empty())
+}
+
+fn table_placeholder(index: usize) -> String {
+    format!("IMPORTEDTABLE{index}END")
+}
+
+/// replace the outermost tables by placeholder paragraphs, tables are kept as html
+/// because markdown tables cannot contain block content or merged cells
+fn extract_tables(html: &str) -> (String, Vec<String>) {
+    let mut content = String::new();
+    let mut tables = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    let mut copied = 0;
+    for caps in TABLE_TAG.captures_iter(html) {
+        let tag = caps.get(0).unwrap();
+        if caps[1].is_empty() {
+            if depth == 0 {
+                start = tag.start();
+            }
+            depth += 1;
+        } else if depth > 0 {
+            depth -= 1;
+            if depth == 0 {
+                content.push_str(&html[copied..start]);
+                content.push_str(&format!("<p>{}</p>", table_placeholder(tables.len())));
+                // a blank line would end the html block in markdown
+                let table: Vec<&str> = html[start..tag.end()]
+                    .lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .collect();
+                tables.push(table.join("\n"));
+                copied = tag.end();
+            }
+        }
+    }
+    content.push_str(&html[copied..]);
+    (content, tables)
+}
+
+/// convert the content of a page, the `<main>` element if there is one
+pub fn html_to_markdown(html: &str) -> anyhow::Result<String> {
+    let content = MAIN
+        .captures(html)
+        .and_then(|caps| caps.get(1))
+        .map_or(html, |main| main.as_str());
+    let (content, tables) = extract_tables(content);
+    let converter = htmd::HtmlToMarkdown::builder()
+        .skip_tags(SKIPPED_TAGS.to_vec())
+        .build();
+    let mut markdown = converter.convert(&content)?;
+    for (i, table) in tables.iter().enumerate() {
+        markdown = markdown.replace(&table_placeholder(i), table);
+    }
+    Ok(markdown)
+}
+
+/// files referenced by a converted page, including the images of its html tables
+fn page_assets(markdown: &str, root: &Path, dir: &Path) -> BTreeMap<PathBuf, Asset> {
+    let images: String = IMG_SRC
+        .captures_iter(markdown)
+        .map(|caps| format!("\n\n![](<{}>)", &caps[1]))
+        .collect();
+    let mut assets = collect_assets(&format!("{markdown}{images}"), root, dir);
+    // links to other pages become links to chapters
+    assets.retain(|path, _| !is_html(path));
+    assets
+}
+
+/// A single html page, split into chapters at its headings
+pub struct HtmlFileImporter;
+
+impl BookImporter for HtmlFileImporter {
+    fn accepts(&self, path: &Path) -> bool {
+        path.is_file() && is_html(path)
+    }
+
+    fn import(&self, path: &Path) -> anyhow::Result<ImportedBook> {
+        let html = String::from_utf8_lossy(&fs::read(path)?).to_string();
+        let markdown = html_to_markdown(&html)?;
+        let (heading, chapters) = document_chapters(&markdown);
+        let title = html_title(&html)
+            .or(heading)
+            .unwrap_or_else(|| humanize_file_name(path));
+        let root = path.parent().unwrap_or(Path::new(""));
+        let mut book = ImportedBook::new(title, chapters);
+        book.assets = page_assets(&markdown, root, Path::new(""));
+        Ok(book)
+    }
+}
+
+/// A directory of html pages, e.g. a static site; each page becomes a chapter,
+/// in the order they are linked from the index page
+pub struct HtmlSiteImporter;
+
+fn site_pages(root: &Path) -> Vec<PathBuf> {
+    let mut pages: Vec<PathBuf> = walkdir::WalkDir::new(root)
+        .into_iter()
+        .filter_entry(|entry| {
+            entry.depth() == 0 || !entry.file_name().to_string_lossy().starts_with(['.', '_'])
+        })
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file() && is_html(entry.path()))
+        .filter_map(|entry| entry.path().strip_prefix(root).ok().map(PathBuf::from))
+        .collect();
+    pages.sort();
+    pages
+}
+
+/// the index page first, then the pages it links to, then the other pages by path
+fn order_pages(root: &Path, pages: Vec<PathBuf>) -> anyhow::Result<Vec<PathBuf>> {
+    let index = pages
+        .iter()
+        .find(|page| {
+            page.file_stem()
+                .is_some_and(|stem| stem.eq_ignore_ascii_case("index"))
+                && page.parent() == Some(Path::new(""))
+        })
+        .or(pages.first())
+        .cloned();
+    let Some(index) = index else {
+        return Ok(pages);
+    };
+    let html = String::from_utf8_lossy(&fs::read(root.join(&index))?).to_string();
+    let dir = index.parent().unwrap_or(Path::new(""));
+    let mut remaining: BTreeSet<PathBuf> = pages.into_iter().collect();
+    remaining.remove(&index);
+    let mut ordered = vec![index.clone()];
+    for caps in LINK_HREF.captures_iter(&html) {
+        if let Some(page) = normalize_path(&dir.join(&caps[1]))
+            && remaining.remove(&page)
+        {
+            ordered.push(page);
+        }
+    }
+    ordered.extend(remaining);
+    Ok(ordered)
+}
+
+impl BookImporter for HtmlSiteImporter {
+    fn accepts(&self, path: &Path) -> bool {
+        path.is_dir() && !path.join("book.toml").exists() && !site_pages(path).is_empty()
+    }
+
+    fn import(&self, path: &Path) -> anyhow::Result<ImportedBook> {
+        let pages = order_pages(path, site_pages(path))?;
+        let mut chapters = vec![];
+        let mut assets = BTreeMap::new();
+        let mut title = None;
+        for (page, i) in pages.into_iter().zip(0..) {
+            let html = String::from_utf8_lossy(&fs::read(path.join(&page))?).to_string();
+            let markdown = html_to_markdown(&html)?;
+            let dir = page.parent().unwrap_or(Path::new(""));
+            assets.extend(page_assets(&markdown, path, dir));
+            let name = match first_heading(&markdown) {
+                Some(heading) => heading,
+                None if i == 0 => "Introduction".to_string(),
+                None => html_title(&html).unwrap_or_else(|| humanize_file_name(&page)),
+            };
+            if i == 0 {
+                title = html_title(&html);
+            }
+            chapters.push(chapter(name, page.with_extension("md"), markdown));
+        }
+        let title = title.unwrap_or_else(|| humanize_file_name(path));
+        let mut book = ImportedBook::new(title, chapters);
+        book.assets = assets;
+        Ok(book)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tables_are_kept() {
+        let html = "<p>before</p><table>\n<tr><td><table><tr><td>inner</td></tr></table></td></tr>\n\n</table><p>after</p>";
+        let (content, tables) = extract_tables(html);
+        assert_eq!(
+            content,
+            format!("<p>before</p><p>{}</p><p>after</p>", table_placeholder(0))
+        );
+        assert_eq!(tables.len(), 1);
+        assert!(tables[0].ends_with("</table></td></tr>\n</table>"));
+    }
+
+    #[test]
+    fn site_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("guide/img")).unwrap();
+        fs::write(
+            root.join("index.html"),
+            "<title>Guide</title><a href=\"guide/setup.html\">Setup</a> <a href='about.html#team'>About</a>",
+        )
+        .unwrap();
+        for page in ["about.html", "appendix.html", "guide/setup.html"] {
+            fs::write(root.join(page), "<h1>Page</h1>").unwrap();
+        }
+        fs::write(root.join("guide/img/a.png"), [0u8; 4]).unwrap();
+
+        let pages = order_pages(root, site_pages(root)).unwrap();
+        assert_eq!(
+            pages,
+            [
+                "index.html",
+                "guide/setup.html",
+                "about.html",
+                "appendix.html"
+            ]
+            .map(PathBuf::from)
+        );
+        let assets = page_assets(
+            "<table><tr><td><img src=\"img/a.png\"></td></tr></table>\n\n[index](../index.html)",
+            root,
+            Path::new("guide"),
+        );
+        assert_eq!(
+            assets.keys().collect::<Vec<_>>(),
+            [Path::new("guide/img/a.png")]
+        );
+        assert_eq!(
+            html_title("<title>\n  My\n Site </title>").as_deref(),
+            Some("My Site")
+        );
+    }
+}
@@ -0,0 +1,482 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::bail;
+use pulldown_cmark::{Event, Parser, Tag, TagEnd};
+
+use super::{
+    BookImporter, ImportedBook, chapter, chapter_file_name, collect_assets, count_headings,
+    has_extension,
+    notebook::{is_notebook, read_notebook},
+    split_at_headings,
+};
+use crate::books::chapter::ChapterRaw;
+
+/// `---` delimited `key: value` front matter, returns the fields and the rest of the document
+pub fn split_front_matter(content: &str) -> (BTreeMap<String, String>, &str) {
+    let mut fields = BTreeMap::new();
+    let Some(rest) = content
+        .strip_prefix("---\n")
+        .or_else(|| content.strip_prefix("---\r\n"))
+    else {
+        return (fields, content);
+    };
+    let Some(end) = rest
+        .match_indices("\n---")
+        .map(|(i, _)| i)
+        .find(|i| matches!(rest[i + 4..].chars().next(), None | Some('\n' | '\r')))
+    else {
+        return (fields, content);
+    };
+    for line in rest[..end].lines() {
+        if let Some((key, value)) = line.split_once(':') {
+            let value = value.trim().trim_matches(|c| c == '"' || c == '\'');
+            fields.insert(key.trim().to_lowercase(), value.to_string());
+        }
+    }
+    let body = rest[end + 4..].trim_start_matches(['\r', '\n']);
+    (fields, body)
+}
+
+/// the text of the first heading of a document
+pub fn first_heading(content: &str) -> Option<String> {
+    let mut title: Option<String> = None;
+    for event in Parser::new(content) {
+        match event {
+            Event::Start(Tag::Heading { .. }) => title = Some(String::new()),
+            Event::Text(text) | Event::Code(text) => {
+                if let Some(title) = &mut title {
+                    title.push_str(&text);
+                }
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                return title
+                    .map(|title| title.trim().to_string())
+                    .filter(|title| !title.is_empty());
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// `02_getting-started.md` -> `Getting started`
+pub fn humanize_file_name(path: &Path) -> String {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let name = stem
+        .trim_start_matches(|c: char| c.is_ascii_digit())
+        .trim_start_matches(['-', '_', '.', ' '])
+        .replace(['-', '_'], " ");
+    let name = if name.trim().is_empty() { stem } else { name };
+    let mut chars = name.trim().chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// order of files: front matter `weight`/`order`, then the leading number, then the name
+/// Folders of code repositories that are not documentation
+const IGNORED_DIRS: &[&str] = &["node_modules", "target", "venv", "site-packages"];
+
+fn sort_key(path: &Path, fields: &BTreeMap<String, String>) -> (i64, u64, String) {
+    let weight = fields
+        .get("weight")
+        .or_else(|| fields.get("order"))
+        .and_then(|w| w.parse().ok())
+        .unwrap_or(i64::MAX);
+    let name = path
+        .file_name()
+        .map(|s| s.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let digits: String = name.chars().take_while(|c| c.is_ascii_digit()).collect();
+    (weight, digits.parse().unwrap_or(u64::MAX), name)
+}
+
+fn is_markdown(path: &Path) -> bool {
+    has_extension(path, &["md", "markdown"])
+}
+
+fn is_index(path: &Path) -> bool {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    is_markdown(path) && (stem == "readme" || stem == "index")
+}
+
+/// a chapter: a markdown file or a notebook
+fn is_document(path: &Path) -> bool {
+    is_markdown(path) || is_notebook(path)
+}
+
+/// hidden files and the dependencies and build output of code repositories
+fn is_hidden(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| {
+        let name = name.to_string_lossy();
+        name.starts_with(['.', '_']) || IGNORED_DIRS.contains(&name.as_ref())
+    })
+}
+
+/// A folder of markdown files and notebooks without `book.toml`, e.g. the documentation
+/// of a repository; sub folders become nested chapters
+pub struct MarkdownFolderImporter;
+
+struct FolderReader<'a> {
+    root: &'a Path,
+    assets: BTreeMap<PathBuf, super::Asset>,
+}
+
+/// a markdown file, read with its front matter, or a notebook converted to markdown
+struct Document {
+    fields: BTreeMap<String, String>,
+    name: String,
+    /// path of the chapter relative to the root
+    path: PathBuf,
+    content: String,
+}
+
+impl FolderReader<'_> {
+    fn read_document(&mut self, relative: &Path) -> anyhow::Result<Document> {
+        let content = fs::read_to_string(self.root.join(relative))?;
+        let dir = relative.parent().unwrap_or(Path::new(""));
+        if is_notebook(relative) {
+            let stem = relative.file_stem().unwrap_or_default().to_string_lossy();
+            let notebook = read_notebook(&content, &stem)?;
+            for (path, data) in notebook.images {
+                self.assets.insert(dir.join(path), super::Asset::Data(data));
+            }
+            let name = notebook
+                .title
+                .or_else(|| first_heading(&notebook.content))
+                .unwrap_or_else(|| humanize_file_name(relative));
+            return Ok(Document {
+                fields: BTreeMap::new(),
+                name,
+                path: relative.with_extension("md"),
+                content: notebook.content,
+            });
+        }
+        let (fields, body) = split_front_matter(&content);
+        let name = fields
+            .get("title")
+            .cloned()
+            .or_else(|| first_heading(body))
+            .unwrap_or_else(|| humanize_file_name(relative));
+        self.assets.extend(collect_assets(body, self.root, dir));
+        Ok(Document {
+            content: body.to_string(),
+            fields,
+            name,
+            path: relative.to_path_buf(),
+        })
+    }
+
+    /// chapters of a folder relative to the root, in reading order
+    fn read_dir(&mut self, relative: &Path) -> anyhow::Result<Vec<ChapterRaw>> {
+        let mut entries = vec![];
+        for entry in fs::read_dir(self.root.join(relative))? {
+            let path = relative.join(entry?.file_name());
+            if is_hidden(&path) {
+                continue;
+            }
+            let full_path = self.root.join(&path);
+            if full_path.is_dir() {
+                let sub_chapters = self.read_dir(&path)?;
+                let index = fs::read_dir(&full_path)?
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| path.join(entry.file_name()))
+                    .find(|p| is_index(p));
+                if sub_chapters.is_empty() && index.is_none() {
+                    continue;
+                }
+                let (fields, mut chapter) = match index {
+                    Some(index) => {
+                        let document = self.read_document(&index)?;
+                        (
+                            document.fields,
+                            chapter(document.name, index, document.content),
+                        )
+                    }
+                    // a draft chapter grouping the files of the folder
+                    None => (
+                        BTreeMap::new(),
+                        ChapterRaw {
+                            name: humanize_file_name(&path),
+                            ..Default::default()
+                        },
+                    ),
+                };
+                chapter.sub_chapters = sub_chapters;
+                entries.push((sort_key(&path, &fields), chapter));
+            } else if is_document(&path) && !is_index(&path) {
+                let file_name = path.file_name().unwrap_or_default();
+                // markdown paired with a notebook, e.g. by jupytext, has no outputs
+                let paired =
+                    is_markdown(&path) && self.root.join(path.with_extension("ipynb")).is_file();
+                if file_name.eq_ignore_ascii_case("SUMMARY.md") || paired {
+                    continue;
+                }
+                let document = self.read_document(&path)?;
+                entries.push((
+                    sort_key(&path, &document.fields),
+                    chapter(document.name, document.path, document.content),
+                ));
+            }
+        }
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(entries.into_iter().map(|(_, chapter)| chapter).collect())
+    }
+}
+
+impl BookImporter for MarkdownFolderImporter {
+    fn accepts(&self, path: &Path) -> bool {
+        path.is_dir()
+            && !path.join("book.toml").exists()
+            && walkdir::WalkDir::new(path)
+                .into_iter()
+                .filter_map(|entry| entry.ok())
+                .any(|entry| entry.file_type().is_file() && is_document(entry.path()))
+    }
+
+    fn import(&self, path: &Path) -> anyhow::Result<ImportedBook> {
+        let mut reader = FolderReader {
+            root: path,
+            assets: BTreeMap::new(),
+        };
+        let mut chapters = reader.read_dir(Path::new(""))?;
+        let mut fields = BTreeMap::new();
+        // the index of the folder is the introduction of the book
+        let index = fs::read_dir(path)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| PathBuf::from(entry.file_name()))
+            .find(|p| is_index(p));
+        if let Some(index) = index {
+            let document = reader.read_document(&index)?;
+            // its front matter describes the book, not the chapter
+            let name =
+                first_heading(&document.content).unwrap_or_else(|| "Introduction".to_string());
+            fields = document.fields;
+            chapters.insert(0, chapter(name, index, document.content));
+        }
+        if chapters.is_empty() {
+            bail!("No markdown files or notebooks in {}", path.display());
+        }
+        let title = fields
+            .get("title")
+            .cloned()
+            .unwrap_or_else(|| humanize_file_name(&path.with_extension("")));
+        let mut book = ImportedBook::new(title, chapters);
+        book.book.authors = authors(&fields);
+        book.book.description = fields.get("description").cloned();
+        book.assets = reader.assets;
+        Ok(book)
+    }
+}
+
+fn authors(fields: &BTreeMap<String, String>) -> Vec<String> {
+    fields
+        .get("authors")
+        .or_else(|| fields.get("author"))
+        .map(|authors| {
+            authors
+                .trim_matches(['[', ']'])
+                .split([',', ';'])
+                .map(|author| author.trim().trim_matches(['"', '\'']).to_string())
+                .filter(|author| !author.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// A single markdown file split into chapters at its top level headings
+pub struct MarkdownFileImporter;
+
+/// split a document into chapters, a single `#` heading is the title of the book
+/// and the next level is used for the chapters
+pub fn split_document(content: &str) -> (Option<String>, String, Vec<super::Section>) {
+    let counts = count_headings(content);
+    let Some(top) = (1..=6).find(|level| counts[*level] > 0) else {
+        return (None, content.to_string(), vec![]);
+    };
+    let next_level = (top + 1..=6).find(|level| counts[*level] > 0);
+    if let (1, Some(level)) = (counts[top], next_level) {
+        let (preamble, mut title) = split_at_headings(content, top);
+        let title_section = title.remove(0);
+        let (intro, sections) = split_at_headings(&title_section.content, level);
+        // drop the title heading from the introduction
+        let intro = intro
+            .split_once('\n')
+            .map(|(_, rest)| rest)
+            .unwrap_or_default();
+        return (
+            Some(title_section.title),
+            format!("{preamble}{intro}"),
+            sections,
+        );
+    }
+    let (preamble, sections) = split_at_headings(content, top);
+    (None, preamble, sections)
+}
+
+/// chapters of a single markdown document and its title heading, see `split_document`
+pub fn document_chapters(content: &str) -> (Option<String>, Vec<ChapterRaw>) {
+    let (heading, preamble, sections) = split_document(content);
+    let mut chapters = vec![];
+    if !preamble.trim().is_empty() {
+        chapters.push(chapter(
+            "Introduction".to_string(),
+            chapter_file_name(0, "introduction"),
+            preamble.trim().to_string(),
+        ));
+    }
+    for (section, i) in sections.into_iter().zip(1..) {
+        let path = chapter_file_name(i, &section.title);
+        chapters.push(chapter(section.title, path, section.content));
+    }
+    (heading, chapters)
+}
+
+impl BookImporter for MarkdownFileImporter {
+    fn accepts(&self, path: &Path) -> bool {
+        path.is_file() && is_markdown(path)
+    }
+
+    fn import(&self, path: &Path) -> anyhow::Result<ImportedBook> {
+        let content = fs::read_to_string(path)?;
+        let (fields, body) = split_front_matter(&content);
+        let (heading, chapters) = document_chapters(body);
+        let title = fields
+            .get("title")
+            .cloned()
+            .or(heading)
+            .unwrap_or_else(|| humanize_file_name(path));
+        let root = path.parent().unwrap_or(Path::new(""));
+        let assets = collect_assets(body, root, Path::new(""));
+        let mut book = ImportedBook::new(title, chapters);
+        book.book.authors = authors(&fields);
+        book.book.description = fields.get("description").cloned();
+        book.assets = assets;
+        Ok(book)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn front_matter() {
+        let (fields, body) = split_front_matter("---\ntitle: \"Intro\"\nweight: 2\n---\n\n# Hi");
+        assert_eq!(fields["title"], "Intro");
+        assert_eq!(fields["weight"], "2");
+        assert_eq!(body, "# Hi");
+        let (fields, body) = split_front_matter("# No front matter");
+        assert!(fields.is_empty());
+        assert_eq!(body, "# No front matter");
+    }
+
+    #[test]
+    fn file_names() {
+        assert_eq!(
+            humanize_file_name(Path::new("02_getting-started.md")),
+            "Getting started"
+        );
+        assert_eq!(humanize_file_name(Path::new("2024.md")), "2024");
+        assert_eq!(
+            chapter_file_name(3, "What's new? (2.0)"),
+            PathBuf::from("03-what-s-new-2-0.md")
+        );
+    }
+
+    #[test]
+    fn split_single_document() {
+        let content = "# My Book\n\nWelcome.\n\n## One\n\ntext\n\n```\n## not a heading\n```\n\n## Two\n\n### Detail\n";
+        let (title, intro, sections) = split_document(content);
+        assert_eq!(title.as_deref(), Some("My Book"));
+        assert_eq!(intro.trim(), "Welcome.");
+        let titles: Vec<_> = sections.iter().map(|s| s.title.as_str()).collect();
+        assert_eq!(titles, ["One", "Two"]);
+        assert!(sections[1].content.contains("### Detail"));
+
+        let (title, _, sections) = split_document("# A\n\n# B\n\n## B.1\n");
+        assert!(title.is_none());
+        assert_eq!(sections.len(), 2);
+    }
+
+    #[test]
+    fn import_folder() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("rust-notes");
+        fs::create_dir_all(root.join("02-basics/img")).unwrap();
+        fs::write(
+            root.join("README.md"),
+            "---\ntitle: Rust Notes\nauthor: Ann, Bob\n---\n# Welcome",
+        )
+        .unwrap();
+        fs::write(root.join("01-setup.md"), "# Setup\n\nInstall it.").unwrap();
+        fs::write(root.join("02-basics/index.md"), "# Basics").unwrap();
+        fs::write(
+            root.join("02-basics/10-types.md"),
+            "---\ntitle: Types\n---\n![diagram](img/types.png)",
+        )
+        .unwrap();
+        fs::write(root.join("02-basics/2-variables.md"), "# Variables").unwrap();
+        fs::write(root.join("02-basics/img/types.png"), [0u8; 4]).unwrap();
+        fs::write(
+            root.join("02-basics/3-loops.ipynb"),
+            r##"{"metadata": {}, "cells": [{"cell_type": "markdown", "source": "# Loops"}]}"##,
+        )
+        .unwrap();
+        fs::write(root.join("02-basics/3-loops.md"), "# Paired copy").unwrap();
+        fs::create_dir_all(root.join("node_modules/pkg")).unwrap();
+        fs::write(root.join("node_modules/pkg/README.md"), "# Package").unwrap();
+
+        let importer = MarkdownFolderImporter;
+        assert!(importer.accepts(&root));
+        let book = importer.import(&root).unwrap();
+        assert_eq!(book.book.title, "Rust Notes");
+        assert_eq!(book.book.authors, ["Ann", "Bob"]);
+        let names: Vec<_> = book
+            .book
+            .chapters
+            .values()
+            .map(|ch| ch.name.as_str())
+            .collect();
+        assert_eq!(names, ["Welcome", "Setup", "Basics"]);
+        let basics = &book.book.chapters[&"3.".parse().unwrap()];
+        let names: Vec<_> = basics
+            .sub_chapters
+            .iter()
+            .map(|ch| ch.name.as_str())
+            .collect();
+        assert_eq!(names, ["Variables", "Loops", "Types"]);
+        assert_eq!(
+            basics.sub_chapters[1].path.as_deref(),
+            Some(Path::new("02-basics/3-loops.md"))
+        );
+        assert_eq!(basics.sub_chapters[2].number.to_string(), "3.3.");
+        assert!(
+            book.assets
+                .contains_key(Path::new("02-basics/img/types.png"))
+        );
+
+        let output = dir.path().join("output");
+        book.write_mdbook(&output).unwrap();
+        let build_config = mdbook::config::BuildConfig {
+            build_dir: PathBuf::from(""),
+            create_missing: false,
+            use_default_preprocessors: true,
+            extra_watch_dirs: vec![],
+        };
+        let loaded = mdbook::book::load_book(output.join("src"), &build_config).unwrap();
+        assert_eq!(loaded.iter().count(), 6);
+        assert!(output.join("src/02-basics/img/types.png").is_file());
+    }
+}
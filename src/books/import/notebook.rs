@@ -0,0 +1,349 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::{
+    Asset, BookImporter, ImportedBook, has_extension,
+    markdown::{document_chapters, humanize_file_name},
+};
+
+/// colors of tracebacks
+static ANSI_ESCAPE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\x1b\[[0-9;]*[A-Za-z]").unwrap());
+
+/// Images of rich outputs, in order of preference
+const IMAGE_TYPES: &[(&str, &str)] = &[
+    ("image/png", "png"),
+    ("image/jpeg", "jpg"),
+    ("image/gif", "gif"),
+    ("image/webp", "webp"),
+    ("image/svg+xml", "svg"),
+];
+
+pub fn is_notebook(path: &Path) -> bool {
+    has_extension(path, &["ipynb"])
+}
+
+/// a string, or a list of lines in the nbformat
+fn text_of(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => Some(text.clone()),
+        Value::Array(lines) => lines.iter().map(|line| line.as_str()).collect(),
+        _ => None,
+    }
+}
+
+fn multiline<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Ok(text_of(&Value::deserialize(deserializer)?).unwrap_or_default())
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "output_type", rename_all = "snake_case")]
+enum Output {
+    /// stdout or stderr
+    Stream {
+        #[serde(deserialize_with = "multiline")]
+        text: String,
+    },
+    ExecuteResult {
+        data: BTreeMap<String, Value>,
+    },
+    DisplayData {
+        data: BTreeMap<String, Value>,
+    },
+    Error {
+        ename: String,
+        evalue: String,
+        #[serde(default)]
+        traceback: Vec<String>,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+struct Cell {
+    cell_type: String,
+    #[serde(default, deserialize_with = "multiline")]
+    source: String,
+    #[serde(default)]
+    metadata: Value,
+    #[serde(default)]
+    outputs: Vec<Output>,
+    /// file name -> mime type -> base64 data, referenced as `attachment:<name>`
+    #[serde(default)]
+    attachments: BTreeMap<String, BTreeMap<String, Value>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NotebookFile {
+    cells: Vec<Cell>,
+    #[serde(default)]
+    metadata: Value,
+}
+
+/// A notebook converted to markdown
+#[derive(Debug, Default)]
+pub struct Notebook {
+    pub title: Option<String>,
+    pub authors: Vec<String>,
+    pub content: String,
+    /// images of outputs and attachments, by their path relative to the chapter
+    pub images: BTreeMap<PathBuf, Vec<u8>>,
+}
+
+/// a fenced code block that is not closed by the code inside it
+fn fenced(language: &str, code: &str) -> String {
+    let fence = if code.contains("```") { "~~~~" } else { "```" };
+    format!("{fence}{language}\n{}\n{fence}", code.trim_end())
+}
+
+struct NotebookWriter<'a> {
+    /// file stem of the notebook, used to name its images
+    stem: &'a str,
+    notebook: Notebook,
+    blocks: Vec<String>,
+}
+
+impl NotebookWriter<'_> {
+    fn image(&mut self, name: String, data: Vec<u8>) -> PathBuf {
+        let path = Path::new("images").join(format!("{}-{}", self.stem, name));
+        self.notebook.images.insert(path.clone(), data);
+        path
+    }
+
+    fn markdown_cell(&mut self, index: usize, cell: &Cell) {
+        let mut source = cell.source.clone();
+        for (name, data) in &cell.attachments {
+            let Some(data) = data
+                .values()
+                .find_map(text_of)
+                .and_then(|data| STANDARD.decode(data.replace('\n', "")).ok())
+            else {
+                continue;
+            };
+            let path = self.image(format!("{index}-{name}"), data);
+            source = source.replace(&format!("attachment:{name}"), &path.to_string_lossy());
+        }
+        self.blocks.push(source);
+    }
+
+    /// the richest representation of an output: an image, markdown, an html table or text
+    fn rich_output(&mut self, index: usize, data: &BTreeMap<String, Value>) {
+        for (mime, extension) in IMAGE_TYPES {
+            let Some(image) = data.get(*mime).and_then(text_of) else {
+                continue;
+            };
+            let image = if *mime == "image/svg+xml" {
+                Some(image.into_bytes())
+            } else {
+                STANDARD.decode(image.replace('\n', "")).ok()
+            };
+            if let Some(image) = image {
+                let number = self.notebook.images.len() + 1;
+                let path = self.image(format!("{index}-{number}.{extension}"), image);
+                self.blocks
+                    .push(format!("![output](<{}>)", path.to_string_lossy()));
+                return;
+            }
+        }
+        if let Some(markdown) = data.get("text/markdown").and_then(text_of) {
+            self.blocks.push(markdown);
+        } else if let Some(html) = data
+            .get("text/html")
+            .and_then(text_of)
+            .filter(|html| html.contains("<table"))
+        {
+            // data frames, blank lines would end the html block
+            let lines: Vec<&str> = html
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .collect();
+            self.blocks.push(lines.join("\n"));
+        } else if let Some(text) = data.get("text/plain").and_then(text_of) {
+            self.blocks.push(fenced("text", &text));
+        }
+    }
+
+    fn code_cell(&mut self, index: usize, cell: &Cell, language: &str) {
+        if !cell.source.trim().is_empty() {
+            let language = cell
+                .metadata
+                .pointer("/vscode/languageId")
+                .and_then(Value::as_str)
+                .unwrap_or(language);
+            self.blocks.push(fenced(language, &cell.source));
+        }
+        // consecutive stream outputs are printed as one block
+        let mut stream = String::new();
+        for output in &cell.outputs {
+            if let Output::Stream { text, .. } = output {
+                stream.push_str(text);
+                continue;
+            }
+            if !stream.is_empty() {
+                self.blocks
+                    .push(fenced("text", &std::mem::take(&mut stream)));
+            }
+            match output {
+                Output::ExecuteResult { data } | Output::DisplayData { data } => {
+                    self.rich_output(index, data)
+                }
+                Output::Error {
+                    ename,
+                    evalue,
+                    traceback,
+                } => {
+                    let text = if traceback.is_empty() {
+                        format!("{ename}: {evalue}")
+                    } else {
+                        ANSI_ESCAPE
+                            .replace_all(&traceback.join("\n"), "")
+                            .to_string()
+                    };
+                    self.blocks.push(fenced("text", &text));
+                }
+                Output::Stream { .. } | Output::Other => {}
+            }
+        }
+        if !stream.is_empty() {
+            self.blocks.push(fenced("text", &stream));
+        }
+    }
+}
+
+/// convert the cells of a notebook in order, markdown cells as they are and code cells
+/// as code blocks in the language of the kernel followed by their outputs
+pub fn read_notebook(json: &str, stem: &str) -> anyhow::Result<Notebook> {
+    let file: NotebookFile = serde_json::from_str(json)?;
+    let metadata = &file.metadata;
+    let language = ["/kernelspec/language", "/language_info/name"]
+        .iter()
+        .find_map(|pointer| metadata.pointer(pointer).and_then(Value::as_str))
+        .unwrap_or("python")
+        .to_lowercase();
+    let mut writer = NotebookWriter {
+        stem,
+        notebook: Notebook {
+            title: metadata
+                .get("title")
+                .and_then(Value::as_str)
+                .map(String::from),
+            authors: metadata
+                .get("authors")
+                .and_then(Value::as_array)
+                .map(|authors| {
+                    authors
+                        .iter()
+                        .filter_map(|author| author.get("name").or(Some(author))?.as_str())
+                        .map(String::from)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            ..Default::default()
+        },
+        blocks: vec![],
+    };
+    for (cell, index) in file.cells.iter().zip(1..) {
+        match cell.cell_type.as_str() {
+            "markdown" => writer.markdown_cell(index, cell),
+            "code" => writer.code_cell(index, cell, &language),
+            _ => {}
+        }
+    }
+    writer.blocks.retain(|block| !block.trim().is_empty());
+    writer.notebook.content = writer.blocks.join("\n\n") + "\n";
+    Ok(writer.notebook)
+}
+
+/// A single Jupyter notebook, split into chapters at its headings
+pub struct NotebookImporter;
+
+impl BookImporter for NotebookImporter {
+    fn accepts(&self, path: &Path) -> bool {
+        path.is_file() && is_notebook(path)
+    }
+
+    fn import(&self, path: &Path) -> anyhow::Result<ImportedBook> {
+        let stem = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let notebook = read_notebook(&fs::read_to_string(path)?, &stem)?;
+        let (heading, chapters) = document_chapters(&notebook.content);
+        let title = notebook
+            .title
+            .or(heading)
+            .unwrap_or_else(|| humanize_file_name(path));
+        let mut book = ImportedBook::new(title, chapters);
+        book.book.authors = notebook.authors;
+        book.assets = notebook
+            .images
+            .into_iter()
+            .map(|(path, data)| (path, Asset::Data(data)))
+            .collect();
+        Ok(book)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOTEBOOK: &str = r##"{
+        "metadata": {
+            "kernelspec": {"display_name": "Python 3", "language": "python", "name": "python3"},
+            "authors": [{"name": "Ada"}]
+        },
+        "nbformat": 4,
+        "nbformat_minor": 5,
+        "cells": [
+            {"cell_type": "markdown", "metadata": {}, "source": ["# Loops\n", "\n", "![plot](attachment:plot.png)"],
+             "attachments": {"plot.png": {"image/png": "iVBORw0KGgo="}}},
+            {"cell_type": "code", "metadata": {}, "execution_count": 1, "source": "for i in range(2):\n    print(i)",
+             "outputs": [
+                {"output_type": "stream", "name": "stdout", "text": ["0\n"]},
+                {"output_type": "stream", "name": "stdout", "text": ["1\n"]},
+                {"output_type": "execute_result", "execution_count": 1, "metadata": {},
+                 "data": {"text/plain": ["'done'"]}}
+             ]},
+            {"cell_type": "code", "metadata": {"vscode": {"languageId": "shellscript"}}, "source": ["ls"], "outputs": []},
+            {"cell_type": "raw", "metadata": {}, "source": "ignored"},
+            {"cell_type": "code", "metadata": {}, "source": "1/0", "outputs": [
+                {"output_type": "error", "ename": "ZeroDivisionError", "evalue": "division by zero",
+                 "traceback": ["\u001b[0;31mZeroDivisionError\u001b[0m: division by zero"]},
+                {"output_type": "display_data", "metadata": {}, "data": {"image/png": "iVBORw0KGgo=", "text/plain": "<Figure>"}}
+            ]}
+        ]
+    }"##;
+
+    #[test]
+    fn notebook_cells() {
+        let notebook = read_notebook(NOTEBOOK, "loops").unwrap();
+        assert_eq!(notebook.authors, ["Ada"]);
+        assert_eq!(
+            notebook.content,
+            "# Loops\n\n![plot](images/loops-1-plot.png)\n\n\
+            ```python\nfor i in range(2):\n    print(i)\n```\n\n\
+            ```text\n0\n1\n```\n\n```text\n'done'\n```\n\n\
+            ```shellscript\nls\n```\n\n```python\n1/0\n```\n\n\
+            ```text\nZeroDivisionError: division by zero\n```\n\n\
+            ![output](<images/loops-5-2.png>)\n"
+        );
+        assert_eq!(
+            notebook.images.keys().collect::<Vec<_>>(),
+            [
+                Path::new("images/loops-1-plot.png"),
+                Path::new("images/loops-5-2.png")
+            ]
+        );
+    }
+}
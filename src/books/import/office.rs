@@ -0,0 +1,530 @@
+pub mod docx;
+pub mod odt;
+
+use std::{
+    collections::BTreeMap,
+    io::{Read, Seek},
+    path::{Path, PathBuf},
+};
+
+use xml::{attribute::OwnedAttribute, reader::XmlEvent};
+use zip::{ZipArchive, result::ZipError};
+
+use super::{Asset, ImportedBook, markdown::document_chapters};
+
+/// Fonts whose runs are written as code
+const MONOSPACE_FONTS: &[&str] = &[
+    "mono",
+    "courier",
+    "consolas",
+    "menlo",
+    "monaco",
+    "lucida console",
+    "source code",
+    "fira code",
+    "inconsolata",
+];
+
+pub fn is_monospace_font(name: &str) -> bool {
+    let name = name.to_lowercase();
+    MONOSPACE_FONTS.iter().any(|font| name.contains(font))
+}
+
+/// whether a style name is used for code, e.g. `Source Code` or `HTML Preformatted`
+pub fn is_code_style(name: &str) -> bool {
+    let name = name.to_lowercase();
+    ["code", "source", "preformatted", "verbatim", "listing"]
+        .iter()
+        .any(|word| name.contains(word))
+}
+
+/// value of the attribute with the local name `name`
+pub fn attribute<'a>(attributes: &'a [OwnedAttribute], name: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|attribute| attribute.name.local_name == name)
+        .map(|attribute| attribute.value.as_str())
+}
+
+/// an entry of a zip archive, `None` if it does not exist
+pub fn read_entry<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    name: &str,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    match archive.by_name(name) {
+        Ok(mut file) => {
+            let mut data = vec![];
+            file.read_to_end(&mut data)?;
+            Ok(Some(data))
+        }
+        Err(ZipError::FileNotFound) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// text of the elements named by `fields` (local names), e.g. `title` and `creator`
+/// of the Dublin Core metadata
+pub fn read_metadata(xml: &[u8], fields: &[&str]) -> anyhow::Result<BTreeMap<String, String>> {
+    let mut metadata = BTreeMap::new();
+    let mut current: Option<String> = None;
+    for event in xml::EventReader::new(xml) {
+        match event? {
+            XmlEvent::StartElement { name, .. } if fields.contains(&name.local_name.as_str()) => {
+                current = Some(name.local_name);
+            }
+            XmlEvent::Characters(text) => {
+                if let Some(field) = &current {
+                    let text = text.trim();
+                    if !text.is_empty() {
+                        metadata.entry(field.clone()).or_insert(text.to_string());
+                    }
+                }
+            }
+            XmlEvent::EndElement { .. } => current = None,
+            _ => {}
+        }
+    }
+    Ok(metadata)
+}
+
+/// escape the characters with a meaning in inline markdown
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(
+            c,
+            '\\' | '*' | '_' | '`' | '[' | ']' | '<' | '>' | '#' | '|'
+        ) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Formatting of a run of text
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RunStyle {
+    pub bold: bool,
+    pub italic: bool,
+    pub code: bool,
+}
+
+impl RunStyle {
+    /// `self` with the formatting of `other` added
+    pub fn with(self, other: RunStyle) -> Self {
+        Self {
+            bold: self.bold || other.bold,
+            italic: self.italic || other.italic,
+            code: self.code || other.code,
+        }
+    }
+}
+
+/// The inline markdown of a paragraph, built run by run
+#[derive(Debug, Default)]
+pub struct Inline {
+    markdown: String,
+    /// text without formatting, used for headings and code blocks
+    plain: String,
+    /// text of the runs with the same formatting, not yet written
+    pending: (RunStyle, String),
+    /// (offset in `markdown`, url) of the open links
+    links: Vec<(usize, Option<String>)>,
+    code_chars: usize,
+    text_chars: usize,
+    images: usize,
+}
+
+impl Inline {
+    pub fn text(&mut self, text: &str, style: RunStyle) {
+        if text.is_empty() {
+            return;
+        }
+        if self.pending.0 != style {
+            self.flush();
+            self.pending.0 = style;
+        }
+        self.pending.1.push_str(text);
+        self.plain.push_str(text);
+        let chars = text.chars().filter(|c| !c.is_whitespace()).count();
+        if style.code {
+            self.code_chars += chars;
+        } else {
+            self.text_chars += chars;
+        }
+    }
+
+    fn flush(&mut self) {
+        let (style, text) = std::mem::take(&mut self.pending);
+        // formatting markers have to be next to the text, not to whitespace
+        let trimmed = text.trim();
+        if trimmed.is_empty() {
+            self.markdown.push_str(&text.replace('\n', "  \n"));
+            return;
+        }
+        let start = text.len() - text.trim_start().len();
+        let end = start + trimmed.len();
+        let mut formatted = if style.code {
+            let ticks = if trimmed.contains('`') { "`` " } else { "`" };
+            format!(
+                "{ticks}{trimmed}{}",
+                ticks.chars().rev().collect::<String>()
+            )
+        } else {
+            escape(trimmed).replace('\n', "  \n")
+        };
+        if style.italic && !style.code {
+            formatted = format!("*{formatted}*");
+        }
+        if style.bold && !style.code {
+            formatted = format!("**{formatted}**");
+        }
+        self.markdown.push_str(&text[..start]);
+        self.markdown.push_str(&formatted);
+        self.markdown.push_str(&text[end..]);
+    }
+
+    pub fn image(&mut self, alt: &str, path: &Path) {
+        self.flush();
+        self.images += 1;
+        self.markdown.push_str(&format!(
+            "![{}](<{}>)",
+            escape(alt.trim()),
+            path.to_string_lossy()
+        ));
+    }
+
+    pub fn start_link(&mut self, url: Option<String>) {
+        self.flush();
+        self.links.push((self.markdown.len(), url));
+    }
+
+    pub fn end_link(&mut self) {
+        self.flush();
+        if let Some((start, Some(url))) = self.links.pop()
+            && !self.markdown[start..].trim().is_empty()
+        {
+            let text = self.markdown.split_off(start);
+            let leading = &text[..text.len() - text.trim_start().len()];
+            let trailing = &text[text.trim_end().len()..];
+            self.markdown
+                .push_str(&format!("{leading}[{}](<{url}>){trailing}", text.trim()));
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plain.trim().is_empty() && self.images == 0
+    }
+
+    /// every character is in a code font, e.g. a line of a code listing
+    pub fn is_code(&self) -> bool {
+        self.code_chars > 0 && self.text_chars == 0 && self.images == 0
+    }
+
+    pub fn plain(&self) -> String {
+        self.plain.trim().to_string()
+    }
+
+    pub fn markdown(mut self) -> String {
+        self.flush();
+        while !self.links.is_empty() {
+            self.end_link();
+        }
+        self.markdown.trim().to_string()
+    }
+}
+
+/// A block of a converted document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Block {
+    Heading(usize, String),
+    Paragraph(String),
+    /// (depth, markdown)
+    ListItem(usize, String),
+    /// a line of code, consecutive lines form one code block
+    Code(String),
+    Table(Vec<Vec<String>>),
+}
+
+impl Block {
+    /// the block as a paragraph of inline markdown
+    fn inline(&self) -> String {
+        match self {
+            Block::Heading(_, text) => format!("**{}**", escape(text)),
+            Block::Paragraph(text) => text.clone(),
+            Block::ListItem(_, text) => format!("• {text}"),
+            Block::Code(code) => format!("`{}`", code.replace('`', "'")),
+            Block::Table(rows) => rows
+                .iter()
+                .map(|row| row.join(" / "))
+                .collect::<Vec<_>>()
+                .join("<br>"),
+        }
+    }
+}
+
+/// a paragraph that does not start a list, heading or quote
+fn escape_paragraph_start(text: &str) -> String {
+    let numbered = text
+        .split_once(['.', ')'])
+        .is_some_and(|(n, _)| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()));
+    if numbered || text.starts_with(['-', '+', '=']) {
+        format!("\\{text}")
+    } else {
+        text.to_string()
+    }
+}
+
+fn table_markdown(rows: &[Vec<String>]) -> String {
+    let columns = rows.iter().map(Vec::len).max().unwrap_or_default();
+    let row = |cells: &[String]| {
+        let cells: Vec<String> = (0..columns)
+            .map(|i| {
+                cells
+                    .get(i)
+                    .map(|cell| cell.trim().replace('\n', "<br>"))
+                    .unwrap_or_default()
+            })
+            .collect();
+        format!("| {} |", cells.join(" | "))
+    };
+    let mut lines = vec![row(&rows[0]), format!("|{}", " --- |".repeat(columns))];
+    lines.extend(rows[1..].iter().map(|cells| row(cells)));
+    lines.join("\n")
+}
+
+/// Blocks of a document read from XML, tables are built cell by cell
+#[derive(Debug, Default)]
+pub struct DocumentBuilder {
+    /// the paragraph with the title style
+    pub title: Option<String>,
+    blocks: Vec<Block>,
+    /// rows of the tables being read, a nested table is flattened into a cell of its parent
+    tables: Vec<Vec<Vec<String>>>,
+}
+
+impl DocumentBuilder {
+    pub fn push(&mut self, block: Block) {
+        let Some(table) = self.tables.last_mut() else {
+            self.blocks.push(block);
+            return;
+        };
+        if table.last().is_none_or(Vec::is_empty) {
+            self.start_cell();
+        }
+        if let Some(cell) = self
+            .tables
+            .last_mut()
+            .and_then(|table| table.last_mut()?.last_mut())
+        {
+            if !cell.is_empty() {
+                cell.push_str("<br>");
+            }
+            cell.push_str(&block.inline());
+        }
+    }
+
+    /// add an inline paragraph, `heading` and `list` are the heading level and list depth
+    pub fn paragraph(
+        &mut self,
+        inline: Inline,
+        heading: Option<usize>,
+        list: Option<usize>,
+        code: bool,
+    ) {
+        if inline.is_empty() {
+            return;
+        }
+        let block = if let Some(level) = heading {
+            Block::Heading(level.clamp(1, 6), inline.plain())
+        } else if code || inline.is_code() {
+            Block::Code(inline.plain.trim_end().to_string())
+        } else if let Some(depth) = list {
+            Block::ListItem(depth, inline.markdown())
+        } else {
+            Block::Paragraph(inline.markdown())
+        };
+        self.push(block);
+    }
+
+    pub fn start_table(&mut self) {
+        self.tables.push(vec![]);
+    }
+
+    pub fn start_row(&mut self) {
+        if let Some(table) = self.tables.last_mut() {
+            table.push(vec![]);
+        }
+    }
+
+    pub fn start_cell(&mut self) {
+        if let Some(table) = self.tables.last_mut() {
+            if table.is_empty() {
+                table.push(vec![]);
+            }
+            if let Some(row) = table.last_mut() {
+                row.push(String::new());
+            }
+        }
+    }
+
+    pub fn end_table(&mut self) {
+        let Some(mut rows) = self.tables.pop() else {
+            return;
+        };
+        rows.retain(|row| !row.is_empty());
+        if !rows.is_empty() {
+            self.push(Block::Table(rows));
+        }
+    }
+
+    pub fn markdown(&self) -> String {
+        let mut markdown = String::new();
+        let mut previous: Option<&Block> = None;
+        for block in &self.blocks {
+            match (previous, block) {
+                (Some(Block::Code(_)), Block::Code(_))
+                | (Some(Block::ListItem(..)), Block::ListItem(..)) => markdown.push('\n'),
+                (Some(Block::Code(_)), _) => markdown.push_str("\n```\n\n"),
+                (Some(_), _) => markdown.push_str("\n\n"),
+                (None, _) => {}
+            }
+            match block {
+                Block::Heading(level, text) => {
+                    markdown.push_str(&format!("{} {}", "#".repeat(*level), escape(text)))
+                }
+                Block::Paragraph(text) => markdown.push_str(&escape_paragraph_start(text)),
+                Block::ListItem(depth, text) => {
+                    markdown.push_str(&format!("{}- {}", "  ".repeat(*depth), text))
+                }
+                Block::Code(code) => {
+                    if !matches!(previous, Some(Block::Code(_))) {
+                        markdown.push_str("```\n");
+                    }
+                    markdown.push_str(&code.replace("```", "` ` `"));
+                }
+                Block::Table(rows) => markdown.push_str(&table_markdown(rows)),
+            }
+            previous = Some(block);
+        }
+        if matches!(previous, Some(Block::Code(_))) {
+            markdown.push_str("\n```");
+        }
+        markdown.push('\n');
+        markdown
+    }
+}
+
+/// A document converted from an office format
+#[derive(Debug, Default)]
+pub struct Document {
+    pub builder: DocumentBuilder,
+    /// the Dublin Core `title`, `creator` and `description`
+    pub metadata: BTreeMap<String, String>,
+    /// images extracted from the archive, by their path in the book
+    pub images: BTreeMap<PathBuf, Vec<u8>>,
+}
+
+impl Document {
+    /// path of an image of the archive in the book, `images/<file name>`
+    pub fn add_image(&mut self, name: &str, data: Vec<u8>) -> PathBuf {
+        let file_name = Path::new(name)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| format!("image{}", self.images.len() + 1));
+        let path = Path::new("images").join(file_name);
+        self.images.insert(path.clone(), data);
+        path
+    }
+
+    /// split at the headings into chapters, the title is taken from the metadata,
+    /// the title paragraph, the only top level heading or the file name
+    pub fn into_book(self, path: &Path) -> ImportedBook {
+        let (heading, chapters) = document_chapters(&self.builder.markdown());
+        let title = self
+            .metadata
+            .get("title")
+            .cloned()
+            .or(self.builder.title)
+            .or(heading)
+            .unwrap_or_else(|| super::markdown::humanize_file_name(path));
+        let mut book = ImportedBook::new(title, chapters);
+        book.book.authors = self
+            .metadata
+            .get("creator")
+            .or(self.metadata.get("initial-creator"))
+            .map(|authors| {
+                authors
+                    .split(';')
+                    .map(|author| author.trim().to_string())
+                    .filter(|author| !author.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        book.book.description = self.metadata.get("description").cloned();
+        book.assets = self
+            .images
+            .into_iter()
+            .map(|(path, data)| (path, Asset::Data(data)))
+            .collect();
+        book
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inline_runs() {
+        let bold = RunStyle {
+            bold: true,
+            ..Default::default()
+        };
+        let code = RunStyle {
+            code: true,
+            ..Default::default()
+        };
+        let mut inline = Inline::default();
+        inline.text("Run ", RunStyle::default());
+        inline.text("car", bold);
+        inline.text("go ", bold);
+        inline.start_link(Some("https://doc.rust-lang.org".to_string()));
+        inline.text("build", code);
+        inline.end_link();
+        inline.text(" [now]", RunStyle::default());
+        assert!(!inline.is_code());
+        assert_eq!(
+            inline.markdown(),
+            "Run **cargo** [`build`](<https://doc.rust-lang.org>) \\[now\\]"
+        );
+    }
+
+    #[test]
+    fn blocks_to_markdown() {
+        let mut builder = DocumentBuilder::default();
+        builder.push(Block::Heading(1, "Intro".to_string()));
+        builder.push(Block::Paragraph("1. not a list".to_string()));
+        builder.push(Block::Code("fn main() {".to_string()));
+        builder.push(Block::Code("}".to_string()));
+        builder.push(Block::ListItem(0, "a".to_string()));
+        builder.push(Block::ListItem(1, "b".to_string()));
+        builder.start_table();
+        builder.start_row();
+        builder.start_cell();
+        builder.push(Block::Paragraph("Name".to_string()));
+        builder.start_cell();
+        builder.push(Block::Paragraph("Value".to_string()));
+        builder.start_row();
+        builder.start_cell();
+        builder.push(Block::Paragraph("x".to_string()));
+        builder.push(Block::Paragraph("y".to_string()));
+        builder.end_table();
+        assert_eq!(
+            builder.markdown(),
+            "# Intro\n\n\\1. not a list\n\n```\nfn main() {\n}\n```\n\n- a\n  - b\n\n\
+            | Name | Value |\n| --- | --- |\n| x<br>y |  |\n"
+        );
+    }
+}
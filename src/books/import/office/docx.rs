@@ -0,0 +1,415 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{Read, Seek},
+    path::Path,
+};
+
+use xml::reader::XmlEvent;
+use zip::ZipArchive;
+
+use super::{
+    Document, Inline, RunStyle, attribute, is_code_style, is_monospace_font, read_entry,
+    read_metadata,
+};
+use crate::books::{
+    import::{BookImporter, ImportedBook, has_extension},
+    render::normalize_path,
+};
+
+/// Word documents (Office Open XML)
+pub struct DocxImporter;
+
+/// What a paragraph or character style is used for
+#[derive(Debug, Clone, Default)]
+struct Style {
+    heading: Option<usize>,
+    title: bool,
+    code: bool,
+}
+
+impl Style {
+    /// the style guessed from its id or name
+    fn from_name(name: &str) -> Self {
+        Self {
+            heading: heading_of_name(name),
+            title: name.eq_ignore_ascii_case("title"),
+            code: is_code_style(name),
+        }
+    }
+}
+
+/// heading level of a style name like `heading 2` or a style id like `Heading2`
+fn heading_of_name(name: &str) -> Option<usize> {
+    let name = name.to_lowercase().replace(' ', "");
+    name.strip_prefix("heading")?
+        .parse()
+        .ok()
+        .filter(|level| (1..=6).contains(level))
+}
+
+fn read_styles(xml: &[u8]) -> anyhow::Result<BTreeMap<String, Style>> {
+    let mut styles = BTreeMap::new();
+    let mut current: Option<(String, Style)> = None;
+    for event in xml::EventReader::new(xml) {
+        match event? {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => match name.local_name.as_str() {
+                "style" => {
+                    let id = attribute(&attributes, "styleId").unwrap_or_default();
+                    current = Some((id.to_string(), Style::from_name(id)));
+                }
+                "name" => {
+                    if let (Some((_, style)), Some(name)) =
+                        (&mut current, attribute(&attributes, "val"))
+                    {
+                        style.heading = style.heading.or(heading_of_name(name));
+                        style.title |= name.eq_ignore_ascii_case("title");
+                        style.code |= is_code_style(name);
+                    }
+                }
+                "outlineLvl" => {
+                    let level = attribute(&attributes, "val").and_then(|val| val.parse().ok());
+                    if let (Some((_, style)), Some(level @ 0..6)) = (&mut current, level) {
+                        style.heading = Some(level + 1);
+                    }
+                }
+                "rFonts" => {
+                    if let (Some((_, style)), Some(font)) =
+                        (&mut current, attribute(&attributes, "ascii"))
+                    {
+                        style.code |= is_monospace_font(font);
+                    }
+                }
+                _ => {}
+            },
+            XmlEvent::EndElement { name } if name.local_name == "style" => {
+                if let Some((id, style)) = current.take() {
+                    styles.insert(id, style);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(styles)
+}
+
+/// relationship id -> target, e.g. `rId5` -> `media/image1.png`
+fn read_relationships(xml: &[u8]) -> anyhow::Result<BTreeMap<String, String>> {
+    let mut relationships = BTreeMap::new();
+    for event in xml::EventReader::new(xml) {
+        if let XmlEvent::StartElement {
+            name, attributes, ..
+        } = event?
+            && name.local_name == "Relationship"
+            && let (Some(id), Some(target)) = (
+                attribute(&attributes, "Id"),
+                attribute(&attributes, "Target"),
+            )
+        {
+            relationships.insert(id.to_string(), target.to_string());
+        }
+    }
+    Ok(relationships)
+}
+
+/// `w:b`, `w:i` are on unless their value turns them off
+fn toggle(attributes: &[xml::attribute::OwnedAttribute]) -> bool {
+    !matches!(attribute(attributes, "val"), Some("0" | "false" | "off"))
+}
+
+#[derive(Debug, Default)]
+struct Paragraph {
+    inline: Inline,
+    style: Style,
+    outline: Option<usize>,
+    list: Option<usize>,
+}
+
+struct DocxReader<'a, R: Read + Seek> {
+    archive: &'a mut ZipArchive<R>,
+    styles: BTreeMap<String, Style>,
+    relationships: BTreeMap<String, String>,
+    document: Document,
+    /// paragraphs being read, text boxes contain paragraphs inside a paragraph
+    paragraphs: Vec<Paragraph>,
+    run: RunStyle,
+    in_text: bool,
+    /// description of the drawing being read, used as alt text
+    image_alt: String,
+}
+
+impl<R: Read + Seek> DocxReader<'_, R> {
+    fn image(&mut self, id: &str) -> anyhow::Result<()> {
+        let Some(target) = self.relationships.get(id) else {
+            return Ok(());
+        };
+        let Some(name) = normalize_path(&Path::new("word").join(target.trim_start_matches('/')))
+        else {
+            return Ok(());
+        };
+        let name = name.to_string_lossy().replace('\\', "/");
+        let Some(data) = read_entry(self.archive, &name)? else {
+            return Ok(());
+        };
+        let path = self.document.add_image(&name, data);
+        let alt = std::mem::take(&mut self.image_alt);
+        if let Some(paragraph) = self.paragraphs.last_mut() {
+            paragraph.inline.image(&alt, &path);
+        }
+        Ok(())
+    }
+
+    fn end_paragraph(&mut self) {
+        let Some(paragraph) = self.paragraphs.pop() else {
+            return;
+        };
+        let style = paragraph.style;
+        if style.title && self.document.builder.title.is_none() && !paragraph.inline.is_empty() {
+            self.document.builder.title = Some(paragraph.inline.plain());
+            return;
+        }
+        let heading = paragraph.outline.or(style.heading);
+        self.document
+            .builder
+            .paragraph(paragraph.inline, heading, paragraph.list, style.code);
+    }
+
+    fn read(&mut self, xml: &[u8]) -> anyhow::Result<()> {
+        // `mc:Fallback` repeats the content of `mc:Choice` for older readers
+        let mut skip = 0;
+        for event in xml::EventReader::new(xml) {
+            let event = event?;
+            if skip > 0 {
+                match event {
+                    XmlEvent::StartElement { .. } => skip += 1,
+                    XmlEvent::EndElement { .. } => skip -= 1,
+                    _ => {}
+                }
+                continue;
+            }
+            match event {
+                XmlEvent::StartElement {
+                    name, attributes, ..
+                } => match name.local_name.as_str() {
+                    "Fallback" => skip = 1,
+                    "p" => self.paragraphs.push(Paragraph::default()),
+                    "pStyle" => {
+                        let style = attribute(&attributes, "val").map(|id| {
+                            self.styles
+                                .get(id)
+                                .cloned()
+                                .unwrap_or_else(|| Style::from_name(id))
+                        });
+                        if let (Some(paragraph), Some(style)) = (self.paragraphs.last_mut(), style)
+                        {
+                            paragraph.style = style;
+                        }
+                    }
+                    "outlineLvl" => {
+                        let level = attribute(&attributes, "val").and_then(|val| val.parse().ok());
+                        if let (Some(paragraph), Some(level @ 0..6)) =
+                            (self.paragraphs.last_mut(), level)
+                        {
+                            paragraph.outline = Some(level + 1);
+                        }
+                    }
+                    "numPr" => {
+                        if let Some(paragraph) = self.paragraphs.last_mut() {
+                            paragraph.list = Some(0);
+                        }
+                    }
+                    "ilvl" => {
+                        let depth = attribute(&attributes, "val").and_then(|val| val.parse().ok());
+                        if let Some(paragraph) = self.paragraphs.last_mut() {
+                            paragraph.list = depth.or(paragraph.list);
+                        }
+                    }
+                    "r" => self.run = RunStyle::default(),
+                    "b" => self.run.bold = toggle(&attributes),
+                    "i" => self.run.italic = toggle(&attributes),
+                    "rStyle" => {
+                        self.run.code |= attribute(&attributes, "val")
+                            .and_then(|id| self.styles.get(id))
+                            .is_some_and(|style| style.code);
+                    }
+                    "rFonts" => {
+                        self.run.code |=
+                            attribute(&attributes, "ascii").is_some_and(is_monospace_font);
+                    }
+                    "t" => self.in_text = true,
+                    "tab" | "br" | "cr" => {
+                        let text = if name.local_name == "tab" { "\t" } else { "\n" };
+                        // tab stops of the paragraph properties and page breaks are not text
+                        let ignored = attribute(&attributes, "pos").is_some()
+                            || attribute(&attributes, "type") == Some("page");
+                        if let (Some(paragraph), false) = (self.paragraphs.last_mut(), ignored) {
+                            paragraph.inline.text(text, self.run);
+                        }
+                    }
+                    "hyperlink" => {
+                        let url = attribute(&attributes, "id")
+                            .and_then(|id| self.relationships.get(id))
+                            .cloned();
+                        if let Some(paragraph) = self.paragraphs.last_mut() {
+                            paragraph.inline.start_link(url);
+                        }
+                    }
+                    "docPr" => {
+                        self.image_alt = attribute(&attributes, "descr")
+                            .or(attribute(&attributes, "title"))
+                            .unwrap_or_default()
+                            .to_string();
+                    }
+                    "blip" => {
+                        if let Some(id) = attribute(&attributes, "embed") {
+                            self.image(id)?;
+                        }
+                    }
+                    "imagedata" => {
+                        if let Some(id) = attribute(&attributes, "id") {
+                            self.image(id)?;
+                        }
+                    }
+                    "tbl" => self.document.builder.start_table(),
+                    "tr" => self.document.builder.start_row(),
+                    "tc" => self.document.builder.start_cell(),
+                    _ => {}
+                },
+                XmlEvent::Characters(text) | XmlEvent::Whitespace(text) if self.in_text => {
+                    if let Some(paragraph) = self.paragraphs.last_mut() {
+                        paragraph.inline.text(&text, self.run);
+                    }
+                }
+                XmlEvent::EndElement { name } => match name.local_name.as_str() {
+                    "p" => self.end_paragraph(),
+                    "t" => self.in_text = false,
+                    "hyperlink" => {
+                        if let Some(paragraph) = self.paragraphs.last_mut() {
+                            paragraph.inline.end_link();
+                        }
+                    }
+                    "tbl" => self.document.builder.end_table(),
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// convert the main part of a Word document with its styles, links and images
+pub fn read_docx<R: Read + Seek>(archive: &mut ZipArchive<R>) -> anyhow::Result<Document> {
+    let xml = read_entry(archive, "word/document.xml")?.ok_or(anyhow::anyhow!(
+        "Not a Word document: word/document.xml is missing"
+    ))?;
+    let styles = match read_entry(archive, "word/styles.xml")? {
+        Some(styles) => read_styles(&styles)?,
+        None => BTreeMap::new(),
+    };
+    let relationships = match read_entry(archive, "word/_rels/document.xml.rels")? {
+        Some(relationships) => read_relationships(&relationships)?,
+        None => BTreeMap::new(),
+    };
+    let metadata = match read_entry(archive, "docProps/core.xml")? {
+        Some(core) => read_metadata(&core, &["title", "creator", "description"])?,
+        None => BTreeMap::new(),
+    };
+    let mut reader = DocxReader {
+        archive,
+        styles,
+        relationships,
+        document: Document {
+            metadata,
+            ..Default::default()
+        },
+        paragraphs: vec![],
+        run: RunStyle::default(),
+        in_text: false,
+        image_alt: String::new(),
+    };
+    reader.read(&xml)?;
+    Ok(reader.document)
+}
+
+impl BookImporter for DocxImporter {
+    fn accepts(&self, path: &Path) -> bool {
+        path.is_file() && has_extension(path, &["docx"])
+    }
+
+    fn import(&self, path: &Path) -> anyhow::Result<ImportedBook> {
+        let mut archive = ZipArchive::new(File::open(path)?)?;
+        Ok(read_docx(&mut archive)?.into_book(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use zip::{ZipWriter, write::SimpleFileOptions};
+
+    use super::*;
+    use crate::books::import::Asset;
+
+    const DOCUMENT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main"
+    xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"
+    xmlns:a="http://schemas.openxmlformats.org/drawingml/2006/main"
+    xmlns:wp="http://schemas.openxmlformats.org/drawingml/2006/wordprocessingDrawing">
+<w:body>
+<w:p><w:pPr><w:pStyle w:val="Title"/></w:pPr><w:r><w:t>Course Notes</w:t></w:r></w:p>
+<w:p><w:pPr><w:pStyle w:val="Heading1"/></w:pPr><w:r><w:t>Getting</w:t></w:r><w:r><w:t xml:space="preserve"> Started</w:t></w:r></w:p>
+<w:p><w:r><w:t xml:space="preserve">Read the </w:t></w:r><w:r><w:rPr><w:b/></w:rPr><w:t>docs</w:t></w:r><w:hyperlink r:id="rId2"><w:r><w:t xml:space="preserve"> online</w:t></w:r></w:hyperlink></w:p>
+<w:p><w:pPr><w:numPr><w:ilvl w:val="0"/><w:numId w:val="1"/></w:numPr></w:pPr><w:r><w:t>first</w:t></w:r></w:p>
+<w:p><w:r><w:drawing><wp:inline><wp:docPr id="1" name="Picture 1" descr="A chart"/><a:graphic><a:graphicData><a:blip r:embed="rId1"/></a:graphicData></a:graphic></wp:inline></w:drawing></w:r></w:p>
+<w:p><w:pPr><w:pStyle w:val="Heading1"/></w:pPr><w:r><w:t>Code</w:t></w:r></w:p>
+<w:p><w:r><w:rPr><w:rFonts w:ascii="Courier New"/></w:rPr><w:t>let x = 1;</w:t></w:r></w:p>
+<w:tbl><w:tr><w:tc><w:p><w:r><w:t>a</w:t></w:r></w:p></w:tc><w:tc><w:p><w:r><w:t>b</w:t></w:r></w:p></w:tc></w:tr>
+<w:tr><w:tc><w:p><w:r><w:t>1</w:t></w:r></w:p></w:tc><w:tc><w:p><w:r><w:t>2</w:t></w:r></w:p></w:tc></w:tr></w:tbl>
+</w:body>
+</w:document>"#;
+
+    const RELATIONSHIPS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+<Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/image" Target="media/image1.png"/>
+<Relationship Id="rId2" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/hyperlink" Target="https://example.com/" TargetMode="External"/>
+</Relationships>"#;
+
+    #[test]
+    fn read_word_document() {
+        let mut data = vec![];
+        let mut writer = ZipWriter::new(Cursor::new(&mut data));
+        for (name, content) in [
+            ("word/document.xml", DOCUMENT.as_bytes()),
+            ("word/_rels/document.xml.rels", RELATIONSHIPS.as_bytes()),
+            ("word/media/image1.png", b"png".as_slice()),
+        ] {
+            writer
+                .start_file(name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(content).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let mut archive = ZipArchive::new(Cursor::new(data)).unwrap();
+        let document = read_docx(&mut archive).unwrap();
+        assert_eq!(document.builder.title.as_deref(), Some("Course Notes"));
+        assert_eq!(
+            document.builder.markdown(),
+            "# Getting Started\n\nRead the **docs** [online](<https://example.com/>)\n\n- first\n\n\
+            ![A chart](<images/image1.png>)\n\n# Code\n\n```\nlet x = 1;\n```\n\n\
+            | a | b |\n| --- | --- |\n| 1 | 2 |\n"
+        );
+
+        let book = document.into_book(Path::new("notes.docx"));
+        assert_eq!(book.book.title, "Course Notes");
+        assert_eq!(book.book.chapters.len(), 2);
+        assert!(matches!(
+            book.assets.get(Path::new("images/image1.png")),
+            Some(Asset::Data(data)) if data == b"png"
+        ));
+    }
+}
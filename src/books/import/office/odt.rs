@@ -0,0 +1,384 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{Read, Seek},
+    path::Path,
+};
+
+use xml::reader::XmlEvent;
+use zip::ZipArchive;
+
+use super::{
+    Document, Inline, RunStyle, attribute, is_code_style, is_monospace_font, read_entry,
+    read_metadata,
+};
+use crate::books::import::{BookImporter, ImportedBook, has_extension};
+
+/// OpenDocument text documents, e.g. written with LibreOffice
+pub struct OdtImporter;
+
+/// A paragraph or text style of `styles.xml` or the automatic styles of `content.xml`
+#[derive(Debug, Clone, Default)]
+struct Style {
+    parent: Option<String>,
+    run: RunStyle,
+}
+
+/// `Heading_20_1` -> `Heading 1`
+fn display_name(name: &str) -> String {
+    name.replace("_20_", " ")
+}
+
+fn read_styles(xml: &[u8], styles: &mut BTreeMap<String, Style>) -> anyhow::Result<()> {
+    let mut current: Option<(String, Style)> = None;
+    for event in xml::EventReader::new(xml) {
+        match event? {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => match name.local_name.as_str() {
+                "style" => {
+                    let style = Style {
+                        parent: attribute(&attributes, "parent-style-name").map(String::from),
+                        ..Default::default()
+                    };
+                    current = attribute(&attributes, "name").map(|name| (name.to_string(), style));
+                }
+                "text-properties" => {
+                    if let Some((_, style)) = &mut current {
+                        style.run.bold |= attribute(&attributes, "font-weight") == Some("bold");
+                        style.run.italic |= attribute(&attributes, "font-style") == Some("italic");
+                        style.run.code |= attribute(&attributes, "font-name")
+                            .or(attribute(&attributes, "font-family"))
+                            .is_some_and(is_monospace_font);
+                    }
+                }
+                _ => {}
+            },
+            XmlEvent::EndElement { name } if name.local_name == "style" => {
+                if let Some((name, style)) = current.take() {
+                    styles.insert(name, style);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// What a paragraph style is used for, found by following the parent styles
+#[derive(Debug, Clone, Copy, Default)]
+struct ParagraphStyle {
+    run: RunStyle,
+    title: bool,
+    code: bool,
+}
+
+#[derive(Debug, Default)]
+struct Paragraph {
+    inline: Inline,
+    style: ParagraphStyle,
+    heading: Option<usize>,
+    list: Option<usize>,
+}
+
+struct OdtReader<'a, R: Read + Seek> {
+    archive: &'a mut ZipArchive<R>,
+    styles: BTreeMap<String, Style>,
+    document: Document,
+    paragraphs: Vec<Paragraph>,
+    /// formatting of the open spans
+    spans: Vec<RunStyle>,
+    /// depth of the open lists
+    lists: usize,
+    /// description of the frame being read, used as alt text
+    image_alt: String,
+}
+
+impl<R: Read + Seek> OdtReader<'_, R> {
+    fn paragraph_style(&self, name: Option<&str>) -> ParagraphStyle {
+        let mut style = ParagraphStyle::default();
+        let mut name = name.map(String::from);
+        // the parents of automatic styles are named styles, stop on cycles
+        for _ in 0..16 {
+            let Some(current) = name else {
+                break;
+            };
+            let display = display_name(&current);
+            style.title |= display.eq_ignore_ascii_case("title");
+            style.code |= is_code_style(&display);
+            match self.styles.get(&current) {
+                Some(found) => {
+                    style.run = style.run.with(found.run);
+                    name = found.parent.clone();
+                }
+                None => name = None,
+            }
+        }
+        style.code |= style.run.code;
+        style
+    }
+
+    fn run(&self) -> RunStyle {
+        let paragraph = self
+            .paragraphs
+            .last()
+            .map(|paragraph| paragraph.style.run)
+            .unwrap_or_default();
+        self.spans
+            .iter()
+            .fold(paragraph, |run, span| run.with(*span))
+    }
+
+    fn text(&mut self, text: &str) {
+        let run = self.run();
+        if let Some(paragraph) = self.paragraphs.last_mut() {
+            paragraph.inline.text(text, run);
+        }
+    }
+
+    fn image(&mut self, href: &str) -> anyhow::Result<()> {
+        let name = href.trim_start_matches("./");
+        if name.contains("://") {
+            return Ok(());
+        }
+        let Some(data) = read_entry(self.archive, name)? else {
+            return Ok(());
+        };
+        let path = self.document.add_image(name, data);
+        let alt = std::mem::take(&mut self.image_alt);
+        if let Some(paragraph) = self.paragraphs.last_mut() {
+            paragraph.inline.image(&alt, &path);
+        }
+        Ok(())
+    }
+
+    fn end_paragraph(&mut self) {
+        let Some(paragraph) = self.paragraphs.pop() else {
+            return;
+        };
+        if paragraph.style.title
+            && self.document.builder.title.is_none()
+            && !paragraph.inline.is_empty()
+        {
+            self.document.builder.title = Some(paragraph.inline.plain());
+            return;
+        }
+        self.document.builder.paragraph(
+            paragraph.inline,
+            paragraph.heading,
+            paragraph.list,
+            paragraph.style.code,
+        );
+    }
+
+    fn read(&mut self, xml: &[u8]) -> anyhow::Result<()> {
+        // annotations and tracked changes are not part of the text
+        let mut skip = 0;
+        let mut in_body = false;
+        for event in xml::EventReader::new(xml) {
+            let event = event?;
+            if skip > 0 {
+                match event {
+                    XmlEvent::StartElement { .. } => skip += 1,
+                    XmlEvent::EndElement { .. } => skip -= 1,
+                    _ => {}
+                }
+                continue;
+            }
+            match event {
+                XmlEvent::StartElement {
+                    name, attributes, ..
+                } => match name.local_name.as_str() {
+                    "text" if name.prefix.as_deref() == Some("office") => in_body = true,
+                    _ if !in_body => {}
+                    "annotation" | "tracked-changes" | "sequence-decls" | "table-of-content" => {
+                        skip = 1
+                    }
+                    "h" | "p" => {
+                        let style = self.paragraph_style(attribute(&attributes, "style-name"));
+                        let heading = (name.local_name == "h").then(|| {
+                            attribute(&attributes, "outline-level")
+                                .and_then(|level| level.parse().ok())
+                                .unwrap_or(1)
+                        });
+                        let list = self.lists.checked_sub(1);
+                        self.paragraphs.push(Paragraph {
+                            style,
+                            heading,
+                            list,
+                            ..Default::default()
+                        });
+                    }
+                    "span" => {
+                        let run = attribute(&attributes, "style-name")
+                            .and_then(|name| self.styles.get(name))
+                            .map(|style| style.run)
+                            .unwrap_or_default();
+                        self.spans.push(run);
+                    }
+                    "a" => {
+                        let url = attribute(&attributes, "href").map(String::from);
+                        if let Some(paragraph) = self.paragraphs.last_mut() {
+                            paragraph.inline.start_link(url);
+                        }
+                    }
+                    "s" => {
+                        let count = attribute(&attributes, "c")
+                            .and_then(|count| count.parse().ok())
+                            .unwrap_or(1);
+                        self.text(&" ".repeat(count));
+                    }
+                    "tab" => self.text("\t"),
+                    "line-break" => self.text("\n"),
+                    "list" => self.lists += 1,
+                    "frame" => {
+                        self.image_alt = attribute(&attributes, "name")
+                            .unwrap_or_default()
+                            .to_string()
+                    }
+                    "image" => {
+                        if let Some(href) = attribute(&attributes, "href") {
+                            self.image(href)?;
+                        }
+                    }
+                    "table" if name.prefix.as_deref() == Some("table") => {
+                        self.document.builder.start_table()
+                    }
+                    "table-row" => self.document.builder.start_row(),
+                    "table-cell" | "covered-table-cell" => self.document.builder.start_cell(),
+                    _ => {}
+                },
+                XmlEvent::Characters(text) | XmlEvent::Whitespace(text)
+                    if !self.paragraphs.is_empty() =>
+                {
+                    self.text(&text);
+                }
+                XmlEvent::EndElement { name } => match name.local_name.as_str() {
+                    "text" if name.prefix.as_deref() == Some("office") => in_body = false,
+                    "h" | "p" => self.end_paragraph(),
+                    "span" => {
+                        self.spans.pop();
+                    }
+                    "a" => {
+                        if let Some(paragraph) = self.paragraphs.last_mut() {
+                            paragraph.inline.end_link();
+                        }
+                    }
+                    "list" => self.lists = self.lists.saturating_sub(1),
+                    "table" if name.prefix.as_deref() == Some("table") => {
+                        self.document.builder.end_table()
+                    }
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// convert the body of an OpenDocument text with its styles, links and images
+pub fn read_odt<R: Read + Seek>(archive: &mut ZipArchive<R>) -> anyhow::Result<Document> {
+    let xml = read_entry(archive, "content.xml")?.ok_or(anyhow::anyhow!(
+        "Not an OpenDocument text: content.xml is missing"
+    ))?;
+    let mut styles = BTreeMap::new();
+    if let Some(named) = read_entry(archive, "styles.xml")? {
+        read_styles(&named, &mut styles)?;
+    }
+    read_styles(&xml, &mut styles)?;
+    let metadata = match read_entry(archive, "meta.xml")? {
+        Some(meta) => read_metadata(
+            &meta,
+            &["title", "creator", "initial-creator", "description"],
+        )?,
+        None => BTreeMap::new(),
+    };
+    let mut reader = OdtReader {
+        archive,
+        styles,
+        document: Document {
+            metadata,
+            ..Default::default()
+        },
+        paragraphs: vec![],
+        spans: vec![],
+        lists: 0,
+        image_alt: String::new(),
+    };
+    reader.read(&xml)?;
+    Ok(reader.document)
+}
+
+impl BookImporter for OdtImporter {
+    fn accepts(&self, path: &Path) -> bool {
+        path.is_file() && has_extension(path, &["odt"])
+    }
+
+    fn import(&self, path: &Path) -> anyhow::Result<ImportedBook> {
+        let mut archive = ZipArchive::new(File::open(path)?)?;
+        Ok(read_odt(&mut archive)?.into_book(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use zip::{ZipWriter, write::SimpleFileOptions};
+
+    use super::*;
+
+    const CONTENT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<office:document-content xmlns:office="urn:oasis:names:tc:opendocument:xmlns:office:1.0"
+    xmlns:style="urn:oasis:names:tc:opendocument:xmlns:style:1.0"
+    xmlns:text="urn:oasis:names:tc:opendocument:xmlns:text:1.0"
+    xmlns:table="urn:oasis:names:tc:opendocument:xmlns:table:1.0"
+    xmlns:draw="urn:oasis:names:tc:opendocument:xmlns:drawing:1.0"
+    xmlns:xlink="http://www.w3.org/1999/xlink"
+    xmlns:fo="urn:oasis:names:tc:opendocument:xmlns:xsl-fo-compatible:1.0">
+<office:automatic-styles>
+<style:style style:name="T1" style:family="text"><style:text-properties fo:font-style="italic"/></style:style>
+<style:style style:name="P1" style:family="paragraph" style:parent-style-name="Preformatted_20_Text"/>
+</office:automatic-styles>
+<office:body><office:text>
+<text:sequence-decls><text:sequence-decl text:name="Figure"/></text:sequence-decls>
+<text:p text:style-name="Title">Field Guide</text:p>
+<text:h text:outline-level="1">Birds</text:h>
+<text:p>A <text:span text:style-name="T1">small</text:span><text:s/>bird, see <text:a xlink:href="https://example.org">the atlas</text:a>.</text:p>
+<text:p><draw:frame draw:name="Robin"><draw:image xlink:href="Pictures/robin.jpg"/></draw:frame></text:p>
+<text:list><text:list-item><text:p>wings</text:p><text:list><text:list-item><text:p>feathers</text:p></text:list-item></text:list></text:list-item></text:list>
+<text:h text:outline-level="1">Counting</text:h>
+<text:p text:style-name="P1">count(birds)</text:p>
+<table:table><table:table-row><table:table-cell><text:p>Bird</text:p></table:table-cell><table:table-cell><text:p>Count</text:p></table:table-cell></table:table-row>
+<table:table-row><table:table-cell><text:p>Robin</text:p></table:table-cell><table:table-cell><text:p>3</text:p></table:table-cell></table:table-row></table:table>
+</office:text></office:body>
+</office:document-content>"#;
+
+    #[test]
+    fn read_open_document() {
+        let mut data = vec![];
+        let mut writer = ZipWriter::new(Cursor::new(&mut data));
+        for (name, content) in [
+            ("content.xml", CONTENT.as_bytes()),
+            ("Pictures/robin.jpg", b"jpg".as_slice()),
+        ] {
+            writer
+                .start_file(name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(content).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let mut archive = ZipArchive::new(Cursor::new(data)).unwrap();
+        let document = read_odt(&mut archive).unwrap();
+        assert_eq!(document.builder.title.as_deref(), Some("Field Guide"));
+        assert_eq!(
+            document.builder.markdown(),
+            "# Birds\n\nA *small* bird, see [the atlas](<https://example.org>).\n\n\
+            ![Robin](<images/robin.jpg>)\n\n- wings\n  - feathers\n\n# Counting\n\n\
+            ```\ncount(birds)\n```\n\n| Bird | Count |\n| --- | --- |\n| Robin | 3 |\n"
+        );
+        assert!(document.images.contains_key(Path::new("images/robin.jpg")));
+    }
+}
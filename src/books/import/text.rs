@@ -0,0 +1,215 @@
+use std::{fs, path::Path, sync::LazyLock};
+
+use regex::Regex;
+
+use super::{BookImporter, ImportedBook, chapter, chapter_file_name, has_extension};
+use crate::books::import::markdown::humanize_file_name;
+
+/// Chapters without headings are cut after this many words
+const WORDS_PER_PART: usize = 3000;
+
+/// `CHAPTER XII.`, `Book 2: The Return`, `PART ONE`, `Chapter 3 - Home`
+static CHAPTER_HEADING: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)^(chapter|book|part|volume|section|letter)\s+([0-9]+|[ivxlcdm]+|one|two|three|four|five|six|seven|eight|nine|ten|eleven|twelve|[a-z]+teen|twenty[a-z-]*|thirty[a-z-]*)\b[.:\-–— ]*.{0,60}$",
+    )
+    .unwrap()
+});
+
+/// a line with only a roman numeral, e.g. `XIV.`
+static ROMAN_HEADING: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^[IVXLC]{1,7}\.?$").unwrap());
+
+/// Plain text, e.g. Project Gutenberg books, split into chapters by their headings
+pub struct TextImporter;
+
+/// metadata of the Project Gutenberg header and the text between its start and end markers
+fn strip_gutenberg(text: &str) -> (Option<String>, Option<String>, &str) {
+    let field = |header: &str, name: &str| {
+        header
+            .lines()
+            .find_map(|line| line.strip_prefix(name))
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+    let Some(start) = text.find("*** START OF") else {
+        return (None, None, text);
+    };
+    let header = &text[..start];
+    let body_start = text[start..]
+        .find('\n')
+        .map_or(text.len(), |i| start + i + 1);
+    let body_end = text[body_start..]
+        .find("*** END OF")
+        .map_or(text.len(), |i| body_start + i);
+    (
+        field(header, "Title:"),
+        field(header, "Author:"),
+        &text[body_start..body_end],
+    )
+}
+
+fn is_heading(line: &str) -> bool {
+    let line = line.trim();
+    !line.is_empty()
+        && line.chars().count() <= 80
+        && (CHAPTER_HEADING.is_match(line) || ROMAN_HEADING.is_match(line))
+}
+
+/// join the hard wrapped lines of each paragraph, markdown syntax at the start is escaped
+fn to_markdown(lines: &[&str]) -> String {
+    let mut paragraphs = vec![];
+    let mut paragraph = String::new();
+    for line in lines.iter().map(|line| line.trim()).chain([""]) {
+        if line.is_empty() {
+            if !paragraph.is_empty() {
+                paragraphs.push(std::mem::take(&mut paragraph));
+            }
+            continue;
+        }
+        if !paragraph.is_empty() {
+            paragraph.push(' ');
+        }
+        paragraph.push_str(line);
+    }
+    paragraphs
+        .into_iter()
+        .map(|paragraph| {
+            let special = paragraph.starts_with(['#', '>', '-', '*', '+', '=', '|', '<'])
+                || paragraph
+                    .split_once(['.', ')'])
+                    .is_some_and(|(n, _)| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()));
+            if special {
+                format!("\\{paragraph}")
+            } else {
+                paragraph
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// (title, lines) of each chapter, the lines before the first heading are returned separately
+fn split_chapters(text: &str) -> (Vec<&str>, Vec<(String, Vec<&str>)>) {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut front = vec![];
+    let mut chapters: Vec<(String, Vec<&str>)> = vec![];
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        let after_blank = i == 0 || lines[i - 1].trim().is_empty();
+        if after_blank && is_heading(line) {
+            let mut title = line.trim().trim_end_matches('.').to_string();
+            i += 1;
+            // a short title line right after the heading, e.g. `CHAPTER I.` `Loomings.`
+            let mut j = i;
+            while j < lines.len() && lines[j].trim().is_empty() && j - i < 2 {
+                j += 1;
+            }
+            let subtitle = lines.get(j).map(|line| line.trim()).unwrap_or_default();
+            let ends_paragraph = lines.get(j + 1).is_none_or(|line| line.trim().is_empty());
+            if !subtitle.is_empty()
+                && subtitle.chars().count() <= 60
+                && ends_paragraph
+                && !is_heading(subtitle)
+            {
+                title = format!("{}: {}", title, subtitle.trim_end_matches('.'));
+                i = j + 1;
+            }
+            chapters.push((title, vec![]));
+            continue;
+        }
+        match chapters.last_mut() {
+            Some((_, chapter_lines)) => chapter_lines.push(line),
+            None => front.push(line),
+        }
+        i += 1;
+    }
+    (front, chapters)
+}
+
+/// cut a text without headings into parts of about `WORDS_PER_PART` words
+fn split_parts(text: &str) -> Vec<(String, Vec<&str>)> {
+    let mut parts: Vec<(String, Vec<&str>)> = vec![];
+    let mut words = 0;
+    for line in text.lines() {
+        if parts.is_empty() || (words >= WORDS_PER_PART && line.trim().is_empty()) {
+            parts.push((format!("Part {}", parts.len() + 1), vec![]));
+            words = 0;
+        }
+        words += line.split_whitespace().count();
+        if let Some((_, lines)) = parts.last_mut() {
+            lines.push(line);
+        }
+    }
+    parts
+}
+
+impl BookImporter for TextImporter {
+    fn accepts(&self, path: &Path) -> bool {
+        path.is_file() && has_extension(path, &["txt"])
+    }
+
+    fn import(&self, path: &Path) -> anyhow::Result<ImportedBook> {
+        let text = String::from_utf8_lossy(&fs::read(path)?).replace("\r\n", "\n");
+        let (title, author, body) = strip_gutenberg(&text);
+        let (front, mut sections) = split_chapters(body);
+        // headings listed in a table of contents have no text
+        sections.retain(|(_, lines)| lines.iter().any(|line| !line.trim().is_empty()));
+        if sections.len() < 2 {
+            sections = split_parts(body);
+        } else if front.iter().any(|line| !line.trim().is_empty()) {
+            sections.insert(0, ("Front Matter".to_string(), front));
+        }
+        let chapters = sections
+            .into_iter()
+            .zip(1..)
+            .map(|((name, lines), i)| {
+                let path = chapter_file_name(i, &name);
+                let content = format!("# {}\n\n{}\n", name, to_markdown(&lines));
+                chapter(name, path, content)
+            })
+            .collect();
+        let title = title.unwrap_or_else(|| humanize_file_name(path));
+        let mut book = ImportedBook::new(title, chapters);
+        book.book.authors = author.into_iter().collect();
+        Ok(book)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOOK: &str = "The Project Gutenberg eBook of Tiny Tales\n\nTitle: Tiny Tales\n\nAuthor: Jane Roe\n\n*** START OF THE PROJECT GUTENBERG EBOOK TINY TALES ***\n\nTINY TALES\n\nCONTENTS\n\nCHAPTER I.\n\nThe Start.\n\nIt was a dark\nand stormy night.\n\n# not markdown\n\nCHAPTER II.\nIn which nothing happens\n\nThe end.\n\n*** END OF THE PROJECT GUTENBERG EBOOK TINY TALES ***\nlicense";
+
+    #[test]
+    fn gutenberg_chapters() {
+        let (title, author, body) = strip_gutenberg(BOOK);
+        assert_eq!(title.as_deref(), Some("Tiny Tales"));
+        assert_eq!(author.as_deref(), Some("Jane Roe"));
+        assert!(!body.contains("license"));
+
+        let (front, chapters) = split_chapters(body);
+        assert!(front.contains(&"CONTENTS"));
+        let titles: Vec<_> = chapters.iter().map(|(title, _)| title.as_str()).collect();
+        assert_eq!(
+            titles,
+            [
+                "CHAPTER I: The Start",
+                "CHAPTER II: In which nothing happens"
+            ]
+        );
+        assert_eq!(
+            to_markdown(&chapters[0].1),
+            "It was a dark and stormy night.\n\n\\# not markdown"
+        );
+    }
+
+    #[test]
+    fn parts_without_headings() {
+        let text = "word ".repeat(WORDS_PER_PART) + "\n\nmore words\n";
+        let parts = split_parts(&text);
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[1].0, "Part 2");
+    }
+}
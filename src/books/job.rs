@@ -0,0 +1,503 @@
+use std::{
+    path::Path,
+    str::FromStr,
+    sync::{Arc, LazyLock},
+    time::Duration,
+};
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use time::OffsetDateTime;
+use tokio::{
+    select,
+    sync::{Notify, Semaphore, broadcast, watch},
+    task::spawn_blocking,
+};
+use tracing::{Instrument, Span, error, info, info_span};
+use utoipa::ToSchema;
+
+use super::{
+    book::{Book, BookRaw, BookTeachingPlan, TEACHING_PLAN_FILE},
+    import,
+    library::Library,
+    revision::{self, ChapterDiff},
+    storage,
+};
+use crate::{config::config, db::Database, student};
+
+/// Number of books imported at the same time
+pub static IMPORT_CONCURRENCY: LazyLock<usize> =
+    LazyLock::new(|| config().quotas.import_concurrency.max(1));
+/// How long a running job stays with its instance without renewing the lease
+const JOB_LEASE: Duration = Duration::from_secs(60);
+/// How often a running job renews its lease and checks whether it was cancelled
+const JOB_RENEWAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Done => "done",
+            JobStatus::Failed => "failed",
+            JobStatus::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            JobStatus::Done | JobStatus::Failed | JobStatus::Cancelled
+        )
+    }
+}
+
+impl FromStr for JobStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "queued" => JobStatus::Queued,
+            "running" => JobStatus::Running,
+            "done" => JobStatus::Done,
+            "failed" => JobStatus::Failed,
+            "cancelled" => JobStatus::Cancelled,
+            _ => bail!("Invalid job status: {}", s),
+        })
+    }
+}
+
+/// the stage a job is in, or stopped in if it failed or was cancelled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStage {
+    Queued,
+    /// converting the upload into an mdbook
+    Converting,
+    /// reading the chapters of the mdbook
+    Parsing,
+    /// generating the teaching plans, `progress_done` of `progress_total` chapters
+    Planning,
+    /// copying the book into the bookbase
+    Storing,
+    Done,
+}
+
+impl JobStage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobStage::Queued => "queued",
+            JobStage::Converting => "converting",
+            JobStage::Parsing => "parsing",
+            JobStage::Planning => "planning",
+            JobStage::Storing => "storing",
+            JobStage::Done => "done",
+        }
+    }
+}
+
+impl FromStr for JobStage {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "queued" => JobStage::Queued,
+            "converting" => JobStage::Converting,
+            "parsing" => JobStage::Parsing,
+            "planning" => JobStage::Planning,
+            "storing" => JobStage::Storing,
+            "done" => JobStage::Done,
+            _ => bail!("Invalid job stage: {}", s),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ImportJob {
+    pub id: i64,
+    pub file_name: String,
+    /// student whose library receives the book, none for uploads of managers
+    pub student_id: Option<i64>,
+    pub status: JobStatus,
+    pub stage: JobStage,
+    pub progress_done: i64,
+    pub progress_total: i64,
+    /// book that receives a new version, or the imported book once it is stored
+    pub book_id: Option<i64>,
+    pub error: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub create_time: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    pub update_time: OffsetDateTime,
+}
+
+pub async fn get_job(database: &Database, id: i64) -> anyhow::Result<ImportJob> {
+    database.get_job(id).await
+}
+
+/// the jobs of a student, or all jobs if `student_id` is none, newest first
+pub async fn list_jobs(
+    database: &Database,
+    student_id: Option<i64>,
+) -> anyhow::Result<Vec<ImportJob>> {
+    database.list_jobs(student_id).await
+}
+
+/// the span of the current stage of a job
+fn stage_span(job: &ImportJob) -> Span {
+    info_span!(
+        "import_stage",
+        stage = job.stage.as_str(),
+        job_id = job.id,
+        book_id = job.book_id
+    )
+}
+
+/// key of the files of a job in the book storage, the upload under `source` and the
+/// converted book of a job that stopped under `book`, so any instance can run the job
+pub fn job_key(id: i64) -> String {
+    format!("imports/job_{}", id)
+}
+
+/// Book imports processed in the background by any instance, persisted in the
+/// `import_job` table
+#[derive(Debug, Clone)]
+pub struct JobQueue {
+    /// wakes the dispatcher when a job is queued
+    notify: Arc<Notify>,
+    updates: broadcast::Sender<ImportJob>,
+}
+
+impl Default for JobQueue {
+    fn default() -> Self {
+        Self {
+            notify: Arc::new(Notify::new()),
+            updates: broadcast::channel(256).0,
+        }
+    }
+}
+
+impl JobQueue {
+    /// every change of a job is sent to the subscribers
+    pub fn subscribe(&self) -> broadcast::Receiver<ImportJob> {
+        self.updates.subscribe()
+    }
+
+    /// queue the import of `file_name` uploaded into `upload`, as a new version of
+    /// `book_id` if given
+    pub async fn submit(
+        &self,
+        library: &Library,
+        upload: TempDir,
+        file_name: &str,
+        student_id: Option<i64>,
+        book_id: Option<i64>,
+    ) -> anyhow::Result<i64> {
+        // the dispatchers must not see the job before its upload is in place
+        let id = library
+            .database
+            .insert_job(file_name, student_id, book_id, async |id| {
+                let key = format!("{}/source", job_key(id));
+                storage::upload_dir(library.storage.as_ref(), upload.path(), &key).await
+            })
+            .await?;
+        info!("queued import job {} of {}", id, file_name);
+        self.notify.notify_one();
+        Ok(id)
+    }
+
+    /// stop a queued or running job, its files are kept so it can be retried. The instance
+    /// running the job stops it when it next renews the lease
+    pub async fn cancel(&self, database: &Database, id: i64) -> anyhow::Result<()> {
+        if database.cancel_queued_job(id).await? {
+            let _ = self.updates.send(get_job(database, id).await?);
+            return Ok(());
+        }
+        if !database.request_job_cancel(id).await? {
+            let job = get_job(database, id).await?;
+            bail!("Job {} is {}", id, job.status.as_str());
+        }
+        Ok(())
+    }
+
+    /// queue a failed or cancelled job again, it resumes from the files it left
+    pub async fn retry(&self, database: &Database, id: i64) -> anyhow::Result<()> {
+        if !database.retry_job(id).await? {
+            bail!("Job {} is not failed or cancelled", id);
+        }
+        let _ = self.updates.send(get_job(database, id).await?);
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    async fn save(&self, database: &Database, job: &mut ImportJob) -> anyhow::Result<()> {
+        job.update_time = OffsetDateTime::now_utc();
+        database.save_job(job).await?;
+        let _ = self.updates.send(job.clone());
+        Ok(())
+    }
+
+    async fn set_stage(
+        &self,
+        database: &Database,
+        job: &mut ImportJob,
+        stage: JobStage,
+    ) -> anyhow::Result<()> {
+        job.stage = stage;
+        job.progress_done = 0;
+        job.progress_total = 0;
+        self.save(database, job).await
+    }
+
+    /// convert, parse and plan the book in the local directory `dir`, the cancellable part
+    /// of a job, returns the changes of the chapters for a new version
+    async fn prepare(
+        &self,
+        library: &Library,
+        job: &mut ImportJob,
+        dir: &Path,
+    ) -> anyhow::Result<(Book, Vec<ChapterDiff>)> {
+        let database = &library.database;
+        let storage = library.storage.as_ref();
+        let key = job_key(job.id);
+        let book_dir = dir.join("book");
+
+        // a retried job keeps the converted book and the plans generated so far
+        let kept_key = format!("{}/book", key);
+        if !storage.list(&kept_key).await?.is_empty() {
+            storage::download_dir(storage, &kept_key, &book_dir).await?;
+        }
+        if !book_dir.join("book.toml").is_file() {
+            self.set_stage(database, job, JobStage::Converting).await?;
+            let source_dir = dir.join("source");
+            storage::download_dir(storage, &format!("{}/source", key), &source_dir).await?;
+            let source = source_dir.join(&job.file_name);
+            let (converting, output) = (dir.join("converting"), book_dir.clone());
+            spawn_blocking(move || -> anyhow::Result<()> {
+                let _ = std::fs::remove_dir_all(&converting);
+                import::convert_to_mdbook(&source, &converting)?;
+                std::fs::rename(&converting, &output)?;
+                Ok(())
+            })
+            .instrument(stage_span(job))
+            .await??;
+        }
+
+        self.set_stage(database, job, JobStage::Parsing).await?;
+        let book_id = job.book_id;
+        let (raw, diffs) = async {
+            let raw = BookRaw::load(&book_dir).await?;
+            let Some(book_id) = book_id else {
+                if let Some(book_id) = library.find_book_by_hash(&raw.content_hash).await? {
+                    bail!("Book already exists with ID {}", book_id);
+                }
+                return anyhow::Ok((raw, vec![]));
+            };
+            let book_path = library.book_dir(book_id).await?;
+            let old = BookRaw::load(&book_path).await?;
+            if old.content_hash == raw.content_hash {
+                bail!("Book {} has no changes", book_id);
+            }
+            let diffs = revision::diff_chapters(&old, &raw);
+            // only the plans of changed chapters are generated again
+            if !book_dir.join(TEACHING_PLAN_FILE).is_file() {
+                let old_plan = BookTeachingPlan::load(&book_path).await;
+                revision::carry_over_plans(&old_plan, &diffs)
+                    .save(&book_dir)
+                    .await?;
+            }
+            Ok((raw, diffs))
+        }
+        .instrument(stage_span(job))
+        .await?;
+
+        self.set_stage(database, job, JobStage::Planning).await?;
+        let (sender, mut receiver) = watch::channel((0, 0));
+        let progress = move |done: usize, total: usize| {
+            sender.send_replace((done, total));
+        };
+        let span = stage_span(job);
+        async {
+            let plan = raw.to_book(&book_dir, &progress);
+            tokio::pin!(plan);
+            loop {
+                select! {
+                    book = &mut plan => return Ok((book?, diffs)),
+                    Ok(()) = receiver.changed() => {
+                        let (done, total) = *receiver.borrow_and_update();
+                        job.progress_done = done as i64;
+                        job.progress_total = total as i64;
+                        self.save(database, job).await?;
+                    }
+                }
+            }
+        }
+        .instrument(span)
+        .await
+    }
+
+    async fn import(
+        &self,
+        library: &Library,
+        job: &mut ImportJob,
+        dir: &Path,
+        cancel: &Notify,
+    ) -> anyhow::Result<()> {
+        let book = select! {
+            book = self.prepare(library, job, dir) => Some(book?),
+            _ = cancel.notified() => None,
+        };
+        let Some((book, diffs)) = book else {
+            job.status = JobStatus::Cancelled;
+            return Ok(());
+        };
+        self.set_stage(&library.database, job, JobStage::Storing)
+            .await?;
+        let span = stage_span(job);
+        async {
+            let book_dir = dir.join("book");
+            if let Some(book_id) = job.book_id {
+                library
+                    .replace_book(book_id, &book_dir, book, &diffs)
+                    .await?;
+            } else {
+                let book_id = library.install_book(&book_dir, book).await?;
+                job.book_id = Some(book_id);
+                if let Some(student_id) = job.student_id {
+                    library.database.set_book_owner(book_id, student_id).await?;
+                    student::add_student_books(&library.database, student_id, vec![book_id])
+                        .await?;
+                }
+            }
+            anyhow::Ok(())
+        }
+        .instrument(span)
+        .await?;
+        job.status = JobStatus::Done;
+        job.stage = JobStage::Done;
+        Ok(())
+    }
+
+    async fn process(&self, library: &Library, mut job: ImportJob, cancel: &Notify) {
+        info!("start import job {} of {}", job.id, job.file_name);
+        let span = info_span!(
+            "import_job",
+            job_id = job.id,
+            file_name = %job.file_name,
+            student_id = job.student_id,
+            book_id = job.book_id
+        );
+        let result = async {
+            let dir = tempfile::tempdir()?;
+            let result = self.import(library, &mut job, dir.path(), cancel).await;
+            if job.status != JobStatus::Done
+                && let Err(e) = keep_book(library, job.id, dir.path()).await
+            {
+                error!("keep the book of import job {} failed: {}", job.id, e);
+            }
+            result
+        }
+        .instrument(span)
+        .await;
+        match result {
+            Ok(()) if job.status == JobStatus::Done => {
+                info!("import job {} done", job.id);
+                let _ = library.storage.delete(&job_key(job.id)).await;
+            }
+            Ok(()) => info!("import job {} cancelled", job.id),
+            Err(e) => {
+                error!("import job {} failed: {:?}", job.id, e);
+                job.status = JobStatus::Failed;
+                job.error = Some(e.to_string());
+            }
+        }
+        if let Err(e) = self.save(&library.database, &mut job).await {
+            error!("save import job {} failed: {}", job.id, e);
+        }
+    }
+
+    /// process a claimed job and renew its lease until it is finished, stopping it when
+    /// it is cancelled
+    async fn run_job(&self, library: &Library, job: ImportJob) {
+        let (id, cancel) = (job.id, Notify::new());
+        let process = self.process(library, job, &cancel);
+        tokio::pin!(process);
+        let mut renewal = tokio::time::interval(JOB_RENEWAL);
+        loop {
+            select! {
+                () = &mut process => return,
+                _ = renewal.tick() => {
+                    let lease_until = OffsetDateTime::now_utc() + JOB_LEASE;
+                    match library.database.renew_job_lease(id, lease_until).await {
+                        Ok(true) => cancel.notify_one(),
+                        Ok(false) => {}
+                        Err(e) => error!("renew lease of import job {} failed: {}", id, e),
+                    }
+                }
+            }
+        }
+    }
+
+    /// run the queued jobs, `IMPORT_CONCURRENCY` at a time, until the server stops
+    pub async fn run(library: Arc<Library>) {
+        let queue = library.jobs.clone();
+        let database = library.database.clone();
+        let semaphore = Arc::new(Semaphore::new(*IMPORT_CONCURRENCY));
+        loop {
+            // jobs of instances that stopped start over from the files they left
+            if let Err(e) = database
+                .requeue_abandoned_jobs(OffsetDateTime::now_utc())
+                .await
+            {
+                error!("requeue import jobs failed: {}", e);
+            }
+            let permit = semaphore.clone().acquire_owned().await.unwrap();
+            match database
+                .claim_next_job(OffsetDateTime::now_utc() + JOB_LEASE)
+                .await
+            {
+                Ok(Some(job)) => {
+                    let (queue, library) = (queue.clone(), library.clone());
+                    tokio::spawn(async move {
+                        queue.run_job(&library, job).await;
+                        drop(permit);
+                    });
+                }
+                Ok(None) => {
+                    drop(permit);
+                    // jobs queued by other instances and expired leases are only
+                    // noticed by polling
+                    let _ = tokio::time::timeout(JOB_LEASE, queue.notify.notified()).await;
+                }
+                Err(e) => {
+                    error!("claim import job failed: {}", e);
+                    drop(permit);
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                }
+            }
+        }
+    }
+}
+
+/// keep the converted book and the plans generated so far of a job that stopped in the
+/// storage, a retry resumes from them on any instance
+async fn keep_book(library: &Library, id: i64, dir: &Path) -> anyhow::Result<()> {
+    let book_dir = dir.join("book");
+    if !book_dir.join("book.toml").is_file() {
+        return Ok(());
+    }
+    let key = format!("{}/book", job_key(id));
+    library.storage.delete(&key).await?;
+    storage::upload_dir(library.storage.as_ref(), &book_dir, &key).await
+}
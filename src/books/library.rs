@@ -1,192 +1,272 @@
 use std::{
-    fs::File,
     path::{Path, PathBuf},
     sync::Arc,
 };
 
-use super::book::{Book, BookMeta};
+use super::{
+    book::{Book, BookMeta, BookTeachingPlan, TEACHING_PLAN_FILE},
+    chapter::ChapterNumber,
+    import,
+    job::JobQueue,
+    plan::PLAN_LOCKS,
+    render::normalize_path,
+    revision::{ChapterChange, ChapterDiff},
+    storage::{self, BookStorage, FileStorage},
+};
+use crate::{
+    config::StorageConfig,
+    db::{
+        Database,
+        book::{MovedChapter, MovedChapters},
+    },
+    server::metrics,
+};
 use anyhow::bail;
 
+use dashmap::DashMap;
 use moka::future::Cache;
-use sqlx::SqlitePool;
-use tokio::task::{block_in_place, spawn_blocking};
+use tokio::{sync::Mutex, task::block_in_place};
 use tracing::{error, info};
-use zip::ZipArchive;
 
 #[derive(Debug, Clone)]
 pub struct Library {
-    pub books: Cache<i64, Arc<Book>>,
+    /// the loaded books with the revision they were loaded at
+    pub books: Cache<i64, (i64, Arc<Book>)>,
     pub bookbase: PathBuf,
-    pub database: SqlitePool,
+    pub database: Database,
+    pub jobs: JobQueue,
+    /// where the books are kept, `bookbase` holds local copies if it is not the file system
+    pub storage: Arc<dyn BookStorage>,
+    /// revision of the local copy of each book fetched from the storage
+    local_copies: Arc<DashMap<i64, Arc<Mutex<Option<i64>>>>>,
+    /// books of the storage that could not be restored into the database, with the error
+    pub failed_books: Arc<DashMap<i64, String>>,
+}
+
+fn book_key(book_id: i64) -> String {
+    format!("book_{}", book_id)
 }
 
 impl Default for Library {
     fn default() -> Self {
-        let database = SqlitePool::connect_lazy("sqlite::memory:").unwrap();
         Self {
             books: Cache::new(1000),
             bookbase: PathBuf::new(),
-            database,
+            database: Database::memory(),
+            jobs: JobQueue::default(),
+            storage: Arc::new(FileStorage::new(PathBuf::new())),
+            local_copies: Arc::default(),
+            failed_books: Arc::default(),
         }
     }
 }
 
 impl Library {
-    /// create a new book server
-    pub async fn new(database: SqlitePool, bookbase: impl AsRef<Path>) -> anyhow::Result<Self> {
-        sqlx::query!("PRAGMA foreign_keys = ON;")
-            .execute(&database)
-            .await?;
+    /// create a new book server, with the storage of `storage.backend`
+    pub async fn new(
+        database: Database,
+        bookbase: impl AsRef<Path>,
+        storage: &StorageConfig,
+    ) -> anyhow::Result<Self> {
+        let storage = storage::from_config(storage, bookbase.as_ref())?;
+        Self::with_storage(database, bookbase, storage).await
+    }
+
+    pub async fn with_storage(
+        database: Database,
+        bookbase: impl AsRef<Path>,
+        storage: Arc<dyn BookStorage>,
+    ) -> anyhow::Result<Self> {
+        tokio::fs::create_dir_all(bookbase.as_ref()).await?;
         let server = Self {
             books: Cache::new(1000),
             bookbase: bookbase.as_ref().to_path_buf(),
             database,
+            jobs: JobQueue::default(),
+            storage,
+            local_copies: Arc::default(),
+            failed_books: Arc::default(),
         };
         server.restore_db_from_bookbase().await?;
         Ok(server)
     }
 
     pub async fn get_book(&self, id: i64) -> anyhow::Result<Arc<Book>> {
-        if let Some(book) = self.books.get(&id).await {
-            Ok(book)
-        } else {
-            let book = self.load_book(id).await?;
-            Ok(book)
+        // the plans may have changed through another instance
+        let Some(revision) = self.database.book_revision(id).await? else {
+            bail!("Book {} not found", id);
+        };
+        if let Some((cached, _)) = self.books.get(&id).await
+            && cached != revision
+        {
+            self.books.invalidate(&id).await;
+        }
+        // concurrent misses of a book wait for a single load
+        let entry = self
+            .books
+            .entry(id)
+            .or_try_insert_with(async { anyhow::Ok((revision, self.load_book(id).await?)) })
+            .await
+            .map_err(|e| anyhow::anyhow!("{:#}", e))?;
+        metrics::cache_lookup("book", !entry.is_fresh());
+        Ok(entry.into_value().1)
+    }
+
+    /// whether the books are kept in `bookbase` itself
+    fn is_local(&self) -> bool {
+        self.storage.local_root() == Some(self.bookbase.as_path())
+    }
+
+    /// local directory of book `book_id`, fetched from the storage when the local copy is
+    /// missing or of another revision
+    pub async fn book_dir(&self, book_id: i64) -> anyhow::Result<PathBuf> {
+        let dir = self.bookbase.join(book_key(book_id));
+        if self.is_local() {
+            return Ok(dir);
+        }
+        let revision = self
+            .database
+            .book_revision(book_id)
+            .await?
+            .unwrap_or_default();
+        let local_copy = self.local_copies.entry(book_id).or_default().clone();
+        let mut local_revision = local_copy.lock().await;
+        if !dir.is_dir() || *local_revision != Some(revision) {
+            storage::download_dir(self.storage.as_ref(), &book_key(book_id), &dir).await?;
+            *local_revision = Some(revision);
         }
+        Ok(dir)
+    }
+
+    /// write a file of the local copy of book `book_id` back to the storage
+    pub async fn store_book_file(&self, book_id: i64, name: &str) -> anyhow::Result<()> {
+        if self.is_local() {
+            return Ok(());
+        }
+        let path = self.bookbase.join(book_key(book_id)).join(name);
+        let Ok(data) = tokio::fs::read(&path).await else {
+            return Ok(());
+        };
+        let key = storage::join_key(&book_key(book_id), Path::new(name));
+        self.storage.write(&key, data).await
     }
 
     async fn load_book(&self, id: i64) -> anyhow::Result<Arc<Book>> {
-        let _exist = sqlx::query_scalar!("select id from book where id = ?", id)
-            .fetch_one(&self.database)
+        if !self.database.book_exists(id).await? {
+            bail!("Book {} not found", id);
+        }
+        let book = Book::load(self.book_dir(id).await?, id).await?;
+        // plans generated while loading are kept with the book
+        self.store_book_file(id, TEACHING_PLAN_FILE).await?;
+        // books stored before content hashes were recorded
+        self.database
+            .fill_content_hash(id, &book.content_hash)
             .await?;
-        let book = Book::load(self.bookbase.join(format!("book_{}", id))).await?;
-        if id != book.id {
-            bail!("Book ID mismatch: {} != {}", id, book.id);
+        // books stored before the objectives were recorded, the objectives of the others
+        // only change with their plans
+        if !self.database.has_chapter_objectives(id).await? {
+            self.database.store_chapter_objectives(&book).await?;
         }
-        let book = Arc::new(book);
-        self.books.insert(id, book.clone()).await;
-        Ok(book)
+        Ok(Arc::new(book))
     }
 
     pub async fn load_books(&self) -> anyhow::Result<()> {
-        let book_ids: Vec<i64> = sqlx::query_scalar!("select id from book")
-            .fetch_all(&self.database)
-            .await?;
-        for id in book_ids {
-            self.load_book(id).await?;
+        for id in self.database.book_ids().await? {
+            self.get_book(id).await?;
         }
         Ok(())
     }
 
     pub async fn delete_book(&self, book_id: i64) -> anyhow::Result<()> {
-        let path = self.bookbase.join(format!("book_{}", book_id));
-        sqlx::query!("delete from chapter where book_id = ?", book_id)
-            .execute(&self.database)
-            .await?;
-        sqlx::query!("delete from book where id = ?", book_id)
-            .execute(&self.database)
-            .await?;
-        let _ = tokio::fs::remove_dir_all(path).await;
-        Ok(())
-    }
-
-    async fn store_book_to_db(&self, book: &Book) -> anyhow::Result<()> {
-        let authors = book.authors.join(",");
-        let description = book.description.clone().unwrap_or_default();
-        sqlx::query!(
-            "insert or replace into book (id, title, authors, description) values (?, ?, ?, ?)",
-            book.id,
-            book.title,
-            authors,
-            description
-        )
-        .execute(&self.database)
-        .await?;
-        sqlx::query!("delete from chapter where book_id = ?", book.id)
-            .execute(&self.database)
-            .await?;
-        for (number, chapter) in book.chapters.iter() {
-            let number = number.to_string();
-            sqlx::query!(
-                "insert or replace into chapter (book_id, chapter_number, name) values (?, ?, ?)",
-                book.id,
-                number,
-                chapter.name
-            )
-            .execute(&self.database)
-            .await?;
+        self.database.delete_book_rows(book_id).await?;
+        self.storage.delete(&book_key(book_id)).await?;
+        if !self.is_local() {
+            self.local_copies.remove(&book_id);
+            let _ = tokio::fs::remove_dir_all(self.bookbase.join(book_key(book_id))).await;
         }
         Ok(())
     }
 
+    /// add the books of the storage that are missing in the database
     pub async fn restore_db_from_bookbase(&self) -> anyhow::Result<()> {
-        let mut entries = tokio::fs::read_dir(&self.bookbase).await?;
-        while let Some(entry) = entries.next_entry().await? {
-            let path = entry.path();
-            if !path.is_dir() {
-                continue;
-            }
-            let Some(Ok(book_id)) = entry
-                .file_name()
-                .to_string_lossy()
-                .strip_prefix("book_")
+        for key in self.storage.list("").await? {
+            let Some(Ok(book_id)) = key
+                .strip_suffix("/book.toml")
+                .and_then(|dir| dir.strip_prefix("book_"))
                 .map(|s| s.parse::<i64>())
             else {
                 continue;
             };
-            let existing = sqlx::query!("select id from book where id = ?", book_id)
-                .fetch_optional(&self.database)
-                .await?;
-            if existing.is_some() {
+            if self.database.book_exists(book_id).await? {
                 continue;
             }
-            let book = match Book::load(&path).await {
+            let book = match self.book_dir(book_id).await {
+                Ok(path) => Book::load(&path, book_id).await,
+                Err(e) => Err(e),
+            };
+            let book = match book {
                 Ok(book) => book,
                 Err(e) => {
-                    error!("load book {} failed: {}", path.display(), e);
+                    error!("load book {} failed: {}", book_key(book_id), e);
+                    self.failed_books.insert(book_id, e.to_string());
                     continue;
                 }
             };
-            if book.id != book_id {
-                error!("Book ID mismatch: {} != {}", book_id, book.id);
-                tokio::fs::remove_dir_all(&path).await?;
-                continue;
-            }
-            self.store_book_to_db(&book).await?;
+            self.database.store_book(&book).await?;
+            self.failed_books.remove(&book_id);
         }
         Ok(())
     }
 
+    pub async fn book_exists(&self, book_id: i64) -> anyhow::Result<bool> {
+        self.database.book_exists(book_id).await
+    }
+
+    /// the book with the same content, if this version of a book was already imported
+    pub async fn find_book_by_hash(&self, content_hash: &str) -> anyhow::Result<Option<i64>> {
+        self.database.find_book_by_hash(content_hash).await
+    }
+
     pub async fn upload_book_from_mdbook(&self, path: impl AsRef<Path>) -> anyhow::Result<i64> {
         let path = path.as_ref();
-        let book = Book::load(path).await?;
+        let book = Book::load(path, 0).await?;
+        self.install_book(path, book).await
+    }
 
-        // Check if the book already exists in the database
-        let existing = sqlx::query!("SELECT id FROM book WHERE id = ?", book.id)
-            .fetch_optional(&self.database)
-            .await?;
-        if existing.is_some() {
-            bail!("Book with ID {} already exists", book.id);
+    /// copy an mdbook loaded from `path` into the storage and store it in the database,
+    /// the book gets a new id that later versions of it keep
+    pub async fn install_book(&self, path: &Path, mut book: Book) -> anyhow::Result<i64> {
+        if let Some(book_id) = self.find_book_by_hash(&book.content_hash).await? {
+            bail!("Book already exists with ID {}", book_id);
         }
-        // Create the book directory in bookbase
-        let book_dir = self.bookbase.join(format!("book_{}", book.id));
-        let _ = tokio::fs::remove_dir_all(&book_dir).await;
-        tokio::fs::create_dir_all(&book_dir).await?;
-
-        // Copy the book files from source path to bookbase/book_id
-        let copy_options = fs_extra::dir::CopyOptions {
-            overwrite: true,
-            skip_exist: false,
-            copy_inside: true,
-            content_only: true,
-            ..Default::default()
+        let authors = book.authors.join(",");
+        let inserted = self
+            .database
+            .insert_book(
+                &book.title,
+                &authors,
+                book.description.as_deref(),
+                &book.content_hash,
+            )
+            .await;
+        book.id = match inserted {
+            Ok(book_id) => book_id,
+            // the content hash is unique, the same book was imported in the meantime
+            Err(e) => match self.find_book_by_hash(&book.content_hash).await? {
+                Some(book_id) => bail!("Book already exists with ID {}", book_id),
+                None => return Err(e),
+            },
         };
-
-        let path_buf = path.to_path_buf();
-        spawn_blocking(move || fs_extra::dir::copy(path_buf, &book_dir, &copy_options)).await??;
+        let key = book_key(book.id);
+        self.storage.delete(&key).await?;
+        if let Err(e) = storage::upload_dir(self.storage.as_ref(), path, &key).await {
+            self.database.delete_book_rows(book.id).await?;
+            let _ = self.storage.delete(&key).await;
+            return Err(e);
+        }
 
         // Insert or replace book in the database
-        self.store_book_to_db(&book).await?;
+        self.database.store_book(&book).await?;
         info!(
             "add book {}-{} from {} success",
             book.id,
@@ -196,47 +276,149 @@ impl Library {
         Ok(book.id)
     }
 
+    /// replace book `book_id` by a new version loaded from `path`, the progress of the
+    /// students follows the chapters that moved and is dropped for removed chapters
+    pub async fn replace_book(
+        &self,
+        book_id: i64,
+        path: &Path,
+        mut book: Book,
+        diffs: &[ChapterDiff],
+    ) -> anyhow::Result<()> {
+        // plan edits must not write into the old version while it is replaced
+        let lock = PLAN_LOCKS.entry(book_id).or_default().clone();
+        let _guard = lock.lock().await;
+        book.id = book_id;
+        let key = book_key(book_id);
+        let (new_key, old_key) = (format!("{}.new", key), format!("{}.old", key));
+        self.storage.delete(&new_key).await?;
+        storage::upload_dir(self.storage.as_ref(), path, &new_key).await?;
+
+        let moved: MovedChapters = diffs
+            .iter()
+            .filter_map(|diff| {
+                let chapter = MovedChapter {
+                    number: diff.new_number.as_ref().map(|n| n.to_string()),
+                    modified: diff.change == ChapterChange::Modified,
+                };
+                Some((diff.old_number.as_ref()?.to_string(), chapter))
+            })
+            .collect();
+        // the files are swapped first and put back if the rows cannot be replaced, so the
+        // database never describes files that are not stored
+        self.swap_book_files(&key, &new_key, &old_key).await?;
+        let replaced = self.database.replace_book_rows(&book, &moved).await;
+        if replaced.is_err()
+            && let Err(e) = self.swap_book_files(&key, &old_key, &new_key).await
+        {
+            error!("restore the files of book {} failed: {}", book_id, e);
+        }
+        // fetch the stored version instead of loading the old local copy
+        self.local_copies.remove(&book_id);
+        let _ = self.storage.delete(&new_key).await;
+        let _ = self.storage.delete(&old_key).await;
+        replaced?;
+        self.reload_book(book_id).await?;
+        info!(
+            "replace book {}-{} from {} success",
+            book_id,
+            book.title,
+            path.display()
+        );
+        Ok(())
+    }
+
+    /// move the files under `key` to `backup` and the files under `from` to `key`, the
+    /// files under `key` are left as they were if it fails
+    async fn swap_book_files(&self, key: &str, from: &str, backup: &str) -> anyhow::Result<()> {
+        if self.is_local() {
+            // renames so readers never see a half copied book
+            let dir = self.bookbase.join(key);
+            let (from, backup) = (self.bookbase.join(from), self.bookbase.join(backup));
+            let _ = tokio::fs::remove_dir_all(&backup).await;
+            tokio::fs::rename(&dir, &backup).await?;
+            if let Err(e) = tokio::fs::rename(&from, &dir).await {
+                tokio::fs::rename(&backup, &dir).await?;
+                return Err(e.into());
+            }
+        } else {
+            self.storage.copy_tree(key, backup).await?;
+            if let Err(e) = self.storage.copy_tree(from, key).await {
+                self.storage.copy_tree(backup, key).await?;
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
     pub async fn upload_book(&self, path: impl AsRef<Path>) -> anyhow::Result<i64> {
         let path = path.as_ref();
-        if path.is_dir() {
+        if path.is_dir() && path.join("book.toml").is_file() {
             self.upload_book_from_mdbook(path).await
-        } else if path.is_file() {
-            match path.extension().map(|s| s.to_string_lossy()) {
-                Some(ext) if ext == "epub" => {
-                    block_in_place(async || -> anyhow::Result<i64> {
-                        let output_dir = tempfile::tempdir()?;
-                        epub2mdbook::convert_epub_to_mdbook(path, &output_dir, false)?;
-                        self.upload_book_from_mdbook(&output_dir).await
-                    })
-                    .await
-                }
-                Some(ext) if ext == "zip" => {
-                    block_in_place(async || -> anyhow::Result<i64> {
-                        let output_dir = tempfile::tempdir()?;
-                        let mut zip = ZipArchive::new(File::open(path)?)?;
-                        zip.extract(&output_dir)?;
-                        self.upload_book_from_mdbook(&output_dir).await
-                    })
-                    .await
-                }
-                _ => Err(anyhow::anyhow!("Invalid book path: {}", path.display())),
-            }
         } else {
-            Err(anyhow::anyhow!("Invalid book path: {}", path.display()))
+            block_in_place(async || -> anyhow::Result<i64> {
+                let output_dir = tempfile::tempdir()?;
+                import::convert_to_mdbook(path, output_dir.path())?;
+                self.upload_book_from_mdbook(&output_dir).await
+            })
+            .await
         }
     }
 
-    pub async fn set_book_public(&self, book_id: i64, is_public: bool) -> anyhow::Result<()> {
-        sqlx::query!(
-            "update book set is_public = ? where id = ?",
-            is_public,
-            book_id
-        )
-        .execute(&self.database)
-        .await?;
+    /// drop the plans of `chapters` and generate them again, with the book plan and the
+    /// prerequisites that are based on them
+    pub async fn regenerate_plans(
+        &self,
+        book_id: i64,
+        chapters: &[ChapterNumber],
+    ) -> anyhow::Result<()> {
+        let book = self.get_book(book_id).await?;
+        let book_path = self.book_dir(book_id).await?;
+        let mut book_plan = BookTeachingPlan::load(&book_path).await;
+        for number in chapters {
+            if !book.chapters.contains_key(number) {
+                bail!("Chapter {} not found in book {}", number, book_id);
+            }
+            book_plan.chapter_plans.remove(number);
+        }
+        book_plan.teaching_plan = None;
+        book_plan.prerequisites = None;
+        book_plan.save(&book_path).await?;
+        self.reload_book(book_id).await?;
         Ok(())
     }
 
+    /// load the book again after its plans were changed, generating the missing ones,
+    /// teachers of every instance pick up the new book at their next message
+    pub async fn reload_book(&self, book_id: i64) -> anyhow::Result<Arc<Book>> {
+        let book = self.load_book(book_id).await?;
+        self.database.store_chapter_objectives(&book).await?;
+        let revision = self.database.bump_book_revision(book_id).await?;
+        // the local copy is the one the new revision was stored from
+        if let Some(local_copy) = self.local_copies.get(&book_id).map(|copy| copy.clone()) {
+            *local_copy.lock().await = Some(revision);
+        }
+        self.books.insert(book_id, (revision, book.clone())).await;
+        Ok(book)
+    }
+
+    pub async fn set_book_public(&self, book_id: i64, is_public: bool) -> anyhow::Result<()> {
+        self.database.set_book_public(book_id, is_public).await
+    }
+
+    /// resolve a file of the book, `path` is relative to `bookbase/book_{id}` and may not leave it
+    pub async fn asset_path(&self, book_id: i64, path: &str) -> anyhow::Result<PathBuf> {
+        let relative = normalize_path(Path::new(path))
+            .ok_or_else(|| anyhow::anyhow!("Invalid asset path: {}", path))?;
+        let book_dir = tokio::fs::canonicalize(self.book_dir(book_id).await?).await?;
+        // symlinks inside the book must not point outside of it either
+        let full_path = tokio::fs::canonicalize(book_dir.join(relative)).await?;
+        if !full_path.starts_with(&book_dir) || !full_path.is_file() {
+            bail!("Asset not found: {}", path);
+        }
+        Ok(full_path)
+    }
+
     pub async fn upload_books_in_dir(&self, dir: impl AsRef<Path>) -> anyhow::Result<()> {
         let mut entries = tokio::fs::read_dir(dir).await?;
         while let Some(entry) = entries.next_entry().await? {
@@ -249,23 +431,8 @@ impl Library {
     }
 
     pub async fn get_book_list(&self, public_only: bool) -> anyhow::Result<Vec<BookMeta>> {
-        let books = sqlx::query!("select id, title, authors, description, is_public from book")
-            .fetch_all(&self.database)
-            .await?;
-        let mut book_list = Vec::new();
-        for book in books {
-            if public_only && !book.is_public {
-                continue;
-            }
-            let book_meta = BookMeta {
-                id: book.id,
-                title: book.title,
-                authors: book.authors.split(',').map(|s| s.to_string()).collect(),
-                description: book.description,
-                is_public: book.is_public,
-            };
-            book_list.push(book_meta);
-        }
+        let mut book_list = self.database.book_list().await?;
+        book_list.retain(|book| !public_only || book.is_public);
         Ok(book_list)
     }
 }
@@ -278,8 +445,8 @@ mod tests {
     #[tokio::test]
     async fn test_load_books() {
         let _guard = init_log(None);
-        let database = SqlitePool::connect("./database/book.db").await.unwrap();
-        let server = Library::new(database, "./bookbase").await;
+        let database = Database::connect("./database/book.db").await.unwrap();
+        let server = Library::new(database, "./bookbase", &StorageConfig::default()).await;
         let server = match server {
             Ok(server) => server,
             Err(e) => {
@@ -0,0 +1,370 @@
+use std::{
+    str::FromStr,
+    sync::{Arc, LazyLock},
+};
+
+use anyhow::bail;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tokio::sync::Mutex;
+use utoipa::ToSchema;
+
+use super::{
+    book::{BookTeachingPlan, TEACHING_PLAN_FILE},
+    chapter::ChapterNumber,
+    library::Library,
+};
+use crate::db::Database;
+
+/// changes of the plans of a book are made one at a time
+pub(crate) static PLAN_LOCKS: LazyLock<DashMap<i64, Arc<Mutex<()>>>> = LazyLock::new(DashMap::new);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PlanChange {
+    /// generated at import, or changed outside of the manager api
+    Generated,
+    Edited,
+    Regenerated,
+    Reverted,
+}
+
+impl PlanChange {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PlanChange::Generated => "generated",
+            PlanChange::Edited => "edited",
+            PlanChange::Regenerated => "regenerated",
+            PlanChange::Reverted => "reverted",
+        }
+    }
+}
+
+impl FromStr for PlanChange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "generated" => PlanChange::Generated,
+            "edited" => PlanChange::Edited,
+            "regenerated" => PlanChange::Regenerated,
+            "reverted" => PlanChange::Reverted,
+            _ => bail!("Invalid plan change: {}", s),
+        })
+    }
+}
+
+/// A version of the teaching plan of a book, or of the plan and summary of a chapter
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PlanVersion {
+    pub id: i64,
+    pub book_id: i64,
+    /// none for the teaching plan of the book
+    #[schema(value_type = Option<String>)]
+    pub chapter_number: Option<ChapterNumber>,
+    pub plan: String,
+    /// none for the teaching plan of the book
+    pub summary: Option<String>,
+    pub change: PlanChange,
+    pub manager_id: Option<i64>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub create_time: OffsetDateTime,
+}
+
+/// the plan and summary of `chapter`, or the teaching plan of the book
+fn current_content(
+    book_plan: &BookTeachingPlan,
+    chapter: Option<&ChapterNumber>,
+) -> anyhow::Result<(String, Option<String>)> {
+    match chapter {
+        Some(number) => {
+            let Some(plan) = book_plan.chapter_plans.get(number) else {
+                bail!("Chapter {} has no plan", number);
+            };
+            Ok((plan.plan.clone(), Some(plan.summary.clone())))
+        }
+        None => Ok((book_plan.teaching_plan.clone().unwrap_or_default(), None)),
+    }
+}
+
+pub async fn get_version(database: &Database, id: i64) -> anyhow::Result<PlanVersion> {
+    database.get_plan_version(id).await
+}
+
+/// versions of the plans of a book, of one chapter if `chapter` is given, newest first
+pub async fn list_versions(
+    database: &Database,
+    book_id: i64,
+    chapter: Option<&ChapterNumber>,
+) -> anyhow::Result<Vec<PlanVersion>> {
+    let chapter = chapter.map(|n| n.to_string());
+    database
+        .list_plan_versions(book_id, chapter.as_deref())
+        .await
+}
+
+/// record the plan in `teaching_plan.toml` if it is not the latest version
+async fn record_version(
+    database: &Database,
+    book_plan: &BookTeachingPlan,
+    book_id: i64,
+    chapter: Option<&ChapterNumber>,
+    change: PlanChange,
+    manager_id: Option<i64>,
+) -> anyhow::Result<PlanVersion> {
+    let (plan, summary) = current_content(book_plan, chapter)?;
+    let number = chapter.map(|n| n.to_string());
+    if let Some(latest) = database
+        .latest_plan_version(book_id, number.as_deref())
+        .await?
+        && latest.plan == plan
+        && latest.summary == summary
+    {
+        return Ok(latest);
+    }
+    let id = database
+        .insert_plan_version(
+            book_id,
+            number.as_deref(),
+            &plan,
+            summary.as_deref(),
+            change.as_str(),
+            manager_id,
+        )
+        .await?;
+    get_version(database, id).await
+}
+
+/// The new content of a plan, unchanged parts are none
+#[derive(Debug, Clone, Default, Deserialize, ToSchema)]
+pub struct PlanEdit {
+    pub plan: Option<String>,
+    /// only used for chapters
+    pub summary: Option<String>,
+}
+
+/// apply `edit` to the plan of `chapter` or of the book, none regenerates it,
+/// and keep the previous version in the history
+async fn change_plan(
+    library: &Library,
+    book_id: i64,
+    chapter: Option<&ChapterNumber>,
+    edit: Option<PlanEdit>,
+    change: PlanChange,
+    manager_id: Option<i64>,
+) -> anyhow::Result<PlanVersion> {
+    let lock = PLAN_LOCKS.entry(book_id).or_default().clone();
+    let _guard = lock.lock().await;
+    let database = &library.database;
+    let book = library.get_book(book_id).await?;
+    if let Some(number) = chapter
+        && !book.chapters.contains_key(number)
+    {
+        bail!("Chapter {} not found in book {}", number, book_id);
+    }
+    let book_path = library.book_dir(book_id).await?;
+    let mut book_plan = BookTeachingPlan::load(&book_path).await;
+    if book_plan.chapter_plans.is_empty() {
+        bail!("Book {} has no {}", book_id, TEACHING_PLAN_FILE);
+    }
+    // changes made outside of the api become versions of their own
+    record_version(
+        database,
+        &book_plan,
+        book_id,
+        chapter,
+        PlanChange::Generated,
+        None,
+    )
+    .await?;
+
+    match (chapter, edit) {
+        (Some(number), Some(edit)) => {
+            let Some(chapter_plan) = book_plan.chapter_plans.get_mut(number) else {
+                bail!("Chapter {} has no plan", number);
+            };
+            if let Some(plan) = edit.plan
+                && plan != chapter_plan.plan
+            {
+                chapter_plan.plan = plan;
+                // extracted from the new plan when the book is loaded
+                chapter_plan.objectives.clear();
+            }
+            if let Some(summary) = edit.summary {
+                chapter_plan.summary = summary;
+            }
+        }
+        (Some(number), None) => {
+            book_plan.chapter_plans.remove(number);
+        }
+        (None, Some(edit)) => {
+            if let Some(plan) = edit.plan {
+                book_plan.teaching_plan = Some(plan);
+            }
+        }
+        (None, None) => book_plan.teaching_plan = None,
+    }
+    book_plan.save(&book_path).await?;
+    library.reload_book(book_id).await?;
+
+    let book_plan = BookTeachingPlan::load(&book_path).await;
+    record_version(database, &book_plan, book_id, chapter, change, manager_id).await
+}
+
+pub async fn edit_plan(
+    library: &Library,
+    book_id: i64,
+    chapter: Option<&ChapterNumber>,
+    edit: PlanEdit,
+    manager_id: Option<i64>,
+) -> anyhow::Result<PlanVersion> {
+    change_plan(
+        library,
+        book_id,
+        chapter,
+        Some(edit),
+        PlanChange::Edited,
+        manager_id,
+    )
+    .await
+}
+
+pub async fn regenerate_plan(
+    library: &Library,
+    book_id: i64,
+    chapter: Option<&ChapterNumber>,
+    manager_id: Option<i64>,
+) -> anyhow::Result<PlanVersion> {
+    change_plan(
+        library,
+        book_id,
+        chapter,
+        None,
+        PlanChange::Regenerated,
+        manager_id,
+    )
+    .await
+}
+
+/// make an earlier version the current plan again
+pub async fn revert_plan(
+    library: &Library,
+    book_id: i64,
+    version_id: i64,
+    manager_id: Option<i64>,
+) -> anyhow::Result<PlanVersion> {
+    let version = get_version(&library.database, version_id).await?;
+    if version.book_id != book_id {
+        bail!(
+            "Version {} is not a version of book {}",
+            version_id,
+            book_id
+        );
+    }
+    let edit = PlanEdit {
+        plan: Some(version.plan),
+        summary: version.summary,
+    };
+    change_plan(
+        library,
+        book_id,
+        version.chapter_number.as_ref(),
+        Some(edit),
+        PlanChange::Reverted,
+        manager_id,
+    )
+    .await
+}
+
+/// Line diffs between two versions of a plan
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PlanDiff {
+    pub from: i64,
+    pub to: i64,
+    pub plan: String,
+    pub summary: String,
+}
+
+/// the lines of `new` prefixed with `+`, the removed lines of `old` with `-`
+/// and the common lines with a space
+pub fn diff_lines(old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    // lengths of the longest common subsequences of the suffixes
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut diff = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            diff.push_str(&format!(" {}\n", old[i]));
+            (i, j) = (i + 1, j + 1);
+        } else if j < new.len() && (i == old.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            diff.push_str(&format!("+{}\n", new[j]));
+            j += 1;
+        } else {
+            diff.push_str(&format!("-{}\n", old[i]));
+            i += 1;
+        }
+    }
+    diff
+}
+
+/// diff two versions of the same plan, `to` defaults to the latest version
+pub async fn diff_versions(
+    database: &Database,
+    book_id: i64,
+    from: i64,
+    to: Option<i64>,
+) -> anyhow::Result<PlanDiff> {
+    let from = get_version(database, from).await?;
+    let number = from.chapter_number.as_ref().map(|n| n.to_string());
+    let to = match to {
+        Some(to) => get_version(database, to).await?,
+        None => database
+            .latest_plan_version(book_id, number.as_deref())
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("No versions of the plan"))?,
+    };
+    if from.book_id != book_id || to.book_id != book_id {
+        bail!("Versions are not versions of book {}", book_id);
+    }
+    if from.chapter_number != to.chapter_number {
+        bail!("Versions are versions of different plans");
+    }
+    Ok(PlanDiff {
+        from: from.id,
+        to: to.id,
+        plan: diff_lines(&from.plan, &to.plan),
+        summary: diff_lines(
+            from.summary.as_deref().unwrap_or_default(),
+            to.summary.as_deref().unwrap_or_default(),
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_diff() {
+        let old = "# Plan\n- objective a\n- objective b\n";
+        let new = "# Plan\n- objective a\n- objective c\n- objective b\n";
+        assert_eq!(
+            diff_lines(old, new),
+            " # Plan\n - objective a\n+- objective c\n - objective b\n"
+        );
+        assert_eq!(diff_lines("a\nb", "b\nc"), "-a\n b\n+c\n");
+        assert_eq!(diff_lines("", ""), "");
+    }
+}
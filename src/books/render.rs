@@ -0,0 +1,494 @@
+use std::{
+    collections::HashMap,
+    ops::Bound,
+    path::{Component, Path, PathBuf},
+    sync::LazyLock,
+};
+
+use pulldown_cmark::{CodeBlockKind, CowStr, Event, Options, Parser, Tag, TagEnd, html};
+use regex::{Captures, Regex};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::{
+    book::Book,
+    chapter::{Chapter, ChapterNumber},
+};
+
+/// An entry of the table of contents
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct TocItem {
+    pub number: ChapterNumber,
+    pub name: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[schema(no_recursion)]
+    pub sub_items: Vec<TocItem>,
+}
+
+/// A heading of a chapter, `id` is the anchor of the heading in the rendered html
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Section {
+    pub level: u8,
+    pub id: String,
+    pub title: String,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ChapterLink {
+    pub number: ChapterNumber,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RenderedChapter {
+    pub number: ChapterNumber,
+    pub name: String,
+    pub html: String,
+    pub sections: Vec<Section>,
+    pub prev: Option<ChapterLink>,
+    pub next: Option<ChapterLink>,
+}
+
+/// build the chapter tree of the book, a chapter is nested under the previous
+/// chapter whose number is a prefix of its own
+pub fn table_of_contents(book: &Book) -> Vec<TocItem> {
+    fn insert(items: &mut Vec<TocItem>, item: TocItem) {
+        match items.last_mut() {
+            Some(last)
+                if item.number.len() > last.number.len()
+                    && item.number.starts_with(&last.number) =>
+            {
+                insert(&mut last.sub_items, item)
+            }
+            _ => items.push(item),
+        }
+    }
+    let mut toc = vec![];
+    for chapter in book.chapters.values() {
+        insert(
+            &mut toc,
+            TocItem {
+                number: chapter.number.clone(),
+                name: chapter.name.clone(),
+                sub_items: vec![],
+            },
+        );
+    }
+    toc
+}
+
+/// resolve `.` and `..` in a relative path, `None` if it escapes the root
+pub fn normalize_path(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(c) => normalized.push(c),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    return None;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(normalized)
+}
+
+/// whether a link points inside the book, e.g. `../intro.md#setup`
+pub fn is_relative_url(url: &str) -> bool {
+    if url.is_empty() || url.starts_with('#') || url.starts_with('/') || url.starts_with('?') {
+        return false;
+    }
+    // a scheme like `https:` or `mailto:` comes before any `/`
+    match url.find(':') {
+        Some(colon) => url.find('/').is_some_and(|slash| slash < colon),
+        None => true,
+    }
+}
+
+/// `src` attribute of images written as raw html
+static HTML_IMG_SRC: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?i)(<img\b[^>]*?\bsrc\s*=\s*")([^"]*)""#).unwrap());
+
+/// Where the links of a rendered chapter point to
+#[derive(Debug, Clone, Copy)]
+pub enum LinkStyle<'a> {
+    /// `{base}/chapter/{number}` and `{base}/asset/{path}` of the reader api
+    Api(&'a str),
+    /// the files of an epub, chapters are `chapter-{number}xhtml` next to the book files
+    Epub,
+}
+
+/// file name of a chapter in an epub
+pub fn epub_chapter_file(number: &ChapterNumber) -> String {
+    format!("chapter-{}xhtml", number)
+}
+
+struct ChapterRenderer<'a> {
+    book: &'a Book,
+    chapter: &'a Chapter,
+    links: LinkStyle<'a>,
+    chapter_paths: HashMap<&'a Path, &'a ChapterNumber>,
+}
+
+impl ChapterRenderer<'_> {
+    fn chapter_dir(&self) -> &Path {
+        self.chapter
+            .path
+            .as_deref()
+            .and_then(Path::parent)
+            .unwrap_or(Path::new(""))
+    }
+
+    /// url of a file of the book, `path` is relative to the source directory
+    fn asset_url(&self, path: &Path) -> String {
+        let path = self.book.src_dir.join(path);
+        let path: Vec<_> = path.iter().map(|c| c.to_string_lossy()).collect();
+        match self.links {
+            LinkStyle::Api(base) => format!("{}/asset/{}", base, path.join("/")),
+            LinkStyle::Epub => path.join("/"),
+        }
+    }
+
+    fn chapter_url(&self, number: &ChapterNumber) -> String {
+        match self.links {
+            LinkStyle::Api(base) => format!("{}/chapter/{}", base, number),
+            LinkStyle::Epub => epub_chapter_file(number),
+        }
+    }
+
+    /// rewrite links to other chapters (`other.md#anchor`) to the reader route
+    /// and links to other files of the book to the asset route
+    fn fix_link<'e>(&self, url: CowStr<'e>) -> CowStr<'e> {
+        if !is_relative_url(&url) {
+            return url;
+        }
+        let (path, fragment) = match url.split_once('#') {
+            Some((path, fragment)) => (path, format!("#{fragment}")),
+            None => (url.as_ref(), String::new()),
+        };
+        let Some(target) = normalize_path(&self.chapter_dir().join(path)) else {
+            return url;
+        };
+        // links to the rendered mdbook point to .html files
+        let number = self.chapter_paths.get(target.as_path()).or_else(|| {
+            self.chapter_paths
+                .get(target.with_extension("md").as_path())
+        });
+        match number {
+            Some(number) => format!("{}{}", self.chapter_url(number), fragment).into(),
+            None if matches!(
+                target.extension().and_then(|ext| ext.to_str()),
+                Some("md" | "html")
+            ) =>
+            {
+                url
+            }
+            None => format!("{}{}", self.asset_url(&target), fragment).into(),
+        }
+    }
+
+    /// rewrite relative image sources to the asset route
+    fn fix_image<'e>(&self, url: CowStr<'e>) -> CowStr<'e> {
+        if !is_relative_url(&url) {
+            return url;
+        }
+        match normalize_path(&self.chapter_dir().join(url.as_ref())) {
+            Some(target) => self.asset_url(&target).into(),
+            None => url,
+        }
+    }
+
+    fn fix_html<'e>(&self, html: CowStr<'e>) -> CowStr<'e> {
+        if !HTML_IMG_SRC.is_match(&html) {
+            return html;
+        }
+        HTML_IMG_SRC
+            .replace_all(&html, |caps: &Captures| {
+                let src = self.fix_image(caps[2].to_string().into());
+                format!("{}{}\"", &caps[1], src)
+            })
+            .into_owned()
+            .into()
+    }
+
+    fn render(&self) -> (String, Vec<Section>) {
+        let mut options = Options::empty();
+        options.insert(Options::ENABLE_TABLES);
+        options.insert(Options::ENABLE_FOOTNOTES);
+        options.insert(Options::ENABLE_STRIKETHROUGH);
+        options.insert(Options::ENABLE_TASKLISTS);
+        options.insert(Options::ENABLE_HEADING_ATTRIBUTES);
+        let mut events: Vec<Event> = Parser::new_ext(&self.chapter.content, options).collect();
+
+        // give every heading the same anchor mdbook would
+        let mut sections = vec![];
+        let mut id_counter = HashMap::new();
+        for i in 0..events.len() {
+            if !matches!(events[i], Event::Start(Tag::Heading { .. })) {
+                continue;
+            }
+            let title = heading_text(&events[i + 1..]);
+            if let Event::Start(Tag::Heading { level, id, .. }) = &mut events[i] {
+                let anchor = match id {
+                    Some(id) => id.to_string(),
+                    None => mdbook::utils::unique_id_from_content(&title, &mut id_counter),
+                };
+                *id = Some(anchor.clone().into());
+                sections.push(Section {
+                    level: *level as u8,
+                    id: anchor,
+                    title,
+                });
+            }
+        }
+
+        let events = events.into_iter().map(|event| match event {
+            Event::Start(Tag::CodeBlock(kind)) => {
+                let lang = match &kind {
+                    CodeBlockKind::Fenced(info) => info
+                        .split(|c: char| c == ',' || c.is_whitespace())
+                        .next()
+                        .unwrap_or_default()
+                        .chars()
+                        .filter(|c| c.is_ascii_alphanumeric() || "-_+#.".contains(*c))
+                        .collect(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                let class = if lang.is_empty() {
+                    "hljs".to_string()
+                } else {
+                    format!("language-{lang} hljs")
+                };
+                Event::Html(format!("<pre><code class=\"{class}\">").into())
+            }
+            Event::End(TagEnd::CodeBlock) => Event::Html("</code></pre>\n".into()),
+            Event::Start(Tag::Link {
+                link_type,
+                dest_url,
+                title,
+                id,
+            }) => Event::Start(Tag::Link {
+                link_type,
+                dest_url: self.fix_link(dest_url),
+                title,
+                id,
+            }),
+            Event::Start(Tag::Image {
+                link_type,
+                dest_url,
+                title,
+                id,
+            }) => Event::Start(Tag::Image {
+                link_type,
+                dest_url: self.fix_image(dest_url),
+                title,
+                id,
+            }),
+            Event::Html(html) => Event::Html(self.fix_html(html)),
+            Event::InlineHtml(html) => Event::InlineHtml(self.fix_html(html)),
+            event => event,
+        });
+        let mut output = String::with_capacity(self.chapter.content.len() * 3 / 2);
+        html::push_html(&mut output, events);
+        (output, sections)
+    }
+
+    fn link(chapter: &Chapter) -> ChapterLink {
+        ChapterLink {
+            number: chapter.number.clone(),
+            name: chapter.name.clone(),
+        }
+    }
+
+    fn rendered(&self) -> RenderedChapter {
+        let (html, sections) = self.render();
+        let number = &self.chapter.number;
+        let prev = self
+            .book
+            .chapters
+            .range(..number)
+            .next_back()
+            .map(|(_, ch)| Self::link(ch));
+        let next = self
+            .book
+            .chapters
+            .range((Bound::Excluded(number), Bound::Unbounded))
+            .next()
+            .map(|(_, ch)| Self::link(ch));
+        RenderedChapter {
+            number: number.clone(),
+            name: self.chapter.name.clone(),
+            html,
+            sections,
+            prev,
+            next,
+        }
+    }
+}
+
+/// plain text of the heading starting right before `events`
+fn heading_text(events: &[Event]) -> String {
+    let mut title = String::new();
+    for event in events {
+        match event {
+            Event::End(TagEnd::Heading(_)) => break,
+            Event::Text(text) | Event::Code(text) => title.push_str(text),
+            _ => {}
+        }
+    }
+    title
+}
+
+/// render a chapter to html, links to other chapters point to `{link_base}/chapter/{number}`
+/// and images and attachments to `{link_base}/asset/{path}`
+pub fn render_chapter(
+    book: &Book,
+    number: &ChapterNumber,
+    link_base: &str,
+) -> anyhow::Result<RenderedChapter> {
+    render_chapter_with(book, number, LinkStyle::Api(link_base))
+}
+
+pub fn render_chapter_with(
+    book: &Book,
+    number: &ChapterNumber,
+    links: LinkStyle,
+) -> anyhow::Result<RenderedChapter> {
+    let chapter = book
+        .chapters
+        .get(number)
+        .ok_or(anyhow::anyhow!("Chapter not found: {}", number))?;
+    let chapter_paths = book
+        .chapters
+        .values()
+        .filter_map(|ch| ch.path.as_deref().map(|path| (path, &ch.number)))
+        .collect();
+    let renderer = ChapterRenderer {
+        book,
+        chapter,
+        links,
+        chapter_paths,
+    };
+    Ok(renderer.rendered())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::books::chapter::ChapterPlan;
+
+    fn book() -> Book {
+        let chapters: BTreeMap<ChapterNumber, Chapter> = [
+            ("1.", "Intro", "intro.md", "# Intro\n\nSee [setup](guide/setup.md#install)."),
+            (
+                "1.1.",
+                "Setup",
+                "guide/setup.md",
+                "# Setup\n\n## Install\n\n## Install\n\n```rust,ignore\nfn main() {}\n```\n\n[back](../intro.html) [web](https://example.com)\n\n![logo](../img/logo.png) [data](files/data.csv)\n\n<img alt=\"x\" src=\"img/a.svg\">",
+            ),
+            ("2.", "Usage", "usage.md", "# Usage"),
+        ]
+        .into_iter()
+        .map(|(number, name, path, content)| {
+            let number: ChapterNumber = number.parse().unwrap();
+            let chapter = Chapter {
+                name: name.to_string(),
+                number: number.clone(),
+                path: Some(PathBuf::from(path)),
+                content: content.to_string(),
+                chapter_plan: ChapterPlan {
+                    plan: String::new(),
+                    summary: String::new(),
+                    objectives: vec![],
+                },
+            };
+            (number, chapter)
+        })
+        .collect();
+        Book {
+            id: 1,
+            title: "Test".to_string(),
+            chapter_numbers: chapters.keys().cloned().collect(),
+            table_of_contents: String::new(),
+            authors: vec![],
+            description: None,
+            teaching_plan: String::new(),
+            prerequisites: BTreeMap::new(),
+            chapters,
+            src_dir: PathBuf::from("src"),
+            figure_descriptions: BTreeMap::new(),
+            content_hash: String::new(),
+        }
+    }
+
+    #[test]
+    fn toc_tree() {
+        let toc = table_of_contents(&book());
+        assert_eq!(toc.len(), 2);
+        assert_eq!(toc[0].sub_items.len(), 1);
+        assert_eq!(toc[0].sub_items[0].name, "Setup");
+    }
+
+    #[test]
+    fn render_links_and_anchors() {
+        let book = book();
+        let intro = render_chapter(&book, &"1.".parse().unwrap(), "/book/1").unwrap();
+        assert!(
+            intro
+                .html
+                .contains(r#"href="/book/1/chapter/1.1.#install""#)
+        );
+        assert!(intro.prev.is_none());
+        assert_eq!(intro.next.unwrap().name, "Setup");
+
+        let setup = render_chapter(&book, &"1.1.".parse().unwrap(), "/book/1").unwrap();
+        let ids: Vec<&str> = setup.sections.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, ["setup", "install", "install-1"]);
+        assert!(setup.html.contains(r#"<h2 id="install-1">"#));
+        assert!(setup.html.contains(r#"<code class="language-rust hljs">"#));
+        assert!(setup.html.contains(r#"href="/book/1/chapter/1.""#));
+        assert!(setup.html.contains(r#"href="https://example.com""#));
+        assert!(
+            setup
+                .html
+                .contains(r#"src="/book/1/asset/src/img/logo.png""#)
+        );
+        assert!(
+            setup
+                .html
+                .contains(r#"href="/book/1/asset/src/guide/files/data.csv""#)
+        );
+        assert!(
+            setup
+                .html
+                .contains(r#"src="/book/1/asset/src/guide/img/a.svg""#)
+        );
+    }
+
+    #[test]
+    fn render_epub_links() {
+        let book = book();
+        let setup = render_chapter_with(&book, &"1.1.".parse().unwrap(), LinkStyle::Epub).unwrap();
+        assert!(setup.html.contains(r#"href="chapter-1.xhtml""#));
+        assert!(setup.html.contains(r#"src="src/img/logo.png""#));
+        assert!(setup.html.contains(r#"src="src/guide/img/a.svg""#));
+    }
+
+    #[test]
+    fn relative_urls() {
+        assert!(is_relative_url("../a.md"));
+        assert!(is_relative_url("img/a:b.png"));
+        assert!(!is_relative_url("https://example.com"));
+        assert!(!is_relative_url("mailto:me@example.com"));
+        assert!(!is_relative_url("#anchor"));
+        assert_eq!(
+            normalize_path(Path::new("a/../b/./c")),
+            Some(PathBuf::from("b/c"))
+        );
+        assert_eq!(normalize_path(Path::new("../a")), None);
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::{
+    book::{BookRaw, BookTeachingPlan},
+    chapter::{ChapterNumber, ChapterRaw},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ChapterChange {
+    Unchanged,
+    /// same name and content under another number
+    Moved,
+    /// same name or number with another content
+    Modified,
+    Added,
+    Removed,
+}
+
+/// How a chapter of the imported version relates to the current version of a book
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct ChapterDiff {
+    #[schema(value_type = Option<String>)]
+    pub old_number: Option<ChapterNumber>,
+    #[schema(value_type = Option<String>)]
+    pub new_number: Option<ChapterNumber>,
+    pub name: String,
+    pub change: ChapterChange,
+}
+
+/// match the chapters of two versions of a book: first by content, then by name and
+/// last by number (a renamed chapter), chapters without a match were added or removed
+pub fn diff_chapters(old: &BookRaw, new: &BookRaw) -> Vec<ChapterDiff> {
+    let old_chapters: Vec<_> = old.iter().map(|ch| (ch, ch.content_hash())).collect();
+    let new_chapters: Vec<_> = new.iter().map(|ch| (ch, ch.content_hash())).collect();
+    let mut matches: BTreeMap<usize, usize> = BTreeMap::new();
+    let mut matched_old = BTreeSet::new();
+
+    type Pair<'a> = (&'a ChapterRaw, String);
+    let passes: [&dyn Fn(&Pair, &Pair) -> bool; 4] = [
+        &|(old, old_hash), (new, new_hash)| old_hash == new_hash && old.number == new.number,
+        &|(_, old_hash), (_, new_hash)| old_hash == new_hash,
+        &|(old, _), (new, _)| old.name == new.name,
+        &|(old, _), (new, _)| old.number == new.number,
+    ];
+    for same in passes {
+        for (new_index, new_chapter) in new_chapters.iter().enumerate() {
+            if matches.contains_key(&new_index) {
+                continue;
+            }
+            let found = (0..old_chapters.len())
+                .find(|i| !matched_old.contains(i) && same(&old_chapters[*i], new_chapter));
+            if let Some(old_index) = found {
+                matches.insert(new_index, old_index);
+                matched_old.insert(old_index);
+            }
+        }
+    }
+
+    let mut diffs: Vec<ChapterDiff> = new_chapters
+        .iter()
+        .enumerate()
+        .map(|(new_index, (new_chapter, new_hash))| {
+            let old = matches.get(&new_index).map(|i| &old_chapters[*i]);
+            let change = match old {
+                None => ChapterChange::Added,
+                Some((_, old_hash)) if old_hash != new_hash => ChapterChange::Modified,
+                Some((old_chapter, _)) if old_chapter.number != new_chapter.number => {
+                    ChapterChange::Moved
+                }
+                Some(_) => ChapterChange::Unchanged,
+            };
+            ChapterDiff {
+                old_number: old.map(|(ch, _)| ch.number.clone()),
+                new_number: Some(new_chapter.number.clone()),
+                name: new_chapter.name.clone(),
+                change,
+            }
+        })
+        .collect();
+    diffs.extend(
+        old_chapters
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !matched_old.contains(i))
+            .map(|(_, (ch, _))| ChapterDiff {
+                old_number: Some(ch.number.clone()),
+                new_number: None,
+                name: ch.name.clone(),
+                change: ChapterChange::Removed,
+            }),
+    );
+    diffs
+}
+
+/// the plans of the new version that can be kept: those of unchanged and moved chapters,
+/// and the book plan if no chapter changed at all
+pub fn carry_over_plans(old_plan: &BookTeachingPlan, diffs: &[ChapterDiff]) -> BookTeachingPlan {
+    let mut plan = BookTeachingPlan {
+        figure_descriptions: old_plan.figure_descriptions.clone(),
+        ..Default::default()
+    };
+    for diff in diffs {
+        if let (ChapterChange::Unchanged | ChapterChange::Moved, Some(old), Some(new)) =
+            (diff.change, &diff.old_number, &diff.new_number)
+            && let Some(chapter_plan) = old_plan.chapter_plans.get(old)
+        {
+            plan.chapter_plans.insert(new.clone(), chapter_plan.clone());
+        }
+    }
+    if diffs
+        .iter()
+        .all(|diff| diff.change == ChapterChange::Unchanged)
+    {
+        plan.teaching_plan = old_plan.teaching_plan.clone();
+        plan.prerequisites = old_plan.prerequisites.clone();
+    }
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::books::chapter::ChapterPlan;
+
+    fn book(chapters: &[(&str, &str, &str)]) -> BookRaw {
+        BookRaw {
+            id: 0,
+            title: "Test".to_string(),
+            chapters: chapters
+                .iter()
+                .map(|(number, name, content)| {
+                    let number: ChapterNumber = number.parse().unwrap();
+                    let chapter = ChapterRaw {
+                        name: name.to_string(),
+                        number: number.clone(),
+                        content: content.to_string(),
+                        ..Default::default()
+                    };
+                    (number, chapter)
+                })
+                .collect(),
+            authors: vec![],
+            description: None,
+            src_dir: PathBuf::from("src"),
+            content_hash: String::new(),
+        }
+    }
+
+    fn change(
+        diffs: &[ChapterDiff],
+        name: &str,
+    ) -> (Option<String>, Option<String>, ChapterChange) {
+        let diff = diffs.iter().find(|diff| diff.name == name).unwrap();
+        (
+            diff.old_number.as_ref().map(|n| n.to_string()),
+            diff.new_number.as_ref().map(|n| n.to_string()),
+            diff.change,
+        )
+    }
+
+    #[test]
+    fn chapter_changes() {
+        let old = book(&[
+            ("1.", "Intro", "hello"),
+            ("2.", "Loops", "for"),
+            ("3.", "Types", "i32"),
+            ("4.", "Old", "gone"),
+        ]);
+        let new = book(&[
+            ("1.", "Intro", "hello"),
+            ("2.", "Types", "i32"),
+            ("3.", "Loops", "for and while"),
+            ("4.", "New", "added"),
+        ]);
+        let diffs = diff_chapters(&old, &new);
+        let some = |n: &str| Some(n.to_string());
+        assert_eq!(
+            change(&diffs, "Intro"),
+            (some("1."), some("1."), ChapterChange::Unchanged)
+        );
+        assert_eq!(
+            change(&diffs, "Types"),
+            (some("3."), some("2."), ChapterChange::Moved)
+        );
+        assert_eq!(
+            change(&diffs, "Loops"),
+            (some("2."), some("3."), ChapterChange::Modified)
+        );
+        // the only chapter left with its number is taken as renamed
+        assert_eq!(
+            change(&diffs, "New"),
+            (some("4."), some("4."), ChapterChange::Modified)
+        );
+        assert_eq!(diffs.len(), 4);
+
+        let plan = |text: &str| ChapterPlan {
+            plan: text.to_string(),
+            summary: String::new(),
+            objectives: vec![],
+        };
+        let old_plan = BookTeachingPlan {
+            teaching_plan: Some("book".to_string()),
+            chapter_plans: [("1.", "intro"), ("2.", "loops"), ("3.", "types")]
+                .into_iter()
+                .map(|(number, text)| (number.parse().unwrap(), plan(text)))
+                .collect(),
+            ..Default::default()
+        };
+        let new_plan = carry_over_plans(&old_plan, &diffs);
+        assert_eq!(new_plan.teaching_plan, None);
+        assert_eq!(
+            new_plan
+                .chapter_plans
+                .iter()
+                .map(|(number, plan)| (number.to_string(), plan.plan.as_str()))
+                .collect::<Vec<_>>(),
+            [("1.".to_string(), "intro"), ("2.".to_string(), "types")]
+        );
+
+        let removed = diff_chapters(&old, &book(&[("1.", "Intro", "hello")]));
+        assert_eq!(
+            change(&removed, "Old"),
+            (some("4."), None, ChapterChange::Removed)
+        );
+    }
+}
@@ -0,0 +1,387 @@
+use std::{
+    fmt::Debug,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{Context, bail};
+use futures::{StreamExt, TryStreamExt, future::BoxFuture};
+use object_store::{
+    ObjectStore, PutPayload, aws::AmazonS3Builder, memory::InMemory, path::Path as ObjectPath,
+};
+use tokio::task::spawn_blocking;
+
+use crate::config::{StorageBackend, StorageConfig};
+
+/// Where the files of the books are kept. Keys are `/` separated paths relative to the
+/// root of the storage like `book_1/src/intro.md`, a prefix stands for a directory.
+pub trait BookStorage: Send + Sync + Debug {
+    /// keys of the files under `prefix`, all files if it is empty
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, anyhow::Result<Vec<String>>>;
+    fn read<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<Vec<u8>>>;
+    fn write<'a>(&'a self, key: &'a str, data: Vec<u8>) -> BoxFuture<'a, anyhow::Result<()>>;
+    /// delete the files under `prefix`
+    fn delete<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, anyhow::Result<()>>;
+    /// replace the files under `to` by a copy of the files under `from`
+    fn copy_tree<'a>(&'a self, from: &'a str, to: &'a str) -> BoxFuture<'a, anyhow::Result<()>>;
+    /// directory the keys are relative to, when the books are on the local file system
+    fn local_root(&self) -> Option<&Path> {
+        None
+    }
+}
+
+/// key of a file under `prefix`
+pub fn join_key(prefix: &str, path: &Path) -> String {
+    let mut key = prefix.trim_end_matches('/').to_string();
+    for part in path.iter() {
+        if !key.is_empty() {
+            key.push('/');
+        }
+        key.push_str(&part.to_string_lossy());
+    }
+    key
+}
+
+/// keys may not leave the root of the storage
+fn check_key(key: &str) -> anyhow::Result<&str> {
+    if key.split('/').any(|part| part == ".." || part == ".") || key.starts_with('/') {
+        bail!("Invalid storage key: {}", key);
+    }
+    Ok(key)
+}
+
+/// write the files of the local directory `dir` under `prefix`
+pub async fn upload_dir(storage: &dyn BookStorage, dir: &Path, prefix: &str) -> anyhow::Result<()> {
+    let root = dir.to_path_buf();
+    let files = spawn_blocking(move || -> Vec<PathBuf> {
+        walkdir::WalkDir::new(&root)
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file())
+            .filter_map(|entry| entry.path().strip_prefix(&root).ok().map(Path::to_path_buf))
+            .collect()
+    })
+    .await?;
+    for path in files {
+        let data = tokio::fs::read(dir.join(&path)).await?;
+        storage.write(&join_key(prefix, &path), data).await?;
+    }
+    Ok(())
+}
+
+/// replace the local directory `dir` by the files under `prefix`
+pub async fn download_dir(
+    storage: &dyn BookStorage,
+    prefix: &str,
+    dir: &Path,
+) -> anyhow::Result<()> {
+    let keys = storage.list(prefix).await?;
+    if keys.is_empty() {
+        bail!("Nothing stored under {}", prefix);
+    }
+    let _ = tokio::fs::remove_dir_all(dir).await;
+    let prefix = format!("{}/", prefix.trim_end_matches('/'));
+    for key in keys {
+        let Some(relative) = key.strip_prefix(&prefix) else {
+            continue;
+        };
+        let path = dir.join(check_key(relative)?);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&path, storage.read(&key).await?).await?;
+    }
+    Ok(())
+}
+
+/// books in a directory of the local file system
+#[derive(Debug, Clone)]
+pub struct FileStorage {
+    root: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    fn path(&self, key: &str) -> anyhow::Result<PathBuf> {
+        Ok(self.root.join(check_key(key.trim_end_matches('/'))?))
+    }
+}
+
+impl BookStorage for FileStorage {
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, anyhow::Result<Vec<String>>> {
+        Box::pin(async move {
+            let dir = self.path(prefix)?;
+            let root = self.root.clone();
+            let keys = spawn_blocking(move || {
+                let mut keys: Vec<String> = walkdir::WalkDir::new(&dir)
+                    .into_iter()
+                    .filter_map(|entry| entry.ok())
+                    .filter(|entry| entry.file_type().is_file())
+                    .filter_map(|entry| {
+                        let relative = entry.path().strip_prefix(&root).ok()?;
+                        Some(join_key("", relative))
+                    })
+                    .collect();
+                keys.sort();
+                keys
+            })
+            .await?;
+            Ok(keys)
+        })
+    }
+
+    fn read<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<Vec<u8>>> {
+        Box::pin(async move {
+            let path = self.path(key)?;
+            tokio::fs::read(&path)
+                .await
+                .with_context(|| format!("read {}", path.display()))
+        })
+    }
+
+    fn write<'a>(&'a self, key: &'a str, data: Vec<u8>) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let path = self.path(key)?;
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::write(&path, data).await?;
+            Ok(())
+        })
+    }
+
+    fn delete<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let path = self.path(prefix)?;
+            let result = if path.is_dir() {
+                tokio::fs::remove_dir_all(&path).await
+            } else {
+                tokio::fs::remove_file(&path).await
+            };
+            match result {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            }
+        })
+    }
+
+    fn copy_tree<'a>(&'a self, from: &'a str, to: &'a str) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let (from, to) = (self.path(from)?, self.path(to)?);
+            let _ = tokio::fs::remove_dir_all(&to).await;
+            tokio::fs::create_dir_all(&to).await?;
+            let copy_options = fs_extra::dir::CopyOptions {
+                overwrite: true,
+                content_only: true,
+                ..Default::default()
+            };
+            spawn_blocking(move || fs_extra::dir::copy(from, &to, &copy_options)).await??;
+            Ok(())
+        })
+    }
+
+    fn local_root(&self) -> Option<&Path> {
+        Some(&self.root)
+    }
+}
+
+/// books in an S3 compatible object store, like AWS S3 or MinIO
+#[derive(Debug, Clone)]
+pub struct S3Storage {
+    store: Arc<dyn ObjectStore>,
+}
+
+impl S3Storage {
+    pub fn new(store: Arc<dyn ObjectStore>) -> Self {
+        Self { store }
+    }
+
+    /// the bucket of `config`, with its endpoint for stores other than AWS, credentials
+    /// and region are read from the usual `AWS_*` variables
+    pub fn from_config(config: &StorageConfig) -> anyhow::Result<Self> {
+        let bucket = config
+            .bucket
+            .as_deref()
+            .context("storage.bucket is not set")?;
+        let mut builder = AmazonS3Builder::from_env().with_bucket_name(bucket);
+        if let Some(endpoint) = &config.endpoint {
+            builder = builder
+                .with_allow_http(endpoint.starts_with("http://"))
+                .with_endpoint(endpoint);
+        }
+        Ok(Self::new(Arc::new(builder.build()?)))
+    }
+
+    /// an in memory store that behaves like a bucket, for tests
+    pub fn in_memory() -> Self {
+        Self::new(Arc::new(InMemory::new()))
+    }
+
+    fn path(key: &str) -> anyhow::Result<ObjectPath> {
+        Ok(ObjectPath::parse(check_key(key.trim_end_matches('/'))?)?)
+    }
+}
+
+impl BookStorage for S3Storage {
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, anyhow::Result<Vec<String>>> {
+        Box::pin(async move {
+            let prefix = Self::path(prefix)?;
+            let mut keys: Vec<String> = self
+                .store
+                .list(Some(&prefix))
+                .map_ok(|meta| meta.location.to_string())
+                .try_collect()
+                .await?;
+            keys.sort();
+            Ok(keys)
+        })
+    }
+
+    fn read<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<Vec<u8>>> {
+        Box::pin(async move {
+            let data = self.store.get(&Self::path(key)?).await?.bytes().await?;
+            Ok(data.to_vec())
+        })
+    }
+
+    fn write<'a>(&'a self, key: &'a str, data: Vec<u8>) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            self.store
+                .put(&Self::path(key)?, PutPayload::from(data))
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn delete<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let keys = self.list(prefix).await?;
+            futures::stream::iter(keys)
+                .map(|key| async move { self.store.delete(&ObjectPath::from(key)).await })
+                .buffer_unordered(16)
+                .try_collect::<Vec<_>>()
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn copy_tree<'a>(&'a self, from: &'a str, to: &'a str) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            self.delete(to).await?;
+            let from_prefix = format!("{}/", from.trim_end_matches('/'));
+            let keys = self.list(from).await?;
+            futures::stream::iter(keys)
+                .map(|key| {
+                    let target = key
+                        .strip_prefix(&from_prefix)
+                        .map(|relative| join_key(to, Path::new(relative)));
+                    async move {
+                        let Some(target) = target else {
+                            return Ok(());
+                        };
+                        self.store
+                            .copy(&ObjectPath::from(key), &Self::path(&target)?)
+                            .await?;
+                        anyhow::Ok(())
+                    }
+                })
+                .buffer_unordered(16)
+                .try_collect::<Vec<_>>()
+                .await?;
+            Ok(())
+        })
+    }
+}
+
+/// the storage of `storage.backend`, `fs` keeps the books in `bookbase` and `s3` in the
+/// bucket of [`S3Storage::from_config`]
+pub fn from_config(
+    config: &StorageConfig,
+    bookbase: &Path,
+) -> anyhow::Result<Arc<dyn BookStorage>> {
+    match config.backend {
+        StorageBackend::Fs => Ok(Arc::new(FileStorage::new(bookbase))),
+        StorageBackend::S3 => Ok(Arc::new(S3Storage::from_config(config)?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn round_trip(storage: &dyn BookStorage) {
+        storage
+            .write("book_1/book.toml", b"[book]".to_vec())
+            .await
+            .unwrap();
+        storage
+            .write("book_1/src/intro.md", b"# Intro".to_vec())
+            .await
+            .unwrap();
+        storage
+            .write("book_10/book.toml", b"[book]".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.list("book_1").await.unwrap(),
+            ["book_1/book.toml", "book_1/src/intro.md"]
+        );
+        assert_eq!(storage.list("").await.unwrap().len(), 3);
+        assert_eq!(
+            storage.read("book_1/src/intro.md").await.unwrap(),
+            b"# Intro"
+        );
+        assert!(storage.read("book_1/../book_10/book.toml").await.is_err());
+
+        storage.copy_tree("book_1", "book_2").await.unwrap();
+        assert_eq!(
+            storage.list("book_2").await.unwrap(),
+            ["book_2/book.toml", "book_2/src/intro.md"]
+        );
+        storage.delete("book_1").await.unwrap();
+        assert!(storage.list("book_1").await.unwrap().is_empty());
+        assert_eq!(storage.list("book_10").await.unwrap().len(), 1);
+
+        let dir = tempfile::tempdir().unwrap();
+        download_dir(storage, "book_2", dir.path()).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("src/intro.md")).unwrap(),
+            "# Intro"
+        );
+        upload_dir(storage, dir.path(), "book_3").await.unwrap();
+        assert_eq!(storage.list("book_3").await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn file_storage() {
+        let dir = tempfile::tempdir().unwrap();
+        round_trip(&FileStorage::new(dir.path())).await;
+    }
+
+    #[tokio::test]
+    async fn s3_storage() {
+        round_trip(&S3Storage::in_memory()).await;
+    }
+
+    /// runs against a real bucket, e.g. a local MinIO, when `S3_TEST_BUCKET` is set
+    #[tokio::test]
+    async fn s3_storage_bucket() {
+        let Ok(bucket) = dotenvy::var("S3_TEST_BUCKET") else {
+            return;
+        };
+        let mut builder = AmazonS3Builder::from_env().with_bucket_name(bucket);
+        if let Ok(endpoint) = dotenvy::var("S3_ENDPOINT") {
+            builder = builder.with_allow_http(true).with_endpoint(endpoint);
+        }
+        let storage = S3Storage::new(Arc::new(builder.build().unwrap()));
+        for prefix in ["book_1", "book_2", "book_3", "book_10"] {
+            storage.delete(prefix).await.unwrap();
+        }
+        round_trip(&storage).await;
+    }
+}
@@ -2,10 +2,11 @@ use std::sync::Arc;
 
 use async_openai::tools::Tool;
 use schemars::JsonSchema;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use super::{
     chapter::{Chapter, ChapterNumber},
+    figure::{self, Figure},
     library::Library,
 };
 
@@ -20,9 +21,18 @@ impl GetChapterTool {
     }
 }
 
+#[derive(Debug, Clone, Serialize)]
+pub struct ChapterContent {
+    #[serde(flatten)]
+    pub chapter: Chapter,
+    /// images of the chapter, use GetFigure to look at one
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub figures: Vec<Figure>,
+}
+
 impl Tool for GetChapterTool {
     type Args = ChapterNumber;
-    type Output = Chapter;
+    type Output = ChapterContent;
     type Error = anyhow::Error;
     fn name() -> String {
         "GetChapterContent".to_string()
@@ -40,7 +50,10 @@ impl Tool for GetChapterTool {
             .chapters
             .get(&args)
             .ok_or(anyhow::anyhow!("Chapter not found: {:?}", args))?;
-        Ok(chapter.clone())
+        Ok(ChapterContent {
+            chapter: chapter.clone(),
+            figures: figure::chapter_figures(&book, chapter),
+        })
     }
 }
 #[tokio::test]
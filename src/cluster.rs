@@ -0,0 +1,23 @@
+pub mod session;
+pub mod turn_lock;
+
+use redis::aio::ConnectionManager;
+
+use crate::config::RedisConfig;
+
+/// connect to `redis.url`, none if it is not set and the server runs as a single instance
+pub async fn connect_redis(config: &RedisConfig) -> anyhow::Result<Option<ConnectionManager>> {
+    let Some(url) = &config.url else {
+        return Ok(None);
+    };
+    let client = redis::Client::open(url.as_str())?;
+    Ok(Some(ConnectionManager::new(client).await?))
+}
+
+/// the connection of the tests, none if `REDIS_TEST_URL` is not set
+#[cfg(test)]
+pub(crate) async fn test_redis() -> Option<ConnectionManager> {
+    let url = dotenvy::var("REDIS_TEST_URL").ok()?;
+    let client = redis::Client::open(url).unwrap();
+    Some(ConnectionManager::new(client).await.unwrap())
+}
@@ -0,0 +1,127 @@
+use std::fmt;
+
+use async_trait::async_trait;
+use redis::{AsyncCommands, aio::ConnectionManager};
+use tower_sessions::{
+    SessionStore,
+    session::{Id, Record},
+    session_store,
+};
+
+/// Sessions kept in Redis so that every instance sees them, a session expires in Redis
+/// together with its cookie
+#[derive(Clone)]
+pub struct RedisStore {
+    connection: ConnectionManager,
+}
+
+impl fmt::Debug for RedisStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedisStore").finish_non_exhaustive()
+    }
+}
+
+fn backend(e: redis::RedisError) -> session_store::Error {
+    session_store::Error::Backend(e.to_string())
+}
+
+impl RedisStore {
+    pub fn new(connection: ConnectionManager) -> Self {
+        Self { connection }
+    }
+
+    fn key(id: &Id) -> String {
+        format!("session:{}", id)
+    }
+
+    /// store the record, if `only_new` only when no session has its id yet
+    async fn set(&self, record: &Record, only_new: bool) -> session_store::Result<bool> {
+        let data =
+            serde_json::to_vec(record).map_err(|e| session_store::Error::Encode(e.to_string()))?;
+        let mut command = redis::cmd("SET");
+        command
+            .arg(Self::key(&record.id))
+            .arg(data)
+            .arg("EXAT")
+            .arg(record.expiry_date.unix_timestamp());
+        if only_new {
+            command.arg("NX");
+        }
+        let reply: Option<String> = command
+            .query_async(&mut self.connection.clone())
+            .await
+            .map_err(backend)?;
+        Ok(reply.is_some())
+    }
+}
+
+#[async_trait]
+impl SessionStore for RedisStore {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        while !self.set(record, true).await? {
+            record.id = Id::default();
+        }
+        Ok(())
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        self.set(record, false).await?;
+        Ok(())
+    }
+
+    async fn load(&self, id: &Id) -> session_store::Result<Option<Record>> {
+        let data: Option<Vec<u8>> = self
+            .connection
+            .clone()
+            .get(Self::key(id))
+            .await
+            .map_err(backend)?;
+        data.map(|data| serde_json::from_slice(&data))
+            .transpose()
+            .map_err(|e| session_store::Error::Decode(e.to_string()))
+    }
+
+    async fn delete(&self, id: &Id) -> session_store::Result<()> {
+        let _: () = self
+            .connection
+            .clone()
+            .del(Self::key(id))
+            .await
+            .map_err(backend)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use time::{Duration, OffsetDateTime};
+
+    use super::*;
+    use crate::cluster::test_redis;
+
+    #[tokio::test]
+    async fn redis_sessions() {
+        let Some(connection) = test_redis().await else {
+            return;
+        };
+        let store = RedisStore::new(connection);
+        let mut record = Record {
+            id: Id::default(),
+            data: HashMap::from([("student_id".to_string(), 1.into())]),
+            expiry_date: OffsetDateTime::now_utc() + Duration::minutes(5),
+        };
+        store.create(&mut record).await.unwrap();
+        let loaded = store.load(&record.id).await.unwrap().unwrap();
+        assert_eq!(loaded.data, record.data);
+
+        record.data.insert("student_id".to_string(), 2.into());
+        store.save(&record).await.unwrap();
+        let loaded = store.load(&record.id).await.unwrap().unwrap();
+        assert_eq!(loaded.data["student_id"], 2);
+
+        store.delete(&record.id).await.unwrap();
+        assert!(store.load(&record.id).await.unwrap().is_none());
+    }
+}
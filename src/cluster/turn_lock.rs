@@ -0,0 +1,246 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use anyhow::bail;
+use redis::{AsyncCommands, Script, aio::ConnectionManager};
+use time::OffsetDateTime;
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+/// how long a lease lasts if its instance stops renewing it
+const LEASE: Duration = Duration::from_secs(30);
+const RENEW_INTERVAL: Duration = Duration::from_secs(10);
+/// how long a turn waits for the turn running on another instance
+const WAIT: Duration = Duration::from_secs(120);
+const RETRY_INTERVAL: Duration = Duration::from_millis(200);
+
+const RENEW_SCRIPT: &str = r"
+if redis.call('get', KEYS[1]) == ARGV[1] then
+    return redis.call('pexpire', KEYS[1], ARGV[2])
+end
+return 0";
+
+const RELEASE_SCRIPT: &str = r"
+if redis.call('get', KEYS[1]) == ARGV[1] then
+    return redis.call('del', KEYS[1])
+end
+return 0";
+
+static NEXT_TOKEN: AtomicU64 = AtomicU64::new(0);
+
+/// Lets one turn of the teacher of a student in a book run at a time. Within a process the
+/// mutex of the cached teacher is enough, with Redis a lease is held across instances and a
+/// turn counter tells an instance that another one changed the conversation
+#[derive(Clone)]
+pub enum TurnLock {
+    Local,
+    Redis(ConnectionManager),
+}
+
+impl std::fmt::Debug for TurnLock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Local => write!(f, "Local"),
+            Self::Redis(_) => write!(f, "Redis"),
+        }
+    }
+}
+
+/// A running turn, the lease is released when the guard is finished or dropped
+pub struct TurnGuard {
+    lease: Option<Lease>,
+    turn: u64,
+}
+
+struct Lease {
+    connection: ConnectionManager,
+    key: String,
+    turn_key: String,
+    token: String,
+    renew: JoinHandle<()>,
+}
+
+fn lock_key(student_id: i64, book_id: i64) -> String {
+    format!("teacher_lock:{}:{}", student_id, book_id)
+}
+
+fn turn_key(student_id: i64, book_id: i64) -> String {
+    format!("teacher_turn:{}:{}", student_id, book_id)
+}
+
+impl TurnLock {
+    /// the number of turns taken, a teacher loaded after reading it is at least as recent
+    pub async fn turn(&self, student_id: i64, book_id: i64) -> anyhow::Result<u64> {
+        match self {
+            Self::Local => Ok(0),
+            Self::Redis(connection) => {
+                let turn: Option<u64> = connection
+                    .clone()
+                    .get(turn_key(student_id, book_id))
+                    .await?;
+                Ok(turn.unwrap_or_default())
+            }
+        }
+    }
+
+    /// wait for the turn of another instance to finish and take the lease
+    pub async fn acquire(&self, student_id: i64, book_id: i64) -> anyhow::Result<TurnGuard> {
+        let Self::Redis(connection) = self else {
+            return Ok(TurnGuard {
+                lease: None,
+                turn: 0,
+            });
+        };
+        let key = lock_key(student_id, book_id);
+        let token = format!(
+            "{}-{}-{}",
+            std::process::id(),
+            OffsetDateTime::now_utc().unix_timestamp_nanos(),
+            NEXT_TOKEN.fetch_add(1, Ordering::Relaxed)
+        );
+        let start = tokio::time::Instant::now();
+        loop {
+            let reply: Option<String> = redis::cmd("SET")
+                .arg(&key)
+                .arg(&token)
+                .arg("NX")
+                .arg("PX")
+                .arg(LEASE.as_millis() as u64)
+                .query_async(&mut connection.clone())
+                .await?;
+            if reply.is_some() {
+                break;
+            }
+            if start.elapsed() > WAIT {
+                bail!("The teacher is busy with another conversation");
+            }
+            tokio::time::sleep(RETRY_INTERVAL).await;
+        }
+        let renew = tokio::spawn({
+            let mut connection = connection.clone();
+            let (key, token) = (key.clone(), token.clone());
+            async move {
+                let script = Script::new(RENEW_SCRIPT);
+                loop {
+                    tokio::time::sleep(RENEW_INTERVAL).await;
+                    let renewed: redis::RedisResult<i64> = script
+                        .key(&key)
+                        .arg(&token)
+                        .arg(LEASE.as_millis() as u64)
+                        .invoke_async(&mut connection)
+                        .await;
+                    if !matches!(renewed, Ok(1)) {
+                        warn!("lease {} lost: {:?}", key, renewed);
+                        return;
+                    }
+                }
+            }
+        });
+        let lease = Lease {
+            connection: connection.clone(),
+            key,
+            turn_key: turn_key(student_id, book_id),
+            token,
+            renew,
+        };
+        let turn = self.turn(student_id, book_id).await?;
+        Ok(TurnGuard {
+            lease: Some(lease),
+            turn,
+        })
+    }
+}
+
+impl TurnGuard {
+    /// the number of turns taken before this one
+    pub fn turn(&self) -> u64 {
+        self.turn
+    }
+
+    /// count the turn and release the lease, returns the number of turns taken
+    pub async fn finish(mut self) -> anyhow::Result<u64> {
+        let Some(lease) = self.lease.take() else {
+            return Ok(self.turn);
+        };
+        let turn: u64 = lease.connection.clone().incr(&lease.turn_key, 1).await?;
+        lease.release().await?;
+        Ok(turn)
+    }
+}
+
+impl Lease {
+    async fn release(self) -> anyhow::Result<()> {
+        self.renew.abort();
+        let _: i64 = Script::new(RELEASE_SCRIPT)
+            .key(&self.key)
+            .arg(&self.token)
+            .invoke_async(&mut self.connection.clone())
+            .await?;
+        Ok(())
+    }
+}
+
+impl Drop for TurnGuard {
+    fn drop(&mut self) {
+        // a turn that failed half way is counted too, the conversation may have changed
+        if let Some(lease) = self.lease.take() {
+            tokio::spawn(async move {
+                let _: redis::RedisResult<u64> =
+                    lease.connection.clone().incr(&lease.turn_key, 1).await;
+                if let Err(e) = lease.release().await {
+                    warn!("release lease failed: {}", e);
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::test_redis;
+
+    #[tokio::test]
+    async fn local_turns() {
+        let lock = TurnLock::Local;
+        let guard = lock.acquire(1, 1).await.unwrap();
+        assert_eq!(guard.turn(), 0);
+        assert_eq!(guard.finish().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn redis_turns() {
+        let Some(mut connection) = test_redis().await else {
+            return;
+        };
+        let (student_id, book_id) = (-1, OffsetDateTime::now_utc().unix_timestamp());
+        let _: () = connection
+            .del(vec![
+                lock_key(student_id, book_id),
+                turn_key(student_id, book_id),
+            ])
+            .await
+            .unwrap();
+        let lock = TurnLock::Redis(connection);
+        let guard = lock.acquire(student_id, book_id).await.unwrap();
+        assert_eq!(guard.turn(), 0);
+
+        // a second instance waits until the first turn is finished
+        let waiting = tokio::spawn({
+            let lock = lock.clone();
+            async move {
+                let guard = lock.acquire(student_id, book_id).await.unwrap();
+                let turn = guard.turn();
+                guard.finish().await.unwrap();
+                turn
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(!waiting.is_finished());
+        assert_eq!(guard.finish().await.unwrap(), 1);
+        assert_eq!(waiting.await.unwrap(), 1);
+        assert_eq!(lock.turn(student_id, book_id).await.unwrap(), 2);
+    }
+}
@@ -0,0 +1,428 @@
+pub mod account;
+pub mod book;
+pub mod job;
+pub mod migrate;
+pub mod plan;
+pub mod progress;
+pub mod study;
+
+use std::str::FromStr;
+
+use sqlx::{PgPool, SqlitePool, sqlite::SqliteConnectOptions};
+
+/// The database of the server. The repository modules keep the queries of both
+/// databases side by side, the SQLite ones are checked at compile time.
+#[derive(Debug, Clone)]
+pub enum Database {
+    Sqlite(SqlitePool),
+    Postgres(PgPool),
+}
+
+impl Database {
+    /// connect by the scheme of `url`, `postgres://` and `postgresql://` are Postgres,
+    /// anything else a SQLite url or file path, whose file is created if missing
+    pub async fn connect(url: &str) -> anyhow::Result<Self> {
+        if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+            return Ok(Self::Postgres(PgPool::connect(url).await?));
+        }
+        let options = SqliteConnectOptions::from_str(url)?
+            .foreign_keys(true)
+            .create_if_missing(true);
+        if let Some(parent) = options.get_filename().parent()
+            && !parent.as_os_str().is_empty()
+        {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        Ok(Self::Sqlite(SqlitePool::connect_with(options).await?))
+    }
+
+    /// an in memory SQLite database that connects on first use
+    pub fn memory() -> Self {
+        Self::Sqlite(SqlitePool::connect_lazy("sqlite::memory:").unwrap())
+    }
+
+    pub fn is_postgres(&self) -> bool {
+        matches!(self, Self::Postgres(_))
+    }
+
+    /// check that the database answers
+    pub async fn ping(&self) -> anyhow::Result<()> {
+        match self {
+            Self::Sqlite(pool) => {
+                sqlx::query("select 1").execute(pool).await?;
+            }
+            Self::Postgres(pool) => {
+                sqlx::query("select 1").execute(pool).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// the version of SQLite or of the Postgres server
+    pub async fn server_version(&self) -> anyhow::Result<String> {
+        let version = match self {
+            Self::Sqlite(pool) => {
+                let version: String = sqlx::query_scalar("select sqlite_version()")
+                    .fetch_one(pool)
+                    .await?;
+                format!("SQLite {}", version)
+            }
+            Self::Postgres(pool) => {
+                let version: String = sqlx::query_scalar("show server_version")
+                    .fetch_one(pool)
+                    .await?;
+                format!("PostgreSQL {}", version)
+            }
+        };
+        Ok(version)
+    }
+
+    /// wait for the queries in progress and close the connections, at shutdown
+    pub async fn close(&self) {
+        match self {
+            Self::Sqlite(pool) => pool.close().await,
+            Self::Postgres(pool) => pool.close().await,
+        }
+    }
+}
+
+/// the database of the tests, `TEST_DATABASE_URL` or an in memory SQLite database,
+/// with the migrations applied
+#[cfg(test)]
+pub(crate) async fn test_database() -> Database {
+    let url = dotenvy::var("TEST_DATABASE_URL").unwrap_or("sqlite::memory:".to_string());
+    let database = Database::connect(&url).await.unwrap();
+    database.migrate().await.unwrap();
+    database
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{BTreeMap, BTreeSet, HashMap},
+        path::PathBuf,
+    };
+
+    use super::*;
+    use crate::{
+        books::{
+            book::Book,
+            chapter::{Chapter, ChapterPlan, PlannedObjective},
+        },
+        db::{
+            book::{MovedChapter, MovedChapters},
+            progress::ProgressRow,
+        },
+        study::StudySession,
+    };
+
+    /// a book of chapters with their numbers, names and planned objectives
+    fn book(id: i64, chapters: &[(&str, &str, &[&str])]) -> Book {
+        let chapters: BTreeMap<_, _> = chapters
+            .iter()
+            .map(|(number, name, objectives)| {
+                let chapter = Chapter {
+                    name: name.to_string(),
+                    number: number.parse().unwrap(),
+                    path: None,
+                    content: String::new(),
+                    chapter_plan: ChapterPlan {
+                        plan: String::new(),
+                        summary: String::new(),
+                        objectives: objectives
+                            .iter()
+                            .enumerate()
+                            .map(|(i, description)| PlannedObjective {
+                                id: i as u32 + 1,
+                                description: description.to_string(),
+                            })
+                            .collect(),
+                    },
+                };
+                (chapter.number.clone(), chapter)
+            })
+            .collect();
+        Book {
+            id,
+            title: "Book".to_string(),
+            chapter_numbers: chapters.keys().cloned().collect(),
+            table_of_contents: String::new(),
+            authors: vec![],
+            description: None,
+            teaching_plan: String::new(),
+            prerequisites: BTreeMap::new(),
+            chapters,
+            src_dir: PathBuf::from("src"),
+            figure_descriptions: BTreeMap::new(),
+            // unique among the books of the shared postgres database
+            content_hash: format!("book_{}", id),
+        }
+    }
+
+    fn objective(id: u32, description: &str, mastery: f64) -> String {
+        format!(
+            r#"{{"id":{},"description":"{}","completed":false,"progress":null,"next_step":null,"mastery":{},"update_time":"2026-01-01T00:00:00Z"}}"#,
+            id, description, mastery
+        )
+    }
+
+    #[tokio::test]
+    async fn accounts_and_books() {
+        let database = test_database().await;
+        // the postgres test database is shared between runs
+        let email = format!(
+            "{}@example.com",
+            time::OffsetDateTime::now_utc().unix_timestamp_nanos()
+        );
+        let student_id = database
+            .insert_student("Ada", &email, "hash")
+            .await
+            .unwrap();
+        assert_eq!(
+            database.student_password(&email).await.unwrap(),
+            (student_id, "hash".to_string())
+        );
+        assert_eq!(database.student_info(student_id).await.unwrap().name, "Ada");
+
+        let book_id = database
+            .insert_book("Book", "Ada", Some("About"), &email)
+            .await
+            .unwrap();
+        assert_eq!(
+            database.find_book_by_hash(&email).await.unwrap(),
+            Some(book_id)
+        );
+        // a concurrent import of the same content
+        assert!(
+            database
+                .insert_book("Book", "Ada", None, &email)
+                .await
+                .is_err()
+        );
+        assert!(!database.book_is_public(book_id).await.unwrap());
+        assert_eq!(database.book_revision(book_id).await.unwrap(), Some(0));
+        assert_eq!(database.bump_book_revision(book_id).await.unwrap(), 1);
+        database.set_book_public(book_id, true).await.unwrap();
+        assert!(database.book_is_public(book_id).await.unwrap());
+        assert_eq!(database.book_owner(book_id).await.unwrap(), None);
+        database.set_book_owner(book_id, student_id).await.unwrap();
+        assert_eq!(
+            database.book_owner(book_id).await.unwrap(),
+            Some(student_id)
+        );
+
+        database
+            .insert_teacher_agent(student_id, book_id)
+            .await
+            .unwrap();
+        database
+            .insert_teacher_agent(student_id, book_id)
+            .await
+            .unwrap();
+        assert!(
+            database
+                .has_teacher_agent(student_id, book_id)
+                .await
+                .unwrap()
+        );
+        let books = database.student_books(student_id).await.unwrap();
+        assert_eq!(books.len(), 1);
+        assert_eq!(books[0].authors, ["Ada"]);
+
+        database.set_daily_goal(student_id, Some(30)).await.unwrap();
+        database.set_daily_goal(student_id, Some(20)).await.unwrap();
+        assert_eq!(database.daily_goal(student_id).await.unwrap(), Some(20));
+
+        database.delete_student(student_id).await.unwrap();
+        database.delete_book_rows(book_id).await.unwrap();
+        assert!(database.student_info(student_id).await.is_err());
+        assert!(!database.book_exists(book_id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn create_sqlite_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("database").join("book.db");
+        let database = Database::connect(&path.to_string_lossy()).await.unwrap();
+        database.migrate().await.unwrap();
+        assert!(path.is_file());
+        assert!(database.pending_migrations().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn health() {
+        let database = test_database().await;
+        database.ping().await.unwrap();
+        assert!(database.pending_migrations().await.unwrap().is_empty());
+        assert!(!database.server_version().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn import_jobs() {
+        let database = test_database().await;
+        let id = database
+            .insert_job("book.epub", None, None, async |_| Ok(()))
+            .await
+            .unwrap();
+        let mut job = database.get_job(id).await.unwrap();
+        assert_eq!(job.file_name, "book.epub");
+        job.progress_total = 3;
+        database.save_job(&job).await.unwrap();
+        assert!(
+            database
+                .list_jobs(None)
+                .await
+                .unwrap()
+                .iter()
+                .any(|j| j.id == id && j.progress_total == 3)
+        );
+        assert!(database.cancel_queued_job(id).await.unwrap());
+        assert!(database.retry_job(id).await.unwrap());
+        assert!(!database.retry_job(id).await.unwrap());
+
+        // other runs of the test leave queued jobs in the postgres database
+        let lease_until = time::OffsetDateTime::now_utc() + time::Duration::minutes(1);
+        while database
+            .claim_next_job(lease_until)
+            .await
+            .unwrap()
+            .is_some_and(|job| job.id != id)
+        {}
+        assert!(!database.cancel_queued_job(id).await.unwrap());
+        assert!(!database.renew_job_lease(id, lease_until).await.unwrap());
+        assert!(database.request_job_cancel(id).await.unwrap());
+        assert!(database.renew_job_lease(id, lease_until).await.unwrap());
+        database
+            .requeue_abandoned_jobs(time::OffsetDateTime::now_utc())
+            .await
+            .unwrap();
+        assert_eq!(database.get_job(id).await.unwrap().status.as_str(), "running");
+        database
+            .requeue_abandoned_jobs(lease_until + time::Duration::seconds(1))
+            .await
+            .unwrap();
+        assert_eq!(database.get_job(id).await.unwrap().status.as_str(), "queued");
+        assert!(database.cancel_queued_job(id).await.unwrap());
+    }
+
+    /// a new version swaps the two chapters of a book and changes the one about ownership,
+    /// its plan lists the objectives in another order
+    #[tokio::test]
+    async fn replace_book_progress() {
+        let database = test_database().await;
+        let email = format!(
+            "{}@example.com",
+            time::OffsetDateTime::now_utc().unix_timestamp_nanos()
+        );
+        let student_id = database
+            .insert_student("Ada", &email, "hash")
+            .await
+            .unwrap();
+        let book_id = database
+            .insert_book("Book", "Ada", None, &email)
+            .await
+            .unwrap();
+        let ownership: &[&str] = &["Explain ownership", "Use references"];
+        let old = book(
+            book_id,
+            &[
+                ("1.", "Ownership", ownership),
+                ("2.", "Loops", &["Write loops"]),
+            ],
+        );
+        database.store_book(&old).await.unwrap();
+        let update_time = time::OffsetDateTime::now_utc();
+        for (number, objectives) in [
+            (
+                "1.",
+                format!(
+                    "[{},{}]",
+                    objective(1, "Explain ownership", 0.0),
+                    objective(2, "Use references", 0.5)
+                ),
+            ),
+            ("2.", format!("[{}]", objective(1, "Write loops", 0.8))),
+        ] {
+            let progress = ProgressRow {
+                chapter_number: number.to_string(),
+                status: 1,
+                objectives,
+                update_time,
+            };
+            database
+                .store_chapter_progress(student_id, book_id, &progress)
+                .await
+                .unwrap();
+        }
+        let session = StudySession {
+            book_id,
+            start_time: update_time,
+            end_time: update_time,
+            chapters: BTreeSet::from(["1.".parse().unwrap()]),
+            message_count: 1,
+        };
+        database
+            .replace_sessions(student_id, book_id, update_time, &[session])
+            .await
+            .unwrap();
+
+        let ownership: &[&str] = &["Use references", "Write lifetimes"];
+        let new = book(
+            book_id,
+            &[
+                ("1.", "Loops", &["Write loops"]),
+                ("2.", "Ownership", ownership),
+            ],
+        );
+        let moved: MovedChapters = HashMap::from([
+            (
+                "1.".to_string(),
+                MovedChapter {
+                    number: Some("2.".to_string()),
+                    modified: true,
+                },
+            ),
+            (
+                "2.".to_string(),
+                MovedChapter {
+                    number: Some("1.".to_string()),
+                    modified: false,
+                },
+            ),
+        ]);
+        database.replace_book_rows(&new, &moved).await.unwrap();
+
+        let objectives = |number: &'static str| {
+            let database = &database;
+            async move {
+                let row = database
+                    .chapter_progress(student_id, book_id, number)
+                    .await
+                    .unwrap()
+                    .unwrap();
+                serde_json::from_str::<Vec<serde_json::Value>>(&row.objectives).unwrap()
+            }
+        };
+        let loops = objectives("1.").await;
+        assert_eq!(loops.len(), 1);
+        assert_eq!(loops[0]["description"], "Write loops");
+        assert_eq!(loops[0]["mastery"], 0.8);
+        let ownership = objectives("2.").await;
+        assert_eq!(ownership.len(), 1);
+        assert_eq!(ownership[0]["id"], 1);
+        assert_eq!(ownership[0]["description"], "Use references");
+        assert_eq!(ownership[0]["mastery"], 0.5);
+        assert_eq!(
+            database.chapter_objectives(book_id, "2.").await.unwrap()[1].description,
+            "Write lifetimes"
+        );
+        let sessions = database.study_sessions(student_id).await.unwrap();
+        assert_eq!(
+            sessions[0].chapters,
+            BTreeSet::from(["2.".parse().unwrap()])
+        );
+
+        database.delete_student(student_id).await.unwrap();
+        database.delete_book_rows(book_id).await.unwrap();
+    }
+}
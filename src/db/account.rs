@@ -0,0 +1,290 @@
+use super::Database;
+use crate::{books::book::BookMeta, student::StudentInfo};
+
+fn book_meta(
+    (id, title, authors, description, is_public): (i64, String, String, Option<String>, bool),
+) -> BookMeta {
+    BookMeta {
+        id,
+        title,
+        authors: authors.split(',').map(|s| s.to_string()).collect(),
+        description,
+        is_public,
+    }
+}
+
+impl Database {
+    pub async fn list_students(&self) -> anyhow::Result<Vec<StudentInfo>> {
+        let students = match self {
+            Self::Sqlite(pool) => {
+                sqlx::query_as!(StudentInfo, "SELECT id, name, email FROM student")
+                    .fetch_all(pool)
+                    .await?
+            }
+            Self::Postgres(pool) => {
+                sqlx::query_as::<_, (i64, String, String)>("SELECT id, name, email FROM student")
+                    .fetch_all(pool)
+                    .await?
+                    .into_iter()
+                    .map(|(id, name, email)| StudentInfo { id, name, email })
+                    .collect()
+            }
+        };
+        Ok(students)
+    }
+
+    pub async fn insert_student(
+        &self,
+        name: &str,
+        email: &str,
+        password_hash: &str,
+    ) -> anyhow::Result<i64> {
+        let id =
+            match self {
+                Self::Sqlite(pool) => sqlx::query!(
+                    "INSERT INTO student (name, email, password) VALUES (?, ?, ?)",
+                    name,
+                    email,
+                    password_hash
+                )
+                .execute(pool)
+                .await?
+                .last_insert_rowid(),
+                Self::Postgres(pool) => sqlx::query_scalar(
+                    "INSERT INTO student (name, email, password) VALUES ($1, $2, $3) RETURNING id",
+                )
+                .bind(name)
+                .bind(email)
+                .bind(password_hash)
+                .fetch_one(pool)
+                .await?,
+            };
+        Ok(id)
+    }
+
+    pub async fn delete_student(&self, id: i64) -> anyhow::Result<()> {
+        match self {
+            Self::Sqlite(pool) => {
+                sqlx::query!("DELETE FROM student WHERE id = ?", id)
+                    .execute(pool)
+                    .await?;
+            }
+            Self::Postgres(pool) => {
+                sqlx::query("DELETE FROM student WHERE id = $1")
+                    .bind(id)
+                    .execute(pool)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn student_info(&self, id: i64) -> anyhow::Result<StudentInfo> {
+        let student = match self {
+            Self::Sqlite(pool) => {
+                sqlx::query_as!(
+                    StudentInfo,
+                    "SELECT id, name, email FROM student WHERE id = ?",
+                    id
+                )
+                .fetch_one(pool)
+                .await?
+            }
+            Self::Postgres(pool) => {
+                let (id, name, email) = sqlx::query_as::<_, (i64, String, String)>(
+                    "SELECT id, name, email FROM student WHERE id = $1",
+                )
+                .bind(id)
+                .fetch_one(pool)
+                .await?;
+                StudentInfo { id, name, email }
+            }
+        };
+        Ok(student)
+    }
+
+    /// id and password hash of the student with `email`
+    pub async fn student_password(&self, email: &str) -> anyhow::Result<(i64, String)> {
+        let student = match self {
+            Self::Sqlite(pool) => {
+                let student =
+                    sqlx::query!("SELECT id, password FROM student WHERE email = ?", email)
+                        .fetch_one(pool)
+                        .await?;
+                (student.id, student.password)
+            }
+            Self::Postgres(pool) => {
+                sqlx::query_as("SELECT id, password FROM student WHERE email = $1")
+                    .bind(email)
+                    .fetch_one(pool)
+                    .await?
+            }
+        };
+        Ok(student)
+    }
+
+    /// id and password hash of the manager with `email`
+    pub async fn manager_password(&self, email: &str) -> anyhow::Result<(i64, String)> {
+        let manager = match self {
+            Self::Sqlite(pool) => {
+                let manager =
+                    sqlx::query!("SELECT id, password FROM manager WHERE email = ?", email)
+                        .fetch_one(pool)
+                        .await?;
+                (manager.id, manager.password)
+            }
+            Self::Postgres(pool) => {
+                sqlx::query_as("SELECT id, password FROM manager WHERE email = $1")
+                    .bind(email)
+                    .fetch_one(pool)
+                    .await?
+            }
+        };
+        Ok(manager)
+    }
+
+    pub async fn student_name(&self, id: i64) -> anyhow::Result<String> {
+        let name = match self {
+            Self::Sqlite(pool) => {
+                sqlx::query_scalar!("select name from student where id = ?", id)
+                    .fetch_one(pool)
+                    .await?
+            }
+            Self::Postgres(pool) => {
+                sqlx::query_scalar("select name from student where id = $1")
+                    .bind(id)
+                    .fetch_one(pool)
+                    .await?
+            }
+        };
+        Ok(name)
+    }
+
+    /// the books added to the student
+    pub async fn student_books(&self, id: i64) -> anyhow::Result<Vec<BookMeta>> {
+        let books = match self {
+            Self::Sqlite(pool) => sqlx::query!("SELECT book.id, book.title, book.authors, book.description, book.is_public FROM book inner join teacher_agent on book.id = teacher_agent.book_id WHERE student_id = ?", id)
+                .fetch_all(pool)
+                .await?
+                .into_iter()
+                .map(|book| (book.id, book.title, book.authors, book.description, book.is_public))
+                .collect::<Vec<_>>(),
+            Self::Postgres(pool) => sqlx::query_as("SELECT book.id, book.title, book.authors, book.description, book.is_public FROM book inner join teacher_agent on book.id = teacher_agent.book_id WHERE student_id = $1")
+                .bind(id)
+                .fetch_all(pool)
+                .await?,
+        };
+        Ok(books.into_iter().map(book_meta).collect())
+    }
+
+    pub async fn book_list(&self) -> anyhow::Result<Vec<BookMeta>> {
+        let books = match self {
+            Self::Sqlite(pool) => {
+                sqlx::query!("select id, title, authors, description, is_public from book")
+                    .fetch_all(pool)
+                    .await?
+                    .into_iter()
+                    .map(|book| {
+                        (
+                            book.id,
+                            book.title,
+                            book.authors,
+                            book.description,
+                            book.is_public,
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            }
+            Self::Postgres(pool) => {
+                sqlx::query_as("select id, title, authors, description, is_public from book")
+                    .fetch_all(pool)
+                    .await?
+            }
+        };
+        Ok(books.into_iter().map(book_meta).collect())
+    }
+
+    /// remove a book and everything the student did in it from the student
+    pub async fn delete_student_book(&self, id: i64, book_id: i64) -> anyhow::Result<()> {
+        match self {
+            Self::Sqlite(pool) => {
+                sqlx::query!(
+                    "DELETE FROM chapter_progress WHERE student_id = ? AND book_id = ?",
+                    id,
+                    book_id
+                )
+                .execute(pool)
+                .await?;
+                sqlx::query!(
+                    "DELETE FROM history_message WHERE student_id = ? AND book_id = ?",
+                    id,
+                    book_id
+                )
+                .execute(pool)
+                .await?;
+                sqlx::query!(
+                    "DELETE FROM study_heartbeat WHERE student_id = ? AND book_id = ?",
+                    id,
+                    book_id
+                )
+                .execute(pool)
+                .await?;
+                sqlx::query!(
+                    "DELETE FROM study_session WHERE student_id = ? AND book_id = ?",
+                    id,
+                    book_id
+                )
+                .execute(pool)
+                .await?;
+                sqlx::query!(
+                    "DELETE FROM teacher_agent WHERE student_id = ? AND book_id = ?",
+                    id,
+                    book_id
+                )
+                .execute(pool)
+                .await?;
+            }
+            Self::Postgres(pool) => {
+                for table in [
+                    "chapter_progress",
+                    "history_message",
+                    "study_heartbeat",
+                    "study_session",
+                    "teacher_agent",
+                ] {
+                    sqlx::query(&format!(
+                        "DELETE FROM {} WHERE student_id = $1 AND book_id = $2",
+                        table
+                    ))
+                    .bind(id)
+                    .bind(book_id)
+                    .execute(pool)
+                    .await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn has_teacher_agent(&self, student_id: i64, book_id: i64) -> anyhow::Result<bool> {
+        let found = match self {
+            Self::Sqlite(pool) => sqlx::query_scalar!(
+                "select book_id from teacher_agent where student_id = ? and book_id = ?",
+                student_id,
+                book_id
+            )
+            .fetch_optional(pool)
+            .await?
+            .is_some(),
+            Self::Postgres(pool) => sqlx::query_scalar::<_, i64>(
+                "select book_id from teacher_agent where student_id = $1 and book_id = $2",
+            )
+            .bind(student_id)
+            .bind(book_id)
+            .fetch_optional(pool)
+            .await?
+            .is_some(),
+        };
+        Ok(found)
+    }
+}
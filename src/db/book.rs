@@ -0,0 +1,792 @@
+use std::collections::{BTreeSet, HashMap};
+
+use sqlx::{PgConnection, SqliteConnection};
+
+use super::Database;
+use crate::{
+    books::{
+        book::Book,
+        chapter::{ChapterNumber, PlannedObjective},
+    },
+    teacher::messages::progress::remap_objectives,
+};
+
+/// where the rows of a chapter of the replaced version of a book go
+#[derive(Debug, Clone)]
+pub struct MovedChapter {
+    /// the number of the chapter in the new version, none if it was removed
+    pub number: Option<String>,
+    /// the content changed, the tracked objectives are matched to its new plan
+    pub modified: bool,
+}
+
+/// the chapters of the replaced version of a book, by their old numbers
+pub type MovedChapters = HashMap<String, MovedChapter>;
+
+impl Database {
+    pub async fn book_exists(&self, book_id: i64) -> anyhow::Result<bool> {
+        let found = match self {
+            Self::Sqlite(pool) => sqlx::query_scalar!("select id from book where id = ?", book_id)
+                .fetch_optional(pool)
+                .await?
+                .is_some(),
+            Self::Postgres(pool) => {
+                sqlx::query_scalar::<_, i64>("select id from book where id = $1")
+                    .bind(book_id)
+                    .fetch_optional(pool)
+                    .await?
+                    .is_some()
+            }
+        };
+        Ok(found)
+    }
+
+    pub async fn book_ids(&self) -> anyhow::Result<Vec<i64>> {
+        let book_ids = match self {
+            Self::Sqlite(pool) => {
+                sqlx::query_scalar!("select id from book")
+                    .fetch_all(pool)
+                    .await?
+            }
+            Self::Postgres(pool) => {
+                sqlx::query_scalar("select id from book")
+                    .fetch_all(pool)
+                    .await?
+            }
+        };
+        Ok(book_ids)
+    }
+
+    pub async fn book_title(&self, book_id: i64) -> anyhow::Result<String> {
+        let title = match self {
+            Self::Sqlite(pool) => {
+                sqlx::query_scalar!("select title from book where id = ?", book_id)
+                    .fetch_one(pool)
+                    .await?
+            }
+            Self::Postgres(pool) => {
+                sqlx::query_scalar("select title from book where id = $1")
+                    .bind(book_id)
+                    .fetch_one(pool)
+                    .await?
+            }
+        };
+        Ok(title)
+    }
+
+    /// content hash of the stored version of the book, none if the book does not exist
+    /// or was stored before content hashes were recorded
+    pub async fn book_content_hash(&self, book_id: i64) -> anyhow::Result<Option<String>> {
+        let content_hash = match self {
+            Self::Sqlite(pool) => {
+                sqlx::query_scalar!("select content_hash from book where id = ?", book_id)
+                    .fetch_optional(pool)
+                    .await?
+            }
+            Self::Postgres(pool) => {
+                sqlx::query_scalar("select content_hash from book where id = $1")
+                    .bind(book_id)
+                    .fetch_optional(pool)
+                    .await?
+            }
+        };
+        Ok(content_hash.flatten())
+    }
+
+    /// revision of the content and the plans of the book, none if the book does not exist
+    pub async fn book_revision(&self, book_id: i64) -> anyhow::Result<Option<i64>> {
+        let revision = match self {
+            Self::Sqlite(pool) => {
+                sqlx::query_scalar!("select revision from book where id = ?", book_id)
+                    .fetch_optional(pool)
+                    .await?
+            }
+            Self::Postgres(pool) => {
+                sqlx::query_scalar("select revision from book where id = $1")
+                    .bind(book_id)
+                    .fetch_optional(pool)
+                    .await?
+            }
+        };
+        Ok(revision)
+    }
+
+    /// record a change of the content or the plans of the book, returns the new revision
+    pub async fn bump_book_revision(&self, book_id: i64) -> anyhow::Result<i64> {
+        let revision = match self {
+            Self::Sqlite(pool) => {
+                sqlx::query_scalar!(
+                    "update book set revision = revision + 1 where id = ? returning revision",
+                    book_id
+                )
+                .fetch_one(pool)
+                .await?
+            }
+            Self::Postgres(pool) => {
+                sqlx::query_scalar(
+                    "update book set revision = revision + 1 where id = $1 returning revision",
+                )
+                .bind(book_id)
+                .fetch_one(pool)
+                .await?
+            }
+        };
+        Ok(revision)
+    }
+
+    /// record the content hash of books stored before content hashes were recorded, unless
+    /// another book has the same content
+    pub async fn fill_content_hash(&self, book_id: i64, content_hash: &str) -> anyhow::Result<()> {
+        match self {
+            Self::Sqlite(pool) => {
+                sqlx::query!(
+                    "update book set content_hash = ? where id = ? and content_hash is null and not exists (select 1 from book where content_hash = ?)",
+                    content_hash,
+                    book_id,
+                    content_hash
+                )
+                .execute(pool)
+                .await?;
+            }
+            Self::Postgres(pool) => {
+                sqlx::query(
+                    "update book set content_hash = $1 where id = $2 and content_hash is null and not exists (select 1 from book where content_hash = $1)",
+                )
+                .bind(content_hash)
+                .bind(book_id)
+                .execute(pool)
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// the book with the same content, if this version of a book was already imported
+    pub async fn find_book_by_hash(&self, content_hash: &str) -> anyhow::Result<Option<i64>> {
+        let book_id = match self {
+            Self::Sqlite(pool) => {
+                sqlx::query_scalar!(
+                    "select id from book where content_hash = ? limit 1",
+                    content_hash
+                )
+                .fetch_optional(pool)
+                .await?
+            }
+            Self::Postgres(pool) => {
+                sqlx::query_scalar("select id from book where content_hash = $1 limit 1")
+                    .bind(content_hash)
+                    .fetch_optional(pool)
+                    .await?
+            }
+        };
+        Ok(book_id)
+    }
+
+    pub async fn book_is_public(&self, book_id: i64) -> anyhow::Result<bool> {
+        let is_public = match self {
+            Self::Sqlite(pool) => {
+                sqlx::query_scalar!("SELECT is_public FROM book WHERE id = ?", book_id)
+                    .fetch_optional(pool)
+                    .await?
+            }
+            Self::Postgres(pool) => {
+                sqlx::query_scalar("SELECT is_public FROM book WHERE id = $1")
+                    .bind(book_id)
+                    .fetch_optional(pool)
+                    .await?
+            }
+        };
+        Ok(is_public.unwrap_or(false))
+    }
+
+    pub async fn set_book_public(&self, book_id: i64, is_public: bool) -> anyhow::Result<()> {
+        match self {
+            Self::Sqlite(pool) => {
+                sqlx::query!(
+                    "update book set is_public = ? where id = ?",
+                    is_public,
+                    book_id
+                )
+                .execute(pool)
+                .await?;
+            }
+            Self::Postgres(pool) => {
+                sqlx::query("update book set is_public = $1 where id = $2")
+                    .bind(is_public)
+                    .bind(book_id)
+                    .execute(pool)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// the student who uploaded the book, none for books of managers and books uploaded
+    /// before owners were recorded
+    pub async fn book_owner(&self, book_id: i64) -> anyhow::Result<Option<i64>> {
+        let owner = match self {
+            Self::Sqlite(pool) => {
+                sqlx::query_scalar!("select owner_student_id from book where id = ?", book_id)
+                    .fetch_optional(pool)
+                    .await?
+            }
+            Self::Postgres(pool) => {
+                sqlx::query_scalar("select owner_student_id from book where id = $1")
+                    .bind(book_id)
+                    .fetch_optional(pool)
+                    .await?
+            }
+        };
+        Ok(owner.flatten())
+    }
+
+    pub async fn set_book_owner(&self, book_id: i64, student_id: i64) -> anyhow::Result<()> {
+        match self {
+            Self::Sqlite(pool) => {
+                sqlx::query!(
+                    "update book set owner_student_id = ? where id = ?",
+                    student_id,
+                    book_id
+                )
+                .execute(pool)
+                .await?;
+            }
+            Self::Postgres(pool) => {
+                sqlx::query("update book set owner_student_id = $1 where id = $2")
+                    .bind(student_id)
+                    .bind(book_id)
+                    .execute(pool)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// add a book row and return its new id
+    pub async fn insert_book(
+        &self,
+        title: &str,
+        authors: &str,
+        description: Option<&str>,
+        content_hash: &str,
+    ) -> anyhow::Result<i64> {
+        let id = match self {
+            Self::Sqlite(pool) => sqlx::query!(
+                "insert into book (title, authors, description, content_hash) values (?, ?, ?, ?)",
+                title,
+                authors,
+                description,
+                content_hash
+            )
+            .execute(pool)
+            .await?
+            .last_insert_rowid(),
+            Self::Postgres(pool) => sqlx::query_scalar(
+                "insert into book (title, authors, description, content_hash) values ($1, $2, $3, $4) returning id",
+            )
+            .bind(title)
+            .bind(authors)
+            .bind(description)
+            .bind(content_hash)
+            .fetch_one(pool)
+            .await?,
+        };
+        Ok(id)
+    }
+
+    pub async fn delete_book_rows(&self, book_id: i64) -> anyhow::Result<()> {
+        match self {
+            Self::Sqlite(pool) => {
+                sqlx::query!("delete from chapter where book_id = ?", book_id)
+                    .execute(pool)
+                    .await?;
+                sqlx::query!("delete from plan_version where book_id = ?", book_id)
+                    .execute(pool)
+                    .await?;
+                sqlx::query!("delete from book where id = ?", book_id)
+                    .execute(pool)
+                    .await?;
+            }
+            Self::Postgres(pool) => {
+                for query in [
+                    "delete from chapter where book_id = $1",
+                    "delete from plan_version where book_id = $1",
+                    "delete from book where id = $1",
+                ] {
+                    sqlx::query(query).bind(book_id).execute(pool).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// move the id sequence of the books past the stored ids, after rows were inserted with
+    /// their own ids. An empty table starts again from 1
+    pub async fn sync_book_id_sequence(&self) -> anyhow::Result<()> {
+        if let Self::Postgres(pool) = self {
+            sqlx::query(
+                "select setval(pg_get_serial_sequence('book', 'id'), greatest(coalesce(max(id), 0), 1), max(id) is not null) from book",
+            )
+            .execute(pool)
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// store the book with its id, and its chapters and their objectives
+    pub async fn store_book(&self, book: &Book) -> anyhow::Result<()> {
+        let authors = book.authors.join(",");
+        let description = book.description.clone().unwrap_or_default();
+        match self {
+            Self::Sqlite(pool) => {
+                // a replace would delete another book with the same content hash
+                sqlx::query!(
+                    "insert into book (id, title, authors, description, content_hash) values (?, ?, ?, ?, ?) \
+                    on conflict (id) do update set title = excluded.title, authors = excluded.authors, \
+                    description = excluded.description, content_hash = excluded.content_hash",
+                    book.id,
+                    book.title,
+                    authors,
+                    description,
+                    book.content_hash
+                )
+                .execute(pool)
+                .await?;
+                sqlx::query!("delete from chapter where book_id = ?", book.id)
+                    .execute(pool)
+                    .await?;
+                for (number, chapter) in book.chapters.iter() {
+                    let number = number.to_string();
+                    sqlx::query!(
+                        "insert or replace into chapter (book_id, chapter_number, name) values (?, ?, ?)",
+                        book.id,
+                        number,
+                        chapter.name
+                    )
+                    .execute(pool)
+                    .await?;
+                }
+            }
+            Self::Postgres(pool) => {
+                sqlx::query(
+                    "insert into book (id, title, authors, description, content_hash) values ($1, $2, $3, $4, $5) \
+                    on conflict (id) do update set title = excluded.title, authors = excluded.authors, \
+                    description = excluded.description, content_hash = excluded.content_hash",
+                )
+                .bind(book.id)
+                .bind(&book.title)
+                .bind(&authors)
+                .bind(&description)
+                .bind(&book.content_hash)
+                .execute(pool)
+                .await?;
+                // ids given by the bookbase must not be handed out again
+                self.sync_book_id_sequence().await?;
+                sqlx::query("delete from chapter where book_id = $1")
+                    .bind(book.id)
+                    .execute(pool)
+                    .await?;
+                for (number, chapter) in book.chapters.iter() {
+                    sqlx::query(
+                        "insert into chapter (book_id, chapter_number, name) values ($1, $2, $3) \
+                        on conflict (book_id, chapter_number) do update set name = excluded.name",
+                    )
+                    .bind(book.id)
+                    .bind(number.to_string())
+                    .bind(&chapter.name)
+                    .execute(pool)
+                    .await?;
+                }
+            }
+        }
+        self.store_chapter_objectives(book).await
+    }
+
+    /// whether objectives of the book are stored
+    pub async fn has_chapter_objectives(&self, book_id: i64) -> anyhow::Result<bool> {
+        let found = match self {
+            Self::Sqlite(pool) => sqlx::query_scalar!(
+                "select book_id from chapter_objective where book_id = ? limit 1",
+                book_id
+            )
+            .fetch_optional(pool)
+            .await?
+            .is_some(),
+            Self::Postgres(pool) => sqlx::query_scalar::<_, i64>(
+                "select book_id from chapter_objective where book_id = $1 limit 1",
+            )
+            .bind(book_id)
+            .fetch_optional(pool)
+            .await?
+            .is_some(),
+        };
+        Ok(found)
+    }
+
+    /// sync the canonical chapter objectives from the teaching plan into the database,
+    /// readers see either the old or the new objectives
+    pub async fn store_chapter_objectives(&self, book: &Book) -> anyhow::Result<()> {
+        match self {
+            Self::Sqlite(pool) => {
+                let mut transaction = pool.begin().await?;
+                sqlx::query!("delete from chapter_objective where book_id = ?", book.id)
+                    .execute(&mut *transaction)
+                    .await?;
+                insert_sqlite_objectives(&mut transaction, book).await?;
+                transaction.commit().await?;
+            }
+            Self::Postgres(pool) => {
+                let mut transaction = pool.begin().await?;
+                sqlx::query("delete from chapter_objective where book_id = $1")
+                    .bind(book.id)
+                    .execute(&mut *transaction)
+                    .await?;
+                insert_postgres_objectives(&mut transaction, book).await?;
+                transaction.commit().await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// store a new version of book `book.id` in one transaction, the progress and study
+    /// sessions of the students follow the chapters that `moved` and the progress is dropped
+    /// for removed chapters. Objectives of modified chapters are matched to their new plans
+    pub async fn replace_book_rows(
+        &self,
+        book: &Book,
+        moved: &MovedChapters,
+    ) -> anyhow::Result<()> {
+        let book_id = book.id;
+        let authors = book.authors.join(",");
+        match self {
+            Self::Sqlite(pool) => {
+                let mut transaction = pool.begin().await?;
+                // the chapters are replaced while rows still refer to them
+                sqlx::query!("PRAGMA defer_foreign_keys = ON")
+                    .execute(&mut *transaction)
+                    .await?;
+                sqlx::query!(
+                    "update book set title = ?, authors = ?, description = ?, content_hash = ? where id = ?",
+                    book.title,
+                    authors,
+                    book.description,
+                    book.content_hash,
+                    book_id
+                )
+                .execute(&mut *transaction)
+                .await?;
+                let progress = sqlx::query!(
+                    "select student_id, chapter_number, status, objectives, update_time from chapter_progress where book_id = ?",
+                    book_id
+                )
+                .fetch_all(&mut *transaction)
+                .await?;
+                sqlx::query!("delete from chapter_progress where book_id = ?", book_id)
+                    .execute(&mut *transaction)
+                    .await?;
+                sqlx::query!("delete from chapter_objective where book_id = ?", book_id)
+                    .execute(&mut *transaction)
+                    .await?;
+                sqlx::query!("delete from chapter where book_id = ?", book_id)
+                    .execute(&mut *transaction)
+                    .await?;
+                for (number, chapter) in book.chapters.iter() {
+                    let number = number.to_string();
+                    sqlx::query!(
+                        "insert into chapter (book_id, chapter_number, name) values (?, ?, ?)",
+                        book_id,
+                        number,
+                        chapter.name
+                    )
+                    .execute(&mut *transaction)
+                    .await?;
+                }
+                insert_sqlite_objectives(&mut transaction, book).await?;
+                for row in progress {
+                    let Some(chapter) = moved.get(&row.chapter_number) else {
+                        continue;
+                    };
+                    let Some(number) = &chapter.number else {
+                        continue;
+                    };
+                    let objectives = if chapter.modified {
+                        remap_objectives(&row.objectives, planned_objectives(book, number))?
+                    } else {
+                        row.objectives
+                    };
+                    sqlx::query!(
+                        "insert into chapter_progress (student_id, book_id, chapter_number, status, objectives, update_time) values (?, ?, ?, ?, ?, ?)",
+                        row.student_id,
+                        book_id,
+                        number,
+                        row.status,
+                        objectives,
+                        row.update_time
+                    )
+                    .execute(&mut *transaction)
+                    .await?;
+                }
+                let sessions = sqlx::query!(
+                    "select id, chapters from study_session where book_id = ?",
+                    book_id
+                )
+                .fetch_all(&mut *transaction)
+                .await?;
+                for session in sessions {
+                    let chapters = renumber_chapters(&session.chapters, moved)?;
+                    if chapters != session.chapters {
+                        sqlx::query!(
+                            "update study_session set chapters = ? where id = ?",
+                            chapters,
+                            session.id
+                        )
+                        .execute(&mut *transaction)
+                        .await?;
+                    }
+                }
+                // renumber in two steps so a chapter can take the number another one left
+                for (old, chapter) in moved {
+                    let new = &chapter.number;
+                    if new.as_ref() == Some(old) {
+                        continue;
+                    }
+                    // teachers of a removed chapter start from the beginning of the book
+                    let new = new
+                        .as_ref()
+                        .map_or(String::new(), |new| format!("~{}", new));
+                    sqlx::query!(
+                        "update teacher_agent set current_chapter_number = ? where book_id = ? and current_chapter_number = ?",
+                        new,
+                        book_id,
+                        old
+                    )
+                    .execute(&mut *transaction)
+                    .await?;
+                    sqlx::query!(
+                        "update study_heartbeat set chapter_number = nullif(?, '') where book_id = ? and chapter_number = ?",
+                        new,
+                        book_id,
+                        old
+                    )
+                    .execute(&mut *transaction)
+                    .await?;
+                    // the history of a removed chapter must not become that of a new chapter
+                    sqlx::query!(
+                        "delete from plan_version where book_id = ? and chapter_number = ? and ? = ''",
+                        book_id,
+                        old,
+                        new
+                    )
+                    .execute(&mut *transaction)
+                    .await?;
+                    sqlx::query!(
+                        "update plan_version set chapter_number = ? where book_id = ? and chapter_number = ?",
+                        new,
+                        book_id,
+                        old
+                    )
+                    .execute(&mut *transaction)
+                    .await?;
+                }
+                sqlx::query!(
+                    "update teacher_agent set current_chapter_number = substr(current_chapter_number, 2) where book_id = ? and current_chapter_number like '~%'",
+                    book_id
+                )
+                .execute(&mut *transaction)
+                .await?;
+                sqlx::query!(
+                    "update study_heartbeat set chapter_number = substr(chapter_number, 2) where book_id = ? and chapter_number like '~%'",
+                    book_id
+                )
+                .execute(&mut *transaction)
+                .await?;
+                sqlx::query!(
+                    "update plan_version set chapter_number = substr(chapter_number, 2) where book_id = ? and chapter_number like '~%'",
+                    book_id
+                )
+                .execute(&mut *transaction)
+                .await?;
+                transaction.commit().await?;
+            }
+            Self::Postgres(pool) => {
+                let mut transaction = pool.begin().await?;
+                sqlx::query("SET CONSTRAINTS ALL DEFERRED")
+                    .execute(&mut *transaction)
+                    .await?;
+                sqlx::query(
+                    "update book set title = $1, authors = $2, description = $3, content_hash = $4 where id = $5",
+                )
+                .bind(&book.title)
+                .bind(&authors)
+                .bind(&book.description)
+                .bind(&book.content_hash)
+                .bind(book_id)
+                .execute(&mut *transaction)
+                .await?;
+                let progress: Vec<(i64, String, i64, String, time::OffsetDateTime)> = sqlx::query_as(
+                    "select student_id, chapter_number, status, objectives, update_time from chapter_progress where book_id = $1",
+                )
+                .bind(book_id)
+                .fetch_all(&mut *transaction)
+                .await?;
+                for query in [
+                    "delete from chapter_progress where book_id = $1",
+                    "delete from chapter_objective where book_id = $1",
+                    "delete from chapter where book_id = $1",
+                ] {
+                    sqlx::query(query)
+                        .bind(book_id)
+                        .execute(&mut *transaction)
+                        .await?;
+                }
+                for (number, chapter) in book.chapters.iter() {
+                    sqlx::query(
+                        "insert into chapter (book_id, chapter_number, name) values ($1, $2, $3)",
+                    )
+                    .bind(book_id)
+                    .bind(number.to_string())
+                    .bind(&chapter.name)
+                    .execute(&mut *transaction)
+                    .await?;
+                }
+                insert_postgres_objectives(&mut transaction, book).await?;
+                for (student_id, chapter_number, status, objectives, update_time) in progress {
+                    let Some(chapter) = moved.get(&chapter_number) else {
+                        continue;
+                    };
+                    let Some(number) = &chapter.number else {
+                        continue;
+                    };
+                    let objectives = if chapter.modified {
+                        remap_objectives(&objectives, planned_objectives(book, number))?
+                    } else {
+                        objectives
+                    };
+                    sqlx::query(
+                        "insert into chapter_progress (student_id, book_id, chapter_number, status, objectives, update_time) values ($1, $2, $3, $4, $5, $6)",
+                    )
+                    .bind(student_id)
+                    .bind(book_id)
+                    .bind(number)
+                    .bind(status)
+                    .bind(objectives)
+                    .bind(update_time)
+                    .execute(&mut *transaction)
+                    .await?;
+                }
+                let sessions: Vec<(i64, String)> =
+                    sqlx::query_as("select id, chapters from study_session where book_id = $1")
+                        .bind(book_id)
+                        .fetch_all(&mut *transaction)
+                        .await?;
+                for (id, chapters) in sessions {
+                    let renumbered = renumber_chapters(&chapters, moved)?;
+                    if renumbered != chapters {
+                        sqlx::query("update study_session set chapters = $1 where id = $2")
+                            .bind(renumbered)
+                            .bind(id)
+                            .execute(&mut *transaction)
+                            .await?;
+                    }
+                }
+                for (old, chapter) in moved {
+                    let new = &chapter.number;
+                    if new.as_ref() == Some(old) {
+                        continue;
+                    }
+                    let new = new
+                        .as_ref()
+                        .map_or(String::new(), |new| format!("~{}", new));
+                    for query in [
+                        "update teacher_agent set current_chapter_number = $1 where book_id = $2 and current_chapter_number = $3",
+                        "update study_heartbeat set chapter_number = nullif($1, '') where book_id = $2 and chapter_number = $3",
+                        "delete from plan_version where book_id = $2 and chapter_number = $3 and $1 = ''",
+                        "update plan_version set chapter_number = $1 where book_id = $2 and chapter_number = $3",
+                    ] {
+                        sqlx::query(query)
+                            .bind(&new)
+                            .bind(book_id)
+                            .bind(old)
+                            .execute(&mut *transaction)
+                            .await?;
+                    }
+                }
+                for query in [
+                    "update teacher_agent set current_chapter_number = substr(current_chapter_number, 2) where book_id = $1 and current_chapter_number like '~%'",
+                    "update study_heartbeat set chapter_number = substr(chapter_number, 2) where book_id = $1 and chapter_number like '~%'",
+                    "update plan_version set chapter_number = substr(chapter_number, 2) where book_id = $1 and chapter_number like '~%'",
+                ] {
+                    sqlx::query(query)
+                        .bind(book_id)
+                        .execute(&mut *transaction)
+                        .await?;
+                }
+                transaction.commit().await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn insert_sqlite_objectives(
+    connection: &mut SqliteConnection,
+    book: &Book,
+) -> anyhow::Result<()> {
+    for (number, chapter) in book.chapters.iter() {
+        let number = number.to_string();
+        for objective in &chapter.chapter_plan.objectives {
+            sqlx::query!(
+                "insert into chapter_objective (book_id, chapter_number, objective_id, description) values (?, ?, ?, ?)",
+                book.id,
+                number,
+                objective.id,
+                objective.description
+            )
+            .execute(&mut *connection)
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+async fn insert_postgres_objectives(
+    connection: &mut PgConnection,
+    book: &Book,
+) -> anyhow::Result<()> {
+    for (number, chapter) in book.chapters.iter() {
+        for objective in &chapter.chapter_plan.objectives {
+            sqlx::query(
+                "insert into chapter_objective (book_id, chapter_number, objective_id, description) values ($1, $2, $3, $4)",
+            )
+            .bind(book.id)
+            .bind(number.to_string())
+            .bind(objective.id as i64)
+            .bind(&objective.description)
+            .execute(&mut *connection)
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+/// the planned objectives of chapter `number` of the book
+fn planned_objectives<'a>(book: &'a Book, number: &str) -> &'a [PlannedObjective] {
+    number
+        .parse::<ChapterNumber>()
+        .ok()
+        .and_then(|number| book.chapters.get(&number))
+        .map_or(&[], |chapter| &chapter.chapter_plan.objectives)
+}
+
+/// the chapters of a study session, as json, with their numbers in the new version
+fn renumber_chapters(chapters: &str, moved: &MovedChapters) -> anyhow::Result<String> {
+    let chapters = serde_json::from_str::<BTreeSet<String>>(chapters)?;
+    let chapters: BTreeSet<&String> = chapters
+        .iter()
+        .filter_map(|number| match moved.get(number) {
+            Some(chapter) => chapter.number.as_ref(),
+            None => Some(number),
+        })
+        .collect();
+    Ok(serde_json::to_string(&chapters)?)
+}
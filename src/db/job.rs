@@ -0,0 +1,349 @@
+use time::OffsetDateTime;
+
+use super::Database;
+use crate::books::job::ImportJob;
+
+#[derive(sqlx::FromRow)]
+struct JobRow {
+    id: i64,
+    file_name: String,
+    student_id: Option<i64>,
+    status: String,
+    stage: String,
+    progress_done: i64,
+    progress_total: i64,
+    book_id: Option<i64>,
+    error: Option<String>,
+    create_time: OffsetDateTime,
+    update_time: OffsetDateTime,
+}
+
+impl TryFrom<JobRow> for ImportJob {
+    type Error = anyhow::Error;
+
+    fn try_from(row: JobRow) -> Result<Self, Self::Error> {
+        Ok(ImportJob {
+            id: row.id,
+            file_name: row.file_name,
+            student_id: row.student_id,
+            status: row.status.parse()?,
+            stage: row.stage.parse()?,
+            progress_done: row.progress_done,
+            progress_total: row.progress_total,
+            book_id: row.book_id,
+            error: row.error,
+            create_time: row.create_time,
+            update_time: row.update_time,
+        })
+    }
+}
+
+impl Database {
+    pub async fn get_job(&self, id: i64) -> anyhow::Result<ImportJob> {
+        let row = match self {
+            Self::Sqlite(pool) => {
+                sqlx::query_as!(
+                    JobRow,
+                    "select id, file_name, student_id, status, stage, progress_done, progress_total, book_id, error, create_time, update_time from import_job where id = ?",
+                    id
+                )
+                .fetch_one(pool)
+                .await?
+            }
+            Self::Postgres(pool) => {
+                sqlx::query_as(
+                    "select id, file_name, student_id, status, stage, progress_done, progress_total, book_id, error, create_time, update_time from import_job where id = $1",
+                )
+                .bind(id)
+                .fetch_one(pool)
+                .await?
+            }
+        };
+        row.try_into()
+    }
+
+    /// the jobs of a student, or all jobs if `student_id` is none, newest first
+    pub async fn list_jobs(&self, student_id: Option<i64>) -> anyhow::Result<Vec<ImportJob>> {
+        let rows = match self {
+            Self::Sqlite(pool) => {
+                sqlx::query_as!(
+                    JobRow,
+                    "select id, file_name, student_id, status, stage, progress_done, progress_total, book_id, error, create_time, update_time from import_job where ? is null or student_id = ? order by id desc",
+                    student_id,
+                    student_id
+                )
+                .fetch_all(pool)
+                .await?
+            }
+            Self::Postgres(pool) => {
+                sqlx::query_as(
+                    "select id, file_name, student_id, status, stage, progress_done, progress_total, book_id, error, create_time, update_time from import_job where $1::bigint is null or student_id = $1 order by id desc",
+                )
+                .bind(student_id)
+                .fetch_all(pool)
+                .await?
+            }
+        };
+        rows.into_iter().map(ImportJob::try_from).collect()
+    }
+
+    /// queue a job, `prepare` puts its files in place before other instances can see it
+    pub async fn insert_job(
+        &self,
+        file_name: &str,
+        student_id: Option<i64>,
+        book_id: Option<i64>,
+        prepare: impl AsyncFnOnce(i64) -> anyhow::Result<()>,
+    ) -> anyhow::Result<i64> {
+        let now = OffsetDateTime::now_utc();
+        let id = match self {
+            Self::Sqlite(pool) => {
+                let mut transaction = pool.begin().await?;
+                let id = sqlx::query!(
+                    "insert into import_job (file_name, student_id, book_id, create_time, update_time) values (?, ?, ?, ?, ?)",
+                    file_name,
+                    student_id,
+                    book_id,
+                    now,
+                    now
+                )
+                .execute(&mut *transaction)
+                .await?
+                .last_insert_rowid();
+                prepare(id).await?;
+                transaction.commit().await?;
+                id
+            }
+            Self::Postgres(pool) => {
+                let mut transaction = pool.begin().await?;
+                let id = sqlx::query_scalar(
+                    "insert into import_job (file_name, student_id, book_id, create_time, update_time) values ($1, $2, $3, $4, $4) returning id",
+                )
+                .bind(file_name)
+                .bind(student_id)
+                .bind(book_id)
+                .bind(now)
+                .fetch_one(&mut *transaction)
+                .await?;
+                prepare(id).await?;
+                transaction.commit().await?;
+                id
+            }
+        };
+        Ok(id)
+    }
+
+    /// mark the first queued job as running with a lease until `lease_until`, none if no
+    /// job is queued
+    pub async fn claim_next_job(
+        &self,
+        lease_until: OffsetDateTime,
+    ) -> anyhow::Result<Option<ImportJob>> {
+        loop {
+            let id = match self {
+                Self::Sqlite(pool) => {
+                    sqlx::query_scalar!(
+                        "select id from import_job where status = 'queued' order by id limit 1"
+                    )
+                    .fetch_optional(pool)
+                    .await?
+                }
+                Self::Postgres(pool) => {
+                    sqlx::query_scalar(
+                        "select id from import_job where status = 'queued' order by id limit 1",
+                    )
+                    .fetch_optional(pool)
+                    .await?
+                }
+            };
+            let Some(id) = id else {
+                return Ok(None);
+            };
+            let now = OffsetDateTime::now_utc();
+            let claimed = match self {
+                Self::Sqlite(pool) => sqlx::query!(
+                    "update import_job set status = 'running', lease_until = ?, cancel_requested = false, update_time = ? where id = ? and status = 'queued'",
+                    lease_until,
+                    now,
+                    id
+                )
+                .execute(pool)
+                .await?
+                .rows_affected(),
+                Self::Postgres(pool) => sqlx::query(
+                    "update import_job set status = 'running', lease_until = $1, cancel_requested = false, update_time = $2 where id = $3 and status = 'queued'",
+                )
+                .bind(lease_until)
+                .bind(now)
+                .bind(id)
+                .execute(pool)
+                .await?
+                .rows_affected(),
+            };
+            // cancelled or claimed by another instance in the meantime
+            if claimed == 1 {
+                return Ok(Some(self.get_job(id).await?));
+            }
+        }
+    }
+
+    /// cancel the job if it is still queued
+    pub async fn cancel_queued_job(&self, id: i64) -> anyhow::Result<bool> {
+        let now = OffsetDateTime::now_utc();
+        let rows = match self {
+            Self::Sqlite(pool) => sqlx::query!(
+                "update import_job set status = 'cancelled', update_time = ? where id = ? and status = 'queued'",
+                now,
+                id
+            )
+            .execute(pool)
+            .await?
+            .rows_affected(),
+            Self::Postgres(pool) => sqlx::query(
+                "update import_job set status = 'cancelled', update_time = $1 where id = $2 and status = 'queued'",
+            )
+            .bind(now)
+            .bind(id)
+            .execute(pool)
+            .await?
+            .rows_affected(),
+        };
+        Ok(rows == 1)
+    }
+
+    /// ask the instance running the job to cancel it, false if the job is not running
+    pub async fn request_job_cancel(&self, id: i64) -> anyhow::Result<bool> {
+        let rows = match self {
+            Self::Sqlite(pool) => sqlx::query!(
+                "update import_job set cancel_requested = true where id = ? and status = 'running'",
+                id
+            )
+            .execute(pool)
+            .await?
+            .rows_affected(),
+            Self::Postgres(pool) => sqlx::query(
+                "update import_job set cancel_requested = true where id = $1 and status = 'running'",
+            )
+            .bind(id)
+            .execute(pool)
+            .await?
+            .rows_affected(),
+        };
+        Ok(rows == 1)
+    }
+
+    /// extend the lease of a running job, true if the job should stop because it was
+    /// cancelled or is no longer running
+    pub async fn renew_job_lease(
+        &self,
+        id: i64,
+        lease_until: OffsetDateTime,
+    ) -> anyhow::Result<bool> {
+        let cancel_requested = match self {
+            Self::Sqlite(pool) => {
+                sqlx::query_scalar!(
+                    "update import_job set lease_until = ? where id = ? and status = 'running' returning cancel_requested",
+                    lease_until,
+                    id
+                )
+                .fetch_optional(pool)
+                .await?
+            }
+            Self::Postgres(pool) => {
+                sqlx::query_scalar(
+                    "update import_job set lease_until = $1 where id = $2 and status = 'running' returning cancel_requested",
+                )
+                .bind(lease_until)
+                .bind(id)
+                .fetch_optional(pool)
+                .await?
+            }
+        };
+        Ok(cancel_requested.unwrap_or(true))
+    }
+
+    /// queue a failed or cancelled job again
+    pub async fn retry_job(&self, id: i64) -> anyhow::Result<bool> {
+        let now = OffsetDateTime::now_utc();
+        let rows = match self {
+            Self::Sqlite(pool) => sqlx::query!(
+                "update import_job set status = 'queued', error = null, progress_done = 0, progress_total = 0, update_time = ? where id = ? and status in ('failed', 'cancelled')",
+                now,
+                id
+            )
+            .execute(pool)
+            .await?
+            .rows_affected(),
+            Self::Postgres(pool) => sqlx::query(
+                "update import_job set status = 'queued', error = null, progress_done = 0, progress_total = 0, update_time = $1 where id = $2 and status in ('failed', 'cancelled')",
+            )
+            .bind(now)
+            .bind(id)
+            .execute(pool)
+            .await?
+            .rows_affected(),
+        };
+        Ok(rows > 0)
+    }
+
+    pub async fn save_job(&self, job: &ImportJob) -> anyhow::Result<()> {
+        let status = job.status.as_str();
+        let stage = job.stage.as_str();
+        match self {
+            Self::Sqlite(pool) => {
+                sqlx::query!(
+                    "update import_job set status = ?, stage = ?, progress_done = ?, progress_total = ?, book_id = ?, error = ?, update_time = ? where id = ?",
+                    status,
+                    stage,
+                    job.progress_done,
+                    job.progress_total,
+                    job.book_id,
+                    job.error,
+                    job.update_time,
+                    job.id
+                )
+                .execute(pool)
+                .await?;
+            }
+            Self::Postgres(pool) => {
+                sqlx::query(
+                    "update import_job set status = $1, stage = $2, progress_done = $3, progress_total = $4, book_id = $5, error = $6, update_time = $7 where id = $8",
+                )
+                .bind(status)
+                .bind(stage)
+                .bind(job.progress_done)
+                .bind(job.progress_total)
+                .bind(job.book_id)
+                .bind(&job.error)
+                .bind(job.update_time)
+                .bind(job.id)
+                .execute(pool)
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// queue the running jobs whose lease expired before `now`, their instance stopped
+    pub async fn requeue_abandoned_jobs(&self, now: OffsetDateTime) -> anyhow::Result<()> {
+        match self {
+            Self::Sqlite(pool) => {
+                sqlx::query!(
+                    "update import_job set status = 'queued', stage = 'queued' where status = 'running' and (lease_until is null or lease_until < ?)",
+                    now
+                )
+                .execute(pool)
+                .await?;
+            }
+            Self::Postgres(pool) => {
+                sqlx::query(
+                    "update import_job set status = 'queued', stage = 'queued' where status = 'running' and (lease_until is null or lease_until < $1)",
+                )
+                .bind(now)
+                .execute(pool)
+                .await?;
+            }
+        }
+        Ok(())
+    }
+}
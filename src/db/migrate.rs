@@ -0,0 +1,277 @@
+use anyhow::bail;
+use sqlx::migrate::Migrator;
+
+use super::Database;
+
+static SQLITE_MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+static POSTGRES_MIGRATOR: Migrator = sqlx::migrate!("./migrations/postgres");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationState {
+    Applied,
+    Pending,
+    /// applied from a file whose content changed since
+    Modified,
+}
+
+impl MigrationState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MigrationState::Applied => "applied",
+            MigrationState::Pending => "pending",
+            MigrationState::Modified => "modified",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub state: MigrationState,
+}
+
+impl Database {
+    /// the migrations of the schema of this database, embedded in the binaries
+    pub fn migrator(&self) -> &'static Migrator {
+        match self {
+            Self::Sqlite(_) => &SQLITE_MIGRATOR,
+            Self::Postgres(_) => &POSTGRES_MIGRATOR,
+        }
+    }
+
+    async fn has_table(&self, name: &str) -> anyhow::Result<bool> {
+        let count: i64 = match self {
+            Self::Sqlite(pool) => {
+                sqlx::query_scalar("select count(*) from sqlite_master where type = 'table' and name = ?")
+                    .bind(name)
+                    .fetch_one(pool)
+                    .await?
+            }
+            Self::Postgres(pool) => {
+                sqlx::query_scalar("select count(*) from information_schema.tables where table_schema = current_schema() and table_name = $1")
+                    .bind(name)
+                    .fetch_one(pool)
+                    .await?
+            }
+        };
+        Ok(count > 0)
+    }
+
+    /// versions and checksums of the applied migrations, none if the database was never migrated
+    async fn applied_migrations(&self) -> anyhow::Result<Option<Vec<(i64, Vec<u8>)>>> {
+        if !self.has_table("_sqlx_migrations").await? {
+            return Ok(None);
+        }
+        let query = "select version, checksum from _sqlx_migrations where success order by version";
+        let applied = match self {
+            Self::Sqlite(pool) => sqlx::query_as(query).fetch_all(pool).await?,
+            Self::Postgres(pool) => sqlx::query_as(query).fetch_all(pool).await?,
+        };
+        Ok(Some(applied))
+    }
+
+    /// every migration of the binary and whether it is applied to the database
+    pub async fn migration_status(&self) -> anyhow::Result<Vec<MigrationStatus>> {
+        let applied = self.applied_migrations().await?.unwrap_or_default();
+        Ok(self
+            .migrator()
+            .iter()
+            .filter(|migration| !migration.migration_type.is_down_migration())
+            .map(|migration| {
+                let state = match applied
+                    .iter()
+                    .find(|(version, _)| *version == migration.version)
+                {
+                    None => MigrationState::Pending,
+                    Some((_, checksum)) if *checksum == *migration.checksum => {
+                        MigrationState::Applied
+                    }
+                    Some(_) => MigrationState::Modified,
+                };
+                MigrationStatus {
+                    version: migration.version,
+                    description: migration.description.to_string(),
+                    state,
+                }
+            })
+            .collect())
+    }
+
+    /// versions of the migrations that are not applied yet
+    pub async fn pending_migrations(&self) -> anyhow::Result<Vec<i64>> {
+        Ok(self
+            .migration_status()
+            .await?
+            .into_iter()
+            .filter(|status| status.state == MigrationState::Pending)
+            .map(|status| status.version)
+            .collect())
+    }
+
+    /// apply the pending migrations. A database whose tables were created without the
+    /// migrations is refused, its schema version is unknown
+    pub async fn migrate(&self) -> anyhow::Result<()> {
+        if self.applied_migrations().await?.is_none() && self.has_table("book").await? {
+            bail!(
+                "the database has tables but no migration history, apply the migrations with `sqlx migrate run` from the version it was created with"
+            );
+        }
+        match self {
+            Self::Sqlite(pool) => SQLITE_MIGRATOR.run(pool).await?,
+            Self::Postgres(pool) => POSTGRES_MIGRATOR.run(pool).await?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{borrow::Cow, str::FromStr};
+
+    use sqlx::{PgPool, postgres::PgConnectOptions};
+    use time::OffsetDateTime;
+
+    use super::*;
+
+    /// an empty database of the kind of the tests, on Postgres in a schema of its own
+    async fn empty_database() -> Database {
+        let url = dotenvy::var("TEST_DATABASE_URL").unwrap_or("sqlite::memory:".to_string());
+        let database = Database::connect(&url).await.unwrap();
+        let Database::Postgres(pool) = database else {
+            return database;
+        };
+        let schema = format!(
+            "migrate_{}",
+            OffsetDateTime::now_utc().unix_timestamp_nanos()
+        );
+        sqlx::query(&format!("create schema {}", schema))
+            .execute(&pool)
+            .await
+            .unwrap();
+        let options = PgConnectOptions::from_str(&url)
+            .unwrap()
+            .options([("search_path", schema.as_str())]);
+        Database::Postgres(PgPool::connect_with(options).await.unwrap())
+    }
+
+    async fn execute(database: &Database, query: &str) {
+        match database {
+            Database::Sqlite(pool) => {
+                sqlx::query(query).execute(pool).await.unwrap();
+            }
+            Database::Postgres(pool) => {
+                sqlx::query(query).execute(pool).await.unwrap();
+            }
+        }
+    }
+
+    async fn drop_database(database: Database) {
+        if let Database::Postgres(pool) = database {
+            let schema: String = sqlx::query_scalar("select current_schema()")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+            sqlx::query(&format!("drop schema {} cascade", schema))
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+    }
+
+    /// a database at every earlier version, with rows in the tables of the first one, is
+    /// migrated to the last version without losing them
+    #[tokio::test]
+    async fn upgrade_from_every_version() {
+        let database = empty_database().await;
+        let migrations = database.migrator().migrations.to_vec();
+        drop_database(database).await;
+        for applied in 1..migrations.len() {
+            let database = empty_database().await;
+            let earlier = Migrator {
+                migrations: Cow::Owned(migrations[..applied].to_vec()),
+                ..Migrator::DEFAULT
+            };
+            match &database {
+                Database::Sqlite(pool) => earlier.run(pool).await.unwrap(),
+                Database::Postgres(pool) => earlier.run(pool).await.unwrap(),
+            }
+            assert_eq!(
+                database.pending_migrations().await.unwrap(),
+                migrations[applied..]
+                    .iter()
+                    .map(|migration| migration.version)
+                    .collect::<Vec<_>>()
+            );
+            let insert = [
+                "insert into student (name, email, password) values ('Ada', 'ada@example.com', 'hash')",
+                "insert into book (id, title, authors, description) values (1, 'Book', 'Ada', null)",
+            ];
+            for query in insert {
+                execute(&database, query).await;
+            }
+
+            database.migrate().await.unwrap();
+            assert!(database.pending_migrations().await.unwrap().is_empty());
+            assert_eq!(
+                database
+                    .student_password("ada@example.com")
+                    .await
+                    .unwrap()
+                    .1,
+                "hash"
+            );
+            assert_eq!(database.book_title(1).await.unwrap(), "Book");
+            assert_eq!(database.book_content_hash(1).await.unwrap(), None);
+            drop_database(database).await;
+        }
+    }
+
+    /// rows inserted with their own ids, or none at all, leave the next id free
+    #[tokio::test]
+    async fn book_id_sequence() {
+        let database = empty_database().await;
+        database.migrate().await.unwrap();
+        database.sync_book_id_sequence().await.unwrap();
+        assert_eq!(
+            database
+                .insert_book("First", "Ada", None, "first")
+                .await
+                .unwrap(),
+            1
+        );
+        execute(
+            &database,
+            "insert into book (id, title, authors, description) values (10, 'Legacy', 'Ada', null)",
+        )
+        .await;
+        database.sync_book_id_sequence().await.unwrap();
+        assert_eq!(
+            database
+                .insert_book("Next", "Ada", None, "next")
+                .await
+                .unwrap(),
+            11
+        );
+        drop_database(database).await;
+    }
+
+    #[tokio::test]
+    async fn status() {
+        let database = empty_database().await;
+        let status = database.migration_status().await.unwrap();
+        assert!(!status.is_empty());
+        assert!(status.iter().all(|s| s.state == MigrationState::Pending));
+        database.migrate().await.unwrap();
+        database.migrate().await.unwrap();
+        let status = database.migration_status().await.unwrap();
+        assert!(status.iter().all(|s| s.state == MigrationState::Applied));
+        drop_database(database).await;
+
+        // tables created by hand without the migration history
+        let database = empty_database().await;
+        execute(&database, "create table book (id integer primary key)").await;
+        assert!(database.migrate().await.is_err());
+        drop_database(database).await;
+    }
+}
@@ -0,0 +1,159 @@
+use time::OffsetDateTime;
+
+use super::Database;
+use crate::books::plan::PlanVersion;
+
+#[derive(sqlx::FromRow)]
+struct VersionRow {
+    id: i64,
+    book_id: i64,
+    chapter_number: Option<String>,
+    plan: String,
+    summary: Option<String>,
+    change: String,
+    manager_id: Option<i64>,
+    create_time: OffsetDateTime,
+}
+
+impl TryFrom<VersionRow> for PlanVersion {
+    type Error = anyhow::Error;
+
+    fn try_from(row: VersionRow) -> Result<Self, Self::Error> {
+        Ok(PlanVersion {
+            id: row.id,
+            book_id: row.book_id,
+            chapter_number: row.chapter_number.map(|n| n.parse()).transpose()?,
+            plan: row.plan,
+            summary: row.summary,
+            change: row.change.parse()?,
+            manager_id: row.manager_id,
+            create_time: row.create_time,
+        })
+    }
+}
+
+impl Database {
+    pub async fn get_plan_version(&self, id: i64) -> anyhow::Result<PlanVersion> {
+        let row = match self {
+            Self::Sqlite(pool) => {
+                sqlx::query_as!(
+                    VersionRow,
+                    "select id, book_id, chapter_number, plan, summary, change, manager_id, create_time from plan_version where id = ?",
+                    id
+                )
+                .fetch_one(pool)
+                .await?
+            }
+            Self::Postgres(pool) => {
+                sqlx::query_as(
+                    "select id, book_id, chapter_number, plan, summary, change, manager_id, create_time from plan_version where id = $1",
+                )
+                .bind(id)
+                .fetch_one(pool)
+                .await?
+            }
+        };
+        row.try_into()
+    }
+
+    /// versions of the plans of a book, of one chapter if `chapter` is given, newest first
+    pub async fn list_plan_versions(
+        &self,
+        book_id: i64,
+        chapter: Option<&str>,
+    ) -> anyhow::Result<Vec<PlanVersion>> {
+        let rows = match self {
+            Self::Sqlite(pool) => {
+                sqlx::query_as!(
+                    VersionRow,
+                    "select id, book_id, chapter_number, plan, summary, change, manager_id, create_time from plan_version where book_id = ? and (? is null or chapter_number = ?) order by id desc",
+                    book_id,
+                    chapter,
+                    chapter
+                )
+                .fetch_all(pool)
+                .await?
+            }
+            Self::Postgres(pool) => {
+                sqlx::query_as(
+                    "select id, book_id, chapter_number, plan, summary, change, manager_id, create_time from plan_version where book_id = $1 and ($2::text is null or chapter_number = $2) order by id desc",
+                )
+                .bind(book_id)
+                .bind(chapter)
+                .fetch_all(pool)
+                .await?
+            }
+        };
+        rows.into_iter().map(PlanVersion::try_from).collect()
+    }
+
+    /// the latest version of the plan of `chapter`, or of the teaching plan of the book
+    pub async fn latest_plan_version(
+        &self,
+        book_id: i64,
+        chapter: Option<&str>,
+    ) -> anyhow::Result<Option<PlanVersion>> {
+        let row = match self {
+            Self::Sqlite(pool) => {
+                sqlx::query_as!(
+                    VersionRow,
+                    "select id, book_id, chapter_number, plan, summary, change, manager_id, create_time from plan_version where book_id = ? and chapter_number is ? order by id desc limit 1",
+                    book_id,
+                    chapter
+                )
+                .fetch_optional(pool)
+                .await?
+            }
+            Self::Postgres(pool) => {
+                sqlx::query_as(
+                    "select id, book_id, chapter_number, plan, summary, change, manager_id, create_time from plan_version where book_id = $1 and chapter_number is not distinct from $2 order by id desc limit 1",
+                )
+                .bind(book_id)
+                .bind(chapter)
+                .fetch_optional(pool)
+                .await?
+            }
+        };
+        row.map(PlanVersion::try_from).transpose()
+    }
+
+    pub async fn insert_plan_version(
+        &self,
+        book_id: i64,
+        chapter: Option<&str>,
+        plan: &str,
+        summary: Option<&str>,
+        change: &str,
+        manager_id: Option<i64>,
+    ) -> anyhow::Result<i64> {
+        let now = OffsetDateTime::now_utc();
+        let id = match self {
+            Self::Sqlite(pool) => sqlx::query!(
+                "insert into plan_version (book_id, chapter_number, plan, summary, change, manager_id, create_time) values (?, ?, ?, ?, ?, ?, ?)",
+                book_id,
+                chapter,
+                plan,
+                summary,
+                change,
+                manager_id,
+                now
+            )
+            .execute(pool)
+            .await?
+            .last_insert_rowid(),
+            Self::Postgres(pool) => sqlx::query_scalar(
+                "insert into plan_version (book_id, chapter_number, plan, summary, change, manager_id, create_time) values ($1, $2, $3, $4, $5, $6, $7) returning id",
+            )
+            .bind(book_id)
+            .bind(chapter)
+            .bind(plan)
+            .bind(summary)
+            .bind(change)
+            .bind(manager_id)
+            .bind(now)
+            .fetch_one(pool)
+            .await?,
+        };
+        Ok(id)
+    }
+}
@@ -0,0 +1,393 @@
+use time::OffsetDateTime;
+
+use super::Database;
+use crate::books::chapter::PlannedObjective;
+
+/// A row of `chapter_progress`, the objectives are json
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct ProgressRow {
+    pub chapter_number: String,
+    pub status: i64,
+    pub objectives: String,
+    pub update_time: OffsetDateTime,
+}
+
+/// A row of `teacher_agent`, the memories are json
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct TeacherAgentRow {
+    pub current_chapter_number: String,
+    pub memories: String,
+    pub update_time: OffsetDateTime,
+}
+
+impl Database {
+    /// the messages of the student in the book as json, oldest first
+    pub async fn conversation(&self, student_id: i64, book_id: i64) -> anyhow::Result<Vec<String>> {
+        let messages = match self {
+            Self::Sqlite(pool) => {
+                sqlx::query_scalar!(
+                    "select content from history_message where student_id = ? and book_id = ? order by update_time asc",
+                    student_id,
+                    book_id
+                )
+                .fetch_all(pool)
+                .await?
+            }
+            Self::Postgres(pool) => {
+                sqlx::query_scalar(
+                    "select content from history_message where student_id = $1 and book_id = $2 order by update_time asc, id asc",
+                )
+                .bind(student_id)
+                .bind(book_id)
+                .fetch_all(pool)
+                .await?
+            }
+        };
+        Ok(messages)
+    }
+
+    pub async fn add_message(
+        &self,
+        student_id: i64,
+        book_id: i64,
+        content: &str,
+        time: OffsetDateTime,
+    ) -> anyhow::Result<()> {
+        match self {
+            Self::Sqlite(pool) => {
+                sqlx::query!(
+                    "insert into history_message (student_id, book_id, content, update_time) values (?, ?, ?, ?)",
+                    student_id,
+                    book_id,
+                    content,
+                    time
+                )
+                .execute(pool)
+                .await?;
+            }
+            Self::Postgres(pool) => {
+                sqlx::query(
+                    "insert into history_message (student_id, book_id, content, update_time) values ($1, $2, $3, $4)",
+                )
+                .bind(student_id)
+                .bind(book_id)
+                .bind(content)
+                .bind(time)
+                .execute(pool)
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn set_memories(
+        &self,
+        student_id: i64,
+        book_id: i64,
+        memories: &str,
+    ) -> anyhow::Result<()> {
+        match self {
+            Self::Sqlite(pool) => {
+                sqlx::query!(
+                    "update teacher_agent set memories = ? where student_id = ? and book_id = ?",
+                    memories,
+                    student_id,
+                    book_id
+                )
+                .execute(pool)
+                .await?;
+            }
+            Self::Postgres(pool) => {
+                sqlx::query(
+                    "update teacher_agent set memories = $1 where student_id = $2 and book_id = $3",
+                )
+                .bind(memories)
+                .bind(student_id)
+                .bind(book_id)
+                .execute(pool)
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn teacher_agent(
+        &self,
+        student_id: i64,
+        book_id: i64,
+    ) -> anyhow::Result<TeacherAgentRow> {
+        let record = match self {
+            Self::Sqlite(pool) => {
+                sqlx::query_as!(
+                    TeacherAgentRow,
+                    "select current_chapter_number, memories, update_time from teacher_agent where student_id = ? and book_id = ?",
+                    student_id,
+                    book_id
+                )
+                .fetch_one(pool)
+                .await?
+            }
+            Self::Postgres(pool) => {
+                sqlx::query_as(
+                    "select current_chapter_number, memories, update_time from teacher_agent where student_id = $1 and book_id = $2",
+                )
+                .bind(student_id)
+                .bind(book_id)
+                .fetch_one(pool)
+                .await?
+            }
+        };
+        Ok(record)
+    }
+
+    /// add the book to the student, nothing happens if it is already added
+    pub async fn insert_teacher_agent(&self, student_id: i64, book_id: i64) -> anyhow::Result<()> {
+        match self {
+            Self::Sqlite(pool) => {
+                sqlx::query!(
+                    "insert or ignore into teacher_agent (student_id, book_id, current_chapter_number, memories) values (?, ?, '', '[]')",
+                    student_id,
+                    book_id,
+                )
+                .execute(pool)
+                .await?;
+            }
+            Self::Postgres(pool) => {
+                sqlx::query(
+                    "insert into teacher_agent (student_id, book_id, current_chapter_number, memories) values ($1, $2, '', '[]') on conflict do nothing",
+                )
+                .bind(student_id)
+                .bind(book_id)
+                .execute(pool)
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn agent_setting(&self) -> anyhow::Result<(String, i64)> {
+        let setting = match self {
+            Self::Sqlite(pool) => {
+                let record = sqlx::query!("select ai_model, token_budget FROM agent_setting")
+                    .fetch_one(pool)
+                    .await?;
+                (record.ai_model, record.token_budget)
+            }
+            Self::Postgres(pool) => {
+                sqlx::query_as("select ai_model, token_budget FROM agent_setting")
+                    .fetch_one(pool)
+                    .await?
+            }
+        };
+        Ok(setting)
+    }
+
+    /// the canonical objectives of a chapter, as seeded from the chapter plan
+    pub async fn chapter_objectives(
+        &self,
+        book_id: i64,
+        chapter_number: &str,
+    ) -> anyhow::Result<Vec<PlannedObjective>> {
+        let objectives: Vec<(i64, String)> = match self {
+            Self::Sqlite(pool) => sqlx::query!(
+                "select objective_id, description from chapter_objective where book_id = ? and chapter_number = ? order by objective_id",
+                book_id,
+                chapter_number
+            )
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .map(|record| (record.objective_id, record.description))
+            .collect(),
+            Self::Postgres(pool) => sqlx::query_as(
+                "select objective_id, description from chapter_objective where book_id = $1 and chapter_number = $2 order by objective_id",
+            )
+            .bind(book_id)
+            .bind(chapter_number)
+            .fetch_all(pool)
+            .await?,
+        };
+        Ok(objectives
+            .into_iter()
+            .map(|(id, description)| PlannedObjective {
+                id: id as u32,
+                description,
+            })
+            .collect())
+    }
+
+    /// the canonical objectives of every chapter of the book, with their chapter numbers
+    pub async fn book_objectives(
+        &self,
+        book_id: i64,
+    ) -> anyhow::Result<Vec<(String, PlannedObjective)>> {
+        let objectives: Vec<(String, i64, String)> = match self {
+            Self::Sqlite(pool) => sqlx::query!(
+                "select chapter_number, objective_id, description from chapter_objective where book_id = ?",
+                book_id
+            )
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .map(|record| (record.chapter_number, record.objective_id, record.description))
+            .collect(),
+            Self::Postgres(pool) => sqlx::query_as(
+                "select chapter_number, objective_id, description from chapter_objective where book_id = $1",
+            )
+            .bind(book_id)
+            .fetch_all(pool)
+            .await?,
+        };
+        Ok(objectives
+            .into_iter()
+            .map(|(number, id, description)| {
+                (
+                    number,
+                    PlannedObjective {
+                        id: id as u32,
+                        description,
+                    },
+                )
+            })
+            .collect())
+    }
+
+    pub async fn chapter_progress(
+        &self,
+        student_id: i64,
+        book_id: i64,
+        chapter_number: &str,
+    ) -> anyhow::Result<Option<ProgressRow>> {
+        let record = match self {
+            Self::Sqlite(pool) => {
+                sqlx::query_as!(
+                    ProgressRow,
+                    "select chapter_number, status, objectives, update_time from chapter_progress where student_id = ? and book_id = ? and chapter_number = ?",
+                    student_id,
+                    book_id,
+                    chapter_number
+                )
+                .fetch_optional(pool)
+                .await?
+            }
+            Self::Postgres(pool) => {
+                sqlx::query_as(
+                    "select chapter_number, status, objectives, update_time from chapter_progress where student_id = $1 and book_id = $2 and chapter_number = $3",
+                )
+                .bind(student_id)
+                .bind(book_id)
+                .bind(chapter_number)
+                .fetch_optional(pool)
+                .await?
+            }
+        };
+        Ok(record)
+    }
+
+    /// the progress of the student in every chapter of the book
+    pub async fn book_progress(
+        &self,
+        student_id: i64,
+        book_id: i64,
+    ) -> anyhow::Result<Vec<ProgressRow>> {
+        let records = match self {
+            Self::Sqlite(pool) => {
+                sqlx::query_as!(
+                    ProgressRow,
+                    "select chapter_number, status, objectives, update_time from chapter_progress where student_id = ? and book_id = ?",
+                    student_id,
+                    book_id
+                )
+                .fetch_all(pool)
+                .await?
+            }
+            Self::Postgres(pool) => {
+                sqlx::query_as(
+                    "select chapter_number, status, objectives, update_time from chapter_progress where student_id = $1 and book_id = $2",
+                )
+                .bind(student_id)
+                .bind(book_id)
+                .fetch_all(pool)
+                .await?
+            }
+        };
+        Ok(records)
+    }
+
+    pub async fn store_chapter_progress(
+        &self,
+        student_id: i64,
+        book_id: i64,
+        progress: &ProgressRow,
+    ) -> anyhow::Result<()> {
+        match self {
+            Self::Sqlite(pool) => {
+                sqlx::query!(
+                    "insert or replace into chapter_progress (student_id, book_id, chapter_number, status, objectives, update_time) values (?, ?, ?, ?, ?, ?)",
+                    student_id,
+                    book_id,
+                    progress.chapter_number,
+                    progress.status,
+                    progress.objectives,
+                    progress.update_time
+                )
+                .execute(pool)
+                .await?;
+            }
+            Self::Postgres(pool) => {
+                sqlx::query(
+                    "insert into chapter_progress (student_id, book_id, chapter_number, status, objectives, update_time) values ($1, $2, $3, $4, $5, $6) \
+                    on conflict (student_id, book_id, chapter_number) do update set status = excluded.status, \
+                    objectives = excluded.objectives, update_time = excluded.update_time",
+                )
+                .bind(student_id)
+                .bind(book_id)
+                .bind(&progress.chapter_number)
+                .bind(progress.status)
+                .bind(&progress.objectives)
+                .bind(progress.update_time)
+                .execute(pool)
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// start the progress of a chapter, nothing happens if the student already has some
+    pub async fn seed_chapter_progress(
+        &self,
+        student_id: i64,
+        book_id: i64,
+        chapter_number: &str,
+        status: i64,
+        objectives: &str,
+    ) -> anyhow::Result<()> {
+        match self {
+            Self::Sqlite(pool) => {
+                sqlx::query!(
+                    "insert or ignore into chapter_progress (student_id, book_id, chapter_number, status, objectives) values (?, ?, ?, ?, ?)",
+                    student_id,
+                    book_id,
+                    chapter_number,
+                    status,
+                    objectives
+                )
+                .execute(pool)
+                .await?;
+            }
+            Self::Postgres(pool) => {
+                sqlx::query(
+                    "insert into chapter_progress (student_id, book_id, chapter_number, status, objectives) values ($1, $2, $3, $4, $5) on conflict do nothing",
+                )
+                .bind(student_id)
+                .bind(book_id)
+                .bind(chapter_number)
+                .bind(status)
+                .bind(objectives)
+                .execute(pool)
+                .await?;
+            }
+        }
+        Ok(())
+    }
+}
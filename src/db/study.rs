@@ -0,0 +1,296 @@
+use time::OffsetDateTime;
+
+use super::Database;
+use crate::study::StudySession;
+
+impl Database {
+    /// record a heartbeat if the book is added to the student, false if it is not
+    pub async fn insert_heartbeat(
+        &self,
+        student_id: i64,
+        book_id: i64,
+        chapter_number: Option<&str>,
+        time: OffsetDateTime,
+    ) -> anyhow::Result<bool> {
+        let rows = match self {
+            Self::Sqlite(pool) => sqlx::query!(
+                "insert into study_heartbeat (student_id, book_id, chapter_number, time) select student_id, book_id, ?, ? from teacher_agent where student_id = ? and book_id = ?",
+                chapter_number,
+                time,
+                student_id,
+                book_id
+            )
+            .execute(pool)
+            .await?
+            .rows_affected(),
+            Self::Postgres(pool) => sqlx::query(
+                "insert into study_heartbeat (student_id, book_id, chapter_number, time) select student_id, book_id, $1, $2 from teacher_agent where student_id = $3 and book_id = $4",
+            )
+            .bind(chapter_number)
+            .bind(time)
+            .bind(student_id)
+            .bind(book_id)
+            .execute(pool)
+            .await?
+            .rows_affected(),
+        };
+        Ok(rows > 0)
+    }
+
+    /// start of the latest stored session of the student in the book
+    pub async fn latest_session_start(
+        &self,
+        student_id: i64,
+        book_id: i64,
+    ) -> anyhow::Result<Option<OffsetDateTime>> {
+        let start = match self {
+            Self::Sqlite(pool) => {
+                sqlx::query_scalar!(
+                    "select start_time from study_session where student_id = ? and book_id = ? order by start_time desc limit 1",
+                    student_id,
+                    book_id
+                )
+                .fetch_optional(pool)
+                .await?
+            }
+            Self::Postgres(pool) => {
+                sqlx::query_scalar(
+                    "select start_time from study_session where student_id = $1 and book_id = $2 order by start_time desc limit 1",
+                )
+                .bind(student_id)
+                .bind(book_id)
+                .fetch_optional(pool)
+                .await?
+            }
+        };
+        Ok(start)
+    }
+
+    /// times of the messages of the student in the book since `since`
+    pub async fn message_times(
+        &self,
+        student_id: i64,
+        book_id: i64,
+        since: OffsetDateTime,
+    ) -> anyhow::Result<Vec<OffsetDateTime>> {
+        let times = match self {
+            Self::Sqlite(pool) => {
+                sqlx::query_scalar!(
+                    "select update_time from history_message where student_id = ? and book_id = ? and update_time >= ?",
+                    student_id,
+                    book_id,
+                    since
+                )
+                .fetch_all(pool)
+                .await?
+            }
+            Self::Postgres(pool) => {
+                sqlx::query_scalar(
+                    "select update_time from history_message where student_id = $1 and book_id = $2 and update_time >= $3",
+                )
+                .bind(student_id)
+                .bind(book_id)
+                .bind(since)
+                .fetch_all(pool)
+                .await?
+            }
+        };
+        Ok(times)
+    }
+
+    /// chapters and times of the heartbeats of the student in the book since `since`
+    pub async fn heartbeats(
+        &self,
+        student_id: i64,
+        book_id: i64,
+        since: OffsetDateTime,
+    ) -> anyhow::Result<Vec<(Option<String>, OffsetDateTime)>> {
+        let heartbeats = match self {
+            Self::Sqlite(pool) => sqlx::query!(
+                "select chapter_number, time from study_heartbeat where student_id = ? and book_id = ? and time >= ?",
+                student_id,
+                book_id,
+                since
+            )
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .map(|heartbeat| (heartbeat.chapter_number, heartbeat.time))
+            .collect(),
+            Self::Postgres(pool) => sqlx::query_as(
+                "select chapter_number, time from study_heartbeat where student_id = $1 and book_id = $2 and time >= $3",
+            )
+            .bind(student_id)
+            .bind(book_id)
+            .bind(since)
+            .fetch_all(pool)
+            .await?,
+        };
+        Ok(heartbeats)
+    }
+
+    /// replace the sessions of the student in the book starting from `since`
+    pub async fn replace_sessions(
+        &self,
+        student_id: i64,
+        book_id: i64,
+        since: OffsetDateTime,
+        sessions: &[StudySession],
+    ) -> anyhow::Result<()> {
+        match self {
+            Self::Sqlite(pool) => {
+                let mut tx = pool.begin().await?;
+                sqlx::query!(
+                    "delete from study_session where student_id = ? and book_id = ? and start_time >= ?",
+                    student_id,
+                    book_id,
+                    since
+                )
+                .execute(&mut *tx)
+                .await?;
+                for session in sessions {
+                    let chapters = serde_json::to_string(&session.chapters)?;
+                    sqlx::query!(
+                        "insert into study_session (student_id, book_id, start_time, end_time, chapters, message_count) values (?, ?, ?, ?, ?, ?)",
+                        student_id,
+                        book_id,
+                        session.start_time,
+                        session.end_time,
+                        chapters,
+                        session.message_count
+                    )
+                    .execute(&mut *tx)
+                    .await?;
+                }
+                tx.commit().await?;
+            }
+            Self::Postgres(pool) => {
+                let mut tx = pool.begin().await?;
+                sqlx::query(
+                    "delete from study_session where student_id = $1 and book_id = $2 and start_time >= $3",
+                )
+                .bind(student_id)
+                .bind(book_id)
+                .bind(since)
+                .execute(&mut *tx)
+                .await?;
+                for session in sessions {
+                    sqlx::query(
+                        "insert into study_session (student_id, book_id, start_time, end_time, chapters, message_count) values ($1, $2, $3, $4, $5, $6)",
+                    )
+                    .bind(student_id)
+                    .bind(book_id)
+                    .bind(session.start_time)
+                    .bind(session.end_time)
+                    .bind(serde_json::to_string(&session.chapters)?)
+                    .bind(session.message_count)
+                    .execute(&mut *tx)
+                    .await?;
+                }
+                tx.commit().await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// the stored sessions of the student in every book
+    pub async fn study_sessions(&self, student_id: i64) -> anyhow::Result<Vec<StudySession>> {
+        let records: Vec<(i64, OffsetDateTime, OffsetDateTime, String, i64)> = match self {
+            Self::Sqlite(pool) => sqlx::query!(
+                "select book_id, start_time, end_time, chapters, message_count from study_session where student_id = ?",
+                student_id
+            )
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .map(|record| {
+                (
+                    record.book_id,
+                    record.start_time,
+                    record.end_time,
+                    record.chapters,
+                    record.message_count,
+                )
+            })
+            .collect(),
+            Self::Postgres(pool) => sqlx::query_as(
+                "select book_id, start_time, end_time, chapters, message_count from study_session where student_id = $1",
+            )
+            .bind(student_id)
+            .fetch_all(pool)
+            .await?,
+        };
+        let mut sessions = Vec::with_capacity(records.len());
+        for (book_id, start_time, end_time, chapters, message_count) in records {
+            sessions.push(StudySession {
+                book_id,
+                start_time,
+                end_time,
+                chapters: serde_json::from_str(&chapters)?,
+                message_count,
+            });
+        }
+        Ok(sessions)
+    }
+
+    pub async fn daily_goal(&self, student_id: i64) -> anyhow::Result<Option<i64>> {
+        let goal = match self {
+            Self::Sqlite(pool) => {
+                sqlx::query_scalar!(
+                    "select daily_minutes from study_goal where student_id = ?",
+                    student_id
+                )
+                .fetch_optional(pool)
+                .await?
+            }
+            Self::Postgres(pool) => {
+                sqlx::query_scalar("select daily_minutes from study_goal where student_id = $1")
+                    .bind(student_id)
+                    .fetch_optional(pool)
+                    .await?
+            }
+        };
+        Ok(goal)
+    }
+
+    /// set or clear (`None`) the daily study goal in minutes
+    pub async fn set_daily_goal(
+        &self,
+        student_id: i64,
+        daily_minutes: Option<i64>,
+    ) -> anyhow::Result<()> {
+        match (self, daily_minutes) {
+            (Self::Sqlite(pool), Some(daily_minutes)) => {
+                sqlx::query!(
+                    "insert or replace into study_goal (student_id, daily_minutes) values (?, ?)",
+                    student_id,
+                    daily_minutes
+                )
+                .execute(pool)
+                .await?;
+            }
+            (Self::Sqlite(pool), None) => {
+                sqlx::query!("delete from study_goal where student_id = ?", student_id)
+                    .execute(pool)
+                    .await?;
+            }
+            (Self::Postgres(pool), Some(daily_minutes)) => {
+                sqlx::query(
+                    "insert into study_goal (student_id, daily_minutes) values ($1, $2) \
+                    on conflict (student_id) do update set daily_minutes = excluded.daily_minutes",
+                )
+                .bind(student_id)
+                .bind(daily_minutes)
+                .execute(pool)
+                .await?;
+            }
+            (Self::Postgres(pool), None) => {
+                sqlx::query("delete from study_goal where student_id = $1")
+                    .bind(student_id)
+                    .execute(pool)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,7 +1,12 @@
 pub mod ai_utils;
 pub mod api;
 pub mod books;
+pub mod cluster;
+pub mod config;
+pub mod db;
 pub mod error;
+pub mod server;
 pub mod student;
+pub mod study;
 pub mod teacher;
 pub mod utils;
@@ -0,0 +1,6 @@
+pub mod health;
+pub mod metrics;
+pub mod proxy;
+pub mod shutdown;
+pub mod telemetry;
+pub mod tls;
@@ -0,0 +1,203 @@
+use std::{
+    sync::{Arc, LazyLock},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    Extension, Json, Router, extract::State, http::StatusCode, response::IntoResponse, routing::get,
+};
+use serde::Serialize;
+use tokio::sync::Mutex;
+use tower_sessions::Session;
+use utoipa::ToSchema;
+
+use super::shutdown::Shutdown;
+use crate::{
+    ai_utils::AI_CLIENT,
+    api::user::TeacherAgentCache,
+    books::library::Library,
+    config::{Config, config},
+};
+
+/// how long the result of a probe of the model provider is reused
+const LLM_PROBE_TTL: Duration = Duration::from_secs(60);
+const LLM_PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+static STARTED: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+/// the time and result of the last probe of the model provider
+static LLM_PROBE: Mutex<Option<(Instant, Result<(), String>)>> = Mutex::const_new(None);
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Check {
+    pub name: &'static str,
+    /// why the check failed, none if it passed
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Readiness {
+    pub ready: bool,
+    pub checks: Vec<Check>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CacheSizes {
+    pub teachers: u64,
+    pub books: u64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FailedBook {
+    pub book_id: i64,
+    pub error: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Diagnostics {
+    pub version: String,
+    /// SQLite or Postgres and its version
+    pub database: String,
+    pub pending_migrations: Vec<i64>,
+    pub uptime_secs: u64,
+    /// the configuration without secrets
+    #[schema(value_type = Object)]
+    pub config: Config,
+    pub caches: CacheSizes,
+    /// books of the bookbase that could not be restored into the database at startup
+    pub failed_books: Vec<FailedBook>,
+}
+
+/// list the models of the provider, the cheapest call, at most once every `LLM_PROBE_TTL`
+async fn probe_llm() -> Result<(), String> {
+    let mut probe = LLM_PROBE.lock().await;
+    if let Some((time, result)) = probe.as_ref()
+        && time.elapsed() < LLM_PROBE_TTL
+    {
+        return result.clone();
+    }
+    let result = match tokio::time::timeout(LLM_PROBE_TIMEOUT, AI_CLIENT.models().list()).await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err("timed out".to_string()),
+    };
+    *probe = Some((Instant::now(), result.clone()));
+    result
+}
+
+async fn check_migrations(library: &Library) -> anyhow::Result<()> {
+    let pending = library.database.pending_migrations().await?;
+    if !pending.is_empty() {
+        anyhow::bail!("pending migrations {:?}", pending);
+    }
+    Ok(())
+}
+
+fn check(name: &'static str, result: Result<(), impl ToString>) -> Check {
+    Check {
+        name,
+        error: result.err().map(|e| e.to_string()),
+    }
+}
+
+/// the process is alive
+async fn healthz() -> impl IntoResponse {
+    "ok"
+}
+
+/// the server can take requests: it is not shutting down, the database answers with the
+/// migrations applied, the bookbase is readable and the model provider is reachable
+async fn readyz(
+    State(library): State<Arc<Library>>,
+    Extension(shutdown): Extension<Shutdown>,
+) -> impl IntoResponse {
+    let (database, migrations, bookbase, llm) = tokio::join!(
+        library.database.ping(),
+        check_migrations(&library),
+        tokio::fs::read_dir(&library.bookbase),
+        probe_llm(),
+    );
+    let stopping = if shutdown.is_stopping() {
+        Err("shutting down")
+    } else {
+        Ok(())
+    };
+    let checks = vec![
+        check("shutdown", stopping),
+        check("database", database),
+        check("migrations", migrations),
+        check("bookbase", bookbase.map(|_| ())),
+        check("llm", llm),
+    ];
+    let ready = checks.iter().all(|check| check.error.is_none());
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(Readiness { ready, checks })).into_response()
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/diagnostics",
+    method(get),
+    responses(
+        (status = 200, description = "Versions, configuration, cache sizes and books that failed to load", body = Diagnostics),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn diagnostics(
+    State(library): State<Arc<Library>>,
+    Extension(cache): Extension<Arc<TeacherAgentCache>>,
+    session: Session,
+) -> impl IntoResponse {
+    let Ok(Some(_)) = session.get::<i64>("manager_id").await else {
+        return (StatusCode::UNAUTHORIZED, ()).into_response();
+    };
+    let database = &library.database;
+    let (database, pending_migrations) =
+        match tokio::try_join!(database.server_version(), database.pending_migrations()) {
+            Ok(database) => database,
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        };
+    let mut failed_books: Vec<FailedBook> = library
+        .failed_books
+        .iter()
+        .map(|entry| FailedBook {
+            book_id: *entry.key(),
+            error: entry.value().clone(),
+        })
+        .collect();
+    failed_books.sort_by_key(|book| book.book_id);
+    Json(Diagnostics {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        database,
+        pending_migrations,
+        uptime_secs: STARTED.elapsed().as_secs(),
+        config: config().redacted(),
+        caches: CacheSizes {
+            teachers: cache.entry_count(),
+            books: library.books.entry_count(),
+        },
+        failed_books,
+    })
+    .into_response()
+}
+
+/// `/healthz` and `/readyz` for the probes of an orchestrator, without authentication
+pub fn get_health_routes(shutdown: Shutdown) -> Router<Arc<Library>> {
+    LazyLock::force(&STARTED);
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz).layer(Extension(shutdown)))
+}
+
+/// `/manager/diagnostics`, nested in `/api` with the other manager routes
+pub fn get_diagnostics_route(cache: Arc<TeacherAgentCache>) -> Router<Arc<Library>> {
+    Router::new().route(
+        "/manager/diagnostics",
+        get(diagnostics).layer(Extension(cache)),
+    )
+}
@@ -0,0 +1,296 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use ::metrics::{
+    Unit, counter, describe_counter, describe_gauge, describe_histogram, gauge, histogram,
+};
+use async_openai::{
+    error::OpenAIError,
+    types::{CompletionUsage, CreateChatCompletionResponse},
+};
+use axum::{
+    Extension, Router,
+    extract::{MatchedPath, Request, State},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use futures::{Stream, StreamExt};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+
+use crate::{api::user::TeacherAgentCache, books::library::Library};
+
+/// buckets of the `_seconds` histograms, from api calls to long answers of the model
+const BUCKETS: [f64; 14] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0,
+];
+
+/// how often the histograms are compacted between scrapes
+const UPKEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+/// install the Prometheus recorder of the `metrics` macros, without it they record nothing
+pub fn install() -> anyhow::Result<PrometheusHandle> {
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Suffix("_seconds".to_string()), &BUCKETS)?
+        .install_recorder()?;
+    describe();
+    tokio::spawn({
+        let handle = handle.clone();
+        async move {
+            let mut interval = tokio::time::interval(UPKEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                handle.run_upkeep();
+            }
+        }
+    });
+    Ok(handle)
+}
+
+fn describe() {
+    describe_counter!(
+        "http_requests_total",
+        "HTTP requests by method, route and status"
+    );
+    describe_histogram!(
+        "http_request_duration_seconds",
+        Unit::Seconds,
+        "Time until the response of HTTP requests by method and route, event streams excluded"
+    );
+    describe_gauge!("sse_streams_active", "Open event streams by kind");
+    describe_gauge!("cache_entries", "Entries of the teacher and book caches");
+    describe_counter!(
+        "cache_requests_total",
+        "Lookups of the teacher and book caches by result, hit or miss"
+    );
+    describe_histogram!(
+        "llm_request_duration_seconds",
+        Unit::Seconds,
+        "Duration of the requests to the model by model and purpose"
+    );
+    describe_histogram!(
+        "llm_time_to_first_token_seconds",
+        Unit::Seconds,
+        "Time until the first streamed token by model and purpose"
+    );
+    describe_counter!(
+        "llm_errors_total",
+        "Failed requests to the model by model and purpose"
+    );
+    describe_counter!(
+        "llm_tokens_total",
+        "Tokens used by model, purpose and kind, prompt or completion"
+    );
+    describe_counter!("tool_calls_total", "Tool calls of the teachers by tool");
+}
+
+/// Middleware that counts the requests and their latency by route pattern, which keeps the
+/// labels few
+pub async fn track_requests(request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched".to_string(), |path| path.as_str().to_string());
+    let method = request.method().to_string();
+    let start = Instant::now();
+    let response = next.run(request).await;
+    let status = response.status().as_u16().to_string();
+    histogram!(
+        "http_request_duration_seconds",
+        "method" => method.clone(),
+        "route" => route.clone()
+    )
+    .record(start.elapsed().as_secs_f64());
+    counter!(
+        "http_requests_total",
+        "method" => method,
+        "route" => route,
+        "status" => status
+    )
+    .increment(1);
+    response
+}
+
+/// count `stream` in `sse_streams_active` until it is dropped, when the client disconnects
+pub fn track_stream<S: Stream>(kind: &'static str, stream: S) -> impl Stream<Item = S::Item> {
+    let open = OpenStream::new(kind);
+    stream.map(move |item| {
+        let _open = &open;
+        item
+    })
+}
+
+struct OpenStream(&'static str);
+
+impl OpenStream {
+    fn new(kind: &'static str) -> Self {
+        gauge!("sse_streams_active", "kind" => kind).increment(1.0);
+        Self(kind)
+    }
+}
+
+impl Drop for OpenStream {
+    fn drop(&mut self) {
+        gauge!("sse_streams_active", "kind" => self.0).decrement(1.0);
+    }
+}
+
+/// count a lookup of the `teacher` or `book` cache
+pub fn cache_lookup(cache: &'static str, hit: bool) {
+    let result = if hit { "hit" } else { "miss" };
+    counter!("cache_requests_total", "cache" => cache, "result" => result).increment(1);
+}
+
+pub fn tool_call(tool: &str) {
+    counter!("tool_calls_total", "tool" => tool.to_string()).increment(1);
+}
+
+/// A request to the model. `purpose` tells what the answer is for, like `chat` or `extract`
+pub struct LlmRequest {
+    model: String,
+    purpose: &'static str,
+    start: Instant,
+    first_token: bool,
+}
+
+impl LlmRequest {
+    pub fn start(model: &str, purpose: &'static str) -> Self {
+        Self {
+            model: model.to_string(),
+            purpose,
+            start: Instant::now(),
+            first_token: false,
+        }
+    }
+
+    /// count the error of a request or of a streamed chunk
+    pub fn check<T>(&self, result: Result<T, OpenAIError>) -> Result<T, OpenAIError> {
+        if result.is_err() {
+            counter!(
+                "llm_errors_total",
+                "model" => self.model.clone(),
+                "purpose" => self.purpose
+            )
+            .increment(1);
+        }
+        result
+    }
+
+    /// a token was streamed, only the first one is timed
+    pub fn token(&mut self) {
+        if self.first_token {
+            return;
+        }
+        self.first_token = true;
+        histogram!(
+            "llm_time_to_first_token_seconds",
+            "model" => self.model.clone(),
+            "purpose" => self.purpose
+        )
+        .record(self.start.elapsed().as_secs_f64());
+    }
+
+    pub fn usage(&self, usage: &CompletionUsage) {
+        for (kind, tokens) in [
+            ("prompt", usage.prompt_tokens),
+            ("completion", usage.completion_tokens),
+        ] {
+            counter!(
+                "llm_tokens_total",
+                "model" => self.model.clone(),
+                "purpose" => self.purpose,
+                "kind" => kind
+            )
+            .increment(tokens as u64);
+        }
+    }
+
+    /// time the request, once its answer is complete
+    pub fn finish(self) {
+        histogram!(
+            "llm_request_duration_seconds",
+            "model" => self.model,
+            "purpose" => self.purpose
+        )
+        .record(self.start.elapsed().as_secs_f64());
+    }
+
+    /// record a request that was not streamed
+    pub fn response(
+        self,
+        result: Result<CreateChatCompletionResponse, OpenAIError>,
+    ) -> Result<CreateChatCompletionResponse, OpenAIError> {
+        let response = self.check(result)?;
+        if let Some(usage) = &response.usage {
+            self.usage(usage);
+        }
+        self.finish();
+        Ok(response)
+    }
+}
+
+/// the metrics in the Prometheus text format, the cache sizes are read at every scrape
+async fn metrics(
+    State(library): State<Arc<Library>>,
+    Extension(handle): Extension<PrometheusHandle>,
+    Extension(cache): Extension<Arc<TeacherAgentCache>>,
+) -> impl IntoResponse {
+    gauge!("cache_entries", "cache" => "teacher").set(cache.entry_count() as f64);
+    gauge!("cache_entries", "cache" => "book").set(library.books.entry_count() as f64);
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        handle.render(),
+    )
+}
+
+pub fn get_metrics_route(
+    handle: PrometheusHandle,
+    cache: Arc<TeacherAgentCache>,
+) -> Router<Arc<Library>> {
+    Router::new().route(
+        "/metrics",
+        get(metrics).layer((Extension(handle), Extension(cache))),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn recorded_metrics() {
+        let recorder = PrometheusBuilder::new()
+            .set_buckets_for_metric(Matcher::Suffix("_seconds".to_string()), &BUCKETS)
+            .unwrap()
+            .build_recorder();
+        let handle = recorder.handle();
+        ::metrics::with_local_recorder(&recorder, || {
+            cache_lookup("teacher", false);
+            cache_lookup("teacher", true);
+            cache_lookup("teacher", true);
+            tool_call("GetChapterContent");
+            let mut llm = LlmRequest::start("model", "chat");
+            llm.token();
+            llm.token();
+            let _ = llm.check(Err::<(), _>(OpenAIError::InvalidArgument("".to_string())));
+            llm.finish();
+            let stream = track_stream("chat", futures::stream::empty::<()>());
+            drop(stream);
+        });
+        let rendered = handle.render();
+        for line in [
+            r#"cache_requests_total{cache="teacher",result="hit"} 2"#,
+            r#"cache_requests_total{cache="teacher",result="miss"} 1"#,
+            r#"tool_calls_total{tool="GetChapterContent"} 1"#,
+            r#"llm_time_to_first_token_seconds_count{model="model",purpose="chat"} 1"#,
+            r#"llm_errors_total{model="model",purpose="chat"} 1"#,
+            r#"llm_request_duration_seconds_count{model="model",purpose="chat"} 1"#,
+            r#"sse_streams_active{kind="chat"} 0"#,
+        ] {
+            assert!(rendered.contains(line), "{} not in\n{}", line, rendered);
+        }
+    }
+}
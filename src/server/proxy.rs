@@ -0,0 +1,281 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+};
+
+use anyhow::Context;
+use axum::{
+    Router,
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, HeaderValue, StatusCode, Uri, header, uri::Authority},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+};
+use tracing::Span;
+
+use crate::config::Config;
+
+/// The address or network of a reverse proxy, like `10.0.0.1` or `10.0.0.0/8`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrustedProxy {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl FromStr for TrustedProxy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (address, prefix) = match s.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (s, None),
+        };
+        let network: IpAddr = address
+            .trim()
+            .parse()
+            .with_context(|| format!("`{}` is not an IP address", s))?;
+        let bits = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .trim()
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= bits)
+                .with_context(|| format!("`{}` has an invalid prefix length", s))?,
+            None => bits,
+        };
+        Ok(Self { network, prefix })
+    }
+}
+
+impl TrustedProxy {
+    fn contains(&self, ip: IpAddr) -> bool {
+        let (network, ip, bits) = match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                (network.to_bits() as u128, ip.to_bits() as u128, 32)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => (network.to_bits(), ip.to_bits(), 128),
+            _ => return false,
+        };
+        let shift = (bits - self.prefix) as u32;
+        network.checked_shr(shift).unwrap_or(0) == ip.checked_shr(shift).unwrap_or(0)
+    }
+}
+
+/// The client of a request, behind trusted proxies the one they forwarded the request for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientInfo {
+    pub ip: IpAddr,
+    /// whether the client used https, to the server or to the proxy
+    pub secure: bool,
+}
+
+/// How requests reach the server, directly or through trusted reverse proxies
+#[derive(Debug, Clone)]
+pub struct ProxySettings {
+    trusted: Vec<TrustedProxy>,
+    /// the listener itself serves https
+    tls: bool,
+    https_redirect: bool,
+}
+
+impl ProxySettings {
+    pub fn new(config: &Config) -> anyhow::Result<Self> {
+        Ok(Self {
+            trusted: config
+                .proxy
+                .trusted
+                .iter()
+                .map(|proxy| proxy.parse())
+                .collect::<anyhow::Result<_>>()?,
+            tls: config.tls.enabled,
+            https_redirect: config.proxy.https_redirect,
+        })
+    }
+
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted.iter().any(|proxy| proxy.contains(ip))
+    }
+
+    /// the forwarded headers are only read from trusted proxies, anyone else could forge them
+    pub fn client(&self, peer: IpAddr, headers: &HeaderMap) -> ClientInfo {
+        let mut client = ClientInfo {
+            ip: peer,
+            secure: self.tls,
+        };
+        if !self.is_trusted(peer) {
+            return client;
+        }
+        // every proxy appends the address it received the request from, the client is the
+        // last address that is not a trusted proxy
+        let forwarded: Vec<IpAddr> = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|ip| ip.trim().parse().ok())
+            .collect();
+        for ip in forwarded.into_iter().rev() {
+            client.ip = ip;
+            if !self.is_trusted(ip) {
+                break;
+            }
+        }
+        if let Some(proto) = headers
+            .get("x-forwarded-proto")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit(',').next())
+        {
+            client.secure = proto.trim().eq_ignore_ascii_case("https");
+        }
+        client
+    }
+}
+
+/// Middleware that adds the [`ClientInfo`] of the request, redirects plain http to https if
+/// configured and marks the cookies of https clients `Secure`
+pub async fn forwarded(
+    State(settings): State<Arc<ProxySettings>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let client = settings.client(peer.ip(), request.headers());
+    if settings.https_redirect
+        && !client.secure
+        && let Some(location) = https_location(request.headers(), request.uri(), None)
+    {
+        return Redirect::permanent(&location).into_response();
+    }
+    request.extensions_mut().insert(client);
+    let mut response = next.run(request).await;
+    if client.secure {
+        secure_cookies(response.headers_mut());
+    }
+    response
+}
+
+/// the span of a request, with the client added by [`forwarded`]
+pub fn request_span<B>(request: &axum::http::Request<B>) -> Span {
+    let client = request.extensions().get::<ClientInfo>();
+    tracing::info_span!(
+        "request",
+        otel.kind = "server",
+        method = %request.method(),
+        uri = %request.uri(),
+        client = ?client.map(|client| client.ip),
+        secure = ?client.map(|client| client.secure),
+    )
+}
+
+/// A plain http server that redirects every request to the https listener on `https_port`
+pub fn redirect_to_https(https_port: u16) -> Router {
+    Router::new().fallback(async move |headers: HeaderMap, uri: Uri| {
+        match https_location(&headers, &uri, Some(https_port)) {
+            Some(location) => Redirect::permanent(&location).into_response(),
+            None => (StatusCode::BAD_REQUEST, "Missing Host header").into_response(),
+        }
+    })
+}
+
+/// the https url of a request, on the default port unless `port` is another one
+fn https_location(headers: &HeaderMap, uri: &Uri, port: Option<u16>) -> Option<String> {
+    let host = headers.get(header::HOST)?.to_str().ok()?;
+    let host = host.parse::<Authority>().ok()?;
+    let path = uri.path_and_query().map_or("/", |path| path.as_str());
+    Some(match port {
+        Some(port) if port != 443 => format!("https://{}:{}{}", host.host(), port, path),
+        _ => format!("https://{}{}", host.host(), path),
+    })
+}
+
+fn secure_cookies(headers: &mut HeaderMap) {
+    for (name, value) in headers.iter_mut() {
+        if name != header::SET_COOKIE {
+            continue;
+        }
+        let Ok(cookie) = value.to_str() else {
+            continue;
+        };
+        let secure = cookie
+            .split(';')
+            .skip(1)
+            .any(|attribute| attribute.trim().eq_ignore_ascii_case("secure"));
+        if !secure && let Ok(secure) = HeaderValue::from_str(&format!("{}; Secure", cookie)) {
+            *value = secure;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(trusted: &[&str]) -> ProxySettings {
+        ProxySettings {
+            trusted: trusted.iter().map(|proxy| proxy.parse().unwrap()).collect(),
+            tls: false,
+            https_redirect: false,
+        }
+    }
+
+    fn headers(forwarded_for: &str, proto: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", forwarded_for.parse().unwrap());
+        headers.insert("x-forwarded-proto", proto.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn trusted_proxies() {
+        let network: TrustedProxy = "10.0.0.0/8".parse().unwrap();
+        assert!(network.contains("10.1.2.3".parse().unwrap()));
+        assert!(network.contains("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!network.contains("11.0.0.1".parse().unwrap()));
+        let any: TrustedProxy = "::/0".parse().unwrap();
+        assert!(any.contains("2001:db8::1".parse().unwrap()));
+        let host: TrustedProxy = "192.168.1.1".parse().unwrap();
+        assert!(!host.contains("192.168.1.2".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<TrustedProxy>().is_err());
+        assert!("proxy".parse::<TrustedProxy>().is_err());
+    }
+
+    #[test]
+    fn forwarded_client() {
+        let settings = settings(&["10.0.0.0/8"]);
+        let proxy = "10.0.0.2".parse().unwrap();
+        let headers = headers("6.6.6.6, 1.2.3.4, 10.0.0.3", "https");
+        let client = settings.client(proxy, &headers);
+        assert_eq!(client.ip, "1.2.3.4".parse::<IpAddr>().unwrap());
+        assert!(client.secure);
+
+        // a client cannot forge the headers
+        let direct = "1.2.3.4".parse().unwrap();
+        let client = settings.client(direct, &headers);
+        assert_eq!(client.ip, direct);
+        assert!(!client.secure);
+    }
+
+    #[test]
+    fn redirects_and_cookies() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::HOST, "example.com:8080".parse().unwrap());
+        let uri: Uri = "/api/books?page=2".parse().unwrap();
+        assert_eq!(
+            https_location(&headers, &uri, Some(8443)).unwrap(),
+            "https://example.com:8443/api/books?page=2"
+        );
+        assert_eq!(
+            https_location(&headers, &uri, None).unwrap(),
+            "https://example.com/api/books?page=2"
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.append(header::SET_COOKIE, "id=1; HttpOnly".parse().unwrap());
+        headers.append(header::SET_COOKIE, "other=2; Secure".parse().unwrap());
+        secure_cookies(&mut headers);
+        let cookies: Vec<_> = headers.get_all(header::SET_COOKIE).iter().collect();
+        assert_eq!(cookies, ["id=1; HttpOnly; Secure", "other=2; Secure"]);
+    }
+}
@@ -0,0 +1,95 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::sync::{OwnedRwLockReadGuard, RwLock, watch};
+use tracing::warn;
+
+/// Held by a running teacher turn, a shutdown waits until every permit is dropped
+pub type TurnPermit = OwnedRwLockReadGuard<()>;
+
+/// Refuses new teacher turns once the server shuts down and waits for the running ones
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    stopping: Arc<watch::Sender<bool>>,
+    turns: Arc<RwLock<()>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self {
+            stopping: Arc::new(watch::channel(false).0),
+            turns: Arc::default(),
+        }
+    }
+}
+
+impl Shutdown {
+    /// a permit for a new turn, none once the shutdown started
+    pub fn begin_turn(&self) -> Option<TurnPermit> {
+        if self.is_stopping() {
+            return None;
+        }
+        self.turns.clone().try_read_owned().ok()
+    }
+
+    /// whether the shutdown started
+    pub fn is_stopping(&self) -> bool {
+        *self.stopping.borrow()
+    }
+
+    /// refuse new turns
+    pub fn trigger(&self) {
+        self.stopping.send_replace(true);
+    }
+
+    /// wait for the running turns, false if some still run after `timeout`
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        tokio::time::timeout(timeout, self.turns.write())
+            .await
+            .is_ok()
+    }
+}
+
+/// resolves on ctrl-c, or on SIGTERM on unix
+pub async fn signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!("listen for ctrl-c failed: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                warn!("listen for SIGTERM failed: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn drain_turns() {
+        let shutdown = Shutdown::default();
+        let permit = shutdown.begin_turn().unwrap();
+        shutdown.trigger();
+        assert!(shutdown.begin_turn().is_none());
+        assert!(!shutdown.drain(Duration::from_millis(50)).await);
+        drop(permit);
+        assert!(shutdown.drain(Duration::from_millis(50)).await);
+    }
+}
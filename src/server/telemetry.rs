@@ -0,0 +1,45 @@
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    Resource,
+    trace::{Sampler, SdkTracerProvider},
+};
+
+use crate::config::TelemetryConfig;
+
+/// the provider of the spans exported to the collector of `config`, none if no endpoint is set.
+/// Spans are exported in batches from a thread of the provider
+pub fn tracer_provider(config: &TelemetryConfig) -> anyhow::Result<Option<SdkTracerProvider>> {
+    let Some(endpoint) = &config.otlp_endpoint else {
+        return Ok(None);
+    };
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+        .build()?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            config.sample_ratio,
+        ))))
+        .with_resource(
+            Resource::builder()
+                .with_service_name(config.service_name.clone())
+                .build(),
+        )
+        .build();
+    Ok(Some(provider))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn provider() {
+        let mut config = TelemetryConfig::default();
+        assert!(tracer_provider(&config).unwrap().is_none());
+        config.otlp_endpoint = Some("http://localhost:4318/".to_string());
+        let provider = tracer_provider(&config).unwrap().unwrap();
+        provider.shutdown().unwrap();
+    }
+}
@@ -0,0 +1,35 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use axum_server::tls_rustls::RustlsConfig;
+use tracing::{info, warn};
+
+/// reload the certificate of `tls` when `cert` or `key` change, they are checked every `interval`
+pub async fn watch_certificate(tls: RustlsConfig, cert: PathBuf, key: PathBuf, interval: Duration) {
+    let mut loaded = modified(&cert, &key).await;
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let current = modified(&cert, &key).await;
+        if current == loaded {
+            continue;
+        }
+        // a failed reload is tried again, the key may be written after the certificate
+        match tls.reload_from_pem_file(&cert, &key).await {
+            Ok(()) => {
+                info!("Reloaded the certificate {}", cert.display());
+                loaded = current;
+            }
+            Err(e) => warn!("reload certificate {} failed: {}", cert.display(), e),
+        }
+    }
+}
+
+async fn modified(cert: &Path, key: &Path) -> Option<(SystemTime, SystemTime)> {
+    let cert = tokio::fs::metadata(cert).await.ok()?.modified().ok()?;
+    let key = tokio::fs::metadata(key).await.ok()?.modified().ok()?;
+    Some((cert, key))
+}
@@ -4,10 +4,9 @@ use argon2::{
 };
 
 use serde::{Deserialize, Serialize};
-use sqlx::SqlitePool;
 use utoipa::ToSchema;
 
-use crate::{books::book::BookMeta, teacher::TeacherAgent};
+use crate::{books::book::BookMeta, db::Database, teacher::TeacherAgent};
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct StudentInfo {
@@ -16,15 +15,12 @@ pub struct StudentInfo {
     pub email: String,
 }
 
-pub async fn get_student_list(database: &SqlitePool) -> anyhow::Result<Vec<StudentInfo>> {
-    let students = sqlx::query_as!(StudentInfo, "SELECT id, name, email FROM student")
-        .fetch_all(database)
-        .await?;
-    Ok(students)
+pub async fn get_student_list(database: &Database) -> anyhow::Result<Vec<StudentInfo>> {
+    database.list_students().await
 }
 
 pub async fn create_student(
-    database: &SqlitePool,
+    database: &Database,
     name: String,
     email: String,
     password: String,
@@ -34,44 +30,19 @@ pub async fn create_student(
         .hash_password(password.as_bytes(), &salt)
         .map_err(|e| anyhow::anyhow!("Failed to hash password: {}", e))?
         .to_string();
-    let student = sqlx::query!(
-        "INSERT INTO student (name, email, password) VALUES (?, ?, ?)",
-        name,
-        email,
-        password_hash
-    )
-    .execute(database)
-    .await?;
-    Ok(student.last_insert_rowid() as i64)
+    database.insert_student(&name, &email, &password_hash).await
 }
 
-pub async fn delete_student(database: &SqlitePool, id: i64) -> anyhow::Result<()> {
-    sqlx::query!("DELETE FROM student WHERE id = ?", id)
-        .execute(database)
-        .await?;
-    Ok(())
+pub async fn delete_student(database: &Database, id: i64) -> anyhow::Result<()> {
+    database.delete_student(id).await
 }
 
-pub async fn get_student_books(database: &SqlitePool, id: i64) -> anyhow::Result<Vec<BookMeta>> {
-    let books = sqlx::query!("SELECT book.id, book.title, book.authors, book.description, book.is_public FROM book inner join teacher_agent on book.id = teacher_agent.book_id WHERE student_id = ?", id)
-        .fetch_all(database)
-        .await?;
-    let mut book_list = Vec::new();
-    for book in books {
-        let book_meta = BookMeta {
-            id: book.id,
-            title: book.title,
-            authors: book.authors.split(',').map(|s| s.to_string()).collect(),
-            description: book.description,
-            is_public: book.is_public,
-        };
-        book_list.push(book_meta);
-    }
-    Ok(book_list)
+pub async fn get_student_books(database: &Database, id: i64) -> anyhow::Result<Vec<BookMeta>> {
+    database.student_books(id).await
 }
 
 pub async fn add_student_books(
-    database: &SqlitePool,
+    database: &Database,
     id: i64,
     book_ids: Vec<i64>,
 ) -> anyhow::Result<()> {
@@ -80,54 +51,38 @@ pub async fn add_student_books(
     }
     Ok(())
 }
-pub async fn delete_student_book(
-    database: &SqlitePool,
-    id: i64,
-    book_id: i64,
-) -> anyhow::Result<()> {
-    sqlx::query!(
-        "DELETE FROM chapter_progress WHERE student_id = ? AND book_id = ?",
-        id,
-        book_id
-    )
-    .execute(database)
-    .await?;
-    sqlx::query!(
-        "DELETE FROM history_message WHERE student_id = ? AND book_id = ?",
-        id,
-        book_id
-    )
-    .execute(database)
-    .await?;
-    sqlx::query!(
-        "DELETE FROM teacher_agent WHERE student_id = ? AND book_id = ?",
-        id,
-        book_id
-    )
-    .execute(database)
-    .await?;
-    Ok(())
+pub async fn delete_student_book(database: &Database, id: i64, book_id: i64) -> anyhow::Result<()> {
+    database.delete_student_book(id, book_id).await
 }
 
-pub async fn login(database: &SqlitePool, email: String, password: String) -> anyhow::Result<i64> {
-    let student = sqlx::query!("SELECT id, password FROM student WHERE email = ?", email)
-        .fetch_one(database)
-        .await?;
-    let parsed_hash = PasswordHash::new(&student.password)
+pub async fn login(database: &Database, email: String, password: String) -> anyhow::Result<i64> {
+    let (id, password_hash) = database.student_password(&email).await?;
+    let parsed_hash = PasswordHash::new(&password_hash)
         .map_err(|e| anyhow::anyhow!("Failed to parse password hash: {}", e))?;
     Argon2::default()
         .verify_password(password.as_bytes(), &parsed_hash)
         .map_err(|e| anyhow::anyhow!("Failed to verify password: {}", e))?;
-    Ok(student.id)
+    Ok(id)
 }
 
-pub async fn get_student_info(database: &SqlitePool, id: i64) -> anyhow::Result<StudentInfo> {
-    let student = sqlx::query_as!(
-        StudentInfo,
-        "SELECT id, name, email FROM student WHERE id = ?",
-        id
-    )
-    .fetch_one(database)
-    .await?;
-    Ok(student)
+pub async fn get_student_info(database: &Database, id: i64) -> anyhow::Result<StudentInfo> {
+    database.student_info(id).await
+}
+
+/// a student can add the public books and the books they uploaded
+pub async fn can_add_book(database: &Database, id: i64, book_id: i64) -> anyhow::Result<bool> {
+    if database.book_owner(book_id).await? == Some(id) {
+        return Ok(true);
+    }
+    database.book_is_public(book_id).await
+}
+
+/// a student can read the books they can add. Books without an owner stay readable to the
+/// students that added them before, a public book can be made private after it was added
+pub async fn can_read_book(database: &Database, id: i64, book_id: i64) -> anyhow::Result<bool> {
+    if can_add_book(database, id, book_id).await? {
+        return Ok(true);
+    }
+    Ok(database.book_owner(book_id).await?.is_none()
+        && database.has_teacher_agent(id, book_id).await?)
 }
@@ -0,0 +1,372 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+use time::{Date, Duration, OffsetDateTime};
+use utoipa::ToSchema;
+
+use crate::{
+    books::chapter::ChapterNumber,
+    db::Database,
+    utils::{LOCAL_OFFSET, now_local},
+};
+
+/// Activities further apart than this belong to different sessions
+pub const SESSION_GAP: Duration = Duration::minutes(15);
+/// Time credited after the last activity of a session
+pub const SESSION_TAIL: Duration = Duration::minutes(1);
+/// Number of days in the daily breakdown of the study stats
+const DAILY_HISTORY: i64 = 28;
+/// Number of weeks in the weekly breakdown of the study stats
+const WEEKLY_HISTORY: i64 = 8;
+/// Number of sessions listed in the study stats
+const RECENT_SESSIONS: usize = 10;
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct StudySession {
+    pub book_id: i64,
+    #[serde(with = "time::serde::rfc3339")]
+    pub start_time: OffsetDateTime,
+    /// time of the last activity of the session
+    #[serde(with = "time::serde::rfc3339")]
+    pub end_time: OffsetDateTime,
+    pub chapters: BTreeSet<ChapterNumber>,
+    pub message_count: i64,
+}
+
+impl StudySession {
+    pub fn duration(&self) -> Duration {
+        self.end_time - self.start_time + SESSION_TAIL
+    }
+}
+
+/// A single timestamped activity of a student in a book
+#[derive(Debug, Clone)]
+pub struct Activity {
+    pub time: OffsetDateTime,
+    pub chapter: Option<ChapterNumber>,
+    pub is_message: bool,
+}
+
+/// group activities into sessions, a new session starts after a gap longer than `SESSION_GAP`
+pub fn derive_sessions(book_id: i64, mut activities: Vec<Activity>) -> Vec<StudySession> {
+    activities.sort_by_key(|a| a.time);
+    let mut sessions: Vec<StudySession> = vec![];
+    for activity in activities {
+        match sessions.last_mut() {
+            Some(session) if activity.time - session.end_time <= SESSION_GAP => {
+                session.end_time = activity.time;
+            }
+            _ => sessions.push(StudySession {
+                book_id,
+                start_time: activity.time,
+                end_time: activity.time,
+                chapters: BTreeSet::new(),
+                message_count: 0,
+            }),
+        }
+        let session = sessions.last_mut().expect("unreachable");
+        if let Some(chapter) = activity.chapter {
+            session.chapters.insert(chapter);
+        }
+        if activity.is_message {
+            session.message_count += 1;
+        }
+    }
+    sessions
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DailyStudy {
+    /// local date, e.g. "2025-03-14"
+    pub date: String,
+    pub minutes: i64,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct WeeklyStudy {
+    /// local date of the monday starting the week, e.g. "2025-03-10"
+    pub week_start: String,
+    pub minutes: i64,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct StudyStats {
+    pub today_minutes: i64,
+    pub week_minutes: i64,
+    /// study time of the last days, oldest first
+    pub daily: Vec<DailyStudy>,
+    /// study time of the last weeks, oldest first
+    pub weekly: Vec<WeeklyStudy>,
+    /// consecutive days (up to today) the daily goal was met, or studied at all without a goal
+    pub current_streak: i64,
+    pub longest_streak: i64,
+    pub daily_goal_minutes: Option<i64>,
+    pub goal_met_today: bool,
+    /// most recent sessions, newest first
+    pub recent_sessions: Vec<StudySession>,
+}
+
+fn week_start(date: Date) -> Date {
+    date - Duration::days(date.weekday().number_days_from_monday() as i64)
+}
+
+impl StudyStats {
+    pub fn compute(mut sessions: Vec<StudySession>, daily_goal: Option<i64>, today: Date) -> Self {
+        let mut minutes_by_day: BTreeMap<Date, i64> = BTreeMap::new();
+        for session in &sessions {
+            let date = session.start_time.to_offset(*LOCAL_OFFSET).date();
+            *minutes_by_day.entry(date).or_default() += session.duration().whole_minutes();
+        }
+        let minutes_on = |date: Date| minutes_by_day.get(&date).copied().unwrap_or_default();
+
+        let daily = (0..DAILY_HISTORY)
+            .rev()
+            .map(|i| today - Duration::days(i))
+            .map(|date| DailyStudy {
+                date: date.to_string(),
+                minutes: minutes_on(date),
+            })
+            .collect();
+        let this_week = week_start(today);
+        let weekly = (0..WEEKLY_HISTORY)
+            .rev()
+            .map(|i| this_week - Duration::weeks(i))
+            .map(|start| WeeklyStudy {
+                week_start: start.to_string(),
+                minutes: (0..7).map(|d| minutes_on(start + Duration::days(d))).sum(),
+            })
+            .collect();
+
+        let threshold = daily_goal.unwrap_or(1).max(1);
+        let goal_days: BTreeSet<Date> = minutes_by_day
+            .iter()
+            .filter(|(_, minutes)| **minutes >= threshold)
+            .map(|(date, _)| *date)
+            .collect();
+        let (current_streak, longest_streak) = streaks(&goal_days, today);
+
+        sessions.sort_by_key(|session| std::cmp::Reverse(session.start_time));
+        sessions.truncate(RECENT_SESSIONS);
+        Self {
+            today_minutes: minutes_on(today),
+            week_minutes: (0..7)
+                .map(|d| minutes_on(this_week + Duration::days(d)))
+                .sum(),
+            daily,
+            weekly,
+            current_streak,
+            longest_streak,
+            daily_goal_minutes: daily_goal,
+            goal_met_today: goal_days.contains(&today),
+            recent_sessions: sessions,
+        }
+    }
+}
+
+/// (current, longest) streak of consecutive days, the current streak is
+/// still alive if the last day is yesterday
+fn streaks(days: &BTreeSet<Date>, today: Date) -> (i64, i64) {
+    let mut longest = 0;
+    let mut run = 0;
+    let mut previous: Option<Date> = None;
+    for day in days {
+        run = match previous {
+            Some(previous) if previous.next_day() == Some(*day) => run + 1,
+            _ => 1,
+        };
+        longest = longest.max(run);
+        previous = Some(*day);
+    }
+    let mut current = 0;
+    let mut day = if days.contains(&today) {
+        Some(today)
+    } else {
+        today.previous_day()
+    };
+    while let Some(d) = day
+        && days.contains(&d)
+    {
+        current += 1;
+        day = d.previous_day();
+    }
+    (current, longest)
+}
+
+/// record that the student has the book open and bring its sessions up to date,
+/// fails if the book is not added to the student
+pub async fn record_heartbeat(
+    database: &Database,
+    student_id: i64,
+    book_id: i64,
+    chapter_number: Option<ChapterNumber>,
+) -> anyhow::Result<()> {
+    let now = OffsetDateTime::now_utc();
+    let chapter_number = chapter_number.map(|n| n.to_string());
+    if !database
+        .insert_heartbeat(student_id, book_id, chapter_number.as_deref(), now)
+        .await?
+    {
+        anyhow::bail!("Book {} is not added to student {}", book_id, student_id);
+    }
+    refresh_sessions(database, student_id, book_id).await
+}
+
+/// re-derive the sessions of a student in a book, starting from the latest stored session,
+/// after a heartbeat or a message
+pub async fn refresh_sessions(
+    database: &Database,
+    student_id: i64,
+    book_id: i64,
+) -> anyhow::Result<()> {
+    let since = database
+        .latest_session_start(student_id, book_id)
+        .await?
+        .unwrap_or(OffsetDateTime::UNIX_EPOCH);
+    let mut activities: Vec<Activity> = database
+        .message_times(student_id, book_id, since)
+        .await?
+        .into_iter()
+        .map(|time| Activity {
+            time,
+            chapter: None,
+            is_message: true,
+        })
+        .collect();
+    for (chapter_number, time) in database.heartbeats(student_id, book_id, since).await? {
+        activities.push(Activity {
+            time,
+            chapter: chapter_number.and_then(|n| n.parse().ok()),
+            is_message: false,
+        });
+    }
+    let sessions = derive_sessions(book_id, activities);
+    database
+        .replace_sessions(student_id, book_id, since, &sessions)
+        .await
+}
+
+pub async fn get_daily_goal(database: &Database, student_id: i64) -> anyhow::Result<Option<i64>> {
+    database.daily_goal(student_id).await
+}
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct DailyGoal {
+    /// daily study goal in minutes, `null` to remove the goal
+    pub minutes: Option<i64>,
+}
+
+/// set or clear (`None`) the daily study goal in minutes
+pub async fn set_daily_goal(
+    database: &Database,
+    student_id: i64,
+    daily_minutes: Option<i64>,
+) -> anyhow::Result<()> {
+    if daily_minutes.is_some_and(|minutes| minutes <= 0) {
+        anyhow::bail!("Daily goal must be positive");
+    }
+    database.set_daily_goal(student_id, daily_minutes).await
+}
+
+/// stats from the stored sessions, kept up to date by `record_heartbeat` and the teacher
+pub async fn get_study_stats(database: &Database, student_id: i64) -> anyhow::Result<StudyStats> {
+    let sessions = database.study_sessions(student_id).await?;
+    let daily_goal = get_daily_goal(database, student_id).await?;
+    Ok(StudyStats::compute(
+        sessions,
+        daily_goal,
+        now_local().date(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::{date, datetime};
+
+    use super::*;
+    use crate::db::test_database;
+
+    fn activity(time: OffsetDateTime, chapter: Option<&str>) -> Activity {
+        Activity {
+            time,
+            chapter: chapter.map(|c| c.parse().unwrap()),
+            is_message: chapter.is_none(),
+        }
+    }
+
+    #[test]
+    fn sessions_split_on_gap() {
+        let sessions = derive_sessions(
+            1,
+            vec![
+                activity(datetime!(2025-03-14 10:10 UTC), None),
+                activity(datetime!(2025-03-14 10:00 UTC), Some("1.")),
+                activity(datetime!(2025-03-14 10:20 UTC), Some("2.")),
+                activity(datetime!(2025-03-14 11:00 UTC), None),
+            ],
+        );
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].duration(), Duration::minutes(21));
+        assert_eq!(sessions[0].chapters.len(), 2);
+        assert_eq!(sessions[0].message_count, 1);
+        assert_eq!(sessions[1].message_count, 1);
+    }
+
+    #[test]
+    fn streak_counts() {
+        let days = BTreeSet::from([
+            date!(2025 - 03 - 01),
+            date!(2025 - 03 - 02),
+            date!(2025 - 03 - 03),
+            date!(2025 - 03 - 10),
+            date!(2025 - 03 - 11),
+        ]);
+        assert_eq!(streaks(&days, date!(2025 - 03 - 12)), (2, 3));
+        assert_eq!(streaks(&days, date!(2025 - 03 - 11)), (2, 3));
+        assert_eq!(streaks(&days, date!(2025 - 03 - 13)), (0, 3));
+    }
+
+    #[tokio::test]
+    async fn messages_without_heartbeats() {
+        let database = test_database().await;
+        // the postgres test database is shared between runs
+        let email = format!(
+            "{}@example.com",
+            OffsetDateTime::now_utc().unix_timestamp_nanos()
+        );
+        let student_id = database
+            .insert_student("Ada", &email, "hash")
+            .await
+            .unwrap();
+        let book_id = database
+            .insert_book("Book", "Ada", None, &email)
+            .await
+            .unwrap();
+        database
+            .insert_teacher_agent(student_id, book_id)
+            .await
+            .unwrap();
+        let start = datetime!(2025-03-14 10:00 UTC);
+        for minutes in [0, 5] {
+            database
+                .add_message(
+                    student_id,
+                    book_id,
+                    "{}",
+                    start + Duration::minutes(minutes),
+                )
+                .await
+                .unwrap();
+        }
+        refresh_sessions(&database, student_id, book_id)
+            .await
+            .unwrap();
+        let sessions = database.study_sessions(student_id).await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].duration(), Duration::minutes(6));
+        assert_eq!(sessions[0].message_count, 2);
+        assert!(sessions[0].chapters.is_empty());
+
+        database.delete_student(student_id).await.unwrap();
+        database.delete_book_rows(book_id).await.unwrap();
+    }
+}
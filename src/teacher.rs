@@ -1,29 +1,58 @@
 pub mod messages;
+pub mod recommend;
+pub mod study_guide;
+pub mod traced;
 
+use std::collections::{BTreeMap, BTreeSet};
 use std::convert::Infallible;
 use std::sync::Arc;
 
 use async_openai::tools::{ToolCallStreamManager, ToolManager};
 use async_openai::types::{
     ChatCompletionMessageToolCall, ChatCompletionRequestAssistantMessageArgs,
-    ChatCompletionRequestMessage, ChatCompletionRequestToolMessage,
-    ChatCompletionRequestUserMessage, CreateChatCompletionRequestArgs,
+    ChatCompletionRequestMessage, ChatCompletionRequestMessageContentPartImage,
+    ChatCompletionRequestMessageContentPartText, ChatCompletionRequestToolMessage,
+    ChatCompletionRequestUserMessage, ChatCompletionRequestUserMessageContent,
+    ChatCompletionRequestUserMessageContentPart, ChatCompletionStreamOptions,
+    CreateChatCompletionRequestArgs,
 };
 use axum::response::sse::Event;
 use futures::StreamExt;
 use messages::MessagesManager;
+use messages::progress::{ChapterObjective, ChapterStatus};
+use recommend::RecommendNextTool;
 use serde::Serialize;
-use sqlx::SqlitePool;
 use tokio::sync::mpsc::Sender;
+use traced::TracedTool;
+use tracing::{Instrument, info_span};
 
 use crate::ai_utils::{AI_CLIENT, AI_MODEL};
+use crate::books::book::Book;
+use crate::books::figure::{FigureAttachments, GetFigureTool};
 use crate::books::library::Library;
 use crate::books::tools::{BookJumpTool, GetChapterTool};
+use crate::config::config;
+use crate::db::Database;
+use crate::server::metrics::{self, LlmRequest};
+use crate::study;
+
+/// rounds of tool calls of the end of session summary
+const SUMMARY_ROUNDS: usize = 4;
+
+const SUMMARY_PROMPT: &str = "The session is ending. Review the conversation since the last summary and record what the student learned: \
+update the progress of the chapters you worked on with [ProgressUpdate], score the objectives the student showed (mis)understanding of with [RecordAssessment] \
+and store what you learned about the student with [AddMemory]. Only call tools, the student will not read your reply.";
 
 /// The AI Teacher Agent that interacts with students
 pub struct TeacherAgent {
     messages: MessagesManager,
     tool_manager: ToolManager,
+    attachments: FigureAttachments,
+    library: Arc<Library>,
+    book: Arc<Book>,
+    student_id: i64,
+    /// turns were taken since the last summary
+    unsummarized: bool,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -35,46 +64,69 @@ pub enum ResponseEvent {
 }
 
 impl TeacherAgent {
-    pub async fn init(student_id: i64, book_id: i64, database: SqlitePool) -> anyhow::Result<()> {
-        sqlx::query!(
-            "insert or ignore into teacher_agent (student_id, book_id, current_chapter_number, memories) values (?, ?, '', '[]')",
-            student_id,
-            book_id,
-        )
-        .execute(&database)
-        .await?;
+    pub async fn init(student_id: i64, book_id: i64, database: Database) -> anyhow::Result<()> {
+        database.insert_teacher_agent(student_id, book_id).await?;
+        // seed the chapter progress with the canonical objectives of each chapter
+        let mut seeded: BTreeMap<String, BTreeSet<ChapterObjective>> = BTreeMap::new();
+        for (chapter_number, objective) in database.book_objectives(book_id).await? {
+            seeded
+                .entry(chapter_number)
+                .or_default()
+                .insert(ChapterObjective::seeded(&objective));
+        }
+        for (chapter_number, objectives) in seeded {
+            let status = ChapterStatus::NotStarted as i64;
+            let objectives = serde_json::to_string(&objectives)?;
+            database
+                .seed_chapter_progress(student_id, book_id, &chapter_number, status, &objectives)
+                .await?;
+        }
         Ok(())
     }
     pub async fn new(library: Arc<Library>, student_id: i64, book_id: i64) -> anyhow::Result<Self> {
         let database = library.database.clone();
 
-        if sqlx::query_scalar!(
-            "select book_id from teacher_agent where student_id = ? and book_id = ?",
-            student_id,
-            book_id
-        )
-        .fetch_optional(&database)
-        .await?
-        .is_none()
-        {
+        if !database.has_teacher_agent(student_id, book_id).await? {
             return Err(anyhow::anyhow!("Teacher agent not found"));
         }
 
-        let record = sqlx::query!("select ai_model, token_budget FROM agent_setting")
-            .fetch_one(&database)
-            .await?;
+        let (_, token_budget) = database.agent_setting().await?;
+        let token_budget = config().quotas.token_budget.unwrap_or(token_budget as u64);
         let book = library.get_book(book_id).await?;
-        let messages =
-            MessagesManager::load(student_id, &book, record.token_budget as u64, database).await?;
+        let messages = MessagesManager::load(student_id, &book, token_budget, database).await?;
         let mut tool_manager = ToolManager::default();
-        tool_manager.add_tool(GetChapterTool::new(book_id, library.clone()));
-        tool_manager.add_tool(BookJumpTool::new(book_id, library.clone()));
+        tool_manager.add_tool(TracedTool::new(
+            GetChapterTool::new(book_id, library.clone()),
+            student_id,
+            book_id,
+        ));
+        tool_manager.add_tool(TracedTool::new(
+            BookJumpTool::new(book_id, library.clone()),
+            student_id,
+            book_id,
+        ));
+        let attachments = FigureAttachments::default();
+        tool_manager.add_tool(TracedTool::new(
+            GetFigureTool::new(book_id, library.clone(), attachments.clone()),
+            student_id,
+            book_id,
+        ));
+        tool_manager.add_tool(TracedTool::new(
+            RecommendNextTool::new(book_id, library.clone(), messages.get_database()),
+            student_id,
+            book_id,
+        ));
         for tool in messages.get_tools() {
             tool_manager.add_tool_dyn(tool);
         }
         Ok(Self {
             messages,
             tool_manager,
+            attachments,
+            library,
+            book,
+            student_id,
+            unsummarized: false,
         })
     }
     pub async fn input<E>(
@@ -85,25 +137,77 @@ impl TeacherAgent {
     where
         E: From<ResponseEvent> + Send + Sync + 'static,
     {
-        self.messages.add_conversation_message(msg).await?;
-        let tools = self.tool_manager.get_tools();
-        loop {
-            let messages = self.messages.get_messages();
-            let request = CreateChatCompletionRequestArgs::default()
-                .model(AI_MODEL.as_str())
-                .messages(messages)
-                .tools(tools.clone())
-                .build()
-                .unwrap();
-            let mut stream = AI_CLIENT.chat().create_stream(request).await?;
+        let span = info_span!(
+            "teacher_input",
+            student_id = self.student_id,
+            book_id = self.book.id,
+            model = AI_MODEL.as_str()
+        );
+        async {
+            // plans changed by a manager replace the book in the library
+            let book = self.library.get_book(self.book.id).await?;
+            if !Arc::ptr_eq(&book, &self.book) {
+                self.messages.set_book(&book)?;
+                self.book = book;
+            }
+            self.messages.add_conversation_message(msg).await?;
+            self.unsummarized = true;
+            for step in 0usize.. {
+                let span = info_span!(
+                    "teacher_step",
+                    step,
+                    student_id = self.student_id,
+                    book_id = self.book.id
+                );
+                if self.step(&tx).instrument(span).await? {
+                    break;
+                }
+            }
+            // students who only chat send no heartbeats
+            study::refresh_sessions(&self.library.database, self.student_id, self.book.id).await
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// one answer of the model and the tools it called, true if it called none
+    async fn step<E>(&mut self, tx: &Sender<E>) -> anyhow::Result<bool>
+    where
+        E: From<ResponseEvent> + Send + Sync + 'static,
+    {
+        let request = CreateChatCompletionRequestArgs::default()
+            .model(AI_MODEL.as_str())
+            .messages(self.messages.get_messages())
+            .tools(self.tool_manager.get_tools())
+            .stream_options(ChatCompletionStreamOptions {
+                include_usage: true,
+            })
+            .build()
+            .unwrap();
+        let span = info_span!(
+            "completion",
+            model = AI_MODEL.as_str(),
+            purpose = "chat",
+            student_id = self.student_id,
+            book_id = self.book.id
+        );
+        let (whole_content, whole_refusal, tool_calls) = async {
+            let mut llm = LlmRequest::start(AI_MODEL.as_str(), "chat");
+            let mut stream = llm.check(AI_CLIENT.chat().create_stream(request).await)?;
             let mut tool_call_manager = ToolCallStreamManager::new();
             let mut whole_content = String::new();
             let mut whole_refusal = String::new();
             while let Some(result) = stream.next().await {
-                let Some(choice) = result?.choices.pop() else {
+                let mut chunk = llm.check(result)?;
+                // the usage comes in a last chunk without choices
+                if let Some(usage) = &chunk.usage {
+                    llm.usage(usage);
+                }
+                let Some(choice) = chunk.choices.pop() else {
                     continue;
                 };
                 if let Some(content) = choice.delta.content.as_ref() {
+                    llm.token();
                     whole_content.push_str(content);
                     tx.send(ResponseEvent::Content(content.to_string()).into())
                         .await?;
@@ -112,47 +216,154 @@ impl TeacherAgent {
                     whole_refusal.push_str(refusal);
                 }
                 if let Some(tool_call_chunks) = choice.delta.tool_calls {
+                    llm.token();
                     tool_call_manager.process_chunks(tool_call_chunks);
                 }
             }
-            let mut message_builder = ChatCompletionRequestAssistantMessageArgs::default();
-            if !whole_content.is_empty() {
-                message_builder.content(whole_content);
-            }
-            if !whole_refusal.is_empty() {
-                tx.send(ResponseEvent::Refusal(whole_refusal.clone()).into())
-                    .await?;
-                message_builder.refusal(whole_refusal);
-            }
-            let tool_calls = tool_call_manager.finish_stream();
-            if !tool_calls.is_empty() {
-                message_builder.tool_calls(tool_calls.clone());
-            }
-            let assistant_message = message_builder.build()?;
-            self.messages
-                .add_conversation_message(assistant_message)
+            llm.finish();
+            anyhow::Ok((
+                whole_content,
+                whole_refusal,
+                tool_call_manager.finish_stream(),
+            ))
+        }
+        .instrument(span)
+        .await?;
+        let mut message_builder = ChatCompletionRequestAssistantMessageArgs::default();
+        if !whole_content.is_empty() {
+            message_builder.content(whole_content);
+        }
+        if !whole_refusal.is_empty() {
+            tx.send(ResponseEvent::Refusal(whole_refusal.clone()).into())
                 .await?;
-            if tool_calls.is_empty() {
-                break;
-            }
-            for tool_call in &tool_calls {
-                tx.send(ResponseEvent::ToolCall(tool_call.clone()).into())
-                    .await?;
-            }
-            let tool_results = self.tool_manager.call(tool_calls).await;
-            for tool_result in &tool_results {
-                tx.send(ResponseEvent::ToolResult(tool_result.clone()).into())
-                    .await?;
-            }
+            message_builder.refusal(whole_refusal);
+        }
+        if !tool_calls.is_empty() {
+            message_builder.tool_calls(tool_calls.clone());
+        }
+        let assistant_message = message_builder.build()?;
+        self.messages
+            .add_conversation_message(assistant_message)
+            .await?;
+        if tool_calls.is_empty() {
+            return Ok(true);
+        }
+        for tool_call in &tool_calls {
+            tx.send(ResponseEvent::ToolCall(tool_call.clone()).into())
+                .await?;
+        }
+        let tool_results = call_tools(
+            &mut self.tool_manager,
+            tool_calls,
+            self.student_id,
+            self.book.id,
+        )
+        .await;
+        for tool_result in &tool_results {
+            tx.send(ResponseEvent::ToolResult(tool_result.clone()).into())
+                .await?;
+        }
+        self.messages
+            .add_conversation_messages(tool_results)
+            .await?;
+        // tool messages can't carry images, attach the requested figures as a user message
+        let images = self.attachments.take();
+        if !images.is_empty() {
+            let mut parts: Vec<ChatCompletionRequestUserMessageContentPart> = vec![
+                ChatCompletionRequestMessageContentPartText {
+                    text: "Figures requested with GetFigure:".to_string(),
+                }
+                .into(),
+            ];
+            parts.extend(images.into_iter().map(|image_url| {
+                ChatCompletionRequestMessageContentPartImage { image_url }.into()
+            }));
             self.messages
-                .add_conversation_messages(tool_results)
+                .add_conversation_message(ChatCompletionRequestUserMessage {
+                    content: ChatCompletionRequestUserMessageContent::Array(parts),
+                    name: None,
+                })
                 .await?;
         }
-        Ok(())
+        Ok(false)
     }
+
     pub async fn get_conversation(&self) -> Vec<ChatCompletionRequestMessage> {
         self.messages.get_conversation()
     }
+
+    /// turns were taken since the last summary
+    pub fn needs_summary(&self) -> bool {
+        self.unsummarized
+    }
+
+    /// record the progress of the turns since the last summary with the progress tools, at the
+    /// end of a session, the summary is not part of the conversation
+    pub async fn summarize(&mut self) -> anyhow::Result<()> {
+        if !self.unsummarized {
+            return Ok(());
+        }
+        let mut tool_manager = ToolManager::default();
+        for tool in self.messages.get_tools() {
+            tool_manager.add_tool_dyn(tool);
+        }
+        let tools = tool_manager.get_tools();
+        let mut messages = self.messages.get_messages();
+        messages.push(ChatCompletionRequestMessage::System(
+            SUMMARY_PROMPT.to_string().into(),
+        ));
+        for _ in 0..SUMMARY_ROUNDS {
+            let request = CreateChatCompletionRequestArgs::default()
+                .model(AI_MODEL.as_str())
+                .messages(messages.clone())
+                .tools(tools.clone())
+                .build()?;
+            let llm = LlmRequest::start(AI_MODEL.as_str(), "session_summary");
+            let response = llm.response(AI_CLIENT.chat().create(request).await)?;
+            let Some(tool_calls) = response
+                .choices
+                .into_iter()
+                .next()
+                .and_then(|choice| choice.message.tool_calls)
+                .filter(|tool_calls| !tool_calls.is_empty())
+            else {
+                break;
+            };
+            let assistant_message = ChatCompletionRequestAssistantMessageArgs::default()
+                .tool_calls(tool_calls.clone())
+                .build()?;
+            messages.push(assistant_message.into());
+            let tool_results =
+                call_tools(&mut tool_manager, tool_calls, self.student_id, self.book.id).await;
+            messages.extend(tool_results.into_iter().map(Into::into));
+        }
+        self.unsummarized = false;
+        Ok(())
+    }
+}
+
+/// call the tools of one answer of the model together, in a span naming them, each call
+/// has a span of its own from [`TracedTool`]
+async fn call_tools(
+    tool_manager: &mut ToolManager,
+    tool_calls: Vec<ChatCompletionMessageToolCall>,
+    student_id: i64,
+    book_id: i64,
+) -> Vec<ChatCompletionRequestToolMessage> {
+    for tool_call in &tool_calls {
+        metrics::tool_call(&tool_call.function.name);
+    }
+    let tools: Vec<&str> = tool_calls
+        .iter()
+        .map(|tool_call| tool_call.function.name.as_str())
+        .collect();
+    let span = info_span!(
+        "tool_calls",
+        tools = %tools.join(","),
+        student_id,
+        book_id
+    );
+    tool_manager.call(tool_calls).instrument(span).await
 }
 
 impl From<ResponseEvent> for Result<Event, Infallible> {
@@ -7,25 +7,30 @@ use std::{
 
 use anyhow::bail;
 use async_openai::{tools::ToolDyn, types::ChatCompletionRequestMessage};
-use progress::{BookProgress, ChapterObjective, ChapterProgress, ChapterStatus};
-use sqlx::SqlitePool;
+use progress::{
+    Assessment, BookProgress, ChapterObjective, ChapterProgress, ChapterProgressUpdate,
+    ChapterStatus,
+};
 use time::OffsetDateTime;
-use tools::{AddMemoryTool, GetBookProgressTool, ProgressUpdateTool};
+use tools::{AddMemoryTool, GetBookProgressTool, ProgressUpdateTool, RecordAssessmentTool};
 
 use crate::{
     ai_utils::Tokens,
     books::{book::Book, chapter::ChapterNumber},
+    db::{Database, progress::ProgressRow},
+    study,
+    teacher::traced::TracedTool,
 };
 
 #[derive(Debug, Clone)]
 pub struct MessagesDatabase {
     book_id: i64,
     student_id: i64,
-    database: SqlitePool,
+    database: Database,
 }
 
 impl MessagesDatabase {
-    pub async fn new(book_id: i64, student_id: i64, database: SqlitePool) -> anyhow::Result<Self> {
+    pub async fn new(book_id: i64, student_id: i64, database: Database) -> anyhow::Result<Self> {
         Ok(Self {
             book_id,
             student_id,
@@ -33,14 +38,9 @@ impl MessagesDatabase {
         })
     }
     pub async fn get_instruction(&self) -> anyhow::Result<String> {
-        let student_name =
-            sqlx::query_scalar!("select name from student where id = ?", self.student_id)
-                .fetch_one(&self.database)
-                .await?;
-        let book_name = sqlx::query_scalar!("select title from book where id = ?", self.book_id)
-            .fetch_one(&self.database)
-            .await?;
-        let instruction = format!(
+        let student_name = self.database.student_name(self.student_id).await?;
+        let book_name = self.database.book_title(self.book_id).await?;
+        let mut instruction = format!(
             r#"
 ## Role:
 You are Vera, a sharp-witted AI tutor who loves Agatha Christie, artisanal coffee, linguistics trivia, comic sketching, and noir films. You’re direct, sarcastic yet motivating, expecting {student_name} to keep up while secretly rooting for them.
@@ -54,16 +54,18 @@ You are Vera, a sharp-witted AI tutor who loves Agatha Christie, artisanal coffe
 1. **Chapter Intro**: Use [GetChapterContent: "X.Y."] to outline objectives. Set the stage briefly. Example: "Hey, {student_name}, Chapter 1.3 is verbs—sentence superstars. Ready?"
 2. **Guided Reading**: Direct to a section with [BookJump: {{"chapter_number": "X.Y.", "sector_title": "Section Title"}}]. Example: "Check out the verb section in Chapter 1.3."
 3. **Explanation**: Explain one concept in 2-3 sentences, using [AddMemory] for personalization. Example: "Verbs are actions, like ‘run.’ Since you love mysteries, think ‘investigate.’"
-4. **Check**: Ask one question post-explanation. Example: "What’s a verb for a detective story?"
+4. **Check**: Ask one question post-explanation and score the answer with [RecordAssessment]. Example: "What’s a verb for a detective story?"
 5. **Feedback**: Encourage or correct, updating [AddMemory]. Example (correct): "‘Snoop’? Nice one, sleuth!" Example (incorrect): "‘Clue’ is a noun. Try an action word."
-6. **Adjust**: Move forward if understood; simplify or revisit (one [BookJump] max) if not. Log issues in [UpdateProgress].
-7. **Summary**: Summarize and log with [UpdateProgress], updating [AddMemory].
+6. **Adjust**: Move forward if understood; simplify or revisit (one [BookJump] max) if not. Log issues in [ProgressUpdate].
+7. **Summary**: Summarize and log with [ProgressUpdate], updating [AddMemory].
 
 ## Tools:
 - **GetChapterContent**: Retrieve chapter objectives and content.
 - **BookJump**: Guide to textbook sections.
 - **AddMemory**: Store student data for personalization.
-- **UpdateProgress**: Log progress with objectives and next steps.
+- **ProgressUpdate**: Log progress against the chapter's objectives, addressed by their `id` from [GetChapterContent]. Never invent new objectives.
+- **RecordAssessment**: After every quiz answer or clear sign of (mis)understanding, score the objective it tests.
+- **RecommendNext**: When a chapter is finished, ask what to learn or review next instead of blindly moving on.
 
 ## Instructions:
 - **Start**: Introduce Vera and {book_name} with [GetChapterContent: "1.0."]. Begin with Chapter 1.1.
@@ -73,24 +75,31 @@ You are Vera, a sharp-witted AI tutor who loves Agatha Christie, artisanal coffe
 - **Constraints**:
   - One concept, one question per step.
   - Responses must be conversational, tool-syntax-free, and tailored to {student_name}.
-  - If tools fail, assume plausible content and log in [UpdateProgress].
+  - If tools fail, assume plausible content and log in [ProgressUpdate].
 "#
         );
+        if let Some(goal) = study::get_daily_goal(&self.database, self.student_id).await? {
+            let stats = study::get_study_stats(&self.database, self.student_id).await?;
+            instruction.push_str(&format!(
+                r#"
+## Daily Goal:
+{student_name} aims to study {goal} minutes a day, has studied {} minutes today and is on a {}-day streak. Mention the goal briefly at the start of the session.
+"#,
+                stats.today_minutes, stats.current_streak
+            ));
+        }
         Ok(instruction)
     }
 
     /// return (saved_conversation, unsaved_conversation)
     pub async fn get_conversation(&self) -> anyhow::Result<Vec<ChatCompletionRequestMessage>> {
-        let conversation: Vec<ChatCompletionRequestMessage> = sqlx::query_scalar!(
-            "select content from history_message where student_id = ? and book_id = ? order by update_time asc",
-            self.student_id,
-            self.book_id
-        )
-        .fetch_all(&self.database)
-        .await?
-        .into_iter()
-        .map(|content| serde_json::from_str::<ChatCompletionRequestMessage>(&content).unwrap())
-        .collect();
+        let conversation: Vec<ChatCompletionRequestMessage> = self
+            .database
+            .conversation(self.student_id, self.book_id)
+            .await?
+            .into_iter()
+            .map(|content| serde_json::from_str::<ChatCompletionRequestMessage>(&content).unwrap())
+            .collect();
         Ok(conversation)
     }
     pub async fn add_conversation_message(
@@ -99,87 +108,104 @@ You are Vera, a sharp-witted AI tutor who loves Agatha Christie, artisanal coffe
     ) -> anyhow::Result<()> {
         let now = OffsetDateTime::now_utc();
         let content = serde_json::to_string(&message)?;
-        sqlx::query!(
-            "insert into history_message (student_id, book_id, content, update_time) values (?, ?, ?, ?)",
-            self.student_id,
-            self.book_id,
-            content,
-            now
-        )
-        .execute(&self.database)
-        .await?;
-        Ok(())
+        self.database
+            .add_message(self.student_id, self.book_id, &content, now)
+            .await
     }
     pub async fn add_memory(&self, memory: String) -> anyhow::Result<()> {
-        let memories = sqlx::query_scalar!(
-            "select memories from teacher_agent where student_id = ? and book_id = ?",
-            self.student_id,
-            self.book_id
-        )
-        .fetch_one(&self.database)
-        .await?;
-        let mut memories = serde_json::from_str::<BTreeSet<String>>(&memories)?;
+        let record = self
+            .database
+            .teacher_agent(self.student_id, self.book_id)
+            .await?;
+        let mut memories = serde_json::from_str::<BTreeSet<String>>(&record.memories)?;
         memories.insert(memory);
         let memories = serde_json::to_string(&memories)?;
-        sqlx::query!(
-            "update teacher_agent set memories = ? where student_id = ? and book_id = ?",
-            memories,
-            self.student_id,
-            self.book_id
-        )
-        .execute(&self.database)
-        .await?;
-        Ok(())
+        self.database
+            .set_memories(self.student_id, self.book_id, &memories)
+            .await
     }
-    pub async fn update_chapter_progress(
+    /// the canonical objectives of a chapter, as seeded from the chapter plan
+    pub async fn get_seeded_objectives(
+        &self,
+        chapter_number: &ChapterNumber,
+    ) -> anyhow::Result<Vec<ChapterObjective>> {
+        let objectives = self
+            .database
+            .chapter_objectives(self.book_id, &chapter_number.to_string())
+            .await?
+            .iter()
+            .map(ChapterObjective::seeded)
+            .collect();
+        Ok(objectives)
+    }
+
+    /// load the progress of a chapter, seeded with the canonical objectives
+    async fn load_chapter_progress(
         &self,
-        chapter_progress: ChapterProgress,
+        chapter_number: &ChapterNumber,
     ) -> anyhow::Result<ChapterProgress> {
-        let chapter_number = chapter_progress.chapter_number.to_string();
-        let record = sqlx::query!(
-            "select status, objectives, update_time from chapter_progress where student_id = ? and book_id = ? and chapter_number = ?",
-            self.student_id,
-            self.book_id,
-            chapter_number
-        )
-        .fetch_optional(&self.database)
-        .await?;
-        let new_chapter_progress = if let Some(record) = record {
-            let mut old_chapter_progress = ChapterProgress {
-                chapter_number: chapter_progress.chapter_number.clone(),
+        let record = self
+            .database
+            .chapter_progress(self.student_id, self.book_id, &chapter_number.to_string())
+            .await?;
+        let mut chapter_progress = match record {
+            Some(record) => ChapterProgress {
+                chapter_number: chapter_number.clone(),
                 status: ChapterStatus::from(record.status),
                 objectives: serde_json::from_str::<BTreeSet<ChapterObjective>>(&record.objectives)?,
                 update_time: record.update_time,
-            };
-            old_chapter_progress.merge(chapter_progress.clone());
-            old_chapter_progress
-        } else {
-            chapter_progress
+            },
+            None => ChapterProgress {
+                chapter_number: chapter_number.clone(),
+                ..Default::default()
+            },
         };
-        let status = new_chapter_progress.status as i64;
-        let objectives = serde_json::to_string(&new_chapter_progress.objectives)?;
-        sqlx::query!(
-            "insert or replace into chapter_progress (student_id, book_id, chapter_number, status, objectives, update_time) values (?, ?, ?, ?, ?, ?)",
-            self.student_id,
-            self.book_id,
-            chapter_number,
-            status,
-            objectives,
-            new_chapter_progress.update_time
-        )
-        .execute(&self.database)
-        .await?;
-        Ok(new_chapter_progress)
+        chapter_progress.seed(self.get_seeded_objectives(chapter_number).await?);
+        Ok(chapter_progress)
+    }
+
+    async fn store_chapter_progress(
+        &self,
+        chapter_progress: &ChapterProgress,
+    ) -> anyhow::Result<()> {
+        let row = ProgressRow {
+            chapter_number: chapter_progress.chapter_number.to_string(),
+            status: chapter_progress.status as i64,
+            objectives: serde_json::to_string(&chapter_progress.objectives)?,
+            update_time: chapter_progress.update_time,
+        };
+        self.database
+            .store_chapter_progress(self.student_id, self.book_id, &row)
+            .await
+    }
+
+    pub async fn update_chapter_progress(
+        &self,
+        update: ChapterProgressUpdate,
+    ) -> anyhow::Result<ChapterProgress> {
+        let mut chapter_progress = self.load_chapter_progress(&update.chapter_number).await?;
+        chapter_progress.apply(update)?;
+        self.store_chapter_progress(&chapter_progress).await?;
+        Ok(chapter_progress)
+    }
+
+    pub async fn record_assessment(
+        &self,
+        assessment: Assessment,
+    ) -> anyhow::Result<ChapterProgress> {
+        let mut chapter_progress = self
+            .load_chapter_progress(&assessment.chapter_number)
+            .await?;
+        chapter_progress.assess(&assessment)?;
+        self.store_chapter_progress(&chapter_progress).await?;
+        Ok(chapter_progress)
     }
 
     pub async fn get_book_progress(&self) -> anyhow::Result<BookProgress> {
-        let record = sqlx::query!(
-            "select current_chapter_number, memories, update_time from teacher_agent where student_id = ? and book_id = ?",
-            self.student_id,
-            self.book_id
-        )
-        .fetch_one(&self.database)
-        .await?;
+        let record = self
+            .database
+            .teacher_agent(self.student_id, self.book_id)
+            .await?;
         let memories = serde_json::from_str::<BTreeSet<String>>(&record.memories)?;
         let current_learning_chapter = record.current_chapter_number.parse()?;
         let mut book_progress = BookProgress {
@@ -188,13 +214,10 @@ You are Vera, a sharp-witted AI tutor who loves Agatha Christie, artisanal coffe
             memories,
             update_time: record.update_time,
         };
-        let chapter_progresses = sqlx::query!(
-            "select chapter_number, status, objectives, update_time from chapter_progress where student_id = ? and book_id = ?",
-            self.student_id,
-            self.book_id
-        )
-        .fetch_all(&self.database)
-        .await?;
+        let chapter_progresses = self
+            .database
+            .book_progress(self.student_id, self.book_id)
+            .await?;
         for record in chapter_progresses {
             let chapter_number: ChapterNumber = record.chapter_number.parse()?;
             let chapter_progress = ChapterProgress {
@@ -225,7 +248,7 @@ impl MessagesManager {
         student_id: i64,
         book: &Book,
         token_budget: u64,
-        database: SqlitePool,
+        database: Database,
     ) -> anyhow::Result<Self> {
         let database = MessagesDatabase::new(book.id, student_id, database).await?;
 
@@ -235,13 +258,7 @@ impl MessagesManager {
         if token_count > token_budget / 4 {
             bail!("Instruction token: {} is too much", token_count);
         }
-        let book_info = ChatCompletionRequestMessage::System(
-            format!("## Book Info\n```toml\n{}\n```", toml::to_string(&book)?).into(),
-        );
-        let token_count = book_info.tokens();
-        if token_count > token_budget / 4 {
-            bail!("Book info token: {} is too much", token_count);
-        }
+        let book_info = Self::book_info(book, token_budget)?;
         let conversation = database.get_conversation().await?;
         let mut messages = Self {
             instruction,
@@ -256,6 +273,25 @@ impl MessagesManager {
         Ok(messages)
     }
 
+    fn book_info(book: &Book, token_budget: u64) -> anyhow::Result<ChatCompletionRequestMessage> {
+        let book_info = ChatCompletionRequestMessage::System(
+            format!("## Book Info\n```toml\n{}\n```", toml::to_string(&book)?).into(),
+        );
+        let token_count = book_info.tokens();
+        if token_count > token_budget / 4 {
+            bail!("Book info token: {} is too much", token_count);
+        }
+        Ok(book_info)
+    }
+
+    /// replace the book info, after the plans of the book were changed
+    pub fn set_book(&mut self, book: &Book) -> anyhow::Result<()> {
+        self.book_info = Self::book_info(book, self.token_budget)?;
+        self.update_token_count();
+        self.clean_conversation_messages();
+        Ok(())
+    }
+
     pub fn get_messages(&self) -> Vec<ChatCompletionRequestMessage> {
         // get system prompt
         let mut result = vec![self.instruction.clone(), self.book_info.clone()];
@@ -312,11 +348,33 @@ impl MessagesManager {
         }
     }
 
+    pub fn get_database(&self) -> MessagesDatabase {
+        self.database.clone()
+    }
+
     pub fn get_tools(&self) -> Vec<Arc<dyn ToolDyn>> {
+        let (student_id, book_id) = (self.database.student_id, self.database.book_id);
         vec![
-            Arc::new(ProgressUpdateTool::new(self.database.clone())),
-            Arc::new(AddMemoryTool::new(self.database.clone())),
-            Arc::new(GetBookProgressTool::new(self.database.clone())),
+            Arc::new(TracedTool::new(
+                ProgressUpdateTool::new(self.database.clone()),
+                student_id,
+                book_id,
+            )),
+            Arc::new(TracedTool::new(
+                AddMemoryTool::new(self.database.clone()),
+                student_id,
+                book_id,
+            )),
+            Arc::new(TracedTool::new(
+                GetBookProgressTool::new(self.database.clone()),
+                student_id,
+                book_id,
+            )),
+            Arc::new(TracedTool::new(
+                RecordAssessmentTool::new(self.database.clone()),
+                student_id,
+                book_id,
+            )),
         ]
     }
 }
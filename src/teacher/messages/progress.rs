@@ -1,9 +1,10 @@
 use std::cmp::Ordering;
 use std::collections::{BTreeMap, BTreeSet};
 
-use crate::books::chapter::ChapterNumber;
+use crate::books::chapter::{ChapterNumber, PlannedObjective};
 use crate::utils::now_local;
 
+use anyhow::bail;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
@@ -30,6 +31,9 @@ impl From<i64> for ChapterStatus {
 /// Contains the objective description and whether it has been completed
 #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 pub struct ChapterObjective {
+    /// The id of the objective within the chapter, 0 for objectives not seeded from the chapter plan
+    #[serde(default)]
+    pub id: u32,
     /// The text description of the learning objective
     pub description: String,
     /// Whether the objective has been completed
@@ -38,14 +42,71 @@ pub struct ChapterObjective {
     pub progress: Option<String>,
     /// Next step to help the student understand the objective, don't set if the objective is completed
     pub next_step: Option<String>,
+    /// Estimated mastery of the objective, from 0.0 (unknown) to 1.0 (mastered)
+    #[serde(default)]
+    #[schemars(skip)]
+    pub mastery: f64,
     /// The time when the objective was last updated
     #[serde(default = "now_local", with = "time::serde::rfc3339")]
     #[schemars(skip)]
     pub update_time: OffsetDateTime,
 }
+impl ChapterObjective {
+    pub fn seeded(objective: &PlannedObjective) -> Self {
+        Self {
+            id: objective.id,
+            description: objective.description.clone(),
+            completed: false,
+            progress: None,
+            next_step: None,
+            mastery: 0.0,
+            update_time: now_local(),
+        }
+    }
+
+    /// blend a new assessment score into the mastery estimate
+    pub fn assess(&mut self, kind: AssessmentKind, score: f64) {
+        let weight = kind.weight();
+        self.mastery =
+            (self.mastery * (1.0 - weight) + score.clamp(0.0, 1.0) * weight).clamp(0.0, 1.0);
+        self.update_time = now_local();
+    }
+}
+
+/// Where an assessment of the student comes from
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, JsonSchema)]
+pub enum AssessmentKind {
+    /// The student answered a quiz question
+    Quiz,
+    /// The tutor's judgement from the conversation
+    TutorAssessment,
+}
+
+impl AssessmentKind {
+    /// how much a single assessment moves the mastery estimate
+    fn weight(self) -> f64 {
+        match self {
+            AssessmentKind::Quiz => 0.5,
+            AssessmentKind::TutorAssessment => 0.3,
+        }
+    }
+}
+
+/// An assessment of how well the student masters a learning objective
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct Assessment {
+    /// The chapter number of the objective. e.g. "3.", "4.2."
+    pub chapter_number: ChapterNumber,
+    /// The id of the objective, as listed in the chapter's objectives
+    pub objective_id: u32,
+    /// Where the assessment comes from
+    pub kind: AssessmentKind,
+    /// The score of the assessment, from 0.0 (wrong / not understood) to 1.0 (correct / fully understood)
+    pub score: f64,
+}
 impl Ord for ChapterObjective {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.description.cmp(&other.description)
+        (self.id, &self.description).cmp(&(other.id, &other.description))
     }
 }
 impl PartialOrd for ChapterObjective {
@@ -55,17 +116,43 @@ impl PartialOrd for ChapterObjective {
 }
 impl PartialEq for ChapterObjective {
     fn eq(&self, other: &Self) -> bool {
-        self.description == other.description
+        self.id == other.id && self.description == other.description
     }
 }
 impl Eq for ChapterObjective {}
 
 impl std::hash::Hash for ChapterObjective {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
         self.description.hash(state);
     }
 }
 
+/// Update of a single learning objective, addressed by its id
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct ObjectiveUpdate {
+    /// The id of the objective, as listed in the chapter's objectives
+    pub id: u32,
+    /// Whether the objective has been completed
+    pub completed: bool,
+    /// The current progress of the objective, don't set if the objective is completed
+    pub progress: Option<String>,
+    /// Next step to help the student understand the objective, don't set if the objective is completed
+    pub next_step: Option<String>,
+}
+
+/// Update of a student's progress through a specific chapter
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct ChapterProgressUpdate {
+    /// The chapter number to update. e.g. "3.", "4.2."
+    pub chapter_number: ChapterNumber,
+    /// The current status of the chapter
+    pub status: ChapterStatus,
+    /// Updates of the chapter's objectives, only the objectives that changed
+    #[serde(default)]
+    pub objectives: Vec<ObjectiveUpdate>,
+}
+
 /// Tracks a student's progress through a specific chapter
 #[derive(Debug, Clone, Deserialize, Serialize, Hash, JsonSchema)]
 pub struct ChapterProgress {
@@ -101,10 +188,122 @@ impl ChapterProgress {
                 objective.progress = None;
                 objective.next_step = None;
             }
-            self.objectives.insert(objective);
+            self.objectives.replace(objective);
         }
         self.update_time = other.update_time;
     }
+
+    /// add the seeded objectives that are not tracked yet
+    pub fn seed(&mut self, objectives: impl IntoIterator<Item = ChapterObjective>) {
+        for objective in objectives {
+            if !self.objectives.iter().any(|o| o.id == objective.id) {
+                self.objectives.insert(objective);
+            }
+        }
+    }
+
+    /// apply an update, every objective must be addressed by a seeded id
+    pub fn apply(&mut self, update: ChapterProgressUpdate) -> anyhow::Result<()> {
+        let now = now_local();
+        for objective_update in update.objectives {
+            let Some(mut objective) = self
+                .objectives
+                .iter()
+                .find(|o| o.id != 0 && o.id == objective_update.id)
+                .cloned()
+            else {
+                let ids: Vec<String> = self
+                    .objectives
+                    .iter()
+                    .filter(|o| o.id != 0)
+                    .map(|o| format!("{}: {}", o.id, o.description))
+                    .collect();
+                bail!(
+                    "Objective {} not found in chapter {}, available objectives: [{}]",
+                    objective_update.id,
+                    self.chapter_number,
+                    ids.join("; ")
+                );
+            };
+            objective.completed = objective_update.completed;
+            if objective.completed {
+                objective.progress = None;
+                objective.next_step = None;
+            } else {
+                objective.progress = objective_update.progress;
+                objective.next_step = objective_update.next_step;
+            }
+            objective.update_time = now;
+            self.objectives.replace(objective);
+        }
+        self.status = update.status;
+        self.update_time = now;
+        Ok(())
+    }
+
+    /// record an assessment of a seeded objective
+    pub fn assess(&mut self, assessment: &Assessment) -> anyhow::Result<()> {
+        let Some(mut objective) = self
+            .objectives
+            .iter()
+            .find(|o| o.id != 0 && o.id == assessment.objective_id)
+            .cloned()
+        else {
+            bail!(
+                "Objective {} not found in chapter {}",
+                assessment.objective_id,
+                self.chapter_number
+            );
+        };
+        objective.assess(assessment.kind, assessment.score);
+        self.update_time = objective.update_time;
+        self.objectives.replace(objective);
+        if let ChapterStatus::NotStarted = self.status {
+            self.status = ChapterStatus::InProgress;
+        }
+        Ok(())
+    }
+
+    /// mean mastery over the seeded objectives, `None` if the chapter has none
+    pub fn mastery(&self) -> Option<f64> {
+        let masteries: Vec<f64> = self
+            .objectives
+            .iter()
+            .filter(|o| o.id != 0)
+            .map(|o| o.mastery)
+            .collect();
+        if masteries.is_empty() {
+            None
+        } else {
+            Some(masteries.iter().sum::<f64>() / masteries.len() as f64)
+        }
+    }
+}
+
+/// match the tracked objectives of a chapter, as json, to the objectives of a new plan of
+/// the chapter by their descriptions. Seeded objectives without a match are dropped, the new
+/// ones are seeded when the chapter is opened
+pub fn remap_objectives(objectives: &str, planned: &[PlannedObjective]) -> anyhow::Result<String> {
+    let objectives = serde_json::from_str::<BTreeSet<ChapterObjective>>(objectives)?;
+    let mut remapped = BTreeSet::new();
+    for mut objective in objectives {
+        if objective.id != 0 {
+            let Some(new) = planned.iter().find(|new| {
+                new.description
+                    .trim()
+                    .eq_ignore_ascii_case(objective.description.trim())
+            }) else {
+                continue;
+            };
+            if remapped.iter().any(|o: &ChapterObjective| o.id == new.id) {
+                continue;
+            }
+            objective.id = new.id;
+            objective.description = new.description.clone();
+        }
+        remapped.insert(objective);
+    }
+    Ok(serde_json::to_string(&remapped)?)
 }
 
 /// Tracks student progress through book chapters and learning objectives
@@ -130,10 +329,12 @@ fn tt() {
     chapter_progress.chapter_number = "3.1".parse().unwrap();
     chapter_progress.status = ChapterStatus::InProgress;
     chapter_progress.objectives.insert(ChapterObjective {
+        id: 1,
         description: "Learn about the chapter".to_string(),
         completed: false,
         progress: Some("50%".to_string()),
         next_step: Some("Learn about the chapter".to_string()),
+        mastery: 0.0,
         update_time: now_local(),
     });
     let mut book_progress = BookProgress {
@@ -149,6 +350,107 @@ fn tt() {
     println!("{}", str);
 }
 
+#[test]
+fn apply_objective_update() {
+    let mut chapter_progress = ChapterProgress {
+        chapter_number: "2.".parse().unwrap(),
+        ..Default::default()
+    };
+    chapter_progress.seed([
+        ChapterObjective::seeded(&PlannedObjective {
+            id: 1,
+            description: "Explain ownership".to_string(),
+        }),
+        ChapterObjective::seeded(&PlannedObjective {
+            id: 2,
+            description: "Use references".to_string(),
+        }),
+    ]);
+    let update = ChapterProgressUpdate {
+        chapter_number: "2.".parse().unwrap(),
+        status: ChapterStatus::InProgress,
+        objectives: vec![ObjectiveUpdate {
+            id: 2,
+            completed: true,
+            progress: Some("done".to_string()),
+            next_step: None,
+        }],
+    };
+    chapter_progress.apply(update.clone()).unwrap();
+    assert_eq!(chapter_progress.objectives.len(), 2);
+    let objective = chapter_progress
+        .objectives
+        .iter()
+        .find(|o| o.id == 2)
+        .unwrap();
+    assert!(objective.completed);
+    assert!(objective.progress.is_none());
+
+    let mut unknown = update;
+    unknown.objectives[0].id = 3;
+    assert!(chapter_progress.apply(unknown).is_err());
+}
+
+#[test]
+fn assess_objective_mastery() {
+    let mut chapter_progress = ChapterProgress::default();
+    chapter_progress.seed([ChapterObjective::seeded(&PlannedObjective {
+        id: 1,
+        description: "Explain ownership".to_string(),
+    })]);
+    let assessment = Assessment {
+        chapter_number: ChapterNumber::default(),
+        objective_id: 1,
+        kind: AssessmentKind::Quiz,
+        score: 1.0,
+    };
+    chapter_progress.assess(&assessment).unwrap();
+    chapter_progress.assess(&assessment).unwrap();
+    assert!((chapter_progress.mastery().unwrap() - 0.75).abs() < 1e-9);
+    assert!(matches!(chapter_progress.status, ChapterStatus::InProgress));
+}
+
+#[test]
+fn remap_objectives_by_description() {
+    let mut chapter_progress = ChapterProgress::default();
+    chapter_progress.seed([
+        ChapterObjective::seeded(&PlannedObjective {
+            id: 1,
+            description: "Explain ownership".to_string(),
+        }),
+        ChapterObjective::seeded(&PlannedObjective {
+            id: 2,
+            description: "Use references".to_string(),
+        }),
+    ]);
+    let assessment = Assessment {
+        chapter_number: ChapterNumber::default(),
+        objective_id: 2,
+        kind: AssessmentKind::Quiz,
+        score: 1.0,
+    };
+    chapter_progress.assess(&assessment).unwrap();
+    let objectives = serde_json::to_string(&chapter_progress.objectives).unwrap();
+    // the new plan lists the objectives in another order and drops ownership
+    let planned = [
+        PlannedObjective {
+            id: 1,
+            description: "use references ".to_string(),
+        },
+        PlannedObjective {
+            id: 2,
+            description: "Write lifetimes".to_string(),
+        },
+    ];
+    let remapped = remap_objectives(&objectives, &planned).unwrap();
+    let remapped: BTreeSet<ChapterObjective> = serde_json::from_str(&remapped).unwrap();
+    assert_eq!(remapped.len(), 1);
+    let objective = remapped.first().unwrap();
+    assert_eq!(objective.id, 1);
+    assert_eq!(objective.description, "use references ");
+    assert!(objective.mastery > 0.0);
+}
+
 impl BookProgress {
     pub fn add_memory(&mut self, memory: String) {
         self.memories.insert(memory);
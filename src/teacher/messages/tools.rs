@@ -2,7 +2,7 @@ use async_openai::tools::Tool;
 
 use super::{
     MessagesDatabase,
-    progress::{BookProgress, ChapterProgress},
+    progress::{Assessment, BookProgress, ChapterProgress, ChapterProgressUpdate},
 };
 
 pub struct ProgressUpdateTool {
@@ -16,19 +16,22 @@ impl ProgressUpdateTool {
 }
 
 impl Tool for ProgressUpdateTool {
-    type Args = ChapterProgress;
+    type Args = ChapterProgressUpdate;
     type Output = ChapterProgress;
     type Error = anyhow::Error;
     fn name() -> String {
         "ProgressUpdate".to_string()
     }
     fn description() -> Option<String> {
-        Some("Update the progress of a chapter".to_string())
+        Some(
+            "Update the progress of a chapter. Objectives are addressed by the ids listed \
+            in the chapter's objectives, only include the objectives that changed"
+                .to_string(),
+        )
     }
     async fn call(&self, args: Self::Args) -> anyhow::Result<Self::Output> {
         self.messages_db.update_chapter_progress(args).await
     }
-    
 }
 
 pub struct AddMemoryTool {
@@ -80,3 +83,32 @@ impl Tool for GetBookProgressTool {
         self.messages_db.get_book_progress().await
     }
 }
+
+pub struct RecordAssessmentTool {
+    messages_db: MessagesDatabase,
+}
+
+impl RecordAssessmentTool {
+    pub fn new(messages_db: MessagesDatabase) -> Self {
+        Self { messages_db }
+    }
+}
+
+impl Tool for RecordAssessmentTool {
+    type Args = Assessment;
+    type Output = ChapterProgress;
+    type Error = anyhow::Error;
+    fn name() -> String {
+        "RecordAssessment".to_string()
+    }
+    fn description() -> Option<String> {
+        Some(
+            "Record how well the student masters a learning objective, \
+            from a quiz answer or your own assessment of the conversation"
+                .to_string(),
+        )
+    }
+    async fn call(&self, args: Self::Args) -> anyhow::Result<Self::Output> {
+        self.messages_db.record_assessment(args).await
+    }
+}
@@ -0,0 +1,304 @@
+use std::sync::Arc;
+
+use async_openai::tools::Tool;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::books::{book::Book, chapter::ChapterNumber, library::Library};
+
+use super::messages::{
+    MessagesDatabase,
+    progress::{BookProgress, ChapterProgress, ChapterStatus},
+};
+
+/// Mastery below which a prerequisite should be reviewed before moving on
+pub const MASTERY_THRESHOLD: f64 = 0.6;
+
+/// What the student should do next
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(tag = "type")]
+pub enum Recommendation {
+    /// Learn (or continue learning) a chapter
+    Chapter {
+        chapter_number: ChapterNumber,
+        name: String,
+        reason: String,
+    },
+    /// Review a weak objective before going further
+    Review {
+        chapter_number: ChapterNumber,
+        name: String,
+        objective_id: Option<u32>,
+        objective: Option<String>,
+        mastery: f64,
+        reason: String,
+    },
+    /// Every chapter is completed and mastered
+    Finished,
+}
+
+/// Mastery of a chapter, completed chapters without assessed objectives count as mastered
+fn chapter_mastery(progress: Option<&ChapterProgress>) -> f64 {
+    match progress {
+        Some(progress) => match (progress.mastery(), progress.status) {
+            (Some(mastery), _) => mastery,
+            (None, ChapterStatus::Completed) => 1.0,
+            (None, _) => 0.0,
+        },
+        None => 0.0,
+    }
+}
+
+fn review(
+    book: &Book,
+    progress: &BookProgress,
+    chapter_number: &ChapterNumber,
+    reason: String,
+) -> Recommendation {
+    let name = book
+        .chapters
+        .get(chapter_number)
+        .map(|ch| ch.name.clone())
+        .unwrap_or_default();
+    let chapter_progress = progress.chapter_progress.get(chapter_number);
+    let weakest = chapter_progress.and_then(|p| {
+        p.objectives
+            .iter()
+            .filter(|o| o.id != 0)
+            .min_by(|a, b| a.mastery.total_cmp(&b.mastery))
+    });
+    Recommendation::Review {
+        chapter_number: chapter_number.clone(),
+        name,
+        objective_id: weakest.map(|o| o.id),
+        objective: weakest.map(|o| o.description.clone()),
+        mastery: weakest.map_or(chapter_mastery(chapter_progress), |o| o.mastery),
+        reason,
+    }
+}
+
+/// Suggest the next chapter or review topic from the mastery estimates and the prerequisite graph
+pub fn recommend_next(book: &Book, progress: &BookProgress) -> Recommendation {
+    // chapters already being learned come first, then the remaining ones in book order
+    let mut pending: Vec<&ChapterNumber> = book
+        .chapters
+        .keys()
+        .filter(|number| {
+            matches!(
+                progress.chapter_progress.get(*number).map(|p| p.status),
+                Some(ChapterStatus::InProgress)
+            )
+        })
+        .collect();
+    pending.extend(book.chapters.keys().filter(|number| {
+        matches!(
+            progress.chapter_progress.get(*number).map(|p| p.status),
+            None | Some(ChapterStatus::NotStarted)
+        )
+    }));
+
+    if let Some(number) = pending.first() {
+        let weak_prerequisite = book
+            .prerequisites
+            .get(*number)
+            .into_iter()
+            .flatten()
+            .map(|p| (p, chapter_mastery(progress.chapter_progress.get(p))))
+            .filter(|(_, mastery)| *mastery < MASTERY_THRESHOLD)
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((prerequisite, _)) = weak_prerequisite {
+            return review(
+                book,
+                progress,
+                prerequisite,
+                format!("Chapter {} builds on chapter {}", number, prerequisite),
+            );
+        }
+        let name = book.chapters[*number].name.clone();
+        let reason = match progress.chapter_progress.get(*number).map(|p| p.status) {
+            Some(ChapterStatus::InProgress) => "Continue the chapter in progress".to_string(),
+            _ => "Next chapter whose prerequisites are mastered".to_string(),
+        };
+        return Recommendation::Chapter {
+            chapter_number: (*number).clone(),
+            name,
+            reason,
+        };
+    }
+
+    // everything is completed, review the weakest chapter if any
+    let weakest = book
+        .chapters
+        .keys()
+        .map(|n| (n, chapter_mastery(progress.chapter_progress.get(n))))
+        .filter(|(_, mastery)| *mastery < MASTERY_THRESHOLD)
+        .min_by(|a, b| a.1.total_cmp(&b.1));
+    match weakest {
+        Some((number, _)) => review(
+            book,
+            progress,
+            number,
+            "All chapters are completed, but this one is not mastered yet".to_string(),
+        ),
+        None => Recommendation::Finished,
+    }
+}
+
+pub struct RecommendNextTool {
+    book_id: i64,
+    library: Arc<Library>,
+    messages_db: MessagesDatabase,
+}
+
+impl RecommendNextTool {
+    pub fn new(book_id: i64, library: Arc<Library>, messages_db: MessagesDatabase) -> Self {
+        Self {
+            book_id,
+            library,
+            messages_db,
+        }
+    }
+}
+
+impl Tool for RecommendNextTool {
+    type Args = ();
+    type Output = Recommendation;
+    type Error = anyhow::Error;
+    fn name() -> String {
+        "RecommendNext".to_string()
+    }
+    fn description() -> Option<String> {
+        Some(
+            "Recommend which chapter to learn or which topic to review next, \
+            based on the student's mastery and the chapter prerequisites"
+                .to_string(),
+        )
+    }
+    async fn call(&self, _args: Self::Args) -> anyhow::Result<Self::Output> {
+        let book = self.library.get_book(self.book_id).await?;
+        let progress = self.messages_db.get_book_progress().await?;
+        Ok(recommend_next(&book, &progress))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{BTreeMap, BTreeSet},
+        path::PathBuf,
+    };
+
+    use super::*;
+    use crate::{
+        books::chapter::{Chapter, ChapterPlan, PlannedObjective},
+        teacher::messages::progress::ChapterObjective,
+        utils::now_local,
+    };
+
+    fn book() -> Book {
+        let chapters: BTreeMap<ChapterNumber, Chapter> = ["1.", "2.", "3."]
+            .into_iter()
+            .map(|n| {
+                let number: ChapterNumber = n.parse().unwrap();
+                let chapter = Chapter {
+                    name: format!("Chapter {n}"),
+                    number: number.clone(),
+                    path: None,
+                    content: String::new(),
+                    chapter_plan: ChapterPlan {
+                        plan: String::new(),
+                        summary: String::new(),
+                        objectives: vec![],
+                    },
+                };
+                (number, chapter)
+            })
+            .collect();
+        Book {
+            id: 1,
+            title: "Test".to_string(),
+            chapter_numbers: chapters.keys().cloned().collect(),
+            table_of_contents: String::new(),
+            authors: vec![],
+            description: None,
+            teaching_plan: String::new(),
+            prerequisites: BTreeMap::from([(
+                "3.".parse().unwrap(),
+                BTreeSet::from(["1.".parse().unwrap()]),
+            )]),
+            chapters,
+            src_dir: PathBuf::from("src"),
+            figure_descriptions: BTreeMap::new(),
+            content_hash: String::new(),
+        }
+    }
+
+    fn progress(chapters: &[(&str, ChapterStatus, f64)]) -> BookProgress {
+        let mut book_progress = BookProgress {
+            current_learning_chapter: ChapterNumber::default(),
+            chapter_progress: BTreeMap::new(),
+            memories: BTreeSet::new(),
+            update_time: now_local(),
+        };
+        for (number, status, score) in chapters {
+            let mut chapter_progress = ChapterProgress {
+                chapter_number: number.parse().unwrap(),
+                status: *status,
+                ..Default::default()
+            };
+            let mut objective = ChapterObjective::seeded(&PlannedObjective {
+                id: 1,
+                description: "objective".to_string(),
+            });
+            objective.mastery = *score;
+            chapter_progress.objectives.insert(objective);
+            book_progress
+                .chapter_progress
+                .insert(chapter_progress.chapter_number.clone(), chapter_progress);
+        }
+        book_progress
+    }
+
+    #[test]
+    fn recommend_in_order() {
+        let book = book();
+        let recommendation = recommend_next(&book, &progress(&[]));
+        assert!(
+            matches!(recommendation, Recommendation::Chapter { chapter_number, .. } if chapter_number.to_string() == "1.")
+        );
+        let recommendation =
+            recommend_next(&book, &progress(&[("1.", ChapterStatus::Completed, 1.0)]));
+        assert!(
+            matches!(recommendation, Recommendation::Chapter { chapter_number, .. } if chapter_number.to_string() == "2.")
+        );
+    }
+
+    #[test]
+    fn recommend_review_weak_prerequisite() {
+        let book = book();
+        let recommendation = recommend_next(
+            &book,
+            &progress(&[
+                ("1.", ChapterStatus::Completed, 0.2),
+                ("2.", ChapterStatus::Completed, 1.0),
+            ]),
+        );
+        assert!(
+            matches!(recommendation, Recommendation::Review { chapter_number, objective_id: Some(1), .. } if chapter_number.to_string() == "1.")
+        );
+    }
+
+    #[test]
+    fn recommend_finished() {
+        let book = book();
+        let recommendation = recommend_next(
+            &book,
+            &progress(&[
+                ("1.", ChapterStatus::Completed, 1.0),
+                ("2.", ChapterStatus::Completed, 1.0),
+                ("3.", ChapterStatus::Completed, 1.0),
+            ]),
+        );
+        assert!(matches!(recommendation, Recommendation::Finished));
+    }
+}
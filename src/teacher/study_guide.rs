@@ -0,0 +1,454 @@
+use std::{
+    collections::BTreeMap,
+    io::{Cursor, Write},
+};
+
+use futures::StreamExt;
+use pulldown_cmark::{Options, Parser, html};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use utoipa::ToSchema;
+use zip::{ZipWriter, write::SimpleFileOptions};
+
+use super::{
+    TeacherAgent,
+    messages::{
+        MessagesDatabase,
+        progress::{BookProgress, ChapterObjective, ChapterStatus},
+    },
+    recommend::MASTERY_THRESHOLD,
+};
+use crate::{
+    ai_utils::{self, AI_PLAN_CONCURRENCY},
+    books::{
+        book::Book,
+        chapter::{Chapter, ChapterNumber},
+        export::escape_xml,
+        library::Library,
+    },
+    student,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum GuideFormat {
+    /// a single markdown document
+    Markdown,
+    /// a single html page, ready to print
+    Html,
+    /// a zip of an mdbook with a page per chapter
+    Mdbook,
+}
+
+impl GuideFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            GuideFormat::Markdown => "md",
+            GuideFormat::Html => "html",
+            GuideFormat::Mdbook => "zip",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            GuideFormat::Markdown => "text/markdown; charset=utf-8",
+            GuideFormat::Html => "text/html; charset=utf-8",
+            GuideFormat::Mdbook => "application/zip",
+        }
+    }
+}
+
+/// A question to check the understanding of a chapter
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct QuizQuestion {
+    /// The question, answerable from the chapter alone
+    pub question: String,
+    /// A short model answer
+    pub answer: String,
+}
+
+/// Quiz questions for a chapter, focused on the objectives the student has not mastered yet
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+struct ChapterQuiz(Vec<QuizQuestion>);
+
+/// A study guide of a book for one student: the plans of the book and its chapters, the
+/// student's objectives and notes, and quiz questions
+pub struct StudyGuide<'a> {
+    book: &'a Book,
+    progress: &'a BookProgress,
+    student_name: String,
+    quizzes: BTreeMap<ChapterNumber, Vec<QuizQuestion>>,
+}
+
+fn heading(level: usize, text: &str) -> String {
+    format!("{} {}\n\n", "#".repeat(level.min(6)), text)
+}
+
+/// move the headings of `markdown` down by `levels`, so a plan nests under its section
+fn demote_headings(markdown: &str, levels: usize) -> String {
+    let mut fence: Option<&str> = None;
+    let mut output = String::with_capacity(markdown.len() + 16);
+    for line in markdown.lines() {
+        let trimmed = line.trim_start();
+        if let Some(marker) = fence {
+            if trimmed.starts_with(marker) {
+                fence = None;
+            }
+        } else if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            fence = Some(&trimmed[..3]);
+        } else if let Some(rest) = trimmed.strip_prefix('#') {
+            let level = 1 + rest.chars().take_while(|c| *c == '#').count();
+            let text = rest.trim_start_matches('#');
+            if level <= 6 && (text.is_empty() || text.starts_with(' ')) {
+                output.push_str(&"#".repeat((level + levels).min(6)));
+                output.push_str(text);
+                output.push('\n');
+                continue;
+            }
+        }
+        output.push_str(line);
+        output.push('\n');
+    }
+    output
+}
+
+fn status_text(status: ChapterStatus) -> &'static str {
+    match status {
+        ChapterStatus::NotStarted => "not started",
+        ChapterStatus::InProgress => "in progress",
+        ChapterStatus::Completed => "completed",
+    }
+}
+
+impl<'a> StudyGuide<'a> {
+    pub fn new(book: &'a Book, progress: &'a BookProgress, student_name: String) -> Self {
+        Self {
+            book,
+            progress,
+            student_name,
+            quizzes: BTreeMap::new(),
+        }
+    }
+
+    /// the objectives of a chapter as tracked for the student, the planned ones if the
+    /// student has no progress in the chapter
+    fn objectives(&self, chapter: &Chapter) -> Vec<ChapterObjective> {
+        match self.progress.chapter_progress.get(&chapter.number) {
+            Some(progress) if !progress.objectives.is_empty() => {
+                progress.objectives.iter().cloned().collect()
+            }
+            _ => chapter
+                .chapter_plan
+                .objectives
+                .iter()
+                .map(ChapterObjective::seeded)
+                .collect(),
+        }
+    }
+
+    /// generate the quiz questions of every planned chapter, `AI_PLAN_CONCURRENCY` at a time,
+    /// a chapter whose quiz fails is left without one
+    pub async fn generate_quizzes(&mut self) {
+        let chapters: Vec<_> = self
+            .book
+            .chapters
+            .values()
+            .filter(|ch| !ch.chapter_plan.plan.is_empty())
+            .map(|ch| (ch.number.clone(), self.quiz_prompt(ch)))
+            .collect();
+        let mut quizzes = futures::stream::iter(chapters)
+            .map(|(number, prompt)| async move {
+                let quiz =
+                    ai_utils::with_retry(|| ai_utils::extract::<ChapterQuiz>(prompt.clone())).await;
+                (number, quiz)
+            })
+            .buffer_unordered(*AI_PLAN_CONCURRENCY);
+        while let Some((number, quiz)) = quizzes.next().await {
+            match quiz {
+                Ok(quiz) => {
+                    self.quizzes.insert(number, quiz.0);
+                }
+                Err(e) => warn!("generate quiz of chapter {} failed: {}", number, e),
+            }
+        }
+    }
+
+    fn quiz_prompt(&self, chapter: &Chapter) -> String {
+        let weak: Vec<String> = self
+            .objectives(chapter)
+            .iter()
+            .filter(|o| !o.completed || o.mastery < MASTERY_THRESHOLD)
+            .map(|o| format!("- {}", o.description))
+            .collect();
+        let focus = if weak.is_empty() {
+            "The student has mastered this chapter, ask review questions.".to_string()
+        } else {
+            format!(
+                "Focus on the objectives the student has not mastered yet:\n{}",
+                weak.join("\n")
+            )
+        };
+        format!(
+            "Write 3 to 5 quiz questions with short answers for a student of the chapter \"{} {}\" of the book \"{}\".\n\
+            {}\n\n## Chapter summary\n{}\n\n## Chapter plan\n{}",
+            chapter.number,
+            chapter.name,
+            self.book.title,
+            focus,
+            chapter.chapter_plan.summary,
+            chapter.chapter_plan.plan
+        )
+    }
+
+    /// the teaching plan of the book and the teacher's notes on the student
+    fn introduction(&self, level: usize) -> String {
+        let mut markdown = heading(
+            level,
+            &format!("Study guide: {} for {}", self.book.title, self.student_name),
+        );
+        if !self.book.teaching_plan.is_empty() {
+            markdown.push_str(&heading(level + 1, "Teaching plan"));
+            markdown.push_str(&demote_headings(&self.book.teaching_plan, level + 1));
+            markdown.push('\n');
+        }
+        if !self.progress.memories.is_empty() {
+            markdown.push_str(&heading(level + 1, "Notes from your teacher"));
+            for memory in &self.progress.memories {
+                markdown.push_str(&format!("- {}\n", memory.trim()));
+            }
+            markdown.push('\n');
+        }
+        markdown
+    }
+
+    fn chapter(&self, chapter: &Chapter, level: usize) -> String {
+        let mut markdown = heading(level, &format!("{} {}", chapter.number, chapter.name));
+        if let Some(progress) = self.progress.chapter_progress.get(&chapter.number) {
+            markdown.push_str(&format!("*Status: {}*\n\n", status_text(progress.status)));
+        }
+        if !chapter.chapter_plan.summary.is_empty() {
+            markdown.push_str(&heading(level + 1, "Summary"));
+            markdown.push_str(chapter.chapter_plan.summary.trim());
+            markdown.push_str("\n\n");
+        }
+        let objectives = self.objectives(chapter);
+        if !objectives.is_empty() {
+            markdown.push_str(&heading(level + 1, "Your objectives"));
+            for objective in objectives {
+                let check = if objective.completed { "x" } else { " " };
+                markdown.push_str(&format!("- [{}] {}", check, objective.description));
+                if objective.mastery > 0.0 {
+                    markdown.push_str(&format!(" (mastery {:.0}%)", objective.mastery * 100.0));
+                }
+                if let Some(next_step) = &objective.next_step {
+                    markdown.push_str(&format!("  \n  Next step: {}", next_step));
+                }
+                markdown.push('\n');
+            }
+            markdown.push('\n');
+        }
+        if !chapter.chapter_plan.plan.is_empty() {
+            markdown.push_str(&heading(level + 1, "Chapter plan"));
+            markdown.push_str(&demote_headings(&chapter.chapter_plan.plan, level + 1));
+            markdown.push('\n');
+        }
+        if let Some(quiz) = self.quizzes.get(&chapter.number) {
+            markdown.push_str(&heading(level + 1, "Quiz"));
+            for (i, question) in quiz.iter().enumerate() {
+                markdown.push_str(&format!("{}. {}\n", i + 1, question.question.trim()));
+            }
+            markdown.push('\n');
+            markdown.push_str(&heading(level + 2, "Answers"));
+            for (i, question) in quiz.iter().enumerate() {
+                markdown.push_str(&format!("{}. {}\n", i + 1, question.answer.trim()));
+            }
+            markdown.push('\n');
+        }
+        markdown
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut markdown = self.introduction(1);
+        for chapter in self.book.chapters.values() {
+            markdown.push_str(&self.chapter(chapter, 2));
+        }
+        markdown
+    }
+
+    pub fn to_html(&self) -> String {
+        let mut options = Options::empty();
+        options.insert(Options::ENABLE_TABLES);
+        options.insert(Options::ENABLE_TASKLISTS);
+        options.insert(Options::ENABLE_STRIKETHROUGH);
+        let markdown = self.to_markdown();
+        let mut body = String::with_capacity(markdown.len() * 3 / 2);
+        html::push_html(&mut body, Parser::new_ext(&markdown, options));
+        format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Study guide: {}</title>\n\
+            <style>body {{ max-width: 48em; margin: auto; font-family: sans-serif; line-height: 1.5; }} \
+            h2 {{ break-before: page; }}</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+            escape_xml(&self.book.title),
+            body
+        )
+    }
+
+    /// an mdbook with the introduction and a page per chapter
+    pub fn to_mdbook_zip(&self) -> anyhow::Result<Vec<u8>> {
+        let mut summary = "# Summary\n\n[Introduction](introduction.md)\n\n".to_string();
+        let mut files = vec![("src/introduction.md".to_string(), self.introduction(1))];
+        for (i, chapter) in self.book.chapters.values().enumerate() {
+            let file = format!("chapter_{}.md", i + 1);
+            // prefix and suffix chapters (0.x and -1.x) are not nested
+            let depth = match chapter.number.first() {
+                Some(0 | -1) | None => 0,
+                Some(_) => chapter.number.len() - 1,
+            };
+            summary.push_str(&format!(
+                "{}- [{}]({})\n",
+                "  ".repeat(depth),
+                chapter.name.replace('[', "\\[").replace(']', "\\]"),
+                file
+            ));
+            files.push((format!("src/{}", file), self.chapter(chapter, 1)));
+        }
+        files.push(("src/SUMMARY.md".to_string(), summary));
+        let title = format!("Study guide: {}", self.book.title);
+        let config = format!(
+            "[book]\ntitle = {}\nauthors = [{}]\n",
+            toml::Value::String(title),
+            toml::Value::String(self.student_name.clone())
+        );
+        files.push(("book.toml".to_string(), config));
+
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in files {
+            zip.start_file(name, SimpleFileOptions::default())?;
+            zip.write_all(content.as_bytes())?;
+        }
+        Ok(zip.finish()?.into_inner())
+    }
+}
+
+/// the study guide of `book_id` for a student, in `format`, with quiz questions if `quiz`
+pub async fn study_guide(
+    library: &Library,
+    student_id: i64,
+    book_id: i64,
+    format: GuideFormat,
+    quiz: bool,
+) -> anyhow::Result<Vec<u8>> {
+    let student = student::get_student_info(&library.database, student_id).await?;
+    let book = library.get_book(book_id).await?;
+    // a book the student has not opened yet gets the planned objectives
+    TeacherAgent::init(student_id, book_id, library.database.clone()).await?;
+    let progress = MessagesDatabase::new(book_id, student_id, library.database.clone())
+        .await?
+        .get_book_progress()
+        .await?;
+    let mut guide = StudyGuide::new(&book, &progress, student.name);
+    if quiz {
+        guide.generate_quizzes().await;
+    }
+    Ok(match format {
+        GuideFormat::Markdown => guide.to_markdown().into_bytes(),
+        GuideFormat::Html => guide.to_html().into_bytes(),
+        GuideFormat::Mdbook => guide.to_mdbook_zip()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeSet, path::PathBuf};
+
+    use super::*;
+    use crate::{
+        books::chapter::{ChapterPlan, PlannedObjective},
+        teacher::messages::progress::ChapterProgress,
+        utils::now_local,
+    };
+
+    #[test]
+    fn headings_are_demoted() {
+        let plan = "# Plan\n## Steps\n```\n# not a heading\n```\n#hashtag\n";
+        assert_eq!(
+            demote_headings(plan, 2),
+            "### Plan\n#### Steps\n```\n# not a heading\n```\n#hashtag\n"
+        );
+    }
+
+    #[test]
+    fn guide_markdown() {
+        let number: ChapterNumber = "1.".parse().unwrap();
+        let chapter = Chapter {
+            name: "Loops".to_string(),
+            number: number.clone(),
+            path: None,
+            content: String::new(),
+            chapter_plan: ChapterPlan {
+                plan: "# Plan for loops".to_string(),
+                summary: "About loops.".to_string(),
+                objectives: vec![PlannedObjective {
+                    id: 1,
+                    description: "Write a for loop".to_string(),
+                }],
+            },
+        };
+        let book = Book {
+            id: 1,
+            title: "Rust".to_string(),
+            chapter_numbers: BTreeSet::from([number.clone()]),
+            table_of_contents: String::new(),
+            authors: vec![],
+            description: None,
+            teaching_plan: "# Overall".to_string(),
+            prerequisites: BTreeMap::new(),
+            chapters: BTreeMap::from([(number.clone(), chapter)]),
+            src_dir: PathBuf::from("src"),
+            figure_descriptions: BTreeMap::new(),
+            content_hash: String::new(),
+        };
+        let mut objective = ChapterObjective::seeded(&PlannedObjective {
+            id: 1,
+            description: "Write a for loop".to_string(),
+        });
+        objective.mastery = 0.5;
+        objective.next_step = Some("Practice ranges".to_string());
+        let progress = BookProgress {
+            current_learning_chapter: number.clone(),
+            chapter_progress: BTreeMap::from([(
+                number.clone(),
+                ChapterProgress {
+                    chapter_number: number.clone(),
+                    status: ChapterStatus::InProgress,
+                    objectives: BTreeSet::from([objective]),
+                    update_time: now_local(),
+                },
+            )]),
+            memories: BTreeSet::from(["Likes examples".to_string()]),
+            update_time: now_local(),
+        };
+        let mut guide = StudyGuide::new(&book, &progress, "Ada".to_string());
+        guide.quizzes.insert(
+            number,
+            vec![QuizQuestion {
+                question: "What does `0..3` yield?".to_string(),
+                answer: "0, 1 and 2".to_string(),
+            }],
+        );
+        let markdown = guide.to_markdown();
+        assert!(
+            markdown
+                .starts_with("# Study guide: Rust for Ada\n\n## Teaching plan\n\n### Overall\n")
+        );
+        assert!(markdown.contains("- Likes examples\n"));
+        assert!(markdown.contains("## 1. Loops\n\n*Status: in progress*\n"));
+        assert!(
+            markdown
+                .contains("- [ ] Write a for loop (mastery 50%)  \n  Next step: Practice ranges\n")
+        );
+        assert!(markdown.contains("### Chapter plan\n\n#### Plan for loops\n"));
+        assert!(markdown.contains("### Quiz\n\n1. What does `0..3` yield?\n"));
+        assert!(guide.to_html().contains("<h2>1. Loops</h2>"));
+    }
+}
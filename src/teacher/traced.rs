@@ -0,0 +1,41 @@
+use async_openai::tools::Tool;
+use tracing::{Instrument, info_span};
+
+/// A tool whose calls each run in a span of their own, so a slow tool stands out while
+/// the tool manager still calls the tools of a round together
+pub struct TracedTool<T> {
+    tool: T,
+    student_id: i64,
+    book_id: i64,
+}
+
+impl<T> TracedTool<T> {
+    pub fn new(tool: T, student_id: i64, book_id: i64) -> Self {
+        Self {
+            tool,
+            student_id,
+            book_id,
+        }
+    }
+}
+
+impl<T: Tool> Tool for TracedTool<T> {
+    type Args = T::Args;
+    type Output = T::Output;
+    type Error = T::Error;
+    fn name() -> String {
+        T::name()
+    }
+    fn description() -> Option<String> {
+        T::description()
+    }
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        let span = info_span!(
+            "tool_call",
+            tool = %T::name(),
+            student_id = self.student_id,
+            book_id = self.book_id
+        );
+        self.tool.call(args).instrument(span).await
+    }
+}
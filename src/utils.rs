@@ -1,8 +1,11 @@
 use std::{path::PathBuf, sync::LazyLock};
 
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_sdk::trace::SdkTracerProvider;
 use time::{UtcOffset, format_description::well_known};
 use tracing::level_filters::LevelFilter;
-use tracing_subscriber::{EnvFilter, fmt::time::OffsetTime};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::{EnvFilter, fmt::time::OffsetTime, layer::SubscriberExt};
 
 pub static LOCAL_OFFSET: LazyLock<UtcOffset> =
     LazyLock::new(|| match time::UtcOffset::current_local_offset() {
@@ -38,8 +41,32 @@ pub fn sleep_until_next_day(until: time::Time) {
     std::thread::sleep((until - now).unsigned_abs());
 }
 
+/// Keeps the log writer and the span exporter running, flushes them when dropped
+pub struct LogGuard {
+    _writer: WorkerGuard,
+    tracer_provider: Option<SdkTracerProvider>,
+}
+
+impl Drop for LogGuard {
+    fn drop(&mut self) {
+        if let Some(provider) = self.tracer_provider.take()
+            && let Err(e) = provider.shutdown()
+        {
+            eprintln!("failed to export the last spans: {}", e);
+        }
+    }
+}
+
 /// initialize the log
-pub fn init_log(log_dir: Option<PathBuf>) -> tracing_appender::non_blocking::WorkerGuard {
+pub fn init_log(log_dir: Option<PathBuf>) -> LogGuard {
+    init_tracing(log_dir, None)
+}
+
+/// initialize the log, the spans are also exported by `tracer_provider` if given
+pub fn init_tracing(
+    log_dir: Option<PathBuf>,
+    tracer_provider: Option<SdkTracerProvider>,
+) -> LogGuard {
     let env_filter = EnvFilter::builder()
         .with_default_directive(LevelFilter::INFO.into())
         .from_env_lossy();
@@ -62,7 +89,16 @@ pub fn init_log(log_dir: Option<PathBuf>) -> tracing_appender::non_blocking::Wor
         // output to stderr
         tracing_appender::non_blocking(std::io::stderr())
     };
-    let subscriber = subscriber_builder.with_writer(non_blocking).finish();
+    let otel_layer = tracer_provider
+        .as_ref()
+        .map(|provider| tracing_opentelemetry::layer().with_tracer(provider.tracer("book-server")));
+    let subscriber = subscriber_builder
+        .with_writer(non_blocking)
+        .finish()
+        .with(otel_layer);
     tracing::subscriber::set_global_default(subscriber).expect("init log failed");
-    guard
+    LogGuard {
+        _writer: guard,
+        tracer_provider,
+    }
 }